@@ -0,0 +1,345 @@
+//! Clients of the local API
+//!
+//! Each client gets its own bearer token, issued from the app, and a set of
+//! scopes that limit what it may do. Only a SHA-256 hash of the token is
+//! stored, so the token is shown once when it is issued. Payments made by a
+//! client go through the spending policy as `api:<id>`, like NWC requests.
+//! Clients paired from a web page are bound to the page's origin.
+//!
+//! Clients are shared by all profiles, so they live in the top-level data
+//! directory.
+
+use crate::storage;
+use crate::tollgate::origin::Origin;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+const CLIENTS_FILE: &str = "api-clients.json";
+const TOKEN_PREFIX: &str = "wally_";
+
+pub type ApiClientsState = Arc<ApiClients>;
+
+#[derive(Debug, Error)]
+pub enum ApiClientError {
+    #[error("Client name must not be empty")]
+    EmptyName,
+    #[error("Client needs at least one scope")]
+    NoScopes,
+    #[error("API client not found: {0}")]
+    NotFound(String),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// What a client may do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiScope {
+    /// Balances, transactions, mints, sessions and connections
+    Read,
+    /// Receive cashu tokens
+    Receive,
+    /// Pay invoices and payment requests, create tokens
+    Spend,
+    /// Change mints and NWC connections
+    Manage,
+    /// Create NWC connection requests on the connection server
+    Connect,
+    /// Use the Routstr proxy, which pays for requests
+    Proxy,
+}
+
+/// A registered client. Never contains the token itself.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiClient {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    pub created_at: u64,
+    /// Web origin the client was paired from; requests from other pages
+    /// are refused
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub web_origin: Option<String>,
+}
+
+impl ApiClient {
+    pub fn has_scope(&self, scope: ApiScope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    /// Origin of payments made with this client's token
+    pub fn origin(&self) -> Origin {
+        Origin::Api {
+            client: self.id.clone(),
+        }
+    }
+}
+
+/// Stored form, which adds the token hash
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredClient {
+    #[serde(flatten)]
+    client: ApiClient,
+    token_hash: String,
+}
+
+/// A newly issued client with its token
+#[derive(Debug, Clone, Serialize)]
+pub struct IssuedApiClient {
+    pub client: ApiClient,
+    pub token: String,
+}
+
+pub struct ApiClients {
+    path: Option<PathBuf>,
+    clients: Mutex<Vec<StoredClient>>,
+}
+
+fn hash_token(token: &str) -> String {
+    hex(&Sha256::digest(token.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex(&bytes)
+}
+
+/// Compare two secrets without leaking where they differ
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+impl ApiClients {
+    /// Load the registered clients, starting empty if there are none. A
+    /// damaged file is moved aside, so its clients have no access until they
+    /// are registered again.
+    pub fn load() -> Self {
+        let path = crate::profiles::root_dir()
+            .ok()
+            .map(|root| root.join(CLIENTS_FILE));
+        let clients = match path.as_deref().map(storage::read_json) {
+            Some(Ok(clients)) => clients.unwrap_or_default(),
+            Some(Err(e)) => {
+                log::error!("Failed to load API clients: {}", e);
+                Vec::new()
+            }
+            None => Vec::new(),
+        };
+
+        Self::with_clients(path, clients)
+    }
+
+    /// Clients kept only in memory when `path` is `None`
+    pub(crate) fn with_clients(path: Option<PathBuf>, clients: Vec<StoredClient>) -> Self {
+        Self {
+            path,
+            clients: Mutex::new(clients),
+        }
+    }
+
+    fn save(&self, clients: &[StoredClient]) -> Result<(), ApiClientError> {
+        if let Some(path) = &self.path {
+            storage::write_atomic(path, serde_json::to_vec_pretty(clients)?)?;
+        }
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<ApiClient> {
+        self.clients
+            .lock()
+            .expect("api clients poisoned")
+            .iter()
+            .map(|stored| stored.client.clone())
+            .collect()
+    }
+
+    /// Register a client and return its token, which is not stored
+    pub fn issue(
+        &self,
+        name: &str,
+        scopes: Vec<ApiScope>,
+        origin: Option<String>,
+    ) -> Result<IssuedApiClient, ApiClientError> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(ApiClientError::EmptyName);
+        }
+        let mut scopes = scopes;
+        scopes.sort_by_key(|scope| *scope as u8);
+        scopes.dedup();
+        if scopes.is_empty() {
+            return Err(ApiClientError::NoScopes);
+        }
+
+        let token = format!("{}{}", TOKEN_PREFIX, random_hex(32));
+        let client = ApiClient {
+            id: random_hex(6),
+            name: name.to_string(),
+            scopes,
+            created_at: chrono::Utc::now().timestamp() as u64,
+            web_origin: origin,
+        };
+
+        let mut clients = self.clients.lock().expect("api clients poisoned");
+        clients.push(StoredClient {
+            client: client.clone(),
+            token_hash: hash_token(&token),
+        });
+        if let Err(e) = self.save(&clients) {
+            clients.pop();
+            return Err(e);
+        }
+        log::info!("Issued local API client {} ({})", client.name, client.id);
+        Ok(IssuedApiClient { client, token })
+    }
+
+    pub fn revoke(&self, id: &str) -> Result<ApiClient, ApiClientError> {
+        let mut clients = self.clients.lock().expect("api clients poisoned");
+        let index = clients
+            .iter()
+            .position(|stored| stored.client.id == id)
+            .ok_or_else(|| ApiClientError::NotFound(id.to_string()))?;
+        let removed = clients.remove(index);
+        if let Err(e) = self.save(&clients) {
+            clients.insert(index, removed);
+            return Err(e);
+        }
+        let removed = removed.client;
+        log::info!("Revoked local API client {} ({})", removed.name, removed.id);
+        Ok(removed)
+    }
+
+    /// Whether a client was paired from `origin`
+    pub fn has_web_origin(&self, origin: &str) -> bool {
+        self.clients
+            .lock()
+            .expect("api clients poisoned")
+            .iter()
+            .any(|stored| stored.client.web_origin.as_deref() == Some(origin))
+    }
+
+    /// Client owning `token`, if any
+    pub fn authenticate(&self, token: &str) -> Option<ApiClient> {
+        let hash = hash_token(token);
+        self.clients
+            .lock()
+            .expect("api clients poisoned")
+            .iter()
+            .find(|stored| constant_time_eq(stored.token_hash.as_bytes(), hash.as_bytes()))
+            .map(|stored| stored.client.clone())
+    }
+}
+
+#[tauri::command]
+pub async fn list_api_clients(
+    clients: tauri::State<'_, ApiClientsState>,
+    lock: tauri::State<'_, crate::app_lock::AppLockState>,
+) -> Result<Vec<ApiClient>, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
+    Ok(clients.list())
+}
+
+#[tauri::command]
+pub async fn create_api_client(
+    name: String,
+    scopes: Vec<ApiScope>,
+    clients: tauri::State<'_, ApiClientsState>,
+    lock: tauri::State<'_, crate::app_lock::AppLockState>,
+) -> Result<IssuedApiClient, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    clients
+        .issue(&name, scopes, None)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn revoke_api_client(
+    id: String,
+    clients: tauri::State<'_, ApiClientsState>,
+    lock: tauri::State<'_, crate::app_lock::AppLockState>,
+) -> Result<ApiClient, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    clients.revoke(&id).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret-token", b"secret-token"));
+        assert!(!constant_time_eq(b"secret-token", b"secret-tokem"));
+        assert!(!constant_time_eq(b"secret", b"secret-token"));
+        assert!(!constant_time_eq(b"", b"secret-token"));
+    }
+
+    #[test]
+    fn test_issue_authenticate_revoke() {
+        let path =
+            std::env::temp_dir().join(format!("wally-api-clients-{}.json", uuid::Uuid::new_v4()));
+        let clients = ApiClients::with_clients(Some(path.clone()), Vec::new());
+
+        let issued = clients
+            .issue(
+                " test runner ",
+                vec![ApiScope::Spend, ApiScope::Read, ApiScope::Read],
+                None,
+            )
+            .unwrap();
+        assert!(issued.token.starts_with(TOKEN_PREFIX));
+        assert_eq!(issued.client.name, "test runner");
+        assert_eq!(issued.client.scopes, vec![ApiScope::Read, ApiScope::Spend]);
+
+        // Only the hash is written to disk
+        let stored = fs::read_to_string(&path).unwrap();
+        assert!(!stored.contains(&issued.token));
+
+        let reloaded =
+            ApiClients::with_clients(Some(path.clone()), serde_json::from_str(&stored).unwrap());
+        let client = reloaded.authenticate(&issued.token).unwrap();
+        assert_eq!(client.id, issued.client.id);
+        assert!(client.has_scope(ApiScope::Spend));
+        assert!(!client.has_scope(ApiScope::Manage));
+        assert_eq!(client.origin().to_string(), format!("api:{}", client.id));
+        assert!(reloaded.authenticate("wally_wrong").is_none());
+
+        reloaded.revoke(&client.id).unwrap();
+        assert!(reloaded.authenticate(&issued.token).is_none());
+        assert!(matches!(
+            reloaded.revoke(&client.id),
+            Err(ApiClientError::NotFound(_))
+        ));
+        assert!(clients.issue("", vec![ApiScope::Read], None).is_err());
+        assert!(clients.issue("x", Vec::new(), None).is_err());
+
+        let paired = clients
+            .issue(
+                "web app",
+                vec![ApiScope::Connect],
+                Some("https://app.example".to_string()),
+            )
+            .unwrap();
+        assert!(clients.has_web_origin("https://app.example"));
+        assert!(!clients.has_web_origin("https://evil.example"));
+        clients.revoke(&paired.client.id).unwrap();
+        assert!(!clients.has_web_origin("https://app.example"));
+
+        let _ = fs::remove_file(path);
+    }
+}
@@ -0,0 +1,213 @@
+//! Wallet backend shared by the app and the headless daemon
+//!
+//! Starts the local relay, the wallet service, NWC, the connection server
+//! and Routstr under the supervisor, serves them on the LAN when LAN mode is
+//! on, and hands back the shared state. The Tauri app registers
+//! that state with the webview; the daemon drives it through the local API.
+//! Both write the ports they ended up on to the discovery file.
+
+use crate::api_clients::{ApiClients, ApiClientsState};
+use crate::app_lock::{AppLock, AppLockState};
+use crate::config;
+use crate::connection_server::{self, ConnectionServerState, PendingConnectionsState};
+use crate::events::{self, BackendEvent};
+use crate::lan::{LanService, LanSettings, LanState};
+use crate::pairing::{Pairings, PairingsState};
+use crate::routstr::{self, RoutstrService, RoutstrState};
+use crate::runtime::{self, RuntimeInfo, RuntimeState};
+use crate::supervisor::supervisor;
+use crate::tollgate::approvals::{ApprovalEvent, ApprovalsState};
+use crate::tollgate::TollGateService;
+use crate::{relay, NwcState, TollGateState};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// How the backend is exposed
+pub(crate) struct BackendOptions {
+    /// Preferred ports; free ones are used when they are taken
+    pub relay_port: u16,
+    pub connection_port: u16,
+    /// Owner token for the local API; clients issued from the app have their
+    /// own. Generated for this run when unset.
+    pub api_token: Option<String>,
+    /// Web origins allowed to call the connection server without pairing
+    pub allowed_origins: Vec<String>,
+    /// LAN mode settings to use instead of the stored ones
+    pub lan: Option<LanSettings>,
+}
+
+impl Default for BackendOptions {
+    /// Ports from the config
+    fn default() -> Self {
+        let config = config::current();
+        Self {
+            relay_port: config.ports.relay,
+            connection_port: config.ports.connection,
+            api_token: None,
+            allowed_origins: Vec::new(),
+            lan: None,
+        }
+    }
+}
+
+/// Running backend services
+#[derive(Clone)]
+pub(crate) struct Backend {
+    pub tollgate: TollGateState,
+    pub nwc: NwcState,
+    pub routstr: RoutstrState,
+    pub pending_connections: PendingConnectionsState,
+    pub app_lock: AppLockState,
+    pub approvals: ApprovalsState,
+    pub api_clients: ApiClientsState,
+    pub pairings: PairingsState,
+    pub lan: LanState,
+    pub runtime: RuntimeState,
+}
+
+impl Backend {
+    /// Start every service. Approval prompts are published on the event bus,
+    /// where the app or the local API answers them.
+    pub async fn start(options: BackendOptions, approvals: ApprovalsState) -> Result<Self, String> {
+        spawn_approval_bridge(&approvals);
+        config::spawn_watcher();
+
+        // Start local Nostr relay before NWC service
+        log::info!("=== Starting local Nostr relay ===");
+        let relay = match relay::start_relay_server(options.relay_port).await {
+            Ok(relay) => {
+                log::info!("Local Nostr relay started on port {}", relay.port());
+                // Give the relay a moment to fully initialize
+                tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+                log::info!("=== Relay ready ===");
+                Some(relay)
+            }
+            Err(e) => {
+                log::error!("Failed to start local Nostr relay: {}", e);
+                None
+            }
+        };
+
+        let mut service = TollGateService::new(approvals.clone())
+            .await
+            .map_err(|e| format!("Failed to create TollGate service: {}", e))?;
+        service
+            .start_background_service()
+            .await
+            .map_err(|e| format!("Failed to start background service: {}", e))?;
+        let tollgate: TollGateState = Arc::new(Mutex::new(service));
+
+        // App-wide lock, shared by commands and the NWC handler
+        let app_lock: AppLockState = Arc::new(AppLock::load());
+
+        let nwc: NwcState = Arc::new(Mutex::new(
+            crate::create_nwc_service(&tollgate, &app_lock).await,
+        ));
+        crate::spawn_nwc_loop(nwc.clone());
+
+        let routstr: RoutstrState = Arc::new(Mutex::new(RoutstrService::new()));
+        tokio::spawn(routstr::initialize_routstr_auto_update(routstr.clone()));
+
+        let listener = runtime::bind_loopback(options.connection_port)
+            .map_err(|e| format!("Failed to bind connection server: {}", e))?;
+        let connection_port = listener
+            .local_addr()
+            .map_err(|e| format!("Failed to bind connection server: {}", e))?
+            .port();
+        let relay_port = relay.as_ref().map(relay::RelayHandle::port);
+        let api_token = options.api_token.unwrap_or_else(runtime::generate_token);
+        let runtime: RuntimeState = Arc::new(RuntimeInfo::new(
+            relay_port,
+            connection_port,
+            Some(api_token.clone()),
+        ));
+
+        let mut lan = LanService::load(relay_port.unwrap_or(options.relay_port), connection_port);
+        if let Some(settings) = options.lan {
+            lan = lan.override_settings(settings);
+        }
+
+        let backend = Self {
+            tollgate,
+            nwc,
+            routstr,
+            pending_connections: Arc::new(Mutex::new(HashMap::new())),
+            app_lock,
+            approvals,
+            api_clients: Arc::new(ApiClients::load()),
+            pairings: Arc::new(Pairings::new(options.allowed_origins)),
+            lan: Arc::new(lan),
+            runtime,
+        };
+
+        // Start connection server to handle wallet connection requests
+        let server_state = ConnectionServerState {
+            pending_connections: backend.pending_connections.clone(),
+            tollgate: backend.tollgate.clone(),
+            nwc: backend.nwc.clone(),
+            routstr: backend.routstr.clone(),
+            app_lock: backend.app_lock.clone(),
+            approvals: backend.approvals.clone(),
+            api_token: Some(api_token),
+            api_clients: backend.api_clients.clone(),
+            pairings: backend.pairings.clone(),
+            lan: backend.lan.clone(),
+        };
+        match relay {
+            Some(relay) => backend
+                .lan
+                .attach(crate::lan::router(server_state.clone()), relay),
+            None => log::warn!("LAN mode is unavailable without the local relay"),
+        }
+        connection_server::start_connection_server(server_state, listener)
+            .await
+            .map_err(|e| format!("Failed to start connection server: {}", e))?;
+        log::info!("Connection server started on port {}", connection_port);
+
+        if let Err(e) = backend.runtime.write() {
+            log::error!("Failed to write discovery file: {}", e);
+        }
+
+        Ok(backend)
+    }
+
+    /// Stop every service and flush what they hold in memory
+    pub async fn shutdown(&self) {
+        log::info!("Shutting down backend");
+        self.approvals.reject_all();
+        self.lan.stop();
+        // Lets the connection server answer requests in flight and NWC
+        // disconnect from its relay
+        supervisor().shutdown().await;
+        if let Some(nwc) = self.nwc.lock().await.take() {
+            nwc.stop().await;
+        }
+        self.tollgate.lock().await.shutdown().await;
+        self.runtime.remove();
+    }
+}
+
+/// Republish approval prompts on the event bus. Being subscribed is also
+/// what lets payments wait for an answer instead of failing.
+fn spawn_approval_bridge(approvals: &ApprovalsState) {
+    let mut approval_events = approvals.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let event = match approval_events.recv().await {
+                Ok(ApprovalEvent::Requested(approval)) => {
+                    BackendEvent::SpendApprovalRequested { approval }
+                }
+                Ok(ApprovalEvent::Resolved { id, outcome }) => {
+                    BackendEvent::SpendApprovalResolved { id, outcome }
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    log::warn!("Approval bridge lagged by {} events", n);
+                    continue;
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            };
+            events::publish(event);
+        }
+    });
+}
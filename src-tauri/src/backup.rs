@@ -0,0 +1,645 @@
+//! Encrypted backup and restore of the full app state
+//!
+//! A backup archive holds every file the wallet, NWC and Routstr subsystems
+//! persist, encrypted with a key derived from a passphrase. Restoring
+//! validates the whole archive before touching the data directory, moves the
+//! current files aside (putting them back if the swap fails) and then reloads
+//! each subsystem from disk. The spend ledger is backed up but never
+//! restored, so a restore cannot roll it back to an older head.
+
+use crate::app_lock::AppLockState;
+use crate::profiles::data_dir;
+use crate::routstr::RoutstrState;
+use crate::tollgate::wallet::validate_secrets_file;
+use crate::{NwcState, TollGateState};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use chrono::{DateTime, Utc};
+use rand::RngCore;
+use rusqlite::Connection;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Component, Path};
+use tauri::State;
+
+const BACKUP_FORMAT: &str = "wally-backup";
+/// Current archive version; restores accept this version and older
+const BACKUP_VERSION: u32 = 1;
+
+const SCRYPT_LOG_N: u8 = 15;
+const SCRYPT_R: u32 = 8;
+const SCRYPT_P: u32 = 1;
+
+/// Files kept in the data directory root
+const ROOT_FILES: &[&str] = &[
+    "wallet-secrets.json",
+    "mints.json",
+    "pending-tokens.json",
+    "token-pool.json",
+    "nwc-connections.sqlite",
+    "schedules.sqlite",
+    "spending-policy.json",
+    "spending-audit.sqlite",
+    "spend-ledger.sqlite",
+    "spend-ledger.head.json",
+    "routstr/config.json",
+];
+/// Spend ledger files, which a restore leaves in place
+const LEDGER_FILES: &[&str] = &["spend-ledger.sqlite", "spend-ledger.head.json"];
+const WALLETS_DIR: &str = "wallets";
+const REQUIRED_FILE: &str = "wallet-secrets.json";
+
+#[derive(thiserror::Error, Debug)]
+pub enum BackupError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Database error: {0}")]
+    Database(#[from] rusqlite::Error),
+
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+
+    #[error("Wrong passphrase or corrupted backup")]
+    Decrypt,
+
+    #[error("Invalid backup: {0}")]
+    Invalid(String),
+}
+
+/// Outer, unencrypted archive envelope
+#[derive(Debug, Serialize, Deserialize)]
+struct BackupEnvelope {
+    format: String,
+    version: u32,
+    kdf: KdfParams,
+    nonce: String,
+    ciphertext: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KdfParams {
+    name: String,
+    log_n: u8,
+    r: u32,
+    p: u32,
+    salt: String,
+}
+
+/// Decrypted archive contents
+#[derive(Debug, Serialize, Deserialize)]
+struct BackupPayload {
+    version: u32,
+    created_at: DateTime<Utc>,
+    app_version: String,
+    files: Vec<BackupFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct BackupFile {
+    /// Path relative to the data directory, with `/` separators
+    path: String,
+    sha256: String,
+    data: String,
+}
+
+/// Result of writing a backup
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupSummary {
+    pub path: String,
+    pub created_at: DateTime<Utc>,
+    pub files: Vec<String>,
+}
+
+/// Result of restoring a backup
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoreSummary {
+    pub created_at: DateTime<Utc>,
+    pub files: Vec<String>,
+    /// Where the replaced files were moved
+    pub previous_state_dir: String,
+    /// The NWC service key changed; NWC only picks it up after a restart
+    pub restart_required: bool,
+}
+
+fn is_sqlite(path: &str) -> bool {
+    path.ends_with(".sqlite")
+}
+
+fn is_ledger(path: &str) -> bool {
+    LEDGER_FILES.contains(&path)
+}
+
+/// Relative paths of every state file currently present
+fn state_files(base_dir: &Path) -> Result<Vec<String>, BackupError> {
+    let mut files: Vec<String> = ROOT_FILES
+        .iter()
+        .filter(|path| base_dir.join(path).is_file())
+        .map(|path| path.to_string())
+        .collect();
+
+    let wallets_dir = base_dir.join(WALLETS_DIR);
+    if wallets_dir.is_dir() {
+        let mut wallets = Vec::new();
+        for entry in fs::read_dir(&wallets_dir)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            if is_sqlite(&name) {
+                wallets.push(format!("{}/{}", WALLETS_DIR, name));
+            }
+        }
+        wallets.sort();
+        files.extend(wallets);
+    }
+
+    Ok(files)
+}
+
+/// Only accept the files a backup is allowed to contain
+fn validate_relative_path(path: &str) -> Result<(), BackupError> {
+    let relative = Path::new(path);
+    let safe = relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    let known = ROOT_FILES.contains(&path)
+        || (relative.parent() == Some(Path::new(WALLETS_DIR)) && is_sqlite(path));
+
+    if safe && known {
+        Ok(())
+    } else {
+        Err(BackupError::Invalid(format!("Unexpected file: {}", path)))
+    }
+}
+
+/// Read a file, taking a consistent snapshot of SQLite databases
+fn read_state_file(base_dir: &Path, path: &str) -> Result<Vec<u8>, BackupError> {
+    let full_path = base_dir.join(path);
+    if !is_sqlite(path) {
+        return Ok(fs::read(full_path)?);
+    }
+
+    let snapshot =
+        std::env::temp_dir().join(format!("wally-backup-{}.sqlite", uuid::Uuid::new_v4()));
+    let conn = Connection::open(&full_path)?;
+    conn.execute("VACUUM INTO ?1", [snapshot.to_string_lossy().as_ref()])?;
+    drop(conn);
+
+    let data = fs::read(&snapshot);
+    let _ = fs::remove_file(&snapshot);
+    Ok(data?)
+}
+
+fn derive_key(passphrase: &str, kdf: &KdfParams) -> Result<Key, BackupError> {
+    if kdf.name != "scrypt" {
+        return Err(BackupError::Invalid(format!(
+            "Unsupported KDF: {}",
+            kdf.name
+        )));
+    }
+
+    let salt = BASE64
+        .decode(&kdf.salt)
+        .map_err(|_| BackupError::Invalid("Invalid salt".to_string()))?;
+    let params = scrypt::Params::new(kdf.log_n, kdf.r, kdf.p, 32)
+        .map_err(|e| BackupError::Invalid(format!("Invalid KDF parameters: {}", e)))?;
+
+    let mut key = [0u8; 32];
+    scrypt::scrypt(passphrase.as_bytes(), &salt, &params, &mut key)
+        .map_err(|e| BackupError::Invalid(format!("Key derivation failed: {}", e)))?;
+    Ok(*Key::from_slice(&key))
+}
+
+fn encrypt_payload(payload: &BackupPayload, passphrase: &str) -> Result<Vec<u8>, BackupError> {
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let kdf = KdfParams {
+        name: "scrypt".to_string(),
+        log_n: SCRYPT_LOG_N,
+        r: SCRYPT_R,
+        p: SCRYPT_P,
+        salt: BASE64.encode(salt),
+    };
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &kdf)?);
+    let ciphertext = cipher
+        .encrypt(
+            Nonce::from_slice(&nonce),
+            serde_json::to_vec(payload)?.as_slice(),
+        )
+        .map_err(|_| BackupError::Invalid("Encryption failed".to_string()))?;
+
+    let envelope = BackupEnvelope {
+        format: BACKUP_FORMAT.to_string(),
+        version: BACKUP_VERSION,
+        kdf,
+        nonce: BASE64.encode(nonce),
+        ciphertext: BASE64.encode(ciphertext),
+    };
+    Ok(serde_json::to_vec_pretty(&envelope)?)
+}
+
+fn decrypt_payload(archive: &[u8], passphrase: &str) -> Result<BackupPayload, BackupError> {
+    let envelope: BackupEnvelope = serde_json::from_slice(archive)
+        .map_err(|_| BackupError::Invalid("Not a backup archive".to_string()))?;
+    if envelope.format != BACKUP_FORMAT {
+        return Err(BackupError::Invalid("Not a backup archive".to_string()));
+    }
+    if envelope.version > BACKUP_VERSION {
+        return Err(BackupError::Invalid(format!(
+            "Backup version {} is newer than supported version {}",
+            envelope.version, BACKUP_VERSION
+        )));
+    }
+
+    let nonce = BASE64
+        .decode(&envelope.nonce)
+        .ok()
+        .filter(|nonce| nonce.len() == 12)
+        .ok_or_else(|| BackupError::Invalid("Invalid nonce".to_string()))?;
+    let ciphertext = BASE64
+        .decode(&envelope.ciphertext)
+        .map_err(|_| BackupError::Invalid("Invalid ciphertext".to_string()))?;
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &envelope.kdf)?);
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| BackupError::Decrypt)?;
+
+    Ok(serde_json::from_slice(&plaintext)?)
+}
+
+/// Check every file in the payload and return the decoded contents
+fn validate_payload(payload: &BackupPayload) -> Result<Vec<(String, Vec<u8>)>, BackupError> {
+    let mut files = Vec::new();
+    for file in &payload.files {
+        validate_relative_path(&file.path)?;
+        if files.iter().any(|(path, _)| path == &file.path) {
+            return Err(BackupError::Invalid(format!(
+                "Duplicate file: {}",
+                file.path
+            )));
+        }
+
+        let data = BASE64
+            .decode(&file.data)
+            .map_err(|_| BackupError::Invalid(format!("Invalid data for {}", file.path)))?;
+        if format!("{:x}", Sha256::digest(&data)) != file.sha256 {
+            return Err(BackupError::Invalid(format!(
+                "Checksum mismatch for {}",
+                file.path
+            )));
+        }
+
+        match file.path.as_str() {
+            "wallet-secrets.json" => validate_secrets_file(&data)
+                .map_err(|e| BackupError::Invalid(format!("wallet-secrets.json: {}", e)))?,
+            path if !is_sqlite(path) => {
+                serde_json::from_slice::<serde_json::Value>(&data)
+                    .map_err(|e| BackupError::Invalid(format!("{}: {}", path, e)))?;
+            }
+            _ => {}
+        }
+
+        files.push((file.path.clone(), data));
+    }
+
+    if !files.iter().any(|(path, _)| path == REQUIRED_FILE) {
+        return Err(BackupError::Invalid(format!("Missing {}", REQUIRED_FILE)));
+    }
+
+    Ok(files)
+}
+
+fn check_sqlite(path: &Path) -> Result<(), BackupError> {
+    let conn = Connection::open(path)?;
+    let result: String = conn.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+    if result != "ok" {
+        return Err(BackupError::Invalid(format!(
+            "{} failed integrity check: {}",
+            path.display(),
+            result
+        )));
+    }
+    Ok(())
+}
+
+/// Write all state files into an encrypted archive at `path`
+pub fn write_backup(path: &Path, passphrase: &str) -> Result<BackupSummary, BackupError> {
+    if passphrase.is_empty() {
+        return Err(BackupError::Invalid(
+            "Passphrase must not be empty".to_string(),
+        ));
+    }
+
+    let base_dir = data_dir()?;
+    let mut files = Vec::new();
+    for relative in state_files(&base_dir)? {
+        let data = read_state_file(&base_dir, &relative)?;
+        files.push(BackupFile {
+            path: relative,
+            sha256: format!("{:x}", Sha256::digest(&data)),
+            data: BASE64.encode(&data),
+        });
+    }
+
+    let payload = BackupPayload {
+        version: BACKUP_VERSION,
+        created_at: Utc::now(),
+        app_version: env!("CARGO_PKG_VERSION").to_string(),
+        files,
+    };
+    let archive = encrypt_payload(&payload, passphrase)?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, archive)?;
+
+    log::info!(
+        "Wrote backup with {} files to {}",
+        payload.files.len(),
+        path.display()
+    );
+
+    Ok(BackupSummary {
+        path: path.display().to_string(),
+        created_at: payload.created_at,
+        files: payload.files.into_iter().map(|file| file.path).collect(),
+    })
+}
+
+/// Move the current state files into `previous_dir` and the staged files into
+/// `base_dir`. If any step fails, the staged files are removed again and the
+/// previous files are moved back.
+fn swap_in_staged(
+    base_dir: &Path,
+    staging_dir: &Path,
+    previous_dir: &Path,
+    files: &[(String, Vec<u8>)],
+) -> Result<(), BackupError> {
+    let mut moved = Vec::new();
+    let mut installed = Vec::new();
+
+    let result = (|| {
+        // Move the current state aside, including SQLite side files
+        let live = state_files(base_dir)?;
+        for relative in live.iter().filter(|path| !is_ledger(path)) {
+            for suffix in ["", "-wal", "-shm"] {
+                let name = format!("{}{}", relative, suffix);
+                let current = base_dir.join(&name);
+                if current.exists() {
+                    let target = previous_dir.join(&name);
+                    if let Some(parent) = target.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::rename(&current, &target)?;
+                    moved.push(name);
+                }
+            }
+        }
+
+        for (relative, _) in files {
+            let target = base_dir.join(relative);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(staging_dir.join(relative), &target)?;
+            installed.push(relative.clone());
+        }
+        Ok::<_, BackupError>(())
+    })();
+
+    if let Err(e) = &result {
+        log::error!("Restore failed, putting back the previous state: {}", e);
+        let mut rolled_back = true;
+        for relative in &installed {
+            if let Err(e) = fs::remove_file(base_dir.join(relative)) {
+                log::error!("Failed to remove restored {}: {}", relative, e);
+                rolled_back = false;
+            }
+        }
+        for name in moved.iter().rev() {
+            if let Err(e) = fs::rename(previous_dir.join(name), base_dir.join(name)) {
+                log::error!("Failed to put back {}: {}", name, e);
+                rolled_back = false;
+            }
+        }
+        // Keep whatever could not be moved back
+        if rolled_back {
+            let _ = fs::remove_dir_all(previous_dir);
+        }
+    }
+
+    result
+}
+
+/// Decrypt and validate `archive`, then replace the state files on disk.
+/// Subsystems must be reloaded afterwards.
+fn apply_backup(archive: &[u8], passphrase: &str) -> Result<RestoreSummary, BackupError> {
+    let payload = decrypt_payload(archive, passphrase)?;
+    let mut files = validate_payload(&payload)?;
+    files.retain(|(path, _)| !is_ledger(path));
+
+    let base_dir = data_dir()?;
+    let staging_dir = base_dir.join(format!("restore-staging-{}", uuid::Uuid::new_v4()));
+    let previous_dir = base_dir.join(format!(
+        "pre-restore-{}",
+        Utc::now().format("%Y%m%d-%H%M%S")
+    ));
+
+    // Stage everything first so a bad database aborts before any change
+    let staged = (|| {
+        for (relative, data) in &files {
+            let staged_path = staging_dir.join(relative);
+            if let Some(parent) = staged_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&staged_path, data)?;
+            if is_sqlite(relative) {
+                check_sqlite(&staged_path)?;
+            }
+        }
+        Ok::<_, BackupError>(())
+    })();
+    if let Err(e) = staged {
+        let _ = fs::remove_dir_all(&staging_dir);
+        return Err(e);
+    }
+
+    let swapped = swap_in_staged(&base_dir, &staging_dir, &previous_dir, &files);
+    let _ = fs::remove_dir_all(&staging_dir);
+    swapped?;
+
+    log::info!(
+        "Restored backup from {} ({} files), previous state kept in {}",
+        payload.created_at,
+        files.len(),
+        previous_dir.display()
+    );
+
+    Ok(RestoreSummary {
+        created_at: payload.created_at,
+        files: files.into_iter().map(|(path, _)| path).collect(),
+        previous_state_dir: previous_dir.display().to_string(),
+        restart_required: false,
+    })
+}
+
+#[tauri::command]
+pub async fn create_backup(
+    path: String,
+    passphrase: String,
+    lock: State<'_, AppLockState>,
+) -> Result<BackupSummary, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    tokio::task::spawn_blocking(move || write_backup(Path::new(&path), &passphrase))
+        .await
+        .map_err(|e| e.to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn restore_backup(
+    path: String,
+    passphrase: String,
+    state: State<'_, TollGateState>,
+    nwc_state: State<'_, NwcState>,
+    routstr_state: State<'_, RoutstrState>,
+    lock: State<'_, AppLockState>,
+) -> Result<RestoreSummary, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    let archive = fs::read(&path).map_err(|e| e.to_string())?;
+
+    let service = state.lock().await;
+    let mut summary = service
+        .reload_wallet_with(move || {
+            apply_backup(&archive, &passphrase)
+                .map_err(|e| crate::tollgate::TollGateError::wallet(e.to_string()))
+        })
+        .await
+        .map_err(|e| e.to_string())?;
+    let service_pubkey = service.get_nwc_service_keys().await.public_key();
+    drop(service);
+
+    if let Some(nwc) = nwc_state.lock().await.as_ref() {
+        nwc.reload_connections().await.map_err(|e| e.to_string())?;
+        summary.restart_required = nwc.service_pubkey() != service_pubkey;
+    }
+
+    routstr_state
+        .lock()
+        .await
+        .reload_config()
+        .map_err(|e| e.to_string())?;
+
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn payload(files: Vec<(&str, &[u8])>) -> BackupPayload {
+        BackupPayload {
+            version: BACKUP_VERSION,
+            created_at: Utc::now(),
+            app_version: "test".to_string(),
+            files: files
+                .into_iter()
+                .map(|(path, data)| BackupFile {
+                    path: path.to_string(),
+                    sha256: format!("{:x}", Sha256::digest(data)),
+                    data: BASE64.encode(data),
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_encrypt_round_trip() {
+        let original = payload(vec![("mints.json", b"{\"mints\":[]}")]);
+        let archive = encrypt_payload(&original, "correct horse").unwrap();
+
+        let restored = decrypt_payload(&archive, "correct horse").unwrap();
+        assert_eq!(restored.files.len(), 1);
+        assert_eq!(restored.files[0].sha256, original.files[0].sha256);
+
+        assert!(matches!(
+            decrypt_payload(&archive, "wrong"),
+            Err(BackupError::Decrypt)
+        ));
+    }
+
+    #[test]
+    fn test_rejects_unexpected_paths() {
+        assert!(validate_relative_path("wallets/mint-abc.sqlite").is_ok());
+        assert!(validate_relative_path("routstr/config.json").is_ok());
+        assert!(validate_relative_path("../wallet-secrets.json").is_err());
+        assert!(validate_relative_path("wallets/../../evil.sqlite").is_err());
+        assert!(validate_relative_path("/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_payload_requires_valid_secrets() {
+        let missing = payload(vec![("mints.json", b"{}")]);
+        assert!(validate_payload(&missing).is_err());
+
+        let mut tampered = payload(vec![("mints.json", b"{}")]);
+        tampered.files[0].data = BASE64.encode(b"{\"mints\":[1]}");
+        assert!(validate_payload(&tampered).is_err());
+
+        let phrase =
+            "leader monkey parrot ring guide accident before fence cannon height naive bean";
+        let secrets = serde_json::to_vec(&serde_json::json!({ "mnemonic": phrase })).unwrap();
+        let valid = payload(vec![("wallet-secrets.json", &secrets)]);
+        assert_eq!(validate_payload(&valid).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_failed_swap_restores_previous_state() {
+        let root = std::env::temp_dir().join(format!("wally-restore-{}", uuid::Uuid::new_v4()));
+        let base_dir = root.join("data");
+        let staging_dir = root.join("staging");
+        let previous_dir = root.join("previous");
+        fs::create_dir_all(base_dir.join(WALLETS_DIR)).unwrap();
+        fs::create_dir_all(&staging_dir).unwrap();
+        fs::write(base_dir.join("wallet-secrets.json"), b"old").unwrap();
+        fs::write(base_dir.join("wallets/mint.sqlite"), b"old wallet").unwrap();
+        fs::write(staging_dir.join("wallet-secrets.json"), b"new").unwrap();
+
+        // mints.json was never staged, so installing it fails halfway
+        let files = vec![
+            ("wallet-secrets.json".to_string(), Vec::new()),
+            ("mints.json".to_string(), Vec::new()),
+        ];
+        assert!(swap_in_staged(&base_dir, &staging_dir, &previous_dir, &files).is_err());
+
+        assert_eq!(
+            fs::read(base_dir.join("wallet-secrets.json")).unwrap(),
+            b"old"
+        );
+        assert_eq!(
+            fs::read(base_dir.join("wallets/mint.sqlite")).unwrap(),
+            b"old wallet"
+        );
+        assert!(!base_dir.join("mints.json").exists());
+        assert!(!previous_dir.exists());
+
+        // The live ledger is not moved aside
+        fs::write(base_dir.join("spend-ledger.sqlite"), b"ledger").unwrap();
+        fs::write(staging_dir.join("mints.json"), b"new mints").unwrap();
+        let files = vec![("mints.json".to_string(), Vec::new())];
+        swap_in_staged(&base_dir, &staging_dir, &previous_dir, &files).unwrap();
+        assert_eq!(
+            fs::read(base_dir.join("spend-ledger.sqlite")).unwrap(),
+            b"ledger"
+        );
+        assert!(!previous_dir.join("spend-ledger.sqlite").exists());
+
+        let _ = fs::remove_dir_all(root);
+    }
+}
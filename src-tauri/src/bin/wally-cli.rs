@@ -0,0 +1,5 @@
+//! Command-line client, see `cli.rs` in the library
+
+fn main() {
+    tollgate_ui_lib::run_cli()
+}
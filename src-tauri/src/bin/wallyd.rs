@@ -0,0 +1,5 @@
+//! Headless wallet daemon, see `daemon.rs` in the library
+
+fn main() {
+    tollgate_ui_lib::run_daemon()
+}
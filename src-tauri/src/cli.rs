@@ -0,0 +1,592 @@
+//! Command-line client
+//!
+//! `wally-cli` sends each command to the local API (`/api/v1`) of a running
+//! app or daemon, found through the discovery file it writes. When no
+//! instance is listening it opens the stores of the active profile itself
+//! and serves the same API in-process, so both paths go through the same
+//! handlers and policy checks.
+//!
+//! With the daemon's owner token (or `--local`) the CLI acts as the user, so
+//! `--yes` approves payments the spending policy would ask about. With a
+//! client token issued from the app it is limited to the client's scopes and
+//! such payments wait for approval in the app.
+
+use crate::api_clients::ApiClients;
+use crate::app_lock::AppLock;
+use crate::connection_server::ConnectionServerState;
+use crate::payment_input::{self, PaymentInputKind};
+use crate::routstr::RoutstrService;
+use crate::runtime::RuntimeInfo;
+use crate::tollgate::TollGateService;
+use crate::{local_api, profiles};
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use axum::Router;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tower::ServiceExt;
+
+const USAGE: &str = "\
+Usage: wally-cli [options] <command>
+
+Options:
+  --url <url>        Local API of a running instance (default from runtime.json,
+                     or the connection port in wally.toml)
+  --token <token>    API token (default $WALLY_API_TOKEN, runtime.json or wallyd.token)
+  --local            Open the wallet directly instead of using a running instance
+  --profile <name>   Profile to open with --local
+
+Commands:
+  balance                           Show balances per mint
+  transactions                      List wallet transactions
+  mints                             List mints
+  mint add|remove <url>             Add or remove a mint
+  receive <token> [--trust-mint] [--offline]
+  pay <invoice|request> [--amount <sats>] [--yes]
+  send <sats> [--mint <url>] [--yes]  Create a cashu token
+  nwc list                          List NWC connections
+  nwc create [--name <name>] [--budget <sats>] [--period <period>] [--local-relay]
+  nwc revoke <pubkey>               Remove an NWC connection
+  sessions                          Show TollGate sessions
+  approvals                         List payments waiting for approval
+  approve|reject <id>               Answer a payment approval
+  pairing list                      List clients waiting to be paired
+  pairing approve|reject <id>       Answer a pairing request
+  lan status                        Show LAN mode addresses and fingerprint
+  lan code                          Show a pairing code for a LAN device
+  services                          Show the health of background services
+  lock                              Show the app lock status
+  unlock                            Unlock with $WALLY_PASSPHRASE or stdin";
+
+#[derive(Debug, Default)]
+struct Options {
+    url: Option<String>,
+    token: Option<String>,
+    local: bool,
+    profile: Option<String>,
+}
+
+/// One request against the local API
+#[derive(Debug, PartialEq)]
+struct ApiCall {
+    method: Method,
+    path: String,
+    body: Option<Value>,
+}
+
+impl ApiCall {
+    fn get(path: &str) -> Self {
+        Self {
+            method: Method::GET,
+            path: path.to_string(),
+            body: None,
+        }
+    }
+
+    fn with(method: Method, path: &str, body: Value) -> Self {
+        Self {
+            method,
+            path: path.to_string(),
+            body: Some(body),
+        }
+    }
+}
+
+/// Remaining arguments of a command, with `--flag [value]` lookup
+struct Args(Vec<String>);
+
+impl Args {
+    fn flag(&mut self, name: &str) -> bool {
+        let before = self.0.len();
+        self.0.retain(|arg| arg != name);
+        self.0.len() != before
+    }
+
+    fn value(&mut self, name: &str) -> Result<Option<String>, String> {
+        let Some(index) = self.0.iter().position(|arg| arg == name) else {
+            return Ok(None);
+        };
+        if index + 1 >= self.0.len() {
+            return Err(format!("{} needs a value", name));
+        }
+        let value = self.0.remove(index + 1);
+        self.0.remove(index);
+        Ok(Some(value))
+    }
+
+    fn number(&mut self, name: &str) -> Result<Option<u64>, String> {
+        self.value(name)?
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|_| format!("{} must be a number of sats", name))
+            })
+            .transpose()
+    }
+
+    fn positional(&mut self, what: &str) -> Result<String, String> {
+        if self.0.is_empty() || self.0[0].starts_with("--") {
+            return Err(format!("Missing {}", what));
+        }
+        Ok(self.0.remove(0))
+    }
+
+    fn finish(self) -> Result<(), String> {
+        match self.0.first() {
+            Some(arg) => Err(format!("Unexpected argument: {}", arg)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn parse_options(args: &mut Vec<String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    while let Some(arg) = args.first().cloned() {
+        let slot = match arg.as_str() {
+            "--url" => &mut options.url,
+            "--token" => &mut options.token,
+            "--profile" => &mut options.profile,
+            "--local" => {
+                args.remove(0);
+                options.local = true;
+                continue;
+            }
+            _ => break,
+        };
+        if args.len() < 2 {
+            return Err(format!("{} needs a value", arg));
+        }
+        args.remove(0);
+        *slot = Some(args.remove(0));
+    }
+    Ok(options)
+}
+
+fn parse_command(args: Vec<String>) -> Result<ApiCall, String> {
+    let mut args = Args(args);
+    let command = args.positional("command")?;
+    let call = match command.as_str() {
+        "balance" => ApiCall::get("/wallet/balance"),
+        "transactions" => ApiCall::get("/wallet/transactions"),
+        "mints" => ApiCall::get("/mints"),
+        "mint" => {
+            let action = args.positional("mint action")?;
+            let mint_url = args.positional("mint url")?;
+            let method = match action.as_str() {
+                "add" => Method::POST,
+                "remove" => Method::DELETE,
+                other => return Err(format!("Unknown mint action: {}", other)),
+            };
+            ApiCall::with(method, "/mints", json!({ "mint_url": mint_url }))
+        }
+        "receive" => {
+            let approve_mint = args.flag("--trust-mint");
+            let offline = args.flag("--offline");
+            let token = args.positional("token")?;
+            ApiCall::with(
+                Method::POST,
+                "/wallet/receive",
+                json!({ "token": token, "approve_mint": approve_mint, "offline": offline }),
+            )
+        }
+        "pay" => {
+            let approve = args.flag("--yes");
+            let amount = args.number("--amount")?;
+            let input = args.positional("invoice or payment request")?;
+            let preview = payment_input::parse_payment_input(&input)?;
+            match preview.kind {
+                PaymentInputKind::Bolt11 => ApiCall::with(
+                    Method::POST,
+                    "/wallet/pay-invoice",
+                    json!({ "invoice": preview.normalized, "approve": approve }),
+                ),
+                PaymentInputKind::Nut18Request => ApiCall::with(
+                    Method::POST,
+                    "/wallet/pay-request",
+                    json!({
+                        "request": preview.normalized,
+                        "amount": amount,
+                        "approve": approve,
+                    }),
+                ),
+                other => return Err(format!("Cannot pay {:?} input", other)),
+            }
+        }
+        "send" => {
+            let approve = args.flag("--yes");
+            let mint_url = args.value("--mint")?;
+            let amount = args.positional("amount")?;
+            let amount: u64 = amount
+                .parse()
+                .map_err(|_| format!("Invalid amount: {}", amount))?;
+            ApiCall::with(
+                Method::POST,
+                "/wallet/send-token",
+                json!({ "amount_sats": amount, "mint_url": mint_url, "approve": approve }),
+            )
+        }
+        "nwc" => {
+            let action = args.positional("nwc action")?;
+            match action.as_str() {
+                "list" => ApiCall::get("/nwc/connections"),
+                "create" => ApiCall::with(
+                    Method::POST,
+                    "/nwc/connections",
+                    json!({
+                        "name": args.value("--name")?,
+                        "budget_sats": args.number("--budget")?,
+                        "renewal_period": args.value("--period")?,
+                        "use_local_relay": args.flag("--local-relay"),
+                    }),
+                ),
+                "revoke" => {
+                    let pubkey = args.positional("connection pubkey")?;
+                    ApiCall {
+                        method: Method::DELETE,
+                        path: format!("/nwc/connections/{}", pubkey),
+                        body: None,
+                    }
+                }
+                other => return Err(format!("Unknown nwc action: {}", other)),
+            }
+        }
+        "sessions" => ApiCall::get("/tollgate/sessions"),
+        "approvals" => ApiCall::get("/approvals"),
+        "approve" | "reject" => {
+            let id = args.positional("approval id")?;
+            ApiCall::with(
+                Method::POST,
+                &format!("/approvals/{}/{}", id, command),
+                json!({}),
+            )
+        }
+        "pairing" => {
+            let action = args.positional("pairing action")?;
+            match action.as_str() {
+                "list" => ApiCall::get("/pairings"),
+                "approve" | "reject" => {
+                    let id = args.positional("pairing id")?;
+                    ApiCall::with(
+                        Method::POST,
+                        &format!("/pairings/{}/{}", id, action),
+                        json!({}),
+                    )
+                }
+                other => return Err(format!("Unknown pairing action: {}", other)),
+            }
+        }
+        "lan" => {
+            let action = args.positional("lan action")?;
+            match action.as_str() {
+                "status" => ApiCall::get("/lan"),
+                "code" => ApiCall::with(Method::POST, "/lan/pairing-code", json!({})),
+                other => return Err(format!("Unknown lan action: {}", other)),
+            }
+        }
+        "services" => ApiCall::get("/services"),
+        "lock" => ApiCall::get("/lock"),
+        "unlock" => ApiCall::with(
+            Method::POST,
+            "/lock/unlock",
+            json!({ "passphrase": read_passphrase()? }),
+        ),
+        other => return Err(format!("Unknown command: {}", other)),
+    };
+    args.finish()?;
+    Ok(call)
+}
+
+fn read_passphrase() -> Result<String, String> {
+    if let Ok(passphrase) = std::env::var("WALLY_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+    let mut line = String::new();
+    std::io::stdin()
+        .read_line(&mut line)
+        .map_err(|e| format!("Failed to read passphrase: {}", e))?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// Token from the options, the environment, the discovery file or the
+/// daemon's token file
+fn api_token(options: &Options, discovered: Option<&RuntimeInfo>) -> Option<String> {
+    options
+        .token
+        .clone()
+        .or_else(|| std::env::var("WALLY_API_TOKEN").ok())
+        .or_else(|| discovered.and_then(|info| info.token.clone()))
+        .or_else(|| {
+            let path = profiles::root_dir().ok()?.join("wallyd.token");
+            std::fs::read_to_string(path)
+                .ok()
+                .map(|token| token.trim().to_string())
+        })
+}
+
+/// Where commands are sent
+enum Target {
+    Remote {
+        client: reqwest::Client,
+        base_url: String,
+        token: String,
+    },
+    InProcess {
+        router: Router,
+        token: String,
+    },
+}
+
+impl Target {
+    async fn send(&self, call: &ApiCall) -> Result<(StatusCode, Value), String> {
+        match self {
+            Target::Remote {
+                client,
+                base_url,
+                token,
+            } => {
+                let method = reqwest::Method::from_bytes(call.method.as_str().as_bytes())
+                    .map_err(|e| e.to_string())?;
+                let mut request = client
+                    .request(method, format!("{}/api/v1{}", base_url, call.path))
+                    .bearer_auth(token);
+                if let Some(body) = &call.body {
+                    request = request.json(body);
+                }
+                let response = request.send().await.map_err(|e| e.to_string())?;
+                let status =
+                    StatusCode::from_u16(response.status().as_u16()).map_err(|e| e.to_string())?;
+                let body = response.json().await.unwrap_or(Value::Null);
+                Ok((status, body))
+            }
+            Target::InProcess { router, token } => {
+                let mut request = Request::builder()
+                    .method(call.method.clone())
+                    .uri(&call.path)
+                    .header(header::AUTHORIZATION, format!("Bearer {}", token));
+                let body = match &call.body {
+                    Some(body) => {
+                        request = request.header(header::CONTENT_TYPE, "application/json");
+                        Body::from(body.to_string())
+                    }
+                    None => Body::empty(),
+                };
+                let request = request.body(body).map_err(|e| e.to_string())?;
+                let response = router
+                    .clone()
+                    .oneshot(request)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                let status = response.status();
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .map_err(|e| e.to_string())?;
+                Ok((
+                    status,
+                    serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+                ))
+            }
+        }
+    }
+}
+
+/// Open the active profile's stores and serve the local API in-process
+async fn open_local(options: &Options) -> Result<Target, String> {
+    // Two processes must not use the same wallet stores at once
+    if let Some(running) = RuntimeInfo::read().filter(RuntimeInfo::is_alive) {
+        return Err(format!(
+            "Wally is already running (pid {}); use it through its API instead of --local",
+            running.pid
+        ));
+    }
+    if let Some(profile) = &options.profile {
+        profiles::use_profile(profile)?;
+    }
+
+    let approvals = Arc::default();
+    let service = TollGateService::new(Arc::clone(&approvals))
+        .await
+        .map_err(|e| format!("Failed to open wallet: {}", e))?;
+    let tollgate: crate::TollGateState = Arc::new(Mutex::new(service));
+    let app_lock: crate::app_lock::AppLockState = Arc::new(AppLock::load());
+    let nwc = crate::create_nwc_service(&tollgate, &app_lock).await;
+
+    let token = uuid::Uuid::new_v4().to_string();
+    let state = ConnectionServerState {
+        pending_connections: Arc::new(Mutex::new(HashMap::new())),
+        tollgate,
+        nwc: Arc::new(Mutex::new(nwc)),
+        routstr: Arc::new(Mutex::new(RoutstrService::new())),
+        app_lock,
+        approvals,
+        api_token: Some(token.clone()),
+        api_clients: Arc::new(ApiClients::load()),
+        pairings: Arc::default(),
+        lan: Arc::default(),
+    };
+    let target = Target::InProcess {
+        router: local_api::router().with_state(state),
+        token,
+    };
+
+    // A freshly opened wallet starts locked when a passphrase is set
+    if let Ok(passphrase) = std::env::var("WALLY_PASSPHRASE") {
+        let unlock = ApiCall::with(
+            Method::POST,
+            "/lock/unlock",
+            json!({ "passphrase": passphrase }),
+        );
+        let (status, body) = target.send(&unlock).await?;
+        if !status.is_success() {
+            return Err(error_message(&body, status));
+        }
+    }
+    Ok(target)
+}
+
+/// Pick a running instance, or the stores when none is listening
+async fn connect(options: &Options) -> Result<Target, String> {
+    if options.local {
+        return open_local(options).await;
+    }
+
+    // The last instance started, unless another one was asked for
+    let discovered = RuntimeInfo::read().filter(|info| {
+        options
+            .url
+            .as_deref()
+            .is_none_or(|url| url.trim_end_matches('/') == info.server_url())
+    });
+    let base_url = options
+        .url
+        .clone()
+        .or_else(|| discovered.as_ref().map(RuntimeInfo::server_url))
+        .unwrap_or_else(|| {
+            format!(
+                "http://127.0.0.1:{}",
+                crate::config::current().ports.connection
+            )
+        });
+    let client = reqwest::Client::new();
+    // Unauthenticated, so it only tells whether something is listening
+    let probe = client.get(format!("{}/api/v1/lock", base_url)).send().await;
+    match probe {
+        Err(e) if e.is_connect() && options.url.is_none() => open_local(options).await,
+        Err(e) => Err(format!("Cannot reach {}: {}", base_url, e)),
+        Ok(_) => {
+            let token = api_token(options, discovered.as_ref()).ok_or_else(|| {
+                "A Wally instance is running; pass its API token with --token or WALLY_API_TOKEN"
+                    .to_string()
+            })?;
+            Ok(Target::Remote {
+                client,
+                base_url: base_url.trim_end_matches('/').to_string(),
+                token,
+            })
+        }
+    }
+}
+
+fn error_message(body: &Value, status: StatusCode) -> String {
+    body.get("error")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .unwrap_or_else(|| format!("Request failed with {}", status))
+}
+
+/// Entry point of the `wally-cli` binary
+pub fn run_cli() {
+    let mut args: Vec<String> = std::env::args().skip(1).collect();
+    if args.is_empty() || args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let parsed = parse_options(&mut args).and_then(|options| Ok((options, parse_command(args)?)));
+    let (options, call) = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("wally-cli: {}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to start tokio runtime");
+    let result = runtime.block_on(async {
+        let target = connect(&options).await?;
+        target.send(&call).await
+    });
+
+    match result {
+        Ok((status, body)) if status.is_success() => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&body).unwrap_or_default()
+            );
+        }
+        Ok((status, body)) => {
+            eprintln!("wally-cli: {}", error_message(&body, status));
+            std::process::exit(1);
+        }
+        Err(e) => {
+            eprintln!("wally-cli: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(line: &str) -> Vec<String> {
+        line.split_whitespace().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_parse_commands() {
+        let mut line = args("--local --profile work nwc create --budget 500 --name bot");
+        let options = parse_options(&mut line).unwrap();
+        assert!(options.local);
+        assert_eq!(options.profile.as_deref(), Some("work"));
+
+        let call = parse_command(line).unwrap();
+        assert_eq!(call.method, Method::POST);
+        assert_eq!(call.path, "/nwc/connections");
+        let body = call.body.unwrap();
+        assert_eq!(body["budget_sats"], 500);
+        assert_eq!(body["name"], "bot");
+        assert_eq!(body["use_local_relay"], false);
+
+        assert_eq!(
+            parse_command(args("mint remove https://mint.example")).unwrap(),
+            ApiCall::with(
+                Method::DELETE,
+                "/mints",
+                json!({ "mint_url": "https://mint.example" })
+            )
+        );
+        assert_eq!(
+            parse_command(args("nwc revoke abc")).unwrap().path,
+            "/nwc/connections/abc"
+        );
+        assert_eq!(
+            parse_command(args("pairing approve p1")).unwrap().path,
+            "/pairings/p1/approve"
+        );
+        assert!(parse_command(args("pairing forget p1")).is_err());
+        assert_eq!(
+            parse_command(args("lan code")).unwrap().path,
+            "/lan/pairing-code"
+        );
+        assert!(parse_command(args("lan")).is_err());
+        assert_eq!(
+            parse_command(args("services")).unwrap(),
+            ApiCall::get("/services")
+        );
+        assert!(parse_command(args("balance extra")).is_err());
+        assert!(parse_command(args("mint add")).is_err());
+        assert!(parse_command(args("frobnicate")).is_err());
+    }
+}
@@ -0,0 +1,477 @@
+//! Unified configuration
+//!
+//! Relays, budgets, TollGate purchase sizes, ports, timeouts, the Tor proxy
+//! and the metrics endpoint are read from `wally.toml` in the top-level data
+//! directory. Every setting has a default, so the file only lists what
+//! differs, and a `[profile.<name>]` table overrides any of them for one
+//! profile:
+//!
+//! ```toml
+//! [relays]
+//! nwc = "wss://relay.example"
+//!
+//! [profile.work.nwc]
+//! default_budget_sats = 5000
+//! ```
+//!
+//! A file that fails to parse or validate is reported and ignored, keeping
+//! the settings in use. The file is checked for changes every few seconds;
+//! discovery relays, budgets, purchase sizes, timeouts, the Tor proxy and
+//! metrics apply to the next operation, while ports and the NWC relay are
+//! only read at startup.
+
+use crate::profiles;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use thiserror::Error;
+
+const CONFIG_FILE: &str = "wally.toml";
+/// Interval between checks for changes to the file
+const RELOAD_INTERVAL_SECS: u64 = 5;
+
+/// Settings in use, with the profile and file version they came from
+static LOADED: RwLock<Option<Loaded>> = RwLock::new(None);
+
+struct Loaded {
+    profile: String,
+    modified: Option<SystemTime>,
+    config: Arc<Config>,
+}
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("Failed to read config: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid config: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Invalid config: {0}")]
+    Invalid(String),
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub relays: RelaysConfig,
+    pub nwc: NwcConfig,
+    pub tollgate: TollGateConfig,
+    pub ports: PortsConfig,
+    pub timeouts: TimeoutsConfig,
+    pub tor: TorConfig,
+    pub metrics: MetricsConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RelaysConfig {
+    /// Relay the NWC service listens on and puts in connection URIs
+    pub nwc: String,
+    /// Relays for provider discovery, settings sync and scheduled payments
+    pub discovery: Vec<String>,
+}
+
+impl Default for RelaysConfig {
+    fn default() -> Self {
+        Self {
+            nwc: crate::nwc::REMOTE_RELAY_URL.to_string(),
+            discovery: crate::nostr_providers::DEFAULT_RELAYS
+                .iter()
+                .map(|relay| relay.to_string())
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NwcConfig {
+    /// Budget of new connections that do not ask for one
+    pub default_budget_sats: u64,
+}
+
+impl Default for NwcConfig {
+    fn default() -> Self {
+        Self {
+            default_budget_sats: 1_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TollGateConfig {
+    /// Least time bought when a session starts, for time-metered gates
+    pub initial_purchase_secs: u64,
+    /// Least data bought when a session starts, for data-metered gates
+    pub initial_purchase_bytes: u64,
+    pub renewal_purchase_secs: u64,
+    pub renewal_purchase_bytes: u64,
+    /// Share of the allotment used before a session is renewed
+    pub renewal_threshold: f64,
+}
+
+impl Default for TollGateConfig {
+    fn default() -> Self {
+        Self {
+            initial_purchase_secs: 300,
+            initial_purchase_bytes: 10 * 1024 * 1024,
+            renewal_purchase_secs: 300,
+            renewal_purchase_bytes: 5 * 1024 * 1024,
+            renewal_threshold: 0.8,
+        }
+    }
+}
+
+/// Preferred ports; free ones are used when they are taken
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PortsConfig {
+    pub relay: u16,
+    pub connection: u16,
+}
+
+impl Default for PortsConfig {
+    fn default() -> Self {
+        Self {
+            relay: crate::relay::DEFAULT_RELAY_PORT,
+            connection: crate::connection_server::DEFAULT_CONNECTION_PORT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeoutsConfig {
+    /// Requests to mints, Routstr providers, TollGates and relays
+    pub request_secs: u64,
+    /// Routstr wallet creation, top-ups and refunds
+    pub payment_secs: u64,
+    /// TollGate discovery on the local network
+    pub gateway_secs: u64,
+    /// Requests forwarded by the Routstr proxy, and streamed responses
+    pub proxy_secs: u64,
+    pub proxy_stream_secs: u64,
+    /// How long payments wait for approval before they are refused
+    pub approval_secs: u64,
+}
+
+impl Default for TimeoutsConfig {
+    fn default() -> Self {
+        Self {
+            request_secs: 10,
+            payment_secs: 30,
+            gateway_secs: 5,
+            proxy_secs: 60,
+            proxy_stream_secs: 300,
+            approval_secs: crate::tollgate::approvals::DEFAULT_APPROVAL_TIMEOUT.as_secs(),
+        }
+    }
+}
+
+impl TimeoutsConfig {
+    pub fn request(&self) -> Duration {
+        Duration::from_secs(self.request_secs)
+    }
+
+    pub fn payment(&self) -> Duration {
+        Duration::from_secs(self.payment_secs)
+    }
+
+    pub fn gateway(&self) -> Duration {
+        Duration::from_secs(self.gateway_secs)
+    }
+
+    pub fn approval(&self) -> Duration {
+        Duration::from_secs(self.approval_secs)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TorConfig {
+    /// SOCKS proxy for .onion providers; `TOR_SOCKS_PROXY` takes precedence
+    pub socks_proxy: String,
+}
+
+impl Default for TorConfig {
+    fn default() -> Self {
+        Self {
+            socks_proxy: "socks5h://127.0.0.1:9050".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Serve Prometheus metrics at `/metrics` on the connection server
+    pub enabled: bool,
+}
+
+fn check_relay(relay: &str) -> Result<(), ConfigError> {
+    match reqwest::Url::parse(relay) {
+        Ok(url) if matches!(url.scheme(), "ws" | "wss") && url.host().is_some() => Ok(()),
+        _ => Err(ConfigError::Invalid(format!(
+            "{} is not a ws:// or wss:// relay URL",
+            relay
+        ))),
+    }
+}
+
+fn check_positive(name: &str, value: u64) -> Result<(), ConfigError> {
+    if value == 0 {
+        return Err(ConfigError::Invalid(format!("{} must be above 0", name)));
+    }
+    Ok(())
+}
+
+impl Config {
+    /// Parse `data`, with the overrides for `profile` applied
+    pub fn parse(data: &str, profile: &str) -> Result<Self, ConfigError> {
+        let mut table: toml::Table = toml::from_str(data)?;
+        let overrides = match table.remove("profile") {
+            Some(toml::Value::Table(mut profiles)) => profiles.remove(profile),
+            Some(_) => {
+                return Err(ConfigError::Invalid(
+                    "profile must be a table of profile names".to_string(),
+                ))
+            }
+            None => None,
+        };
+        match overrides {
+            Some(toml::Value::Table(overrides)) => merge(&mut table, overrides),
+            Some(_) => {
+                return Err(ConfigError::Invalid(format!(
+                    "profile.{} must be a table",
+                    profile
+                )))
+            }
+            None => {}
+        }
+
+        let config: Config = toml::Value::Table(table).try_into()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        check_relay(&self.relays.nwc)?;
+        if self.relays.discovery.is_empty() {
+            return Err(ConfigError::Invalid(
+                "relays.discovery needs at least one relay".to_string(),
+            ));
+        }
+        for relay in &self.relays.discovery {
+            check_relay(relay)?;
+        }
+
+        check_positive("nwc.default_budget_sats", self.nwc.default_budget_sats)?;
+
+        let tollgate = &self.tollgate;
+        check_positive(
+            "tollgate.initial_purchase_secs",
+            tollgate.initial_purchase_secs,
+        )?;
+        check_positive(
+            "tollgate.initial_purchase_bytes",
+            tollgate.initial_purchase_bytes,
+        )?;
+        check_positive(
+            "tollgate.renewal_purchase_secs",
+            tollgate.renewal_purchase_secs,
+        )?;
+        check_positive(
+            "tollgate.renewal_purchase_bytes",
+            tollgate.renewal_purchase_bytes,
+        )?;
+        if !(tollgate.renewal_threshold > 0.0 && tollgate.renewal_threshold <= 1.0) {
+            return Err(ConfigError::Invalid(
+                "tollgate.renewal_threshold must be above 0 and at most 1".to_string(),
+            ));
+        }
+
+        check_positive("timeouts.request_secs", self.timeouts.request_secs)?;
+        check_positive("timeouts.payment_secs", self.timeouts.payment_secs)?;
+        check_positive("timeouts.gateway_secs", self.timeouts.gateway_secs)?;
+        check_positive("timeouts.proxy_secs", self.timeouts.proxy_secs)?;
+        check_positive(
+            "timeouts.proxy_stream_secs",
+            self.timeouts.proxy_stream_secs,
+        )?;
+        check_positive("timeouts.approval_secs", self.timeouts.approval_secs)?;
+
+        let proxy = &self.tor.socks_proxy;
+        if !(proxy.starts_with("socks5://") || proxy.starts_with("socks5h://"))
+            || reqwest::Url::parse(proxy).is_err()
+        {
+            return Err(ConfigError::Invalid(format!(
+                "tor.socks_proxy must be a socks5:// or socks5h:// URL, not {}",
+                proxy
+            )));
+        }
+        Ok(())
+    }
+
+    /// Settings that only apply after a restart
+    fn needs_restart(&self, other: &Config) -> bool {
+        self.ports != other.ports || self.relays.nwc != other.relays.nwc
+    }
+}
+
+/// Merge `overrides` into `base`, table by table
+fn merge(base: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overrides)) => {
+                merge(base, overrides)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+pub fn config_path() -> std::io::Result<PathBuf> {
+    Ok(profiles::root_dir()?.join(CONFIG_FILE))
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).ok()?.modified().ok()
+}
+
+fn load(path: &Path, profile: &str) -> Result<Config, ConfigError> {
+    match fs::read_to_string(path) {
+        Ok(data) => Config::parse(&data, profile),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Config::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Settings for the active profile
+pub fn current() -> Arc<Config> {
+    let profile = profiles::active_profile();
+    if let Some(loaded) = LOADED.read().expect("config lock poisoned").as_ref() {
+        if loaded.profile == profile {
+            return loaded.config.clone();
+        }
+    }
+    reload()
+}
+
+/// Read the file again. When it is invalid the settings in use are kept.
+pub fn reload() -> Arc<Config> {
+    let profile = profiles::active_profile();
+    let path = config_path();
+    let modified = path.as_deref().ok().and_then(modified);
+
+    let mut loaded = LOADED.write().expect("config lock poisoned");
+    let previous = loaded.as_ref().map(|loaded| loaded.config.clone());
+    let config = match path
+        .map_err(ConfigError::from)
+        .and_then(|path| load(&path, &profile))
+    {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            log::error!("Ignoring {}: {}", CONFIG_FILE, e);
+            previous.clone().unwrap_or_default()
+        }
+    };
+    if let Some(previous) = previous {
+        if previous.needs_restart(&config) {
+            log::warn!("Changes to ports and the NWC relay apply after a restart");
+        }
+    }
+    *loaded = Some(Loaded {
+        profile,
+        modified,
+        config: config.clone(),
+    });
+    config
+}
+
+/// Reload the settings whenever the file changes
+pub fn spawn_watcher() -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(RELOAD_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            let Ok(path) = config_path() else {
+                continue;
+            };
+            let modified = modified(&path);
+            let changed = LOADED
+                .read()
+                .expect("config lock poisoned")
+                .as_ref()
+                .is_some_and(|loaded| loaded.modified != modified);
+            if changed {
+                log::info!("{} changed, reloading", path.display());
+                reload();
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_defaults_and_profile_overrides() {
+        let config = Config::parse("", "default").unwrap();
+        assert_eq!(config, Config::default());
+        assert!(config.validate().is_ok());
+
+        let data = r#"
+            [relays]
+            nwc = "wss://relay.example"
+
+            [nwc]
+            default_budget_sats = 2000
+
+            [profile.work.nwc]
+            default_budget_sats = 5000
+
+            [profile.work.ports]
+            relay = 5000
+
+            [profile.work.metrics]
+            enabled = true
+        "#;
+        let config = Config::parse(data, "default").unwrap();
+        assert_eq!(config.relays.nwc, "wss://relay.example");
+        assert_eq!(config.nwc.default_budget_sats, 2000);
+        assert_eq!(config.ports, PortsConfig::default());
+        assert!(!config.metrics.enabled);
+
+        let work = Config::parse(data, "work").unwrap();
+        assert_eq!(work.relays.nwc, "wss://relay.example");
+        assert_eq!(work.nwc.default_budget_sats, 5000);
+        assert_eq!(work.ports.relay, 5000);
+        assert!(work.metrics.enabled);
+        assert_eq!(
+            work.ports.connection,
+            crate::connection_server::DEFAULT_CONNECTION_PORT
+        );
+        assert!(work.needs_restart(&config));
+    }
+
+    #[test]
+    fn test_invalid_config_is_refused() {
+        for data in [
+            "[relays]\nnwc = \"https://relay.example\"",
+            "[relays]\ndiscovery = []",
+            "[nwc]\ndefault_budget_sats = 0",
+            "[tollgate]\nrenewal_threshold = 1.5",
+            "[timeouts]\nrequest_secs = 0",
+            "[tor]\nsocks_proxy = \"http://127.0.0.1:9050\"",
+            "[nwc]\nbudget = 1",
+            "profile = 1",
+        ] {
+            assert!(Config::parse(data, "default").is_err(), "{}", data);
+        }
+    }
+}
@@ -3,9 +3,11 @@
 //! This module provides a simple HTTP server that handles Nostr Wallet Connect
 //! connection requests and exposes wallet information to connecting applications.
 
+use crate::api_clients::ApiScope;
+use crate::pairing;
 use axum::{
     extract::{Path, State},
-    http::{Method, StatusCode},
+    http::{HeaderMap, Method, StatusCode},
     response::{IntoResponse, Response},
     routing::{get, post},
     Json, Router,
@@ -13,18 +15,29 @@ use axum::{
 use serde::{Deserialize, Serialize};
 use serde_json::json;
 use std::collections::HashMap;
-use std::net::SocketAddr;
 use std::sync::Arc;
+use tauri::AppHandle;
 use tauri::Manager;
-use tauri::{AppHandle, Emitter};
 #[cfg(target_os = "macos")]
 use tauri_nspanel::ManagerExt;
 use tokio::sync::Mutex;
-use tower_http::cors::CorsLayer;
+use tower_http::cors::{AllowOrigin, CorsLayer};
 
-/// Default port for the connection server
+/// Default port for the connection server, `ports.connection` in the config
 pub const DEFAULT_CONNECTION_PORT: u16 = 3737;
 
+/// How long a connection request waits for the user, and an approved NWC
+/// URI waits to be collected
+const PENDING_CONNECTION_TTL_SECS: u64 = 600;
+/// How long polls report an expired request before it is forgotten
+const EXPIRED_CONNECTION_RETENTION_SECS: u64 = 600;
+/// Connection requests outstanding at once
+const MAX_PENDING_CONNECTIONS: usize = 32;
+/// Interval between sweeps of expired connection requests
+const PENDING_CONNECTION_GC_SECS: u64 = 60;
+/// Name of the server under the supervisor
+const CONNECTION_SERVICE: &str = "connection_server";
+
 /// Request body for POST / endpoint
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ConnectRequest {
@@ -66,16 +79,167 @@ pub struct PendingConnectionRequest {
     pub approved: bool,
     /// Whether this request has been rejected
     pub rejected: bool,
+    /// Caller that made the request: a paired client's id, or "owner".
+    /// Only that caller may poll it.
+    #[serde(default)]
+    pub client_id: Option<String>,
+    /// Unix timestamp after which the request can no longer be approved or
+    /// its NWC URI collected
+    #[serde(default)]
+    pub expires_at: u64,
+    /// Connection created on approval of a standard NWC request, removed
+    /// again if its URI is never collected
+    #[serde(default)]
+    pub connection_pubkey: Option<String>,
+}
+
+impl PendingConnectionRequest {
+    fn new(nwa_request: Option<NostrWalletAuthRequest>, client_id: String) -> Self {
+        let now = now_secs();
+        Self {
+            request_id: uuid::Uuid::new_v4().to_string(),
+            nwa_request,
+            received_at: now,
+            nwc_uri: None,
+            approved: false,
+            rejected: false,
+            client_id: Some(client_id),
+            expires_at: now + PENDING_CONNECTION_TTL_SECS,
+            connection_pubkey: None,
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.expires_at
+    }
 }
 
 /// State for managing pending connection requests
 pub type PendingConnectionsState = Arc<Mutex<HashMap<String, PendingConnectionRequest>>>;
 
-/// Server state that includes both TollGate and AppHandle
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Drop the NWC URIs of expired requests, and forget them once polls no
+/// longer need to report them as expired. Returns the connections whose URI
+/// was never collected, for [`revoke_orphaned_connections`].
+pub(crate) fn prune_pending_connections(
+    connections: &mut HashMap<String, PendingConnectionRequest>,
+    now: u64,
+) -> Vec<String> {
+    let mut orphaned = Vec::new();
+    connections.retain(|request_id, request| {
+        if !request.is_expired(now) {
+            return true;
+        }
+        if request.nwc_uri.take().is_some() {
+            log::warn!(
+                "NWC URI for request {} was never collected, removing its connection",
+                request_id
+            );
+            orphaned.extend(request.connection_pubkey.take());
+        }
+        now < request.expires_at + EXPIRED_CONNECTION_RETENTION_SECS
+    });
+    orphaned
+}
+
+/// Remove connections returned by [`prune_pending_connections`], so nobody
+/// can use a URI that was approved but never handed over
+pub(crate) fn revoke_orphaned_connections(nwc_state: &crate::NwcState, pubkeys: Vec<String>) {
+    if pubkeys.is_empty() {
+        return;
+    }
+    // Callers hold the pending connections lock, so remove them separately
+    let nwc_state = nwc_state.clone();
+    tokio::spawn(async move {
+        let nwc_lock = nwc_state.lock().await;
+        let Some(nwc) = nwc_lock.as_ref() else {
+            return;
+        };
+        for pubkey in pubkeys {
+            if let Err(e) = nwc.remove_connection(&pubkey).await {
+                log::error!(
+                    "Failed to remove uncollected NWC connection {}: {}",
+                    pubkey,
+                    e
+                );
+            }
+        }
+    });
+}
+
+/// Store a new request unless too many are outstanding
+async fn store_pending_connection(
+    pending_connections: &PendingConnectionsState,
+    nwc_state: &crate::NwcState,
+    request: &PendingConnectionRequest,
+) -> Result<(), Response> {
+    let mut connections = pending_connections.lock().await;
+    let now = now_secs();
+    let orphaned = prune_pending_connections(&mut connections, now);
+    revoke_orphaned_connections(nwc_state, orphaned);
+    let outstanding = connections
+        .values()
+        .filter(|request| !request.is_expired(now))
+        .count();
+    if outstanding >= MAX_PENDING_CONNECTIONS {
+        log::warn!(
+            "Refusing connection request, {} are outstanding",
+            outstanding
+        );
+        return Err((
+            StatusCode::TOO_MANY_REQUESTS,
+            Json(json!({
+                "success": false,
+                "error": "Too many connection requests are waiting, try again later"
+            })),
+        )
+            .into_response());
+    }
+    connections.insert(request.request_id.clone(), request.clone());
+    Ok(())
+}
+
+/// Periodically prune expired requests, so uncollected NWC URIs do not
+/// stay in memory
+fn spawn_pending_connection_gc(
+    pending_connections: PendingConnectionsState,
+    nwc_state: crate::NwcState,
+) {
+    tokio::spawn(async move {
+        let mut interval =
+            tokio::time::interval(std::time::Duration::from_secs(PENDING_CONNECTION_GC_SECS));
+        loop {
+            interval.tick().await;
+            let orphaned =
+                prune_pending_connections(&mut *pending_connections.lock().await, now_secs());
+            revoke_orphaned_connections(&nwc_state, orphaned);
+        }
+    });
+}
+
+/// Server state shared by the connection, proxy and local API handlers
 #[derive(Clone)]
 pub struct ConnectionServerState {
-    pub app_handle: AppHandle,
     pub pending_connections: PendingConnectionsState,
+    pub tollgate: crate::TollGateState,
+    pub nwc: crate::NwcState,
+    pub routstr: crate::routstr::RoutstrState,
+    pub app_lock: crate::app_lock::AppLockState,
+    pub approvals: crate::tollgate::approvals::ApprovalsState,
+    /// Owner token for the local API, with access to every endpoint
+    pub api_token: Option<String>,
+    /// Clients with their own scoped local API tokens
+    pub api_clients: crate::api_clients::ApiClientsState,
+    /// Clients waiting to be paired, and the CORS allow-list
+    pub pairings: crate::pairing::PairingsState,
+    /// LAN listeners and their pairing codes
+    pub lan: crate::lan::LanState,
 }
 
 /// Response for approve/reject operations
@@ -85,130 +249,135 @@ pub struct ConnectionResponse {
     pub message: String,
 }
 
-/// Start the connection HTTP server
-pub async fn start_connection_server(
-    app_handle: AppHandle,
-    pending_connections: PendingConnectionsState,
-    port: u16,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let server_state = ConnectionServerState {
-        app_handle,
-        pending_connections,
-    };
-
-    // Configure CORS to allow any origin
+/// Routes of the connection server, served on loopback and in LAN mode
+pub(crate) fn router(server_state: ConnectionServerState) -> Router {
+    // Any page may ask to pair; everything else is limited to the origins
+    // of paired clients and the configured allow-list
+    let pairings = server_state.pairings.clone();
+    let api_clients = server_state.api_clients.clone();
+    let allow_origin = AllowOrigin::predicate(move |origin, parts| {
+        if parts.uri.path().starts_with("/pair") {
+            return true;
+        }
+        origin
+            .to_str()
+            .is_ok_and(|origin| pairings.origin_allowed(origin, &api_clients))
+    });
     let cors = CorsLayer::new()
-        .allow_origin(tower_http::cors::Any)
+        .allow_origin(allow_origin)
         .allow_methods([Method::GET, Method::POST, Method::OPTIONS])
         .allow_headers(tower_http::cors::Any);
 
-    let app = Router::new()
+    Router::new()
         .route("/", get(get_wallet_info).post(post_wallet_connect))
         .route("/poll/:request_id", get(poll_connection_status))
+        .route("/pair", post(pairing::request_pairing))
+        .route("/pair/:id", get(pairing::poll_pairing))
+        .route("/metrics", get(crate::metrics::serve_metrics))
+        .nest("/api/v1", crate::local_api::router())
         .route("/*path", get(crate::proxy::forward_request_get))
         .route("/*path", post(crate::proxy::forward_request_post))
         .layer(cors)
-        .with_state(server_state);
-
-    let addr = SocketAddr::from(([127, 0, 0, 1], port));
-
-    log::info!("Attempting to bind connection server to {}", addr);
+        .with_state(server_state)
+}
 
-    let listener = match tokio::net::TcpListener::bind(addr).await {
-        Ok(l) => {
-            log::info!("Successfully bound connection server to {}", addr);
-            l
-        }
-        Err(e) => {
-            log::error!("Failed to bind connection server to {}: {}", addr, e);
-            return Err(Box::new(e));
-        }
-    };
+/// Start the connection HTTP server on `listener`, from
+/// [`crate::runtime::bind_loopback`]
+pub async fn start_connection_server(
+    server_state: ConnectionServerState,
+    listener: std::net::TcpListener,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    spawn_pending_connection_gc(
+        server_state.pending_connections.clone(),
+        server_state.nwc.clone(),
+    );
+    let app = router(server_state);
+    let addr = listener.local_addr()?;
 
     log::info!("Connection server listening on http://{}", addr);
     log::info!("  GET  / - Create a new connection request (returns request_id)");
     log::info!("  GET  /poll/:request_id - Poll connection status and retrieve NWC URI");
     log::info!("  POST / - Connect via Nostr Wallet Auth (NWA)");
-
-    tokio::spawn(async move {
-        log::info!("Connection server task started, beginning to serve requests");
-        if let Err(e) = axum::serve(listener, app).await {
-            log::error!("Connection server encountered an error: {}", e);
+    log::info!("  POST /pair - Ask the user for a client token (sent as X-Wally-Token)");
+    if crate::config::current().metrics.enabled {
+        log::info!("  GET  /metrics - Prometheus metrics (bearer token required)");
+    }
+    log::info!("  /api/v1 - Local API (bearer token required, see /api/v1/openapi.json)");
+
+    // Each run serves a clone of the listener, so a restart keeps the port.
+    // On shutdown, requests in flight are answered first.
+    crate::supervisor::supervisor().spawn(CONNECTION_SERVICE, move |mut shutdown| {
+        let app = app.clone();
+        let listener = listener
+            .try_clone()
+            .and_then(tokio::net::TcpListener::from_std);
+        async move {
+            let listener = listener.map_err(|e| e.to_string())?;
+            log::info!("Connection server task started, beginning to serve requests");
+            axum::serve(listener, app)
+                .with_graceful_shutdown(async move { shutdown.wait().await })
+                .await
+                .map_err(|e| format!("Connection server encountered an error: {}", e))
         }
-        log::warn!("Connection server task ended");
     });
 
     Ok(())
 }
 
+/// Check the caller's token and rate limit, returning the caller's id
+fn authorize_connect(
+    state: &ConnectionServerState,
+    headers: &HeaderMap,
+) -> Result<String, Response> {
+    let refuse = |status: StatusCode, message: String| {
+        (
+            status,
+            Json(json!({
+                "success": false,
+                "error": message
+            })),
+        )
+            .into_response()
+    };
+
+    let auth = pairing::authorize(state, headers, ApiScope::Connect)
+        .map_err(|(status, message)| refuse(status, message))?;
+    let caller = pairing::caller_id(&auth);
+    state
+        .pairings
+        .check_connection_rate(&caller)
+        .map_err(|e| refuse(StatusCode::TOO_MANY_REQUESTS, e.to_string()))?;
+    Ok(caller)
+}
+
 /// Handler for GET / - Creates a pending connection request
-async fn get_wallet_info(State(state): State<ConnectionServerState>) -> Response {
+async fn get_wallet_info(
+    State(state): State<ConnectionServerState>,
+    headers: HeaderMap,
+) -> Response {
     log::info!("Received GET request to create connection");
 
-    // Generate a unique request ID
-    let request_id = uuid::Uuid::new_v4().to_string();
-
-    // Create pending connection request for standard NWC flow
-    let pending_request = PendingConnectionRequest {
-        request_id: request_id.clone(),
-        nwa_request: None, // Standard NWC flow doesn't use NWA
-        received_at: std::time::SystemTime::now()
-            .duration_since(std::time::UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs(),
-        nwc_uri: None,
-        approved: false,
-        rejected: false,
+    let caller = match authorize_connect(&state, &headers) {
+        Ok(caller) => caller,
+        Err(response) => return response,
     };
 
-    // Store pending request
-    {
-        let mut pending_connections = state.pending_connections.lock().await;
-        pending_connections.insert(request_id.clone(), pending_request.clone());
-    }
+    // Create pending connection request for standard NWC flow, which
+    // doesn't use NWA
+    let pending_request = PendingConnectionRequest::new(None, caller);
+    let request_id = pending_request.request_id.clone();
+    let expires_at = pending_request.expires_at;
 
-    // Emit event to frontend to prompt user
+    if let Err(response) =
+        store_pending_connection(&state.pending_connections, &state.nwc, &pending_request).await
     {
-        #[cfg(target_os = "macos")]
-        {
-            let app_handle = state.app_handle.clone();
-            let app_handle_for_closure = app_handle.clone();
-            let _ = app_handle.run_on_main_thread(move || {
-                if let Ok(panel) = app_handle_for_closure.get_webview_panel("main") {
-                    if !panel.is_visible() {
-                        panel.order_front_regardless();
-                    }
-                    panel.make_key_and_order_front(None);
-                } else if let Some(window) = app_handle_for_closure.get_webview_window("main") {
-                    let _ = window.show();
-                    let _ = window.set_focus();
-                }
-            });
-        }
-
-        #[cfg(not(target_os = "macos"))]
-        {
-            if let Some(window) = state.app_handle.get_webview_window("main") {
-                let _ = window.show();
-                let _ = window.set_focus();
-            }
-        }
+        return response;
     }
 
-    if let Err(e) = state
-        .app_handle
-        .emit("nwc-connection-request", &pending_request)
-    {
-        log::error!("Failed to emit connection request event: {}", e);
-        return (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(json!({
-                "success": false,
-                "error": "Failed to process connection request"
-            })),
-        )
-            .into_response();
-    }
+    // Ask the user in the app, or through the local API when headless
+    crate::events::publish(crate::events::BackendEvent::ConnectionRequested {
+        request: pending_request,
+    });
 
     log::info!(
         "Created standard NWC connection request with ID: {}",
@@ -219,7 +388,8 @@ async fn get_wallet_info(State(state): State<ConnectionServerState>) -> Response
         "success": true,
         "request_id": request_id,
         "message": "Connection request created, awaiting user approval",
-        "poll_url": format!("/poll/{}", request_id)
+        "poll_url": format!("/poll/{}", request_id),
+        "expires_at": expires_at
     }))
     .into_response()
 }
@@ -227,9 +397,15 @@ async fn get_wallet_info(State(state): State<ConnectionServerState>) -> Response
 /// Handler for POST /
 async fn post_wallet_connect(
     State(state): State<ConnectionServerState>,
+    headers: HeaderMap,
     Json(payload): Json<ConnectRequest>,
 ) -> Response {
     log::info!("Received POST connection request");
+
+    let caller = match authorize_connect(&state, &headers) {
+        Ok(caller) => caller,
+        Err(response) => return response,
+    };
     log::debug!("Connection URI: {}", payload.nwa);
 
     // Parse the Nostr Wallet Auth URI
@@ -244,79 +420,30 @@ async fn post_wallet_connect(
             log::info!("  Budget: {:?}", nwa_request.budget);
             log::info!("  Identity: {:?}", nwa_request.identity);
 
-            // Generate a unique request ID
-            let request_id = uuid::Uuid::new_v4().to_string();
-
             // Create pending connection request
-            let pending_request = PendingConnectionRequest {
-                request_id: request_id.clone(),
-                nwa_request: Some(nwa_request.clone()),
-                received_at: std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap_or_default()
-                    .as_secs(),
-                nwc_uri: None,
-                approved: false,
-                rejected: false,
-            };
+            let pending_request = PendingConnectionRequest::new(Some(nwa_request), caller);
+            let request_id = pending_request.request_id.clone();
+            let expires_at = pending_request.expires_at;
 
-            // Store pending request
+            if let Err(response) =
+                store_pending_connection(&state.pending_connections, &state.nwc, &pending_request)
+                    .await
             {
-                let mut pending_connections = state.pending_connections.lock().await;
-                pending_connections.insert(request_id.clone(), pending_request.clone());
+                return response;
             }
 
-            // Emit event to frontend to prompt user
-            // Ensure the app window is visible and focused so the user sees the prompt
-            {
-                #[cfg(target_os = "macos")]
-                {
-                    let app_handle = state.app_handle.clone();
-                    let app_handle_for_closure = app_handle.clone();
-                    let _ = app_handle.run_on_main_thread(move || {
-                        if let Ok(panel) = app_handle_for_closure.get_webview_panel("main") {
-                            if !panel.is_visible() {
-                                panel.order_front_regardless();
-                            }
-                            panel.make_key_and_order_front(None);
-                        } else if let Some(window) =
-                            app_handle_for_closure.get_webview_window("main")
-                        {
-                            let _ = window.show();
-                            let _ = window.set_focus();
-                        }
-                    });
-                }
-
-                #[cfg(not(target_os = "macos"))]
-                {
-                    if let Some(window) = state.app_handle.get_webview_window("main") {
-                        let _ = window.show();
-                        let _ = window.set_focus();
-                    }
-                }
-            }
-            if let Err(e) = state
-                .app_handle
-                .emit("nwc-connection-request", &pending_request)
-            {
-                log::error!("Failed to emit connection request event: {}", e);
-                return (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    Json(json!({
-                        "success": false,
-                        "error": "Failed to process connection request"
-                    })),
-                )
-                    .into_response();
-            }
+            // Ask the user in the app, or through the local API when headless
+            crate::events::publish(crate::events::BackendEvent::ConnectionRequested {
+                request: pending_request,
+            });
 
-            log::info!("Emitted connection request event with ID: {}", request_id);
+            log::info!("Published connection request event with ID: {}", request_id);
 
             Json(json!({
                 "success": true,
                 "message": "Connection request received, awaiting user approval",
-                "request_id": request_id
+                "request_id": request_id,
+                "expires_at": expires_at
             }))
             .into_response()
         }
@@ -334,70 +461,135 @@ async fn post_wallet_connect(
     }
 }
 
+/// Bring the main window to the front so the user sees a prompt
+pub(crate) fn show_main_window(app_handle: &AppHandle) {
+    #[cfg(target_os = "macos")]
+    {
+        let app_handle_for_closure = app_handle.clone();
+        let _ = app_handle.run_on_main_thread(move || {
+            if let Ok(panel) = app_handle_for_closure.get_webview_panel("main") {
+                if !panel.is_visible() {
+                    panel.order_front_regardless();
+                }
+                panel.make_key_and_order_front(None);
+            } else if let Some(window) = app_handle_for_closure.get_webview_window("main") {
+                let _ = window.show();
+                let _ = window.set_focus();
+            }
+        });
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    {
+        if let Some(window) = app_handle.get_webview_window("main") {
+            let _ = window.show();
+            let _ = window.set_focus();
+        }
+    }
+}
+
 /// Handler for GET /poll/:request_id - Poll connection status
 async fn poll_connection_status(
     State(state): State<ConnectionServerState>,
+    headers: HeaderMap,
     Path(request_id): Path<String>,
 ) -> Response {
     log::debug!("Polling connection status for request: {}", request_id);
 
-    let pending_connections = state.pending_connections.lock().await;
-
-    if let Some(pending_request) = pending_connections.get(&request_id) {
-        if pending_request.approved {
-            if let Some(ref nwc_uri) = pending_request.nwc_uri {
-                log::info!(
-                    "Connection approved, returning NWC URI for request: {}",
-                    request_id
-                );
+    let caller = match pairing::authorize(&state, &headers, ApiScope::Connect) {
+        Ok(auth) => pairing::caller_id(&auth),
+        Err((status, message)) => {
+            return (
+                status,
                 Json(json!({
-                    "status": "approved",
-                    "nwc_uri": nwc_uri
-                }))
-                .into_response()
-            } else {
-                log::warn!(
-                    "Connection approved but NWC URI not set for request: {}",
-                    request_id
-                );
-                Json(json!({
-                    "status": "approved",
-                    "error": "NWC URI not available yet"
-                }))
+                    "status": "unauthorized",
+                    "error": message
+                })),
+            )
                 .into_response()
-            }
-        } else if pending_request.rejected {
-            log::info!("Connection rejected for request: {}", request_id);
+        }
+    };
+
+    let mut pending_connections = state.pending_connections.lock().await;
+    let now = now_secs();
+    let orphaned = prune_pending_connections(&mut pending_connections, now);
+    revoke_orphaned_connections(&state.nwc, orphaned);
+
+    // Requests made by other callers look like they do not exist
+    let Some(pending_request) = pending_connections
+        .get(&request_id)
+        .filter(|request| request.client_id.as_deref() == Some(caller.as_str()))
+    else {
+        log::warn!("Connection request not found: {}", request_id);
+        return (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "status": "not_found",
+                "error": "Connection request not found"
+            })),
+        )
+            .into_response();
+    };
+
+    if pending_request.is_expired(now) {
+        log::info!("Connection request expired: {}", request_id);
+        (
+            StatusCode::GONE,
+            Json(json!({
+                "status": "expired",
+                "error": "Connection request expired"
+            })),
+        )
+            .into_response()
+    } else if pending_request.approved {
+        if pending_request.nwc_uri.is_some() {
+            // The URI is a secret, so it is handed out once
+            let nwc_uri = pending_connections
+                .remove(&request_id)
+                .and_then(|request| request.nwc_uri);
+            log::info!(
+                "Connection approved, returning NWC URI for request: {}",
+                request_id
+            );
             Json(json!({
-                "status": "rejected",
-                "message": "Connection request was rejected by user"
+                "status": "approved",
+                "nwc_uri": nwc_uri
             }))
             .into_response()
         } else {
-            log::debug!("Connection still pending for request: {}", request_id);
+            log::warn!(
+                "Connection approved but NWC URI not set for request: {}",
+                request_id
+            );
             Json(json!({
-                "status": "pending",
-                "message": "Waiting for user approval"
+                "status": "approved",
+                "error": "NWC URI not available yet"
             }))
             .into_response()
         }
+    } else if pending_request.rejected {
+        log::info!("Connection rejected for request: {}", request_id);
+        pending_connections.remove(&request_id);
+        Json(json!({
+            "status": "rejected",
+            "message": "Connection request was rejected by user"
+        }))
+        .into_response()
     } else {
-        log::warn!("Connection request not found: {}", request_id);
-        (
-            StatusCode::NOT_FOUND,
-            Json(json!({
-                "status": "not_found",
-                "error": "Connection request not found or expired"
-            })),
-        )
-            .into_response()
+        log::debug!("Connection still pending for request: {}", request_id);
+        Json(json!({
+            "status": "pending",
+            "message": "Waiting for user approval",
+            "expires_at": pending_request.expires_at
+        }))
+        .into_response()
     }
 }
 
 /// Parse a Nostr Wallet Auth URI
 ///
 /// Format: nostr+walletauth://{pubkey}?relay={relay}&secret={secret}&request_methods={methods}&...
-fn parse_nwa_uri(uri: &str) -> Result<NostrWalletAuthRequest, String> {
+pub(crate) fn parse_nwa_uri(uri: &str) -> Result<NostrWalletAuthRequest, String> {
     // Check protocol
     if !uri.starts_with("nostr+walletauth://") {
         return Err("URI must start with 'nostr+walletauth://'".to_string());
@@ -509,12 +701,30 @@ pub async fn nwc_approve_connection(
     request_id: String,
     pending_connections: tauri::State<'_, PendingConnectionsState>,
     nwc_state: tauri::State<'_, crate::NwcState>,
+    lock: tauri::State<'_, crate::app_lock::AppLockState>,
+) -> Result<ConnectionResponse, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    approve_connection(&request_id, &pending_connections, &nwc_state).await
+}
+
+/// Approve a pending connection request, from the app or the local API
+pub(crate) async fn approve_connection(
+    request_id: &str,
+    pending_connections: &PendingConnectionsState,
+    nwc_state: &crate::NwcState,
 ) -> Result<ConnectionResponse, String> {
     log::info!("Approving connection request: {}", request_id);
 
     let mut connections = pending_connections.lock().await;
+    let now = now_secs();
+    let orphaned = prune_pending_connections(&mut connections, now);
+    revoke_orphaned_connections(nwc_state, orphaned);
+
+    if let Some(mut pending_request) = connections.get(request_id).cloned() {
+        if pending_request.is_expired(now) {
+            return Err(format!("Connection request expired: {}", request_id));
+        }
 
-    if let Some(mut pending_request) = connections.get(&request_id).cloned() {
         // Get the NWC service
         let nwc_lock = nwc_state.lock().await;
         let nwc = nwc_lock.as_ref().ok_or_else(|| {
@@ -571,13 +781,17 @@ pub async fn nwc_approve_connection(
             })?;
 
             // Mark as approved and remove from pending
-            connections.remove(&request_id);
+            connections.remove(request_id);
             drop(connections);
 
             log::info!(
                 "NWA connection approved successfully for request: {}",
                 request_id
             );
+            crate::events::publish(crate::events::BackendEvent::ConnectionApproved {
+                request_id: request_id.to_string(),
+                app_pubkey: Some(nwa_request.app_pubkey.clone()),
+            });
 
             Ok(ConnectionResponse {
                 success: true,
@@ -588,23 +802,33 @@ pub async fn nwc_approve_connection(
             log::info!("Creating standard NWC connection");
 
             // Create standard NWC connection
-            let nwc_uri = nwc.create_standard_nwc_uri(false).await.map_err(|e| {
-                log::error!("Failed to create NWC URI: {}", e);
-                format!("Failed to create NWC URI: {}", e)
-            })?;
+            let (connection, nwc_uri) = nwc
+                .create_connection(None, crate::nwc::ConnectionBudget::default(), false)
+                .await
+                .map_err(|e| {
+                    log::error!("Failed to create NWC URI: {}", e);
+                    format!("Failed to create NWC URI: {}", e)
+                })?;
 
             log::info!("Created standard NWC connection with URI");
 
-            // Update pending request with the URI and mark as approved
+            // Update pending request with the URI and mark as approved; the
+            // client gets a fresh window to collect it
             pending_request.nwc_uri = Some(nwc_uri.clone());
+            pending_request.connection_pubkey = Some(connection.keys.public_key().to_hex());
             pending_request.approved = true;
-            connections.insert(request_id.clone(), pending_request);
+            pending_request.expires_at = now_secs() + PENDING_CONNECTION_TTL_SECS;
+            connections.insert(request_id.to_string(), pending_request);
             drop(connections);
 
             log::info!(
                 "Standard NWC connection approved successfully for request: {}",
                 request_id
             );
+            crate::events::publish(crate::events::BackendEvent::ConnectionApproved {
+                request_id: request_id.to_string(),
+                app_pubkey: None,
+            });
 
             Ok(ConnectionResponse {
                 success: true,
@@ -650,15 +874,31 @@ fn parse_budget(budget_str: &str) -> Option<crate::nwc::ConnectionBudget> {
 pub async fn nwc_reject_connection(
     request_id: String,
     pending_connections: tauri::State<'_, PendingConnectionsState>,
+    nwc_state: tauri::State<'_, crate::NwcState>,
+) -> Result<ConnectionResponse, String> {
+    reject_connection(&request_id, &pending_connections, &nwc_state).await
+}
+
+/// Reject a pending connection request, from the app or the local API
+pub(crate) async fn reject_connection(
+    request_id: &str,
+    pending_connections: &PendingConnectionsState,
+    nwc_state: &crate::NwcState,
 ) -> Result<ConnectionResponse, String> {
     log::info!("Rejecting connection request: {}", request_id);
 
     let mut connections = pending_connections.lock().await;
+    let now = now_secs();
+    let orphaned = prune_pending_connections(&mut connections, now);
+    revoke_orphaned_connections(nwc_state, orphaned);
 
-    if let Some(mut pending_request) = connections.get(&request_id).cloned() {
-        // Mark as rejected instead of removing it
+    if let Some(mut pending_request) = connections.get(request_id).cloned() {
+        if pending_request.is_expired(now) {
+            return Err(format!("Connection request expired: {}", request_id));
+        }
+        // Mark as rejected instead of removing it, so the client sees it
         pending_request.rejected = true;
-        connections.insert(request_id.clone(), pending_request);
+        connections.insert(request_id.to_string(), pending_request);
         drop(connections);
 
         log::info!("Connection request rejected: {}", request_id);
@@ -718,6 +958,53 @@ mod tests {
         assert_eq!(result.relays, vec!["wss://relay1.com", "wss://relay2.com"]);
     }
 
+    #[test]
+    fn test_prune_pending_connections() {
+        let mut fresh = PendingConnectionRequest::new(None, "owner".to_string());
+        let now = fresh.received_at;
+        fresh.approved = true;
+        fresh.nwc_uri = Some("nostr+walletconnect://fresh".to_string());
+
+        let mut stale = PendingConnectionRequest::new(None, "owner".to_string());
+        stale.approved = true;
+        stale.nwc_uri = Some("nostr+walletconnect://stale".to_string());
+        stale.connection_pubkey = Some("stale-pubkey".to_string());
+        let stale_id = stale.request_id.clone();
+
+        let mut connections: HashMap<_, _> = [fresh, stale]
+            .into_iter()
+            .map(|request| (request.request_id.clone(), request))
+            .collect();
+        connections.get_mut(&stale_id).unwrap().expires_at = now;
+
+        // Expired requests lose their URI and connection but are still
+        // reported as expired
+        assert_eq!(
+            prune_pending_connections(&mut connections, now),
+            vec!["stale-pubkey".to_string()]
+        );
+        assert_eq!(connections.len(), 2);
+        assert!(connections[&stale_id].is_expired(now));
+        assert!(connections[&stale_id].nwc_uri.is_none());
+        assert!(connections
+            .values()
+            .any(|request| request.nwc_uri.as_deref() == Some("nostr+walletconnect://fresh")));
+
+        assert!(prune_pending_connections(
+            &mut connections,
+            now + EXPIRED_CONNECTION_RETENTION_SECS
+        )
+        .is_empty());
+        assert_eq!(connections.len(), 1);
+        assert!(!connections.contains_key(&stale_id));
+
+        prune_pending_connections(
+            &mut connections,
+            now + PENDING_CONNECTION_TTL_SECS + EXPIRED_CONNECTION_RETENTION_SECS,
+        );
+        assert!(connections.is_empty());
+    }
+
     #[test]
     fn test_parse_nwa_uri_invalid() {
         let uri = "invalid://test";
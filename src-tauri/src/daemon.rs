@@ -0,0 +1,310 @@
+//! Headless daemon
+//!
+//! Runs the same backend as the app without a webview, for servers that
+//! only provide NWC and the Routstr proxy. Daemon settings come from
+//! `wallyd.toml`; the ports and the approval timeout fall back to the shared
+//! `wally.toml` (see `config.rs`) when they are not set there. Approval
+//! prompts and connection requests are answered through the local API
+//! (`/api/v1` on the connection server) with the configured token.
+
+use crate::backend::{Backend, BackendOptions};
+use crate::events::{self, BackendEvent};
+use crate::lan::LanSettings;
+use crate::tollgate::approvals::{ApprovalBroker, ApprovalsState};
+use crate::{profiles, runtime};
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+const CONFIG_FILE: &str = "wallyd.toml";
+const TOKEN_FILE: &str = "wallyd.token";
+
+/// Daemon settings, read from `wallyd.toml`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Top-level data directory; the platform default when unset
+    pub data_dir: Option<PathBuf>,
+    /// Profile to open; the last active profile when unset
+    pub profile: Option<String>,
+    /// Preferred ports, overriding `wally.toml`. Free ones are used when they
+    /// are taken, and the ports in use are written to `runtime.json` in the
+    /// data directory.
+    pub relay_port: Option<u16>,
+    pub connection_port: Option<u16>,
+    /// Bearer token for the local API. When unset a token is generated and
+    /// stored in `wallyd.token` in the data directory.
+    pub api_token: Option<String>,
+    /// Web origins allowed to call the connection server without pairing
+    pub allowed_origins: Vec<String>,
+    /// Network interface to serve on in LAN mode; LAN mode uses the settings
+    /// saved by the app when unset
+    pub lan_interface: Option<String>,
+    /// How long payments wait for approval before they are refused,
+    /// overriding `wally.toml`
+    pub approval_timeout_secs: Option<u64>,
+    pub log_level: String,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            data_dir: None,
+            profile: None,
+            relay_port: None,
+            connection_port: None,
+            api_token: None,
+            allowed_origins: Vec::new(),
+            lan_interface: None,
+            approval_timeout_secs: None,
+            log_level: "info".to_string(),
+        }
+    }
+}
+
+impl DaemonConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let data = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        toml::from_str(&data).map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+    }
+}
+
+/// Config path from `--config <path>`, `WALLYD_CONFIG`, or `wallyd.toml` in
+/// the data directory
+fn config_path() -> Result<Option<PathBuf>, String> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" | "-c" => {
+                return args
+                    .next()
+                    .map(|path| Some(PathBuf::from(path)))
+                    .ok_or_else(|| "--config needs a path".to_string());
+            }
+            "--help" | "-h" => {
+                println!("Usage: wallyd [--config <path>]");
+                std::process::exit(0);
+            }
+            other => return Err(format!("Unknown argument: {}", other)),
+        }
+    }
+
+    if let Ok(path) = std::env::var("WALLYD_CONFIG") {
+        return Ok(Some(PathBuf::from(path)));
+    }
+    let default = profiles::root_dir()
+        .map_err(|e| e.to_string())?
+        .join(CONFIG_FILE);
+    Ok(default.exists().then_some(default))
+}
+
+/// Token from the config, or the one generated on first start
+fn api_token(config: &DaemonConfig) -> Result<String, String> {
+    if let Some(token) = &config.api_token {
+        return Ok(token.clone());
+    }
+
+    let path = profiles::root_dir()
+        .map_err(|e| e.to_string())?
+        .join(TOKEN_FILE);
+    if let Ok(token) = fs::read_to_string(&path) {
+        return Ok(token.trim().to_string());
+    }
+
+    let token = runtime::generate_token();
+    write_private(&path, &token).map_err(|e| format!("Failed to write API token: {}", e))?;
+    log::info!("Generated local API token in {}", path.display());
+    Ok(token)
+}
+
+/// Write a file only the current user can read
+pub(crate) fn write_private(path: &Path, contents: impl AsRef<[u8]>) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::unix::fs::OpenOptionsExt;
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?;
+        file.write_all(contents.as_ref())
+    }
+    #[cfg(not(unix))]
+    {
+        fs::write(path, contents)
+    }
+}
+
+/// Log prompts so an operator knows to answer them through the local API
+fn spawn_prompt_logger() {
+    let mut receiver = events::subscribe();
+    tokio::spawn(async move {
+        loop {
+            let envelope = match receiver.recv().await {
+                Ok(envelope) => envelope,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    log::warn!("Prompt logger lagged by {} events", n);
+                    continue;
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            };
+            match &envelope.event {
+                BackendEvent::SpendApprovalRequested { approval } => log::warn!(
+                    "Payment of {} sats from {} needs approval ({}): POST /api/v1/approvals/{}/approve",
+                    approval.amount_sats,
+                    approval.origin,
+                    approval.reason,
+                    approval.id
+                ),
+                BackendEvent::SpendApprovalResolved { id, outcome } => {
+                    log::info!("Payment approval {} finished: {:?}", id, outcome)
+                }
+                BackendEvent::ConnectionRequested { request } => log::warn!(
+                    "Connection request {} needs approval: POST /api/v1/connections/{}/approve",
+                    request.request_id,
+                    request.request_id
+                ),
+                BackendEvent::PairingRequested { pairing } => log::warn!(
+                    "{} ({}) asks to pair: POST /api/v1/pairings/{}/approve",
+                    pairing.name,
+                    pairing.origin.as_deref().unwrap_or("no origin"),
+                    pairing.id
+                ),
+                BackendEvent::LanDevicePaired {
+                    name,
+                    connection_pubkey,
+                } => log::info!(
+                    "LAN device {} paired as NWC connection {}",
+                    name,
+                    connection_pubkey
+                ),
+                _ => {}
+            }
+        }
+    });
+}
+
+/// Resolves on SIGINT or SIGTERM
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut term = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = term.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+async fn serve(config: DaemonConfig) -> Result<(), String> {
+    if let Some(profile) = &config.profile {
+        profiles::use_profile(profile)?;
+    }
+    log::info!(
+        "Starting wallyd with profile {}",
+        profiles::active_profile()
+    );
+
+    let settings = crate::config::current();
+    // An explicit --approval-timeout pins the timeout, otherwise it follows
+    // the config file
+    let approvals: ApprovalsState = Arc::new(
+        config
+            .approval_timeout_secs
+            .map_or_else(ApprovalBroker::from_config, |secs| {
+                ApprovalBroker::new(Duration::from_secs(secs))
+            }),
+    );
+    spawn_prompt_logger();
+
+    let backend = Backend::start(
+        BackendOptions {
+            relay_port: config.relay_port.unwrap_or(settings.ports.relay),
+            connection_port: config.connection_port.unwrap_or(settings.ports.connection),
+            api_token: Some(api_token(&config)?),
+            allowed_origins: config.allowed_origins,
+            lan: config.lan_interface.map(|interface| LanSettings {
+                enabled: true,
+                interface: Some(interface),
+            }),
+        },
+        approvals,
+    )
+    .await?;
+
+    log::info!("wallyd running, local API at {}", backend.runtime.api_url);
+    shutdown_signal().await;
+
+    backend.shutdown().await;
+    log::info!("wallyd stopped");
+    Ok(())
+}
+
+/// Entry point of the `wallyd` binary
+pub fn run_daemon() {
+    let result = config_path().and_then(|path| match path {
+        Some(path) => DaemonConfig::load(&path),
+        None => Ok(DaemonConfig::default()),
+    });
+    let config = match result {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("wallyd: {}", e);
+            std::process::exit(2);
+        }
+    };
+
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or(&config.log_level))
+        .init();
+
+    if let Some(data_dir) = &config.data_dir {
+        if let Err(e) = profiles::set_root_dir(data_dir.clone()) {
+            log::error!("Cannot use data directory {}: {}", data_dir.display(), e);
+            std::process::exit(1);
+        }
+    }
+
+    let runtime = tokio::runtime::Runtime::new().expect("Failed to start tokio runtime");
+    if let Err(e) = runtime.block_on(serve(config)) {
+        log::error!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_defaults_and_overrides() {
+        let config: DaemonConfig = toml::from_str(
+            r#"
+            data_dir = "/var/lib/wally"
+            connection_port = 4000
+            api_token = "secret"
+            allowed_origins = ["https://chat.example"]
+            lan_interface = "eth0"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.data_dir, Some(PathBuf::from("/var/lib/wally")));
+        assert_eq!(config.connection_port, Some(4000));
+        // Left to wally.toml
+        assert_eq!(config.relay_port, None);
+        assert_eq!(config.approval_timeout_secs, None);
+        assert_eq!(config.allowed_origins, vec!["https://chat.example"]);
+        assert_eq!(config.lan_interface.as_deref(), Some("eth0"));
+
+        assert!(toml::from_str::<DaemonConfig>("conection_port = 1").is_err());
+    }
+}
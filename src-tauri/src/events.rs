@@ -0,0 +1,257 @@
+//! Backend event bus
+//!
+//! Services publish typed events here as things happen: balance changes,
+//! TollGate sessions, NWC requests, connection requests, paid mint quotes,
+//! billed proxy requests and payment approvals. The app forwards them to the
+//! webview as Tauri events and the local API streams them over SSE, so both
+//! see the same events in the same order.
+//!
+//! Events are also counted in the metrics as they are published.
+//!
+//! The bus is process-wide, like the active profile, because events come
+//! from deep inside services that are rebuilt on profile switches.
+
+use crate::connection_server::PendingConnectionRequest;
+use crate::pairing::PairingRequest;
+use crate::tollgate::approvals::{ApprovalOutcome, PendingApproval};
+use crate::tollgate::service::SessionInfo;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock};
+use tokio::sync::broadcast;
+
+/// Events kept for subscribers that fall behind
+const BUS_CAPACITY: usize = 256;
+
+static BUS: OnceLock<broadcast::Sender<Arc<EventEnvelope>>> = OnceLock::new();
+static NEXT_SEQ: AtomicU64 = AtomicU64::new(1);
+
+/// Coarse grouping of events, used to filter subscriptions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventTopic {
+    Wallet,
+    Session,
+    Nwc,
+    Connection,
+    Mint,
+    Proxy,
+    Approval,
+}
+
+impl EventTopic {
+    pub const ALL: [EventTopic; 7] = [
+        EventTopic::Wallet,
+        EventTopic::Session,
+        EventTopic::Nwc,
+        EventTopic::Connection,
+        EventTopic::Mint,
+        EventTopic::Proxy,
+        EventTopic::Approval,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventTopic::Wallet => "wallet",
+            EventTopic::Session => "session",
+            EventTopic::Nwc => "nwc",
+            EventTopic::Connection => "connection",
+            EventTopic::Mint => "mint",
+            EventTopic::Proxy => "proxy",
+            EventTopic::Approval => "approval",
+        }
+    }
+}
+
+impl fmt::Display for EventTopic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for EventTopic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        EventTopic::ALL
+            .into_iter()
+            .find(|topic| topic.as_str() == s)
+            .ok_or_else(|| format!("Unknown event topic: {}", s))
+    }
+}
+
+/// Something that happened in the backend
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BackendEvent {
+    /// Total balance across mints after a payment, receive or mint
+    BalanceChanged {
+        total_sats: u64,
+    },
+    SessionStarted {
+        session: SessionInfo,
+    },
+    SessionRenewed {
+        session: SessionInfo,
+    },
+    SessionExpired {
+        session: SessionInfo,
+    },
+    /// An NWC request was answered
+    NwcRequestHandled {
+        connection: String,
+        method: String,
+        /// Error returned to the app, if the request failed
+        error: Option<String>,
+    },
+    /// An app asked to connect and is waiting for the user
+    ConnectionRequested {
+        request: PendingConnectionRequest,
+    },
+    ConnectionApproved {
+        request_id: String,
+        /// Pubkey of the app, for Nostr Wallet Auth requests
+        app_pubkey: Option<String>,
+    },
+    /// A web page or tool asked for a client token
+    PairingRequested {
+        pairing: PairingRequest,
+    },
+    /// A device on the LAN redeemed a pairing code and got an NWC connection
+    LanDevicePaired {
+        name: String,
+        connection_pubkey: String,
+    },
+    /// A lightning invoice was paid and its ecash minted
+    MintQuotePaid {
+        mint_url: String,
+        quote_id: String,
+        amount_sats: u64,
+    },
+    /// The Routstr proxy paid for a request
+    ProxyRequestBilled {
+        target_url: String,
+        model: Option<String>,
+        status: u16,
+        paid_sats: u64,
+        refunded_sats: u64,
+    },
+    SpendApprovalRequested {
+        approval: PendingApproval,
+    },
+    SpendApprovalResolved {
+        id: String,
+        outcome: ApprovalOutcome,
+    },
+}
+
+impl BackendEvent {
+    pub fn topic(&self) -> EventTopic {
+        match self {
+            BackendEvent::BalanceChanged { .. } => EventTopic::Wallet,
+            BackendEvent::SessionStarted { .. }
+            | BackendEvent::SessionRenewed { .. }
+            | BackendEvent::SessionExpired { .. } => EventTopic::Session,
+            BackendEvent::NwcRequestHandled { .. } => EventTopic::Nwc,
+            BackendEvent::ConnectionRequested { .. }
+            | BackendEvent::ConnectionApproved { .. }
+            | BackendEvent::PairingRequested { .. }
+            | BackendEvent::LanDevicePaired { .. } => EventTopic::Connection,
+            BackendEvent::MintQuotePaid { .. } => EventTopic::Mint,
+            BackendEvent::ProxyRequestBilled { .. } => EventTopic::Proxy,
+            BackendEvent::SpendApprovalRequested { .. }
+            | BackendEvent::SpendApprovalResolved { .. } => EventTopic::Approval,
+        }
+    }
+}
+
+/// An event as delivered to subscribers
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventEnvelope {
+    /// Increases by one per event for the lifetime of the process
+    pub seq: u64,
+    /// Unix timestamp in seconds
+    pub at: u64,
+    pub topic: EventTopic,
+    #[serde(flatten)]
+    pub event: BackendEvent,
+}
+
+fn bus() -> &'static broadcast::Sender<Arc<EventEnvelope>> {
+    BUS.get_or_init(|| broadcast::channel(BUS_CAPACITY).0)
+}
+
+/// Publish an event to every subscriber. Events published while nobody is
+/// subscribed are dropped.
+pub fn publish(event: BackendEvent) {
+    crate::metrics::record_event(&event);
+    let envelope = EventEnvelope {
+        seq: NEXT_SEQ.fetch_add(1, Ordering::Relaxed),
+        at: chrono::Utc::now().timestamp() as u64,
+        topic: event.topic(),
+        event,
+    };
+    log::debug!("Event {} {}", envelope.seq, envelope.topic);
+    let _ = bus().send(Arc::new(envelope));
+}
+
+/// Receive events published from now on
+pub fn subscribe() -> broadcast::Receiver<Arc<EventEnvelope>> {
+    bus().subscribe()
+}
+
+/// Parse a comma-separated topic list; empty means every topic
+pub fn parse_topics(list: &str) -> Result<Vec<EventTopic>, String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|topic| !topic.is_empty())
+        .map(EventTopic::from_str)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_publish_and_filter() {
+        assert_eq!(
+            parse_topics("wallet, mint").unwrap(),
+            vec![EventTopic::Wallet, EventTopic::Mint]
+        );
+        assert!(parse_topics("").unwrap().is_empty());
+        assert!(parse_topics("wallet,bogus").is_err());
+
+        let mut events = subscribe();
+        publish(BackendEvent::BalanceChanged { total_sats: 21 });
+        publish(BackendEvent::MintQuotePaid {
+            mint_url: "https://mint.example".to_string(),
+            quote_id: "q1".to_string(),
+            amount_sats: 21,
+        });
+
+        // Other tests may publish too, so look for ours
+        let mut seen = Vec::new();
+        while seen.len() < 2 {
+            let envelope = events.recv().await.unwrap();
+            match &envelope.event {
+                BackendEvent::BalanceChanged { total_sats: 21 } => {
+                    assert_eq!(envelope.topic, EventTopic::Wallet);
+                    let json = serde_json::to_value(envelope.as_ref()).unwrap();
+                    assert_eq!(json["type"], "balance_changed");
+                    assert_eq!(json["topic"], "wallet");
+                    assert_eq!(json["total_sats"], 21);
+                    seen.push(envelope.seq);
+                }
+                BackendEvent::MintQuotePaid { quote_id, .. } if quote_id == "q1" => {
+                    assert_eq!(envelope.topic, EventTopic::Mint);
+                    seen.push(envelope.seq);
+                }
+                _ => {}
+            }
+        }
+        assert!(seen[0] < seen[1]);
+    }
+}
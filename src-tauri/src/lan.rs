@@ -0,0 +1,701 @@
+//! LAN mode for the relay and the connection server
+//!
+//! By default both only listen on loopback. LAN mode adds TLS listeners on
+//! one network interface, so phones on the same network can use NWC over the
+//! local relay instead of a public one. The certificate is self-signed and
+//! clients pin its SHA-256 fingerprint, which they get together with a short
+//! pairing code, typed in or scanned as a QR code. Redeeming the code at
+//! `POST /lan/pair` creates an NWC connection whose URI points at the relay's
+//! LAN address.
+//!
+//! The settings and the certificate are shared by all profiles, so they live
+//! in the top-level data directory.
+
+use crate::api_clients::constant_time_eq;
+use crate::connection_server::ConnectionServerState;
+use crate::events::{self, BackendEvent};
+use crate::nwc::ConnectionBudget;
+use crate::relay::RelayHandle;
+use crate::storage;
+use crate::supervisor::supervisor;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::post,
+    Json, Router,
+};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::task::JoinHandle;
+use tokio_rustls::{rustls, TlsAcceptor};
+
+const SETTINGS_FILE: &str = "lan.json";
+const CERT_FILE: &str = "lan-cert.der";
+const KEY_FILE: &str = "lan-key.der";
+const PAIRING_CODE_TTL_SECS: u64 = 300;
+const PAIRING_CODE_LEN: usize = 8;
+/// Wrong guesses before a pairing code is discarded
+const MAX_CODE_ATTEMPTS: u32 = 5;
+/// Letters and digits that are hard to confuse when typed
+const CODE_ALPHABET: &[u8] = b"ABCDEFGHJKMNPQRSTUVWXYZ23456789";
+const MAX_DEVICE_NAME_LEN: usize = 64;
+const LAN_SERVICE: &str = "lan_server";
+/// How long requests in flight get to finish when the LAN server stops
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(3);
+
+pub type LanState = Arc<LanService>;
+
+#[derive(Debug, Error)]
+pub enum LanError {
+    #[error("Choose a network interface for LAN mode")]
+    NoInterface,
+    #[error("No IPv4 address found for interface {0}")]
+    UnknownInterface(String),
+    #[error("LAN mode is not running")]
+    NotRunning,
+    #[error("Invalid or expired pairing code")]
+    InvalidCode,
+    #[error("Failed to create TLS certificate: {0}")]
+    Certificate(String),
+    #[error("TLS error: {0}")]
+    Tls(#[from] rustls::Error),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+impl LanError {
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            LanError::InvalidCode => StatusCode::FORBIDDEN,
+            LanError::NotRunning => StatusCode::SERVICE_UNAVAILABLE,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LanSettings {
+    pub enabled: bool,
+    /// Interface name, such as `wlan0`, or one of its IPv4 addresses
+    pub interface: Option<String>,
+}
+
+/// A network interface LAN mode can listen on
+#[derive(Debug, Clone, Serialize)]
+pub struct LanInterface {
+    pub name: String,
+    pub address: IpAddr,
+}
+
+/// IPv4 addresses of the machine's network interfaces, without loopback
+pub fn list_interfaces() -> Vec<LanInterface> {
+    if_addrs::get_if_addrs()
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|iface| !iface.is_loopback() && iface.ip().is_ipv4())
+        .map(|iface| LanInterface {
+            address: iface.ip(),
+            name: iface.name,
+        })
+        .collect()
+}
+
+fn resolve_interface(interface: &str, interfaces: &[LanInterface]) -> Result<IpAddr, LanError> {
+    interfaces
+        .iter()
+        .find(|candidate| candidate.name == interface || candidate.address.to_string() == interface)
+        .map(|candidate| candidate.address)
+        .ok_or_else(|| LanError::UnknownInterface(interface.to_string()))
+}
+
+/// SHA-256 of a DER certificate, as colon-separated hex pairs
+pub fn fingerprint(der: &[u8]) -> String {
+    Sha256::digest(der)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Self-signed certificate served by the LAN listeners
+struct Identity {
+    tls: Arc<rustls::ServerConfig>,
+    fingerprint: String,
+}
+
+impl Identity {
+    /// Load the certificate from `dir`, creating it on first use so the
+    /// fingerprint devices pinned stays valid
+    fn load_or_create(dir: Option<&Path>) -> Result<Self, LanError> {
+        let stored = dir.and_then(|dir| {
+            Some((
+                fs::read(dir.join(CERT_FILE)).ok()?,
+                fs::read(dir.join(KEY_FILE)).ok()?,
+            ))
+        });
+        let (cert, key) = match stored {
+            Some(stored) => stored,
+            None => {
+                let generated = rcgen::generate_simple_self_signed(vec![
+                    "wally.local".to_string(),
+                    "localhost".to_string(),
+                ])
+                .map_err(|e| LanError::Certificate(e.to_string()))?;
+                let cert = generated
+                    .serialize_der()
+                    .map_err(|e| LanError::Certificate(e.to_string()))?;
+                let key = generated.serialize_private_key_der();
+                if let Some(dir) = dir {
+                    fs::create_dir_all(dir)?;
+                    fs::write(dir.join(CERT_FILE), &cert)?;
+                    crate::daemon::write_private(&dir.join(KEY_FILE), &key)?;
+                }
+                log::info!("Created LAN certificate {}", fingerprint(&cert));
+                (cert, key)
+            }
+        };
+
+        let tls = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![rustls::Certificate(cert.clone())],
+                rustls::PrivateKey(key),
+            )?;
+        Ok(Self {
+            tls: Arc::new(tls),
+            fingerprint: fingerprint(&cert),
+        })
+    }
+}
+
+/// A code shown to the user, to be entered on the device being paired
+#[derive(Debug, Clone, Serialize)]
+pub struct LanPairingCode {
+    /// The code as shown, such as `ABCD-EFGH`
+    pub code: String,
+    pub expires_at: u64,
+    /// Address and certificate fingerprint along with the code, for a QR code
+    pub uri: String,
+    pub server_url: String,
+    pub fingerprint: String,
+}
+
+struct ActiveCode {
+    code: String,
+    expires_at: u64,
+    attempts: u32,
+}
+
+fn generate_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..PAIRING_CODE_LEN)
+        .map(|_| CODE_ALPHABET[rng.gen_range(0..CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Codes are case-insensitive and may be typed with separators
+fn normalize_code(code: &str) -> String {
+    code.chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn display_code(code: &str) -> String {
+    let (first, second) = code.split_at(code.len() / 2);
+    format!("{}-{}", first, second)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LanStatus {
+    pub enabled: bool,
+    pub interface: Option<String>,
+    /// Address the listeners are bound to, while running
+    pub address: Option<IpAddr>,
+    pub relay_url: Option<String>,
+    pub server_url: Option<String>,
+    pub fingerprint: Option<String>,
+    /// Why LAN mode is not running although enabled
+    pub error: Option<String>,
+    pub interfaces: Vec<LanInterface>,
+}
+
+/// What the LAN listeners serve
+struct Targets {
+    router: Router,
+    relay: RelayHandle,
+}
+
+struct Listeners {
+    address: IpAddr,
+    server: axum_server::Handle,
+    relay: JoinHandle<()>,
+}
+
+pub struct LanService {
+    dir: Option<PathBuf>,
+    relay_port: u16,
+    connection_port: u16,
+    settings: Mutex<LanSettings>,
+    identity: Mutex<Option<Arc<Identity>>>,
+    targets: Mutex<Option<Targets>>,
+    listeners: Mutex<Option<Listeners>>,
+    code: Mutex<Option<ActiveCode>>,
+    last_error: Mutex<Option<String>>,
+}
+
+impl Default for LanService {
+    /// LAN mode off, with nothing stored
+    fn default() -> Self {
+        Self::with_settings(
+            None,
+            crate::relay::DEFAULT_RELAY_PORT,
+            crate::connection_server::DEFAULT_CONNECTION_PORT,
+            LanSettings::default(),
+        )
+    }
+}
+
+fn now_secs() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+impl LanService {
+    /// Load the stored settings; LAN mode starts once `attach` is called.
+    /// Damaged settings are moved aside and leave LAN mode off.
+    pub fn load(relay_port: u16, connection_port: u16) -> Self {
+        let dir = crate::profiles::root_dir().ok();
+        let settings = match dir
+            .as_ref()
+            .map(|dir| storage::read_json(&dir.join(SETTINGS_FILE)))
+        {
+            Some(Ok(settings)) => settings.unwrap_or_default(),
+            Some(Err(e)) => {
+                log::error!("Failed to load LAN settings: {}", e);
+                LanSettings::default()
+            }
+            None => LanSettings::default(),
+        };
+
+        Self::with_settings(dir, relay_port, connection_port, settings)
+    }
+
+    fn with_settings(
+        dir: Option<PathBuf>,
+        relay_port: u16,
+        connection_port: u16,
+        settings: LanSettings,
+    ) -> Self {
+        Self {
+            dir,
+            relay_port,
+            connection_port,
+            settings: Mutex::new(settings),
+            identity: Mutex::new(None),
+            targets: Mutex::new(None),
+            listeners: Mutex::new(None),
+            code: Mutex::new(None),
+            last_error: Mutex::new(None),
+        }
+    }
+
+    /// Use `settings` instead of the stored ones until they are changed,
+    /// as the daemon does with its config file
+    pub fn override_settings(self, settings: LanSettings) -> Self {
+        *self.settings.lock().expect("lan settings poisoned") = settings;
+        self
+    }
+
+    pub fn settings(&self) -> LanSettings {
+        self.settings.lock().expect("lan settings poisoned").clone()
+    }
+
+    /// Serve `router` and `relay` on the LAN whenever LAN mode is on
+    pub fn attach(&self, router: Router, relay: RelayHandle) {
+        *self.targets.lock().expect("lan targets poisoned") = Some(Targets { router, relay });
+        if let Err(e) = self.apply() {
+            log::error!("Failed to start LAN mode: {}", e);
+        }
+    }
+
+    /// Change and store the settings, restarting the listeners
+    pub fn update(&self, settings: LanSettings) -> Result<LanStatus, LanError> {
+        if settings.enabled {
+            let interface = settings.interface.as_deref().ok_or(LanError::NoInterface)?;
+            resolve_interface(interface, &list_interfaces())?;
+        }
+        if let Some(dir) = &self.dir {
+            storage::write_atomic(
+                &dir.join(SETTINGS_FILE),
+                serde_json::to_vec_pretty(&settings)?,
+            )?;
+        }
+        *self.settings.lock().expect("lan settings poisoned") = settings;
+        self.apply()?;
+        Ok(self.status())
+    }
+
+    /// Start or stop the listeners to match the settings
+    fn apply(&self) -> Result<(), LanError> {
+        self.stop();
+        let settings = self.settings();
+        let result = if settings.enabled {
+            self.start(&settings)
+        } else {
+            Ok(())
+        };
+        *self.last_error.lock().expect("lan error poisoned") =
+            result.as_ref().err().map(|e| e.to_string());
+        result
+    }
+
+    fn identity(&self) -> Result<Arc<Identity>, LanError> {
+        let mut identity = self.identity.lock().expect("lan identity poisoned");
+        if let Some(identity) = identity.as_ref() {
+            return Ok(identity.clone());
+        }
+        let loaded = Arc::new(Identity::load_or_create(self.dir.as_deref())?);
+        *identity = Some(loaded.clone());
+        Ok(loaded)
+    }
+
+    fn start(&self, settings: &LanSettings) -> Result<(), LanError> {
+        let interface = settings.interface.as_deref().ok_or(LanError::NoInterface)?;
+        let address = resolve_interface(interface, &list_interfaces())?;
+        let targets = self.targets.lock().expect("lan targets poisoned");
+        let Some(targets) = targets.as_ref() else {
+            // Not attached yet; `attach` starts the listeners
+            return Ok(());
+        };
+        let identity = self.identity()?;
+
+        let relay = targets.relay.serve_tls(
+            SocketAddr::new(address, self.relay_port),
+            TlsAcceptor::from(identity.tls.clone()),
+        )?;
+        let listener =
+            match std::net::TcpListener::bind(SocketAddr::new(address, self.connection_port))
+                .and_then(|listener| listener.set_nonblocking(true).map(|_| listener))
+            {
+                Ok(listener) => listener,
+                Err(e) => {
+                    relay.abort();
+                    return Err(e.into());
+                }
+            };
+
+        let server = axum_server::Handle::new();
+        let config = axum_server::tls_rustls::RustlsConfig::from_config(identity.tls.clone());
+        let router = targets.router.clone();
+        let handle = server.clone();
+        // Each run serves a clone of the listener, so a restart keeps the
+        // port. On shutdown, requests in flight are answered first.
+        supervisor().spawn(LAN_SERVICE, move |mut shutdown| {
+            let serve = listener.try_clone().map(|listener| {
+                axum_server::from_tcp_rustls(listener, config.clone())
+                    .handle(handle.clone())
+                    .serve(router.clone().into_make_service())
+            });
+            let handle = handle.clone();
+            async move {
+                let serve = serve.map_err(|e| e.to_string())?;
+                tokio::pin!(serve);
+                let result = tokio::select! {
+                    result = &mut serve => result,
+                    _ = shutdown.wait() => {
+                        handle.graceful_shutdown(Some(SHUTDOWN_GRACE));
+                        serve.await
+                    }
+                };
+                result.map_err(|e| format!("LAN connection server encountered an error: {}", e))
+            }
+        });
+
+        log::info!(
+            "LAN mode on {} ({}): relay wss://{}:{}, server https://{}:{}, certificate {}",
+            interface,
+            address,
+            address,
+            self.relay_port,
+            address,
+            self.connection_port,
+            identity.fingerprint
+        );
+        *self.listeners.lock().expect("lan listeners poisoned") = Some(Listeners {
+            address,
+            server,
+            relay,
+        });
+        Ok(())
+    }
+
+    /// Stop the listeners; connections already open are left to finish
+    pub fn stop(&self) {
+        *self.code.lock().expect("lan code poisoned") = None;
+        if let Some(listeners) = self
+            .listeners
+            .lock()
+            .expect("lan listeners poisoned")
+            .take()
+        {
+            listeners.relay.abort();
+            listeners.server.graceful_shutdown(Some(SHUTDOWN_GRACE));
+            supervisor().stop(LAN_SERVICE);
+            log::info!("LAN mode stopped on {}", listeners.address);
+        }
+    }
+
+    fn address(&self) -> Option<IpAddr> {
+        self.listeners
+            .lock()
+            .expect("lan listeners poisoned")
+            .as_ref()
+            .map(|listeners| listeners.address)
+    }
+
+    fn relay_url(&self, address: IpAddr) -> String {
+        format!("wss://{}:{}", address, self.relay_port)
+    }
+
+    fn server_url(&self, address: IpAddr) -> String {
+        format!("https://{}:{}", address, self.connection_port)
+    }
+
+    pub fn status(&self) -> LanStatus {
+        let settings = self.settings();
+        let address = self.address();
+        LanStatus {
+            enabled: settings.enabled,
+            interface: settings.interface,
+            address,
+            relay_url: address.map(|address| self.relay_url(address)),
+            server_url: address.map(|address| self.server_url(address)),
+            fingerprint: address
+                .and_then(|_| self.identity().ok())
+                .map(|identity| identity.fingerprint.clone()),
+            error: self.last_error.lock().expect("lan error poisoned").clone(),
+            interfaces: list_interfaces(),
+        }
+    }
+
+    /// Show a new pairing code, replacing any earlier one
+    pub fn new_pairing_code(&self) -> Result<LanPairingCode, LanError> {
+        let address = self.address().ok_or(LanError::NotRunning)?;
+        let identity = self.identity()?;
+        let code = generate_code();
+        let expires_at = now_secs() + PAIRING_CODE_TTL_SECS;
+        *self.code.lock().expect("lan code poisoned") = Some(ActiveCode {
+            code: code.clone(),
+            expires_at,
+            attempts: 0,
+        });
+
+        Ok(LanPairingCode {
+            code: display_code(&code),
+            expires_at,
+            uri: format!(
+                "wally+lan://{}:{}?code={}&fingerprint={}",
+                address, self.connection_port, code, identity.fingerprint
+            ),
+            server_url: self.server_url(address),
+            fingerprint: identity.fingerprint.clone(),
+        })
+    }
+
+    /// Check a code entered on a device and return the LAN relay URL. Each
+    /// code works once.
+    fn redeem_code(&self, code: &str) -> Result<String, LanError> {
+        let address = self.address().ok_or(LanError::NotRunning)?;
+        let mut active = self.code.lock().expect("lan code poisoned");
+        let Some(current) = active.as_mut() else {
+            return Err(LanError::InvalidCode);
+        };
+        if current.expires_at <= now_secs() {
+            *active = None;
+            return Err(LanError::InvalidCode);
+        }
+        if constant_time_eq(normalize_code(code).as_bytes(), current.code.as_bytes()) {
+            *active = None;
+            return Ok(self.relay_url(address));
+        }
+
+        current.attempts += 1;
+        if current.attempts >= MAX_CODE_ATTEMPTS {
+            log::warn!(
+                "Discarding LAN pairing code after {} wrong guesses",
+                current.attempts
+            );
+            *active = None;
+        }
+        Err(LanError::InvalidCode)
+    }
+}
+
+fn failure(status: StatusCode, message: impl Into<String>) -> Response {
+    (
+        status,
+        Json(json!({
+            "success": false,
+            "error": message.into()
+        })),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub(crate) struct RedeemBody {
+    code: String,
+    /// Shown as the connection's name
+    #[serde(default)]
+    name: Option<String>,
+}
+
+/// Routes served on the LAN listener. Only pairing is reachable from the
+/// network; the rest of the connection server stays on loopback.
+pub(crate) fn router(state: ConnectionServerState) -> Router {
+    Router::new()
+        .route("/lan/pair", post(redeem_pairing_code))
+        .with_state(state)
+}
+
+/// Handler for POST /lan/pair - Exchange a pairing code for an NWC
+/// connection on the LAN relay
+pub(crate) async fn redeem_pairing_code(
+    State(state): State<ConnectionServerState>,
+    Json(body): Json<RedeemBody>,
+) -> Response {
+    if state.app_lock.is_locked() {
+        return failure(StatusCode::LOCKED, "Wallet is locked");
+    }
+    let relay_url = match state.lan.redeem_code(&body.code) {
+        Ok(relay_url) => relay_url,
+        Err(e) => return failure(e.status(), e.to_string()),
+    };
+
+    let name: String = body
+        .name
+        .as_deref()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .unwrap_or("LAN device")
+        .chars()
+        .take(MAX_DEVICE_NAME_LEN)
+        .collect();
+
+    let nwc = state.nwc.lock().await;
+    let Some(nwc) = nwc.as_ref() else {
+        return failure(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "NWC service not initialized",
+        );
+    };
+    match nwc
+        .create_lan_connection(Some(&name), ConnectionBudget::default(), &relay_url)
+        .await
+    {
+        Ok((connection, uri)) => {
+            let connection_pubkey = connection.keys.public_key().to_hex();
+            log::info!("Paired LAN device {} as {}", name, connection_pubkey);
+            events::publish(BackendEvent::LanDevicePaired {
+                name,
+                connection_pubkey,
+            });
+            Json(json!({
+                "success": true,
+                "nwc_uri": uri,
+                "relay_url": relay_url
+            }))
+            .into_response()
+        }
+        Err(e) => failure(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()),
+    }
+}
+
+#[tauri::command]
+pub async fn get_lan_status(
+    lan: tauri::State<'_, LanState>,
+    lock: tauri::State<'_, crate::app_lock::AppLockState>,
+) -> Result<LanStatus, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
+    Ok(lan.status())
+}
+
+#[tauri::command]
+pub async fn set_lan_mode(
+    enabled: bool,
+    interface: Option<String>,
+    lan: tauri::State<'_, LanState>,
+    lock: tauri::State<'_, crate::app_lock::AppLockState>,
+) -> Result<LanStatus, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    lan.update(LanSettings { enabled, interface })
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn create_lan_pairing_code(
+    lan: tauri::State<'_, LanState>,
+    lock: tauri::State<'_, crate::app_lock::AppLockState>,
+) -> Result<LanPairingCode, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    lan.new_pairing_code().map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_interface() {
+        let interfaces = vec![LanInterface {
+            name: "wlan0".to_string(),
+            address: "192.168.1.20".parse().unwrap(),
+        }];
+        let address: IpAddr = "192.168.1.20".parse().unwrap();
+        assert_eq!(resolve_interface("wlan0", &interfaces).unwrap(), address);
+        assert_eq!(
+            resolve_interface("192.168.1.20", &interfaces).unwrap(),
+            address
+        );
+        assert!(matches!(
+            resolve_interface("eth1", &interfaces),
+            Err(LanError::UnknownInterface(_))
+        ));
+    }
+
+    #[test]
+    fn test_pairing_codes() {
+        let code = generate_code();
+        assert_eq!(code.len(), PAIRING_CODE_LEN);
+        assert!(code.bytes().all(|b| CODE_ALPHABET.contains(&b)));
+        assert_eq!(normalize_code(&display_code(&code).to_lowercase()), code);
+        assert_eq!(display_code("ABCDEFGH"), "ABCD-EFGH");
+
+        // Codes are only redeemable while LAN mode runs
+        let lan = LanService::default();
+        assert!(matches!(lan.new_pairing_code(), Err(LanError::NotRunning)));
+        assert!(matches!(lan.redeem_code(&code), Err(LanError::NotRunning)));
+    }
+
+    #[test]
+    fn test_certificate_is_kept() {
+        let dir = std::env::temp_dir().join(format!("wally-lan-{}", uuid::Uuid::new_v4()));
+        let created = Identity::load_or_create(Some(&dir)).unwrap();
+        let loaded = Identity::load_or_create(Some(&dir)).unwrap();
+        assert_eq!(created.fingerprint, loaded.fingerprint);
+        assert_eq!(created.fingerprint.split(':').count(), 32);
+        let _ = fs::remove_dir_all(dir);
+    }
+}
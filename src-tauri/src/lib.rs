@@ -13,7 +13,7 @@ use tauri::{
     tray::{MouseButtonState, TrayIconBuilder, TrayIconEvent},
     ActivationPolicy, LogicalPosition, PhysicalPosition,
 };
-use tauri::{Manager, State};
+use tauri::{Emitter, Manager, State};
 #[cfg(target_os = "macos")]
 use tauri_nspanel::{ManagerExt, WebviewWindowExt};
 use tauri_plugin_androidwifi::{AndroidwifiExt, Empty, GetMacAddressPayload};
@@ -21,6 +21,7 @@ use tauri_plugin_log::{Target, TargetKind};
 use tokio::sync::Mutex;
 
 mod tollgate;
+use tollgate::approvals::{ApprovalBroker, ApprovalsState};
 use tollgate::session::SessionStatus;
 use tollgate::TollGateService;
 
@@ -30,16 +31,40 @@ type TollGateState = Arc<Mutex<TollGateService>>;
 // Global state for the NWC service
 type NwcState = Arc<Mutex<Option<NostrWalletConnect>>>;
 
+/// Name of the NWC event loop under the supervisor
+const NWC_SERVICE: &str = "nwc";
+
+mod api_clients;
+mod app_lock;
+mod backend;
+mod backup;
+mod cli;
+mod config;
 mod connection_server;
+mod daemon;
+mod events;
+mod lan;
+mod lnurl;
+mod local_api;
+mod metrics;
 mod nostr_providers;
 mod nwc;
 mod nwc_storage;
+mod pairing;
+mod payment_input;
+mod profiles;
 mod proxy;
 mod relay;
 mod routstr;
+mod runtime;
+mod settings_sync;
+mod storage;
+mod supervisor;
 mod wallet;
 
 use nwc::{BudgetRenewalPeriod, NostrWalletConnect};
+pub use cli::run_cli;
+pub use daemon::run_daemon;
 use wallet::*;
 
 #[tauri::command]
@@ -78,8 +103,9 @@ async fn force_session_renewal(
     tollgate_pubkey: String,
     state: State<'_, TollGateState>,
 ) -> Result<(), String> {
-    let service = state.lock().await;
-    service
+    // Purchases can wait for approval, so the service lock is not held
+    let sessions = state.lock().await.sessions();
+    sessions
         .force_renewal(&tollgate_pubkey)
         .await
         .map_err(|e| e.to_string())
@@ -91,8 +117,8 @@ async fn handle_network_connected(
     mac_address: String,
     state: State<'_, TollGateState>,
 ) -> Result<(), String> {
-    let service = state.lock().await;
-    service
+    let sessions = state.lock().await.sessions();
+    sessions
         .handle_network_connected(gateway_ip, mac_address)
         .await
         .map_err(|e| e.to_string())
@@ -169,29 +195,27 @@ async fn get_active_sessions(
         .get_active_sessions()
         .await
         .map_err(|e| e.to_string())?;
-    let session_data: Vec<serde_json::Value> = sessions
-        .iter()
-        .map(|session| {
-            serde_json::json!({
-                "id": session.id,
-                "tollgate_pubkey": session.tollgate_pubkey,
-                "gateway_ip": session.gateway_ip,
-                "status": match session.status {
-                    SessionStatus::Initializing => "initializing",
-                    SessionStatus::Active => "active",
-                    SessionStatus::Renewing => "renewing",
-                    SessionStatus::Expired => "expired",
-                    SessionStatus::Error(_) => "error"
-                },
-                "usage_percentage": session.usage_percentage,
-                "remaining_time_seconds": session.remaining_time_seconds,
-                "remaining_data_bytes": session.remaining_data_bytes,
-                "total_spent": session.total_spent
-            })
-        })
-        .collect();
+    Ok(sessions.iter().map(session_json).collect())
+}
 
-    Ok(session_data)
+/// Session summary shared by the app and the local API
+pub(crate) fn session_json(session: &tollgate::service::SessionInfo) -> serde_json::Value {
+    serde_json::json!({
+        "id": session.id,
+        "tollgate_pubkey": session.tollgate_pubkey,
+        "gateway_ip": session.gateway_ip,
+        "status": match session.status {
+            SessionStatus::Initializing => "initializing",
+            SessionStatus::Active => "active",
+            SessionStatus::Renewing => "renewing",
+            SessionStatus::Expired => "expired",
+            SessionStatus::Error(_) => "error"
+        },
+        "usage_percentage": session.usage_percentage,
+        "remaining_time_seconds": session.remaining_time_seconds,
+        "remaining_data_bytes": session.remaining_data_bytes,
+        "total_spent": session.total_spent
+    })
 }
 
 #[tauri::command]
@@ -201,31 +225,33 @@ async fn nwc_list_connections(
     let nwc_lock = nwc_state.lock().await;
     let nwc = nwc_lock.as_ref().ok_or("NWC service not initialized")?;
 
-    let connections = nwc.get_connections().await;
-    let connection_data: Vec<serde_json::Value> = connections
+    Ok(nwc
+        .get_connections()
+        .await
         .iter()
-        .map(|conn| {
-            serde_json::json!({
-                "pubkey": conn.keys.public_key().to_string(),
-                "pubkey_hex": conn.keys.public_key().to_hex(),
-                "budget_msats": conn.budget.total_budget_msats,
-                "used_budget_msats": conn.budget.used_budget_msats,
-                "renewal_period": match conn.budget.renewal_period {
-                    BudgetRenewalPeriod::Daily => "daily",
-                    BudgetRenewalPeriod::Weekly => "weekly",
-                    BudgetRenewalPeriod::Monthly => "monthly",
-                    BudgetRenewalPeriod::Yearly => "yearly",
-                    BudgetRenewalPeriod::Never => "never",
-                },
-                "name": conn.name.clone(),
-            })
-        })
-        .collect();
+        .map(connection_json)
+        .collect())
+}
 
-    Ok(connection_data)
+/// Connection summary shared by the app and the local API
+pub(crate) fn connection_json(conn: &nwc::WalletConnection) -> serde_json::Value {
+    serde_json::json!({
+        "pubkey": conn.keys.public_key().to_string(),
+        "pubkey_hex": conn.keys.public_key().to_hex(),
+        "budget_msats": conn.budget.total_budget_msats,
+        "used_budget_msats": conn.budget.used_budget_msats,
+        "renewal_period": match conn.budget.renewal_period {
+            BudgetRenewalPeriod::Daily => "daily",
+            BudgetRenewalPeriod::Weekly => "weekly",
+            BudgetRenewalPeriod::Monthly => "monthly",
+            BudgetRenewalPeriod::Yearly => "yearly",
+            BudgetRenewalPeriod::Never => "never",
+        },
+        "name": conn.name.clone(),
+    })
 }
 
-fn parse_budget_period(value: &str) -> Result<BudgetRenewalPeriod, String> {
+pub(crate) fn parse_budget_period(value: &str) -> Result<BudgetRenewalPeriod, String> {
     match value {
         "daily" => Ok(BudgetRenewalPeriod::Daily),
         "weekly" => Ok(BudgetRenewalPeriod::Weekly),
@@ -242,7 +268,9 @@ async fn nwc_update_connection_budget(
     budget_sats: u64,
     renewal_period: String,
     nwc_state: State<'_, NwcState>,
+    lock: State<'_, app_lock::AppLockState>,
 ) -> Result<serde_json::Value, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
     let period = parse_budget_period(&renewal_period)?;
 
     let nwc_lock = nwc_state.lock().await;
@@ -292,7 +320,9 @@ async fn nwc_get_service_pubkey(nwc_state: State<'_, NwcState>) -> Result<String
 async fn nwc_remove_connection(
     pubkey: String,
     nwc_state: State<'_, NwcState>,
+    lock: State<'_, app_lock::AppLockState>,
 ) -> Result<(), String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
     println!("Rust: removing NWC connection {pubkey}");
     let nwc_lock = nwc_state.lock().await;
     let nwc = nwc_lock.as_ref().ok_or("NWC service not initialized")?;
@@ -306,7 +336,9 @@ async fn nwc_remove_connection(
 async fn nwc_create_standard_connection(
     use_local_relay: Option<bool>,
     nwc_state: State<'_, NwcState>,
+    lock: State<'_, app_lock::AppLockState>,
 ) -> Result<String, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
     let nwc_lock = nwc_state.lock().await;
     let nwc = nwc_lock.as_ref().ok_or("NWC service not initialized")?;
 
@@ -315,6 +347,175 @@ async fn nwc_create_standard_connection(
         .map_err(|e| e.to_string())
 }
 
+/// Create the NWC service for the wallet in `service_state`
+async fn create_nwc_service(
+    service_state: &TollGateState,
+    app_lock: &app_lock::AppLockState,
+) -> Option<NostrWalletConnect> {
+    // Generate or load NWC service key (using wallet's secret key)
+    let service = service_state.lock().await;
+    let wallet_keys = service.get_nwc_service_keys().await;
+
+    // Use wallet's secret key for NWC service. Wallets created
+    // before NIP-06 keep their legacy key here so existing
+    // connection URIs stay valid.
+    let nwc_secret = wallet_keys.secret_key().clone();
+    drop(service); // Release lock before creating NWC
+
+    match NostrWalletConnect::new(nwc_secret, service_state.clone()).await {
+        Ok(nwc) => {
+            log::info!("NWC service initialized");
+            Some(nwc.with_app_lock(app_lock.clone()))
+        }
+        Err(e) => {
+            log::error!("Failed to initialize NWC service: {}", e);
+            None
+        }
+    }
+}
+
+/// Run the NWC event loop for the service in `nwc_state` under the
+/// supervisor, replacing the loop of a previous service
+fn spawn_nwc_loop(nwc_state: NwcState) {
+    supervisor::supervisor().spawn(NWC_SERVICE, move |shutdown| {
+        run_nwc_loop(nwc_state.clone(), shutdown)
+    });
+}
+
+async fn run_nwc_loop(
+    nwc_state: NwcState,
+    mut shutdown: supervisor::Shutdown,
+) -> Result<(), String> {
+    log::info!("=== Starting NWC event processing task ===");
+
+    // Clone the NWC service out of the Arc<Mutex<>> to avoid holding the lock
+    let nwc_service = {
+        let nwc_lock = nwc_state.lock().await;
+        nwc_lock.as_ref().cloned()
+    }; // Lock is released here
+
+    let Some(nwc) = nwc_service else {
+        log::warn!("NWC service not initialized, skipping event processing");
+        return Ok(());
+    };
+
+    // Start the NWC service (connect to relay)
+    log::info!("Starting NWC service and connecting to relay...");
+    nwc.start()
+        .await
+        .map_err(|e| format!("Failed to start NWC service: {}", e))?;
+    log::info!("✓ NWC service started and connected to wss://nostrue.com");
+
+    // Process events in a loop
+    log::info!("Starting NWC event processing loop...");
+    tokio::select! {
+        result = nwc.process_events_loop() => match result {
+            Ok(()) => Err("NWC event processing loop ended (should run indefinitely)".to_string()),
+            Err(e) => Err(format!("NWC event processing loop ended with error: {}", e)),
+        },
+        _ = shutdown.wait() => {
+            nwc.stop().await;
+            Ok(())
+        }
+    }
+}
+
+/// Forward backend events to the frontend, as `backend-event` and under
+/// the older names for connection requests and payment approvals
+fn spawn_event_forwarder(app_handle: tauri::AppHandle) {
+    let mut receiver = events::subscribe();
+    tokio::spawn(async move {
+        loop {
+            let envelope = match receiver.recv().await {
+                Ok(envelope) => envelope,
+                Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                    log::warn!("Event forwarder lagged by {} events", n);
+                    continue;
+                }
+                Err(tokio::sync::broadcast::error::RecvError::Closed) => break,
+            };
+
+            let legacy = match &envelope.event {
+                events::BackendEvent::ConnectionRequested { request } => {
+                    connection_server::show_main_window(&app_handle);
+                    app_handle.emit("nwc-connection-request", request)
+                }
+                events::BackendEvent::SpendApprovalRequested { approval } => {
+                    connection_server::show_main_window(&app_handle);
+                    app_handle.emit("spend-approval-request", approval)
+                }
+                events::BackendEvent::PairingRequested { .. } => {
+                    connection_server::show_main_window(&app_handle);
+                    Ok(())
+                }
+                events::BackendEvent::SpendApprovalResolved { id, outcome } => app_handle.emit(
+                    "spend-approval-resolved",
+                    serde_json::json!({ "id": id, "outcome": outcome }),
+                ),
+                _ => Ok(()),
+            };
+            if let Err(e) = legacy.and_then(|_| app_handle.emit("backend-event", &*envelope)) {
+                log::error!("Failed to emit backend event {}: {}", envelope.seq, e);
+            }
+        }
+    });
+}
+
+/// Switch to another profile, rebuilding every service on its data directory
+#[tauri::command]
+async fn switch_wallet_profile(
+    name: String,
+    state: State<'_, TollGateState>,
+    nwc_state: State<'_, NwcState>,
+    routstr_state: State<'_, routstr::RoutstrState>,
+    approvals: State<'_, ApprovalsState>,
+    lock: State<'_, app_lock::AppLockState>,
+) -> Result<profiles::ProfileList, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    if name == profiles::active_profile() {
+        return profiles::list_profiles().map_err(|e| e.to_string());
+    }
+
+    let previous = profiles::active_profile();
+    profiles::set_active_profile(&name)?;
+
+    // Open and start the new wallet before tearing anything down, so a
+    // failure leaves the current profile running
+    let started = match TollGateService::new(approvals.inner().clone()).await {
+        Ok(mut service) => service.start_background_service().await.map(|_| service),
+        Err(e) => Err(e),
+    };
+    let service = match started {
+        Ok(service) => service,
+        Err(e) => {
+            log::error!("Failed to open profile {}: {}", name, e);
+            profiles::set_active_profile(&previous)?;
+            return Err(e.to_string());
+        }
+    };
+
+    // Tear down services bound to the current profile
+    supervisor::supervisor().stop(NWC_SERVICE);
+    if let Some(nwc) = nwc_state.lock().await.take() {
+        nwc.stop().await;
+    }
+    routstr_state.lock().await.stop_auto_update();
+    // Payments of the old profile that are still waiting will not go ahead
+    approvals.reject_all();
+
+    let mut previous_service = std::mem::replace(&mut *state.lock().await, service);
+    previous_service.shutdown().await;
+
+    *nwc_state.lock().await = create_nwc_service(state.inner(), lock.inner()).await;
+    spawn_nwc_loop(nwc_state.inner().clone());
+
+    *routstr_state.lock().await = routstr::RoutstrService::new();
+    routstr::initialize_routstr_auto_update(routstr_state.inner().clone()).await;
+
+    log::info!("Switched to profile {}", name);
+    profiles::list_profiles().map_err(|e| e.to_string())
+}
+
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
     let mut builder = tauri::Builder::default();
@@ -323,141 +524,39 @@ pub fn run() {
         // Initialize TollGate service and runtime
         let rt = Arc::new(tokio::runtime::Runtime::new().unwrap());
 
-        // Start local Nostr relay before NWC service
+        // Forward backend events before anything can publish them
         {
-            let rt_clone = rt.clone();
-            rt_clone.block_on(async {
-                log::info!("=== Starting local Nostr relay ===");
-                if let Err(e) = relay::start_relay_server(relay::DEFAULT_RELAY_PORT).await {
-                    log::error!("Failed to start local Nostr relay: {}", e);
-                } else {
-                    log::info!(
-                        "Local Nostr relay started on port {}",
-                        relay::DEFAULT_RELAY_PORT
-                    );
-                    // Give the relay a moment to fully initialize
-                    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
-                    log::info!("=== Relay ready ===");
-                }
-            });
+            let _guard = rt.enter();
+            spawn_event_forwarder(app.handle().clone());
         }
 
-        let service_arc = {
-            let rt_clone = rt.clone();
-            rt_clone.block_on(async {
-                let mut service = TollGateService::new()
-                    .await
-                    .expect("Failed to create TollGate service");
-
-                service
-                    .start_background_service()
-                    .await
-                    .expect("Failed to start background service");
-
-                Arc::new(Mutex::new(service))
-            })
-        };
-
-        // Initialize NWC service
-        let nwc_arc = {
-            let rt_clone = rt.clone();
-            let service_clone = service_arc.clone();
-            rt_clone.block_on(async {
-                // Generate or load NWC service key (using wallet's secret key)
-                let service = service_clone.lock().await;
-                let wallet_keys = service.get_wallet_keys().await;
-
-                // Use wallet's secret key for NWC service
-                let nwc_secret = wallet_keys.secret_key().clone();
-                drop(service); // Release lock before creating NWC
-
-                match NostrWalletConnect::new(nwc_secret, service_clone.clone()).await {
-                    Ok(nwc) => {
-                        log::info!("NWC service initialized");
-                        Arc::new(Mutex::new(Some(nwc)))
-                    }
-                    Err(e) => {
-                        log::error!("Failed to initialize NWC service: {}", e);
-                        Arc::new(Mutex::new(None))
-                    }
-                }
-            })
-        };
-
-        // Start NWC event processing loop
-        let nwc_clone = nwc_arc.clone();
-        let rt_clone = rt.clone();
-        rt_clone.spawn(async move {
-            log::info!("=== Starting NWC event processing task ===");
-
-            // Clone the NWC service out of the Arc<Mutex<>> to avoid holding the lock
-            let nwc_service = {
-                let nwc_lock = nwc_clone.lock().await;
-                nwc_lock.as_ref().cloned()
-            }; // Lock is released here
-
-            if let Some(nwc) = nwc_service {
-                // Start the NWC service (connect to relay)
-                log::info!("Starting NWC service and connecting to relay...");
-                if let Err(e) = nwc.start().await {
-                    log::error!("Failed to start NWC service: {}", e);
-                    return;
-                }
-                log::info!("✓ NWC service started and connected to wss://nostrue.com");
-
-                // Process events in a loop
-                log::info!("Starting NWC event processing loop...");
-                if let Err(e) = nwc.process_events_loop().await {
-                    log::error!("NWC event processing loop ended with error: {}", e);
-                } else {
-                    log::warn!("NWC event processing loop ended (should run indefinitely)");
-                }
-            } else {
-                log::warn!("NWC service not initialized, skipping event processing");
-            }
-        });
+        // Payment approval prompts, shared across profile switches
+        let approvals: ApprovalsState = Arc::new(ApprovalBroker::from_config());
 
-        // Start connection server to handle wallet connection requests
-        let _connection_service = service_arc.clone();
-        let connection_app_handle = app.handle().clone();
-        let pending_connections =
-            Arc::new(tokio::sync::Mutex::new(std::collections::HashMap::new()));
-        let pending_connections_for_server = pending_connections.clone();
-        let rt_clone = rt.clone();
-        rt_clone.spawn(async move {
-            if let Err(e) = connection_server::start_connection_server(
-                connection_app_handle,
-                pending_connections_for_server,
-                connection_server::DEFAULT_CONNECTION_PORT,
-            )
-            .await
-            {
-                log::error!("Failed to start connection server: {}", e);
-            } else {
-                log::info!(
-                    "Connection server started successfully on port {}",
-                    connection_server::DEFAULT_CONNECTION_PORT
-                );
-            }
-        });
+        let backend = rt.block_on(backend::Backend::start(
+            backend::BackendOptions::default(),
+            approvals,
+        ))?;
 
-        // Initialize Routstr service
-        let routstr_arc = Arc::new(Mutex::new(routstr::RoutstrService::new()));
-
-        // Initialize auto-update for Routstr if already connected
-        let routstr_clone = routstr_arc.clone();
-        let rt_clone = rt.clone();
-        rt_clone.spawn(async move {
-            routstr::initialize_routstr_auto_update(routstr_clone).await;
-        });
-
-        app.manage(service_arc);
-        app.manage(nwc_arc);
-        app.manage(routstr_arc);
+        // Kept whole to shut the services down on exit
+        app.manage(backend.clone());
+        app.manage(backend.tollgate);
+        app.manage(backend.nwc);
+        app.manage(backend.routstr);
         app.manage(rt.clone());
-        app.manage(pending_connections);
+        app.manage(backend.pending_connections);
+        app.manage(backend.app_lock);
+        app.manage(backend.approvals);
+        app.manage(backend.api_clients);
+        app.manage(backend.pairings);
+        app.manage(backend.lan);
+        let server_url = backend.runtime.server_url();
+        app.manage(backend.runtime);
 
-        rt.spawn(start_provider_monitoring());
+        {
+            let _guard = rt.enter();
+            supervisor::supervisor().spawn("provider_monitoring", monitor_providers);
+        }
 
         #[cfg(target_os = "macos")]
         {
@@ -472,10 +571,7 @@ pub fn run() {
         }
 
         log::info!("TollGate service initialized");
-        log::info!(
-            "Connection server available at http://127.0.0.1:{}",
-            connection_server::DEFAULT_CONNECTION_PORT
-        );
+        log::info!("Connection server available at {}", server_url);
         Ok(())
     });
 
@@ -486,21 +582,22 @@ pub fn run() {
             .map_err(|e| e.to_string())
     }
 
-    async fn start_provider_monitoring() {
-        tokio::spawn(async {
-            let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600));
-            loop {
-                interval.tick().await;
-                match nostr_providers::discover_providers().await {
-                    Ok(providers) => {
-                        log::info!("Updated provider list: {} providers found", providers.len());
-                    }
-                    Err(e) => {
-                        log::warn!("Failed to update providers: {}", e);
-                    }
+    async fn monitor_providers(mut shutdown: supervisor::Shutdown) -> Result<(), String> {
+        let mut interval = tokio::time::interval(tokio::time::Duration::from_secs(3600));
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.wait() => return Ok(()),
+            }
+            match nostr_providers::discover_providers().await {
+                Ok(providers) => {
+                    log::info!("Updated provider list: {} providers found", providers.len());
+                }
+                Err(e) => {
+                    log::warn!("Failed to update providers: {}", e);
                 }
             }
-        });
+        }
     }
 
     #[cfg(target_os = "macos")]
@@ -562,7 +659,35 @@ pub fn run() {
             get_wallet_summary,
             list_wallet_transactions,
             receive_cashu_token,
+            get_mint_trust_policy,
+            set_mint_trust_policy,
+            get_spending_policy,
+            set_spending_policy,
+            list_spending_audit,
+            list_spend_ledger,
+            verify_spend_ledger,
+            set_spend_approval_threshold,
+            list_spend_approvals,
+            resolve_spend_approval,
+            list_pending_tokens,
+            list_damaged_pending_token_files,
+            retry_pending_token,
+            discard_pending_token,
+            get_split_strategy,
+            set_split_strategy,
+            consolidate_proofs,
+            get_token_pool_status,
+            get_token_pool_config,
+            set_token_pool_config,
             create_external_token,
+            parse_payment_input,
+            create_scheduled_payment,
+            list_scheduled_payments,
+            pause_scheduled_payment,
+            resume_scheduled_payment,
+            delete_scheduled_payment,
+            list_scheduled_payment_runs,
+            retire_legacy_nostr_key,
             nwc_list_connections,
             nwc_remove_connection,
             nwc_get_service_pubkey,
@@ -571,6 +696,33 @@ pub fn run() {
             nwc_create_standard_connection,
             connection_server::nwc_approve_connection,
             connection_server::nwc_reject_connection,
+            backup::create_backup,
+            backup::restore_backup,
+            settings_sync::publish_settings_to_nostr,
+            settings_sync::pull_settings_from_nostr,
+            settings_sync::restore_wallet_from_mnemonic,
+            profiles::list_wallet_profiles,
+            profiles::create_wallet_profile,
+            profiles::delete_wallet_profile,
+            app_lock::get_app_lock_status,
+            app_lock::lock_app,
+            app_lock::unlock_app,
+            app_lock::app_lock_heartbeat,
+            app_lock::set_app_lock_passphrase,
+            app_lock::disable_app_lock,
+            app_lock::set_app_lock_config,
+            api_clients::list_api_clients,
+            api_clients::create_api_client,
+            api_clients::revoke_api_client,
+            pairing::list_pairing_requests,
+            pairing::approve_pairing,
+            pairing::reject_pairing,
+            lan::get_lan_status,
+            lan::set_lan_mode,
+            lan::create_lan_pairing_code,
+            runtime::get_runtime_info,
+            supervisor::get_service_status,
+            switch_wallet_profile,
             routstr::routstr_connect_service,
             routstr::routstr_disconnect_service,
             routstr::routstr_refresh_models,
@@ -594,8 +746,20 @@ pub fn run() {
         ]);
 
     builder
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            if let tauri::RunEvent::Exit = event {
+                // Stop the services and flush their state; this also removes
+                // the discovery file, so local tools do not find an instance
+                // that is gone
+                let backend = app.try_state::<backend::Backend>();
+                let rt = app.try_state::<Arc<tokio::runtime::Runtime>>();
+                if let (Some(backend), Some(rt)) = (backend, rt) {
+                    rt.block_on(backend.shutdown());
+                }
+            }
+        });
 }
 
 #[cfg(target_os = "macos")]
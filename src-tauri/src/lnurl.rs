@@ -0,0 +1,197 @@
+//! LNURL and Lightning address helpers
+//!
+//! Decodes bech32 `lnurl1...` strings and Lightning addresses into the
+//! LNURL endpoint URL, and talks to LNURL-pay endpoints.
+
+use serde::{Deserialize, Serialize};
+
+/// Decode a bech32 encoded LNURL into its URL
+pub fn decode_lnurl(lnurl: &str) -> Result<String, String> {
+    let lnurl = lnurl.trim();
+    let (hrp, data) =
+        bech32::decode(lnurl).map_err(|e| format!("Invalid LNURL encoding: {}", e))?;
+
+    if !hrp.as_str().eq_ignore_ascii_case("lnurl") {
+        return Err(format!("Unexpected LNURL prefix: {}", hrp));
+    }
+
+    String::from_utf8(data).map_err(|_| "LNURL does not contain a valid URL".to_string())
+}
+
+/// Whether `input` looks like a Lightning address (`name@domain.tld`)
+pub fn is_lightning_address(input: &str) -> bool {
+    let Some((name, domain)) = input.split_once('@') else {
+        return false;
+    };
+
+    !name.is_empty()
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.+".contains(c))
+        && domain
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-.:".contains(c))
+}
+
+/// LNURL-pay endpoint for a Lightning address (LUD-16)
+pub fn lightning_address_url(address: &str) -> Result<String, String> {
+    if !is_lightning_address(address) {
+        return Err(format!("Invalid Lightning address: {}", address));
+    }
+
+    let (name, domain) = address
+        .split_once('@')
+        .ok_or_else(|| format!("Invalid Lightning address: {}", address))?;
+    let scheme = if domain.ends_with(".onion") {
+        "http"
+    } else {
+        "https"
+    };
+
+    Ok(format!(
+        "{}://{}/.well-known/lnurlp/{}",
+        scheme,
+        domain.to_lowercase(),
+        name.to_lowercase()
+    ))
+}
+
+/// Parameters returned by an LNURL-pay endpoint (LUD-06)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlPayParams {
+    pub callback: String,
+    pub min_sendable: u64,
+    pub max_sendable: u64,
+    pub metadata: String,
+    #[serde(default)]
+    pub comment_allowed: Option<u64>,
+    pub tag: String,
+}
+
+impl LnurlPayParams {
+    /// Plain text description from the LNURL metadata, if present
+    pub fn description(&self) -> Option<String> {
+        let entries: Vec<(String, serde_json::Value)> =
+            serde_json::from_str(&self.metadata).ok()?;
+        entries
+            .into_iter()
+            .find(|(kind, _)| kind == "text/plain")
+            .and_then(|(_, value)| value.as_str().map(|s| s.to_string()))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LnurlErrorResponse {
+    status: String,
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// Fetch LNURL-pay parameters from `url`
+pub async fn fetch_pay_params(url: &str) -> Result<LnurlPayParams, String> {
+    let body: serde_json::Value = reqwest::get(url)
+        .await
+        .map_err(|e| format!("Failed to reach LNURL endpoint: {}", e))?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid LNURL response: {}", e))?;
+
+    if let Ok(error) = serde_json::from_value::<LnurlErrorResponse>(body.clone()) {
+        if error.status.eq_ignore_ascii_case("error") {
+            return Err(format!(
+                "LNURL endpoint returned an error: {}",
+                error.reason.unwrap_or_default()
+            ));
+        }
+    }
+
+    let params: LnurlPayParams = serde_json::from_value(body)
+        .map_err(|e| format!("Invalid LNURL-pay response: {}", e))?;
+    if params.tag != "payRequest" {
+        return Err(format!("LNURL is not a pay request: {}", params.tag));
+    }
+
+    Ok(params)
+}
+
+#[derive(Debug, Deserialize)]
+struct LnurlInvoiceResponse {
+    pr: String,
+}
+
+/// Request a BOLT11 invoice for `amount_msats` from an LNURL-pay callback
+pub async fn request_invoice(
+    params: &LnurlPayParams,
+    amount_msats: u64,
+    comment: Option<&str>,
+) -> Result<String, String> {
+    if amount_msats < params.min_sendable || amount_msats > params.max_sendable {
+        return Err(format!(
+            "Amount {} msats is outside the allowed range {}-{} msats",
+            amount_msats, params.min_sendable, params.max_sendable
+        ));
+    }
+
+    let separator = if params.callback.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+    let mut url = format!("{}{}amount={}", params.callback, separator, amount_msats);
+    if let (Some(comment), Some(max_len)) = (comment, params.comment_allowed) {
+        let comment: String = comment.chars().take(max_len as usize).collect();
+        if !comment.is_empty() {
+            url.push_str(&format!("&comment={}", urlencoding::encode(&comment)));
+        }
+    }
+
+    let body: serde_json::Value = reqwest::get(&url)
+        .await
+        .map_err(|e| format!("Failed to reach LNURL callback: {}", e))?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid LNURL callback response: {}", e))?;
+
+    if let Ok(error) = serde_json::from_value::<LnurlErrorResponse>(body.clone()) {
+        if error.status.eq_ignore_ascii_case("error") {
+            return Err(format!(
+                "LNURL callback returned an error: {}",
+                error.reason.unwrap_or_default()
+            ));
+        }
+    }
+
+    let response: LnurlInvoiceResponse = serde_json::from_value(body)
+        .map_err(|e| format!("LNURL callback did not return an invoice: {}", e))?;
+    Ok(response.pr)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_lnurl() {
+        // LUD-01 example
+        let lnurl = "LNURL1DP68GURN8GHJ7UM9WFMXJCM99E3K7MF0V9CXJ0M385EKVCENXC6R2C35XVUKXEFCV5MKVV34X5EKZD3EV56NYD3HXQURZEPEXEJXXEPNXSCRVWFNV9NXZCN9XQ6XYEFHVGCXXCMYXYMNSERXFQ5FNS";
+        assert_eq!(
+            decode_lnurl(lnurl).unwrap(),
+            "https://service.com/api?q=3fc3645b439ce8e7f2553a69e5267081d96dcd340693afabe04be7b0ccd178df"
+        );
+        assert!(decode_lnurl("lnbc1invalid").is_err());
+    }
+
+    #[test]
+    fn test_lightning_address_url() {
+        assert_eq!(
+            lightning_address_url("Alice@Example.com").unwrap(),
+            "https://example.com/.well-known/lnurlp/alice"
+        );
+        assert!(lightning_address_url("alice@localhost").is_err());
+        assert!(!is_lightning_address("not an address"));
+    }
+}
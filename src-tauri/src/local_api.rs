@@ -0,0 +1,704 @@
+//! Local HTTP API
+//!
+//! Served by the connection server under `/api/v1` and described by
+//! `/api/v1/openapi.json`. It covers the wallet operations used by local tools
+//! and `wally-cli`, and answers the prompts the app would otherwise show
+//! (payment approvals, NWC connection requests and the app lock).
+//! `/api/v1/events` streams the backend event bus as server-sent events.
+//!
+//! Requests carry either a client token issued from the app, limited to the
+//! client's scopes, or the instance's owner token (the daemon's `api_token`,
+//! the app's per-run token in the discovery file, or the in-process token of
+//! `wally-cli --local`). Client payments are checked like NWC payments:
+//! they never count as user approval and wait for the user when the
+//! spending policy asks. Only the owner token can answer prompts.
+
+use crate::api_clients::{constant_time_eq, ApiClient, ApiScope};
+use crate::connection_server::{self, ConnectionServerState};
+use crate::events::{self, EventTopic};
+use crate::nwc::ConnectionBudget;
+use crate::tollgate::origin::Origin;
+use crate::tollgate::{TollGateError, TollGateResult};
+use axum::{
+    async_trait,
+    extract::{FromRequestParts, Path, Query, State},
+    http::{header, request::Parts, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    routing::{delete, get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+const OPENAPI: &str = include_str!("../openapi/local-api-v1.json");
+
+pub(crate) fn router() -> Router<ConnectionServerState> {
+    Router::new()
+        .route("/openapi.json", get(openapi))
+        .route("/wallet/balance", get(wallet_balance))
+        .route("/wallet/transactions", get(wallet_transactions))
+        .route("/wallet/receive", post(receive_token))
+        .route("/wallet/send-token", post(send_token))
+        .route("/wallet/pay-invoice", post(pay_invoice))
+        .route("/wallet/pay-request", post(pay_request))
+        .route("/mints", get(list_mints).post(add_mint).delete(remove_mint))
+        .route(
+            "/nwc/connections",
+            get(list_nwc_connections).post(create_nwc_connection),
+        )
+        .route("/nwc/connections/:pubkey", delete(revoke_nwc_connection))
+        .route("/tollgate/sessions", get(list_sessions))
+        .route("/approvals", get(list_approvals))
+        .route("/approvals/:id/approve", post(approve_spend))
+        .route("/approvals/:id/reject", post(reject_spend))
+        .route("/connections/pending", get(list_pending_connections))
+        .route("/connections/:id/approve", post(approve_connection))
+        .route("/connections/:id/reject", post(reject_connection))
+        .route("/pairings", get(list_pairings))
+        .route("/pairings/:id/approve", post(approve_pairing))
+        .route("/pairings/:id/reject", post(reject_pairing))
+        .route("/lan", get(lan_status))
+        .route("/lan/pairing-code", post(create_lan_pairing_code))
+        .route("/services", get(list_services))
+        .route("/lock", get(lock_status))
+        .route("/lock/unlock", post(unlock))
+        .route("/events", get(stream_events))
+}
+
+/// Handlers answer with the success body, or an error response
+type ApiResult = Result<Response, Response>;
+
+/// Error body shared by all local API handlers
+fn error(status: StatusCode, message: impl Into<String>) -> Response {
+    (
+        status,
+        Json(json!({
+            "success": false,
+            "error": message.into()
+        })),
+    )
+        .into_response()
+}
+
+/// Map a wallet error to a response, so callers can tell refusals apart
+fn wallet_error(e: TollGateError) -> Response {
+    let status = match e {
+        TollGateError::MintApprovalRequired(_) | TollGateError::SpendApprovalRequired(_) => {
+            StatusCode::CONFLICT
+        }
+        TollGateError::SpendDenied(_) | TollGateError::SpendRejected(_) => StatusCode::FORBIDDEN,
+        TollGateError::SpendApprovalTimedOut => StatusCode::REQUEST_TIMEOUT,
+        TollGateError::InsufficientFunds { .. } => StatusCode::PAYMENT_REQUIRED,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    error(status, e.to_string())
+}
+
+fn reply<T: Serialize>(result: TollGateResult<T>) -> ApiResult {
+    result
+        .map(|value| Json(value).into_response())
+        .map_err(wallet_error)
+}
+
+fn success() -> ApiResult {
+    Ok(Json(json!({ "success": true })).into_response())
+}
+
+/// Caller of a request, from its bearer token
+pub(crate) enum ApiAuth {
+    /// The instance's own token
+    Owner,
+    Client(ApiClient),
+}
+
+impl ApiAuth {
+    /// Caller owning `token`, if it is the owner token or a client's
+    pub(crate) fn from_token(state: &ConnectionServerState, token: &str) -> Option<Self> {
+        if let Some(owner) = state.api_token.as_deref() {
+            if constant_time_eq(token.as_bytes(), owner.as_bytes()) {
+                return Some(ApiAuth::Owner);
+            }
+        }
+        state.api_clients.authenticate(token).map(ApiAuth::Client)
+    }
+
+    /// Origin of payments made by this caller
+    fn origin(&self) -> Origin {
+        match self {
+            ApiAuth::Owner => Origin::Cli,
+            ApiAuth::Client(client) => client.origin(),
+        }
+    }
+
+    /// Check the caller's scope and the app lock
+    pub(crate) fn allow(
+        &self,
+        state: &ConnectionServerState,
+        scope: ApiScope,
+    ) -> Result<(), Response> {
+        let lock = &state.app_lock;
+        let result = match self {
+            ApiAuth::Owner => match scope {
+                ApiScope::Read => lock.authorize_read(),
+                ApiScope::Receive => Ok(()),
+                ApiScope::Spend | ApiScope::Manage | ApiScope::Connect | ApiScope::Proxy => {
+                    lock.authorize_spend()
+                }
+            },
+            ApiAuth::Client(client) => {
+                if !client.has_scope(scope) {
+                    return Err(error(
+                        StatusCode::FORBIDDEN,
+                        format!("Token lacks the {:?} scope", scope).to_lowercase(),
+                    ));
+                }
+                // Like NWC, clients do not keep the app unlocked
+                if scope == ApiScope::Read && lock.status().allow_reads_while_locked {
+                    Ok(())
+                } else {
+                    lock.ensure_unlocked()
+                }
+            }
+        };
+        result.map_err(|e| error(StatusCode::FORBIDDEN, e.to_string()))
+    }
+
+    /// Prompts are answered by the user, never by a client
+    fn require_owner(&self) -> Result<(), Response> {
+        match self {
+            ApiAuth::Owner => Ok(()),
+            ApiAuth::Client(_) => Err(error(
+                StatusCode::FORBIDDEN,
+                "Only the owner token can answer prompts",
+            )),
+        }
+    }
+}
+
+#[async_trait]
+impl FromRequestParts<ConnectionServerState> for ApiAuth {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &ConnectionServerState,
+    ) -> Result<Self, Self::Rejection> {
+        let provided = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        if provided.is_empty() {
+            return Err(error(StatusCode::UNAUTHORIZED, "Missing API token"));
+        }
+        ApiAuth::from_token(state, provided)
+            .ok_or_else(|| error(StatusCode::UNAUTHORIZED, "Invalid API token"))
+    }
+}
+
+async fn openapi() -> Response {
+    ([(header::CONTENT_TYPE, "application/json")], OPENAPI).into_response()
+}
+
+async fn wallet_balance(auth: ApiAuth, State(state): State<ConnectionServerState>) -> ApiResult {
+    auth.allow(&state, ApiScope::Read)?;
+    let service = state.tollgate.lock().await;
+    reply(service.get_wallet_summary().await)
+}
+
+async fn wallet_transactions(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+) -> ApiResult {
+    auth.allow(&state, ApiScope::Read)?;
+    let service = state.tollgate.lock().await;
+    reply(service.list_wallet_transactions().await)
+}
+
+#[derive(Deserialize)]
+struct ReceiveRequest {
+    token: String,
+    #[serde(default)]
+    approve_mint: bool,
+    #[serde(default)]
+    offline: bool,
+}
+
+async fn receive_token(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+    Json(body): Json<ReceiveRequest>,
+) -> ApiResult {
+    auth.allow(&state, ApiScope::Receive)?;
+    let origin = auth.origin();
+    let service = state.tollgate.lock().await;
+    reply(if body.offline {
+        service
+            .receive_cashu_token_offline(&body.token, &origin, body.approve_mint)
+            .await
+    } else {
+        service
+            .receive_cashu_token(&body.token, &origin, body.approve_mint)
+            .await
+    })
+}
+
+#[derive(Deserialize)]
+struct SendTokenRequest {
+    amount_sats: u64,
+    mint_url: Option<String>,
+    counterparty: Option<String>,
+    #[serde(default)]
+    approve: bool,
+}
+
+async fn send_token(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+    Json(body): Json<SendTokenRequest>,
+) -> ApiResult {
+    auth.allow(&state, ApiScope::Spend)?;
+    let payments = state.tollgate.lock().await.payments();
+    let token = payments
+        .create_external_token(
+            body.amount_sats,
+            body.mint_url,
+            &auth.origin(),
+            body.counterparty,
+            body.approve,
+        )
+        .await
+        .map_err(wallet_error)?;
+    Ok(Json(json!({ "token": token })).into_response())
+}
+
+#[derive(Deserialize)]
+struct PayInvoiceRequest {
+    invoice: String,
+    #[serde(default)]
+    approve: bool,
+}
+
+async fn pay_invoice(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+    Json(body): Json<PayInvoiceRequest>,
+) -> ApiResult {
+    auth.allow(&state, ApiScope::Spend)?;
+    let payments = state.tollgate.lock().await.payments();
+    reply(
+        payments
+            .pay_bolt11_invoice(&body.invoice, &auth.origin(), body.approve)
+            .await,
+    )
+}
+
+#[derive(Deserialize)]
+struct PayRequestRequest {
+    request: String,
+    amount: Option<u64>,
+    #[serde(default)]
+    approve: bool,
+}
+
+async fn pay_request(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+    Json(body): Json<PayRequestRequest>,
+) -> ApiResult {
+    auth.allow(&state, ApiScope::Spend)?;
+    let payments = state.tollgate.lock().await.payments();
+    reply(
+        payments
+            .pay_nut18_payment_request_with_token(
+                &body.request,
+                body.amount,
+                &auth.origin(),
+                body.approve,
+            )
+            .await,
+    )
+}
+
+async fn list_mints(auth: ApiAuth, State(state): State<ConnectionServerState>) -> ApiResult {
+    auth.allow(&state, ApiScope::Read)?;
+    let (mints, default_mint) = state.tollgate.lock().await.mint_settings().await;
+    Ok(Json(json!({ "mints": mints, "default_mint": default_mint })).into_response())
+}
+
+#[derive(Deserialize)]
+struct MintRequest {
+    mint_url: String,
+}
+
+async fn add_mint(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+    Json(body): Json<MintRequest>,
+) -> ApiResult {
+    auth.allow(&state, ApiScope::Manage)?;
+    let service = state.tollgate.lock().await;
+    service
+        .add_mint(&body.mint_url)
+        .await
+        .map_err(wallet_error)?;
+    success()
+}
+
+async fn remove_mint(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+    Json(body): Json<MintRequest>,
+) -> ApiResult {
+    auth.allow(&state, ApiScope::Manage)?;
+    let service = state.tollgate.lock().await;
+    service
+        .remove_mint(&body.mint_url)
+        .await
+        .map_err(wallet_error)?;
+    success()
+}
+
+fn nwc_unavailable() -> Response {
+    error(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "NWC service not initialized",
+    )
+}
+
+async fn list_nwc_connections(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+) -> ApiResult {
+    auth.allow(&state, ApiScope::Read)?;
+    let nwc_lock = state.nwc.lock().await;
+    let nwc = nwc_lock.as_ref().ok_or_else(nwc_unavailable)?;
+    let connections: Vec<_> = nwc
+        .get_connections()
+        .await
+        .iter()
+        .map(crate::connection_json)
+        .collect();
+    Ok(Json(connections).into_response())
+}
+
+#[derive(Deserialize)]
+struct CreateConnectionRequest {
+    name: Option<String>,
+    budget_sats: Option<u64>,
+    renewal_period: Option<String>,
+    #[serde(default)]
+    use_local_relay: bool,
+}
+
+async fn create_nwc_connection(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+    Json(body): Json<CreateConnectionRequest>,
+) -> ApiResult {
+    auth.allow(&state, ApiScope::Manage)?;
+    let mut budget = ConnectionBudget::default();
+    if let Some(sats) = body.budget_sats {
+        budget.total_budget_msats = sats.saturating_mul(1_000);
+    }
+    if let Some(period) = &body.renewal_period {
+        budget.renewal_period =
+            crate::parse_budget_period(period).map_err(|e| error(StatusCode::BAD_REQUEST, e))?;
+    }
+
+    let nwc_lock = state.nwc.lock().await;
+    let nwc = nwc_lock.as_ref().ok_or_else(nwc_unavailable)?;
+    let (connection, uri) = nwc
+        .create_connection(body.name.as_deref(), budget, body.use_local_relay)
+        .await
+        .map_err(|e| error(StatusCode::BAD_REQUEST, e.to_string()))?;
+    let mut response = crate::connection_json(&connection);
+    response["uri"] = json!(uri);
+    Ok(Json(response).into_response())
+}
+
+async fn revoke_nwc_connection(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+    Path(pubkey): Path<String>,
+) -> ApiResult {
+    auth.allow(&state, ApiScope::Manage)?;
+    let nwc_lock = state.nwc.lock().await;
+    let nwc = nwc_lock.as_ref().ok_or_else(nwc_unavailable)?;
+    nwc.remove_connection(&pubkey)
+        .await
+        .map_err(|e| error(StatusCode::BAD_REQUEST, e.to_string()))?;
+    success()
+}
+
+async fn list_sessions(auth: ApiAuth, State(state): State<ConnectionServerState>) -> ApiResult {
+    auth.allow(&state, ApiScope::Read)?;
+    let service = state.tollgate.lock().await;
+    let sessions = service.get_active_sessions().await.map_err(wallet_error)?;
+    let sessions: Vec<_> = sessions.iter().map(crate::session_json).collect();
+    Ok(Json(sessions).into_response())
+}
+
+async fn list_approvals(auth: ApiAuth, State(state): State<ConnectionServerState>) -> ApiResult {
+    auth.allow(&state, ApiScope::Read)?;
+    Ok(Json(state.approvals.pending()).into_response())
+}
+
+async fn approve_spend(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+    Path(id): Path<String>,
+) -> ApiResult {
+    auth.require_owner()?;
+    auth.allow(&state, ApiScope::Spend)?;
+    resolve_spend(&state, &id, true)
+}
+
+async fn reject_spend(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+    Path(id): Path<String>,
+) -> ApiResult {
+    auth.require_owner()?;
+    resolve_spend(&state, &id, false)
+}
+
+fn resolve_spend(state: &ConnectionServerState, id: &str, approved: bool) -> ApiResult {
+    if !state.approvals.resolve(id, approved) {
+        return Err(error(
+            StatusCode::NOT_FOUND,
+            format!("Approval request not found: {}", id),
+        ));
+    }
+    log::info!(
+        "Payment approval {} {} via local API",
+        id,
+        if approved { "approved" } else { "rejected" }
+    );
+    success()
+}
+
+async fn list_pending_connections(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+) -> ApiResult {
+    auth.allow(&state, ApiScope::Read)?;
+    let now = chrono::Utc::now().timestamp() as u64;
+    let mut pending: Vec<_> = state
+        .pending_connections
+        .lock()
+        .await
+        .values()
+        .filter(|request| !request.approved && !request.rejected && !request.is_expired(now))
+        .cloned()
+        .collect();
+    pending.sort_by_key(|request| request.received_at);
+    Ok(Json(pending).into_response())
+}
+
+async fn approve_connection(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+    Path(id): Path<String>,
+) -> ApiResult {
+    auth.require_owner()?;
+    auth.allow(&state, ApiScope::Manage)?;
+    let response =
+        connection_server::approve_connection(&id, &state.pending_connections, &state.nwc)
+            .await
+            .map_err(|e| error(StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(response).into_response())
+}
+
+async fn reject_connection(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+    Path(id): Path<String>,
+) -> ApiResult {
+    auth.require_owner()?;
+    let response =
+        connection_server::reject_connection(&id, &state.pending_connections, &state.nwc)
+            .await
+            .map_err(|e| error(StatusCode::NOT_FOUND, e))?;
+    Ok(Json(response).into_response())
+}
+
+async fn list_pairings(auth: ApiAuth, State(state): State<ConnectionServerState>) -> ApiResult {
+    auth.allow(&state, ApiScope::Read)?;
+    Ok(Json(state.pairings.list()).into_response())
+}
+
+async fn approve_pairing(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+    Path(id): Path<String>,
+) -> ApiResult {
+    auth.require_owner()?;
+    auth.allow(&state, ApiScope::Manage)?;
+    let client = state
+        .pairings
+        .approve(&id, &state.api_clients)
+        .map_err(|e| error(e.status(), e.to_string()))?;
+    Ok(Json(client).into_response())
+}
+
+async fn reject_pairing(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+    Path(id): Path<String>,
+) -> ApiResult {
+    auth.require_owner()?;
+    state
+        .pairings
+        .reject(&id)
+        .map_err(|e| error(e.status(), e.to_string()))?;
+    success()
+}
+
+async fn lan_status(auth: ApiAuth, State(state): State<ConnectionServerState>) -> ApiResult {
+    auth.allow(&state, ApiScope::Read)?;
+    Ok(Json(state.lan.status()).into_response())
+}
+
+/// A pairing code hands out an NWC connection, so only the owner shows one
+async fn create_lan_pairing_code(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+) -> ApiResult {
+    auth.require_owner()?;
+    auth.allow(&state, ApiScope::Manage)?;
+    let code = state
+        .lan
+        .new_pairing_code()
+        .map_err(|e| error(e.status(), e.to_string()))?;
+    Ok(Json(code).into_response())
+}
+
+async fn list_services(auth: ApiAuth, State(state): State<ConnectionServerState>) -> ApiResult {
+    auth.allow(&state, ApiScope::Read)?;
+    Ok(Json(crate::supervisor::supervisor().status()).into_response())
+}
+
+async fn lock_status(auth: ApiAuth, State(state): State<ConnectionServerState>) -> ApiResult {
+    // Lock status is readable while locked, so a caller can tell why it failed
+    if let ApiAuth::Client(client) = &auth {
+        if !client.has_scope(ApiScope::Read) {
+            return Err(error(StatusCode::FORBIDDEN, "Token lacks the read scope"));
+        }
+    }
+    Ok(Json(state.app_lock.status()).into_response())
+}
+
+#[derive(Deserialize)]
+struct UnlockRequest {
+    passphrase: String,
+}
+
+async fn unlock(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+    Json(body): Json<UnlockRequest>,
+) -> ApiResult {
+    auth.require_owner()?;
+    let lock = state.app_lock.clone();
+    // scrypt is deliberately slow, keep it off the async workers
+    let status =
+        tokio::task::spawn_blocking(move || lock.unlock(&body.passphrase).map(|_| lock.status()))
+            .await
+            .map_err(|e| error(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+            .map_err(|e| error(StatusCode::FORBIDDEN, e.to_string()))?;
+    Ok(Json(status).into_response())
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    #[serde(default)]
+    topics: String,
+}
+
+/// Stream backend events, optionally only some topics (`?topics=wallet,mint`)
+async fn stream_events(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+    Query(query): Query<EventsQuery>,
+) -> ApiResult {
+    auth.allow(&state, ApiScope::Read)?;
+    let topics =
+        events::parse_topics(&query.topics).map_err(|e| error(StatusCode::BAD_REQUEST, e))?;
+
+    let stream = futures_util::stream::unfold(
+        (events::subscribe(), topics),
+        |(mut receiver, topics)| async move {
+            loop {
+                let event = match receiver.recv().await {
+                    Ok(envelope) => {
+                        if !wants(&topics, envelope.topic) {
+                            continue;
+                        }
+                        Event::default()
+                            .id(envelope.seq.to_string())
+                            .json_data(&*envelope)
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(n)) => {
+                        Ok(Event::default().comment(format!("missed {} events", n)))
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+                };
+                return Some((event, (receiver, topics)));
+            }
+        },
+    );
+    Ok(Sse::new(stream)
+        .keep_alive(KeepAlive::default())
+        .into_response())
+}
+
+fn wants(topics: &[EventTopic], topic: EventTopic) -> bool {
+    topics.is_empty() || topics.contains(&topic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openapi_describes_routes() {
+        let doc: serde_json::Value = serde_json::from_str(OPENAPI).unwrap();
+        assert!(doc["openapi"].as_str().unwrap().starts_with("3."));
+
+        let paths = doc["paths"].as_object().unwrap();
+        for path in [
+            "/wallet/balance",
+            "/wallet/transactions",
+            "/wallet/receive",
+            "/wallet/send-token",
+            "/wallet/pay-invoice",
+            "/wallet/pay-request",
+            "/mints",
+            "/nwc/connections",
+            "/nwc/connections/{pubkey}",
+            "/tollgate/sessions",
+            "/approvals",
+            "/approvals/{id}/approve",
+            "/approvals/{id}/reject",
+            "/connections/pending",
+            "/connections/{id}/approve",
+            "/connections/{id}/reject",
+            "/pairings",
+            "/pairings/{id}/approve",
+            "/pairings/{id}/reject",
+            "/lan",
+            "/lan/pairing-code",
+            "/services",
+            "/lock",
+            "/lock/unlock",
+            "/events",
+        ] {
+            assert!(paths.contains_key(path), "{} is not documented", path);
+        }
+    }
+}
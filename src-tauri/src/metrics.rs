@@ -0,0 +1,434 @@
+//! Prometheus metrics
+//!
+//! Counters and gauges are process-wide, like the event bus, and updated
+//! where things happen: NWC requests, TollGate sessions and billed proxy
+//! requests are counted from the events they publish, spends as they are
+//! written to the spend ledger, and the relay and mint requests where they
+//! are handled. Balances and active sessions are read when scraped.
+//!
+//! When `metrics.enabled` is set in the config, the connection server
+//! serves them at `/metrics` in the Prometheus text format. Scrapers need a
+//! local API token with the read scope, sent as a bearer token.
+
+use crate::api_clients::ApiScope;
+use crate::connection_server::ConnectionServerState;
+use crate::events::BackendEvent;
+use crate::local_api::ApiAuth;
+use axum::{
+    extract::State,
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds of the proxy latency buckets, in seconds
+const LATENCY_BUCKETS: &[f64] = &[0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 120.0];
+
+pub static WALLET_BALANCE: Metric = Metric::gauge(
+    "wally_wallet_balance",
+    "Spendable balance per mint, in the mint's unit",
+    &["mint", "unit"],
+);
+pub static SPENT_SATS: Metric = Metric::counter(
+    "wally_spent_sats_total",
+    "Sats spent, by the subsystem that spent them",
+    &["subsystem"],
+);
+pub static TOLLGATE_SESSIONS: Metric = Metric::counter(
+    "wally_tollgate_sessions_total",
+    "TollGate sessions started",
+    &[],
+);
+pub static TOLLGATE_RENEWALS: Metric = Metric::counter(
+    "wally_tollgate_renewals_total",
+    "TollGate sessions renewed",
+    &[],
+);
+pub static TOLLGATE_ACTIVE_SESSIONS: Metric = Metric::gauge(
+    "wally_tollgate_active_sessions",
+    "TollGate sessions currently active",
+    &[],
+);
+pub static NWC_REQUESTS: Metric = Metric::counter(
+    "wally_nwc_requests_total",
+    "NWC requests answered, by method and result",
+    &["method", "result"],
+);
+pub static RELAY_CONNECTIONS: Metric = Metric::gauge(
+    "wally_relay_connections",
+    "Open connections to the local relay",
+    &[],
+);
+pub static RELAY_STORED_EVENTS: Metric = Metric::gauge(
+    "wally_relay_stored_events",
+    "Events stored by the local relay",
+    &[],
+);
+pub static RELAY_SUBSCRIPTIONS: Metric = Metric::gauge(
+    "wally_relay_subscriptions",
+    "Open subscriptions on the local relay",
+    &[],
+);
+pub static PROXY_COST_SATS: Metric = Metric::counter(
+    "wally_proxy_cost_sats_total",
+    "Sats paid for proxy requests after refunds, by model",
+    &["model"],
+);
+pub static PROXY_LATENCY: Histogram = Histogram::new(
+    "wally_proxy_request_duration_seconds",
+    "Time until the provider answered a proxy request, by model",
+    &["model"],
+    LATENCY_BUCKETS,
+);
+pub static MINT_REQUEST_ERRORS: Metric = Metric::counter(
+    "wally_mint_request_errors_total",
+    "Failed requests to mints, by mint and operation",
+    &["mint", "operation"],
+);
+
+static METRICS: &[&Metric] = &[
+    &WALLET_BALANCE,
+    &SPENT_SATS,
+    &TOLLGATE_SESSIONS,
+    &TOLLGATE_RENEWALS,
+    &TOLLGATE_ACTIVE_SESSIONS,
+    &NWC_REQUESTS,
+    &RELAY_CONNECTIONS,
+    &RELAY_STORED_EVENTS,
+    &RELAY_SUBSCRIPTIONS,
+    &PROXY_COST_SATS,
+    &MINT_REQUEST_ERRORS,
+];
+static HISTOGRAMS: &[&Histogram] = &[&PROXY_LATENCY];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum MetricKind {
+    Counter,
+    Gauge,
+}
+
+/// A counter or gauge, with one value per combination of label values
+pub struct Metric {
+    name: &'static str,
+    help: &'static str,
+    kind: MetricKind,
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, f64>>,
+}
+
+impl Metric {
+    pub const fn counter(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+    ) -> Self {
+        Self::new(name, help, MetricKind::Counter, labels)
+    }
+
+    pub const fn gauge(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+    ) -> Self {
+        Self::new(name, help, MetricKind::Gauge, labels)
+    }
+
+    const fn new(
+        name: &'static str,
+        help: &'static str,
+        kind: MetricKind,
+        labels: &'static [&'static str],
+    ) -> Self {
+        Self {
+            name,
+            help,
+            kind,
+            labels,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn inc(&self, labels: &[&str]) {
+        self.add(labels, 1.0);
+    }
+
+    /// Only gauges go down
+    pub fn dec(&self, labels: &[&str]) {
+        debug_assert_eq!(self.kind, MetricKind::Gauge);
+        self.add(labels, -1.0);
+    }
+
+    pub fn add(&self, labels: &[&str], value: f64) {
+        *self.lock().entry(key(self.labels, labels)).or_default() += value;
+    }
+
+    pub fn set(&self, labels: &[&str], value: f64) {
+        self.lock().insert(key(self.labels, labels), value);
+    }
+
+    /// Forget every value, before setting the current ones
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, BTreeMap<Vec<String>, f64>> {
+        self.values.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn render(&self, out: &mut String) {
+        let kind = match self.kind {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+        };
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} {}", self.name, kind);
+        let values = self.lock();
+        // Unlabelled metrics read 0 before anything is recorded
+        if values.is_empty() && self.labels.is_empty() {
+            let _ = writeln!(out, "{} 0", self.name);
+        }
+        for (label_values, value) in values.iter() {
+            let _ = writeln!(
+                out,
+                "{}{} {}",
+                self.name,
+                label_set(self.labels, label_values, None),
+                value
+            );
+        }
+    }
+}
+
+#[derive(Default)]
+struct HistogramValues {
+    /// Observations at or below each bucket's bound
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+/// Observed durations, in buckets per combination of label values
+pub struct Histogram {
+    name: &'static str,
+    help: &'static str,
+    labels: &'static [&'static str],
+    bounds: &'static [f64],
+    values: Mutex<BTreeMap<Vec<String>, HistogramValues>>,
+}
+
+impl Histogram {
+    pub const fn new(
+        name: &'static str,
+        help: &'static str,
+        labels: &'static [&'static str],
+        bounds: &'static [f64],
+    ) -> Self {
+        Self {
+            name,
+            help,
+            labels,
+            bounds,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn observe(&self, labels: &[&str], duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        let entry = values
+            .entry(key(self.labels, labels))
+            .or_insert_with(|| HistogramValues {
+                buckets: vec![0; self.bounds.len()],
+                ..Default::default()
+            });
+        for (count, bound) in entry.buckets.iter_mut().zip(self.bounds) {
+            if seconds <= *bound {
+                *count += 1;
+            }
+        }
+        entry.sum += seconds;
+        entry.count += 1;
+    }
+
+    fn render(&self, out: &mut String) {
+        let _ = writeln!(out, "# HELP {} {}", self.name, self.help);
+        let _ = writeln!(out, "# TYPE {} histogram", self.name);
+        let values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        for (label_values, histogram) in values.iter() {
+            for (count, bound) in histogram.buckets.iter().zip(self.bounds) {
+                let le = bound.to_string();
+                let labels = label_set(self.labels, label_values, Some(&le));
+                let _ = writeln!(out, "{}_bucket{} {}", self.name, labels, count);
+            }
+            let labels = label_set(self.labels, label_values, Some("+Inf"));
+            let _ = writeln!(out, "{}_bucket{} {}", self.name, labels, histogram.count);
+            let labels = label_set(self.labels, label_values, None);
+            let _ = writeln!(out, "{}_sum{} {}", self.name, labels, histogram.sum);
+            let _ = writeln!(out, "{}_count{} {}", self.name, labels, histogram.count);
+        }
+    }
+}
+
+/// Label values, one per label name
+fn key(names: &[&str], values: &[&str]) -> Vec<String> {
+    debug_assert_eq!(names.len(), values.len());
+    values.iter().map(|value| value.to_string()).collect()
+}
+
+/// `{name="value",...}`, or nothing without labels
+fn label_set(names: &[&str], values: &[String], le: Option<&str>) -> String {
+    let mut pairs: Vec<String> = names
+        .iter()
+        .zip(values)
+        .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+        .collect();
+    if let Some(le) = le {
+        pairs.push(format!("le=\"{}\"", le));
+    }
+    if pairs.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", pairs.join(","))
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Count what an event reports
+pub(crate) fn record_event(event: &BackendEvent) {
+    match event {
+        BackendEvent::SessionStarted { .. } => TOLLGATE_SESSIONS.inc(&[]),
+        BackendEvent::SessionRenewed { .. } => TOLLGATE_RENEWALS.inc(&[]),
+        BackendEvent::NwcRequestHandled { method, error, .. } => {
+            let result = if error.is_some() { "error" } else { "ok" };
+            NWC_REQUESTS.inc(&[method, result]);
+        }
+        BackendEvent::ProxyRequestBilled {
+            model,
+            paid_sats,
+            refunded_sats,
+            ..
+        } => PROXY_COST_SATS.add(
+            &[model.as_deref().unwrap_or("unknown")],
+            paid_sats.saturating_sub(*refunded_sats) as f64,
+        ),
+        _ => {}
+    }
+}
+
+/// Every metric in the Prometheus text format
+pub fn render() -> String {
+    let mut out = String::new();
+    for metric in METRICS {
+        metric.render(&mut out);
+    }
+    for histogram in HISTOGRAMS {
+        histogram.render(&mut out);
+    }
+    out
+}
+
+/// Read the gauges that are not updated as things happen
+async fn refresh(state: &ConnectionServerState) {
+    let service = state.tollgate.lock().await;
+    match service.get_wallet_summary().await {
+        Ok(summary) => {
+            WALLET_BALANCE.clear();
+            for balance in summary.balances {
+                WALLET_BALANCE.set(&[&balance.mint_url, &balance.unit], balance.balance as f64);
+            }
+        }
+        Err(e) => log::warn!("Failed to read balances for metrics: {}", e),
+    }
+    match service.get_active_sessions().await {
+        Ok(sessions) => TOLLGATE_ACTIVE_SESSIONS.set(&[], sessions.len() as f64),
+        Err(e) => log::warn!("Failed to read sessions for metrics: {}", e),
+    }
+}
+
+/// GET /metrics
+pub(crate) async fn serve_metrics(
+    auth: ApiAuth,
+    State(state): State<ConnectionServerState>,
+) -> Response {
+    if !crate::config::current().metrics.enabled {
+        return StatusCode::NOT_FOUND.into_response();
+    }
+    if let Err(response) = auth.allow(&state, ApiScope::Read) {
+        return response;
+    }
+    refresh(&state).await;
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        render(),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_text_format() {
+        let requests = Metric::counter("test_requests_total", "Requests", &["method"]);
+        requests.inc(&["pay_invoice"]);
+        requests.add(&["say \"hi\""], 2.0);
+        let idle = Metric::gauge("test_idle", "Idle", &[]);
+
+        let mut out = String::new();
+        requests.render(&mut out);
+        idle.render(&mut out);
+        assert_eq!(
+            out,
+            "# HELP test_requests_total Requests\n\
+             # TYPE test_requests_total counter\n\
+             test_requests_total{method=\"pay_invoice\"} 1\n\
+             test_requests_total{method=\"say \\\"hi\\\"\"} 2\n\
+             # HELP test_idle Idle\n\
+             # TYPE test_idle gauge\n\
+             test_idle 0\n"
+        );
+
+        let latency = Histogram::new("test_seconds", "Latency", &["model"], &[0.5, 1.0]);
+        latency.observe(&["gpt"], Duration::from_millis(200));
+        latency.observe(&["gpt"], Duration::from_secs(2));
+        let mut out = String::new();
+        latency.render(&mut out);
+        assert!(out.contains("test_seconds_bucket{model=\"gpt\",le=\"0.5\"} 1\n"));
+        assert!(out.contains("test_seconds_bucket{model=\"gpt\",le=\"1\"} 1\n"));
+        assert!(out.contains("test_seconds_bucket{model=\"gpt\",le=\"+Inf\"} 2\n"));
+        assert!(out.contains("test_seconds_count{model=\"gpt\"} 2\n"));
+    }
+
+    fn value(metric: &Metric, labels: &[&str]) -> f64 {
+        metric
+            .lock()
+            .get(&key(metric.labels, labels))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_events_are_counted() {
+        let labels = ["test_method", "error"];
+        let before = value(&NWC_REQUESTS, &labels);
+        record_event(&BackendEvent::NwcRequestHandled {
+            connection: "abc".to_string(),
+            method: "test_method".to_string(),
+            error: Some("Insufficient balance".to_string()),
+        });
+        assert_eq!(value(&NWC_REQUESTS, &labels), before + 1.0);
+        assert!(
+            render().contains("wally_nwc_requests_total{method=\"test_method\",result=\"error\"}")
+        );
+    }
+}
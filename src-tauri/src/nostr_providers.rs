@@ -7,7 +7,8 @@ use std::time::Duration;
 
 const PROVIDER_ANNOUNCEMENT_KIND: u16 = 38421;
 
-const DEFAULT_RELAYS: &[&str] = &[
+/// Default for `relays.discovery` in the config
+pub(crate) const DEFAULT_RELAYS: &[&str] = &[
     "wss://relay.damus.io",
     "wss://relay.snort.social",
     "wss://nos.lol",
@@ -77,10 +78,11 @@ fn parse_tags_to_map(tags: &Tags) -> HashMap<String, Vec<String>> {
 
 impl NostrProviderDiscovery {
     pub async fn new() -> Result<Self> {
-        let relays: Vec<String> = DEFAULT_RELAYS.iter().map(|&s| s.to_string()).collect();
+        let config = crate::config::current();
+        let relays = config.relays.discovery.clone();
         let client = Client::default();
         let http_client = reqwest::Client::builder()
-            .timeout(Duration::from_secs(10))
+            .timeout(config.timeouts.request())
             .build()?;
 
         for relay in relays.iter() {
@@ -113,7 +115,7 @@ impl NostrProviderDiscovery {
 
         let events = self
             .client
-            .fetch_events(filter, Duration::from_secs(10))
+            .fetch_events(filter, crate::config::current().timeouts.request())
             .await?;
 
         log::info!("Retrieved {} provider events", events.len());
@@ -3,10 +3,15 @@
 //! This module provides NWC functionality that allows external applications
 //! to interact with the wallet through Nostr relays.
 
+use crate::app_lock::AppLockState;
+use crate::config;
 use crate::nwc_storage::NwcConnectionStorage;
+use crate::relay;
+use crate::tollgate::origin::Origin;
 use crate::tollgate::wallet::{
     Bolt11InvoiceInfo, Bolt11PaymentResult, CashuReceiveResult, PayNut18Result,
 };
+use crate::tollgate::TollGateError;
 use crate::TollGateState;
 use lightning_invoice::Bolt11Invoice;
 use nostr_sdk::prelude::FromBech32;
@@ -19,15 +24,14 @@ use nostr_sdk::{
     SecretKey, SingleLetterTag, Tag, TagStandard, Timestamp, Url,
 };
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::sync::{Mutex, RwLock};
 
-const REMOTE_RELAY_URL: &str = "wss://nostr.chaima.info";
-const LOCAL_RELAY_URL: &str = "ws://localhost:4869";
-const NWC_BUDGET_MSATS: u64 = 1_000_000_000; // 1,000 sats budget
+/// Default for `relays.nwc` in the config
+pub(crate) const REMOTE_RELAY_URL: &str = "wss://nostr.chaima.info";
 
 fn parse_connection_pubkey(value: &str) -> Result<PublicKey, Error> {
     if let Ok(pk) = PublicKey::from_str(value) {
@@ -55,12 +59,19 @@ pub struct NostrWalletConnect {
     last_check: Arc<Mutex<Timestamp>>,
     /// Response event cache to avoid reprocessing
     response_event_cache: Arc<Mutex<HashMap<String, Event>>>,
+    /// Requests being handled, so one delivered by several relays is only
+    /// handled once
+    in_flight: Arc<Mutex<HashSet<String>>>,
     /// Active connections
     connections: Arc<RwLock<Vec<WalletConnection>>>,
     /// Reference to the TollGate service state
     service_state: TollGateState,
     /// Connection storage
     storage: Arc<NwcConnectionStorage>,
+    /// App lock; payments are refused while it is locked
+    app_lock: Option<AppLockState>,
+    /// Public relay the service listens on, from the config
+    relay_url: String,
 }
 
 impl NostrWalletConnect {
@@ -103,20 +114,36 @@ impl NostrWalletConnect {
             client,
             last_check: Arc::new(Mutex::new(Timestamp::now())),
             response_event_cache: Arc::new(Mutex::new(HashMap::new())),
+            in_flight: Arc::new(Mutex::new(HashSet::new())),
             connections: Arc::new(RwLock::new(connections)),
             service_state,
             storage,
+            app_lock: None,
+            relay_url: config::current().relays.nwc.clone(),
         })
     }
 
+    /// Refuse payments while `app_lock` is locked
+    pub fn with_app_lock(mut self, app_lock: AppLockState) -> Self {
+        self.app_lock = Some(app_lock);
+        self
+    }
+
+    fn ensure_unlocked(&self) -> Result<(), Error> {
+        match &self.app_lock {
+            Some(lock) if lock.is_locked() => Err(Error::Locked),
+            _ => Ok(()),
+        }
+    }
+
     /// Starts the NWC service.
     pub async fn start(&self) -> Result<(), Error> {
         log::info!(
             "Starting NWC service, ensuring relay connectivity: {}",
-            REMOTE_RELAY_URL
+            self.relay_url
         );
 
-        self.ensure_relay(REMOTE_RELAY_URL).await?;
+        self.ensure_relay(&self.relay_url).await?;
 
         // Connect to relay with timeout
         log::info!("Connecting to relay...");
@@ -125,7 +152,7 @@ impl NostrWalletConnect {
         // Wait a moment for connection to establish
         tokio::time::sleep(std::time::Duration::from_millis(500)).await;
 
-        log::info!("NWC service connected to relay: {}", REMOTE_RELAY_URL);
+        log::info!("NWC service connected to relay: {}", self.relay_url);
 
         // Publish info event
         log::info!("Publishing NWC info event...");
@@ -160,6 +187,24 @@ impl NostrWalletConnect {
         Ok(())
     }
 
+    /// Disconnects from all relays.
+    pub async fn stop(&self) {
+        self.client.disconnect().await;
+        log::info!("NWC service stopped");
+    }
+
+    /// Replaces the in-memory connections with those in storage.
+    pub async fn reload_connections(&self) -> Result<(), Error> {
+        let loaded = self
+            .storage
+            .load_connections()
+            .map_err(|e| Error::Wallet(format!("Failed to load NWC connections: {}", e)))?;
+
+        log::info!("Reloaded {} NWC connections from storage", loaded.len());
+        *self.connections.write().await = loaded;
+        Ok(())
+    }
+
     /// Gets all wallet connections.
     pub async fn get_connections(&self) -> Vec<WalletConnection> {
         self.connections.read().await.clone()
@@ -251,39 +296,78 @@ impl NostrWalletConnect {
     }
 
     /// Creates a new standard NWC connection and returns the connection URI.
+    /// With `use_local_relay` the URI points at the loopback relay on the
+    /// port it actually listens on.
     pub async fn create_standard_nwc_uri(&self, use_local_relay: bool) -> Result<String, Error> {
+        let (_, uri) = self
+            .create_connection(None, ConnectionBudget::default(), use_local_relay)
+            .await?;
+        Ok(uri)
+    }
+
+    /// Creates a standard NWC connection with the given name and budget,
+    /// returning the connection and its URI.
+    pub async fn create_connection(
+        &self,
+        name: Option<&str>,
+        budget: ConnectionBudget,
+        use_local_relay: bool,
+    ) -> Result<(WalletConnection, String), Error> {
+        let relay_url = if use_local_relay {
+            relay::local_relay_url()
+        } else {
+            self.relay_url.clone()
+        };
+        self.create_connection_via(name, budget, &relay_url, &relay_url)
+            .await
+    }
+
+    /// Creates a connection for a device on the LAN. Its URI points at the
+    /// relay's LAN address, which shares events with the loopback relay the
+    /// service listens on.
+    pub async fn create_lan_connection(
+        &self,
+        name: Option<&str>,
+        budget: ConnectionBudget,
+        lan_relay_url: &str,
+    ) -> Result<(WalletConnection, String), Error> {
+        self.create_connection_via(name, budget, &relay::local_relay_url(), lan_relay_url)
+            .await
+    }
+
+    async fn create_connection_via(
+        &self,
+        name: Option<&str>,
+        budget: ConnectionBudget,
+        listen_relay_url: &str,
+        uri_relay_url: &str,
+    ) -> Result<(WalletConnection, String), Error> {
         // Generate new keys for the connection
         let connection_key = SecretKey::generate();
 
-        // Create a default budget
-        let budget = ConnectionBudget::default();
-
         // Create a new WalletConnection
-        let connection = WalletConnection::new(connection_key, budget);
+        let mut connection = WalletConnection::new(connection_key, budget);
+        if let Some(name) = name.map(str::trim).filter(|name| !name.is_empty()) {
+            connection.name = name.to_string();
+        }
         let connection_pubkey = connection.keys.public_key();
 
         // Add and persist the connection
         self.add_connection(connection.clone()).await?;
 
         // Create the URI
-        let relay_url_str = if use_local_relay {
-            LOCAL_RELAY_URL
-        } else {
-            REMOTE_RELAY_URL
-        };
-
-        self.ensure_relay(relay_url_str).await?;
+        self.ensure_relay(listen_relay_url).await?;
 
-        let relay_url = Url::from_str(relay_url_str).map_err(|e| Error::Url(e.to_string()))?;
+        let relay_url = Url::from_str(uri_relay_url).map_err(|e| Error::Url(e.to_string()))?;
         let uri = connection.uri(self.service_pubkey(), relay_url)?;
 
         log::info!(
             "Created new standard NWC URI for connection: {} via {}",
             connection_pubkey,
-            relay_url_str
+            uri_relay_url
         );
 
-        Ok(uri)
+        Ok((connection, uri))
     }
 
     /// Creates a kind 13194 info event for the NWC service.
@@ -315,6 +399,15 @@ impl NostrWalletConnect {
             .collect()
     }
 
+    /// Public keys of the current connections, used to detect changes.
+    async fn connection_pubkeys(&self) -> Vec<PublicKey> {
+        let connections = self.connections.read().await;
+        connections
+            .iter()
+            .map(|conn| conn.keys.public_key())
+            .collect()
+    }
+
     /// Processes incoming NWC events in a loop.
     pub async fn process_events_loop(&self) -> Result<(), Error> {
         log::info!("Starting NWC event processing loop");
@@ -322,6 +415,7 @@ impl NostrWalletConnect {
         loop {
             // Get filters for active connections
             let filters = self.filters().await;
+            let subscribed = self.connection_pubkeys().await;
 
             if filters.is_empty() {
                 log::debug!("No active connections, waiting...");
@@ -332,7 +426,7 @@ impl NostrWalletConnect {
             log::debug!("Subscribing with {} filter(s)", filters.len());
 
             // Subscribe to events matching our filters
-            for filter in filters.clone() {
+            for filter in filters {
                 let _ = match self.client.subscribe(filter, None).await {
                     Ok(sub_output) => {
                         log::info!(
@@ -352,41 +446,46 @@ impl NostrWalletConnect {
             // Create a channel to receive notifications
             let mut notifications = self.client.notifications();
 
-            // Process events from the subscription
+            // Process events from the subscription. Each request is handled
+            // in its own task, since a payment can wait minutes for approval.
             loop {
                 tokio::select! {
                     // Check for new events
                     notification = notifications.recv() => {
-                        if let Ok(notification) = notification {
-                            use nostr_sdk::RelayPoolNotification;
-                            if let RelayPoolNotification::Event { event, .. } = notification {
+                        use nostr_sdk::RelayPoolNotification;
+                        use tokio::sync::broadcast::error::RecvError;
+                        match notification {
+                            Ok(RelayPoolNotification::Event { event, .. }) => {
                                 log::debug!("Received event: {} kind={}", event.id, event.kind);
 
                                 // Check if this is a WalletConnectRequest event
                                 if event.kind == Kind::WalletConnectRequest {
-                                    match self.handle_event(*event).await {
-                                        Ok(Some(response)) => {
-                                            log::info!("Sending response event: {}", response.id);
-                                            if let Err(e) = self.client.send_event(&response).await {
-                                                log::error!("Failed to send response: {}", e);
-                                            }
-                                        }
-                                        Ok(None) => {
-                                            log::debug!("Event already processed, skipping");
-                                        }
-                                        Err(e) => {
-                                            log::error!("Error handling event: {}", e);
-                                        }
-                                    }
+                                    let nwc = self.clone();
+                                    tokio::spawn(async move { nwc.respond(*event).await });
                                 }
                             }
+                            Ok(_) => {}
+                            Err(RecvError::Lagged(missed)) => {
+                                // Resubscribing fetches recent requests again
+                                log::warn!(
+                                    "Missed {} relay notifications, resubscribing",
+                                    missed
+                                );
+                                let _ = self.client.unsubscribe_all().await;
+                                break;
+                            }
+                            Err(RecvError::Closed) => {
+                                return Err(Error::Wallet(
+                                    "Relay notifications closed".to_string(),
+                                ));
+                            }
                         }
                     },
                     // Periodically check if filters need updating
                     _ = tokio::time::sleep(Duration::from_secs(10)) => {
-                        let new_filters = self.filters().await;
-                        if new_filters.len() != filters.len() || new_filters.is_empty() {
-                            log::info!("Connection count changed, resubscribing...");
+                        let current = self.connection_pubkeys().await;
+                        if current != subscribed || current.is_empty() {
+                            log::info!("Connections changed, resubscribing...");
                             // Unsubscribe from old filters
                             let _ = self.client.unsubscribe_all().await;
                             break; // Break inner loop to resubscribe with new filters
@@ -397,6 +496,32 @@ impl NostrWalletConnect {
         }
     }
 
+    /// Handles a request event and sends the response.
+    async fn respond(&self, event: Event) {
+        let event_id = event.id.to_string();
+        if !self.in_flight.lock().await.insert(event_id.clone()) {
+            log::debug!("Event {} is already being handled, skipping", event_id);
+            return;
+        }
+        let result = self.handle_event(event).await;
+        self.in_flight.lock().await.remove(&event_id);
+
+        match result {
+            Ok(Some(response)) => {
+                log::info!("Sending response event: {}", response.id);
+                if let Err(e) = self.client.send_event(&response).await {
+                    log::error!("Failed to send response: {}", e);
+                }
+            }
+            Ok(None) => {
+                log::debug!("Event already processed, skipping");
+            }
+            Err(e) => {
+                log::error!("Error handling event: {}", e);
+            }
+        }
+    }
+
     /// Handles a single NWC request event.
     pub async fn handle_event(&self, event: Event) -> Result<Option<Event>, Error> {
         if event.kind != Kind::WalletConnectRequest {
@@ -450,8 +575,10 @@ impl NostrWalletConnect {
 
         log::info!("Processing new NWC event: {}", event_id);
 
-        // Find matching connection (check both standard and NWA connections)
-        let mut connections = self.connections.write().await;
+        // Find matching connection (check both standard and NWA connections).
+        // It is copied out so the connections are not locked while the
+        // request waits for a payment.
+        let connections = self.connections.read().await;
 
         log::debug!(
             "Searching for connection among {} connections for event from {}",
@@ -472,8 +599,8 @@ impl NostrWalletConnect {
             .collect::<Vec<_>>()
             .join(", ");
 
-        let connection = connections
-            .iter_mut()
+        let mut connection = connections
+            .iter()
             .find(|conn| {
                 // For NWA connections, match on app_pubkey
                 if let Some(app_pubkey) = conn.app_pubkey {
@@ -502,7 +629,10 @@ impl NostrWalletConnect {
                     available_connections_str
                 );
                 Error::ConnectionNotFound
-            })?;
+            })?
+            .clone();
+        drop(connections);
+        let connection = &mut connection;
 
         log::info!("Found matching connection for event {}", event_id);
 
@@ -594,30 +724,39 @@ impl NostrWalletConnect {
         // TODO: do thhis so that it acutally updates the budget correctly
         // let remaining_budget_msats = connection.check_and_update_remaining_budget();
 
-        // If budget was renewed, persist the update
         let connection_pubkey = connection.keys.public_key().to_hex();
-        if let Err(e) = self
-            .storage
-            .update_budget(&connection_pubkey, &connection.budget)
-        {
-            log::error!("Failed to persist budget renewal: {}", e);
-        }
 
         // Handle request
-        let (response, payment_amount, balance_info) =
-            self.handle_request(request, remaining_budget_msats).await;
+        let origin = Origin::Nwc {
+            connection: connection_pubkey,
+        };
+        let (response, payment_amount, balance_info) = self
+            .handle_request(request, remaining_budget_msats, &origin)
+            .await;
+        crate::events::publish(crate::events::BackendEvent::NwcRequestHandled {
+            connection: connection.keys.public_key().to_hex(),
+            method: method.to_string(),
+            error: response.error.as_ref().map(|e| e.message.clone()),
+        });
 
-        // Update budget if payment was made
+        // Update budget if payment was made. Other requests may have been
+        // paid in the meantime, so the stored connection is updated.
         if let Some(amount) = payment_amount {
-            connection.budget.used_budget_msats += amount;
-
-            // Persist updated budget to storage
-            let connection_pubkey = connection.keys.public_key().to_hex();
-            if let Err(e) = self
-                .storage
-                .update_budget(&connection_pubkey, &connection.budget)
+            let mut connections = self.connections.write().await;
+            if let Some(stored) = connections
+                .iter_mut()
+                .find(|conn| conn.keys.public_key() == connection.keys.public_key())
             {
-                log::error!("Failed to update connection budget in storage: {}", e);
+                stored.budget.used_budget_msats += amount;
+
+                // Persist updated budget to storage
+                let connection_pubkey = stored.keys.public_key().to_hex();
+                if let Err(e) = self
+                    .storage
+                    .update_budget(&connection_pubkey, &stored.budget)
+                {
+                    log::error!("Failed to update connection budget in storage: {}", e);
+                }
             }
         }
 
@@ -712,7 +851,15 @@ impl NostrWalletConnect {
         log::info!("Processing receive_cashu request");
 
         // Call receive_cashu
-        let result = self.receive_cashu(token).await;
+        let origin = Origin::Nwc {
+            connection: connection.keys.public_key().to_hex(),
+        };
+        let result = self.receive_cashu(token, &origin).await;
+        crate::events::publish(crate::events::BackendEvent::NwcRequestHandled {
+            connection: connection.keys.public_key().to_hex(),
+            method: "receive_cashu".to_string(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
 
         // Build response JSON
         let response_json = match result {
@@ -722,6 +869,8 @@ impl NostrWalletConnect {
                     "result": {
                         "amount": receive_result.amount,
                         "mint_url": receive_result.mint_url,
+                        "status": receive_result.status,
+                        "source_mint_url": receive_result.source_mint_url,
                     }
                 })
             }
@@ -799,7 +948,17 @@ impl NostrWalletConnect {
         log::info!("Processing pay_cashu_request request");
 
         // Call pay_cashu_request
-        let result = self.pay_cashu_request(payment_request, amount).await;
+        let origin = Origin::Nwc {
+            connection: connection.keys.public_key().to_hex(),
+        };
+        let result = self
+            .pay_cashu_request(payment_request, amount, &origin)
+            .await;
+        crate::events::publish(crate::events::BackendEvent::NwcRequestHandled {
+            connection: connection.keys.public_key().to_hex(),
+            method: "pay_cashu_request".to_string(),
+            error: result.as_ref().err().map(|e| e.to_string()),
+        });
 
         // Build response JSON
         let response_json = match result {
@@ -876,6 +1035,7 @@ impl NostrWalletConnect {
         &self,
         request: nip47::Request,
         remaining_budget_msats: u64,
+        origin: &Origin,
     ) -> (nip47::Response, Option<u64>, Option<BalanceInfo>) {
         match request.params {
             nip47::RequestParams::GetBalance => match self.get_balance().await {
@@ -945,7 +1105,7 @@ impl NostrWalletConnect {
             }
             nip47::RequestParams::PayInvoice(params) => {
                 match self
-                    .pay_invoice(&params.invoice, remaining_budget_msats)
+                    .pay_invoice(&params.invoice, remaining_budget_msats, origin)
                     .await
                 {
                     Ok((payment_result, amount_msats)) => (
@@ -1041,8 +1201,10 @@ impl NostrWalletConnect {
         &self,
         invoice: &str,
         remaining_budget_msats: u64,
+        origin: &Origin,
     ) -> Result<(Bolt11PaymentResult, u64), Error> {
         log::info!("Paying invoice via NWC: {}", invoice);
+        self.ensure_unlocked()?;
 
         // Parse invoice to check amount
         let parsed_invoice = Bolt11Invoice::from_str(invoice)?;
@@ -1061,24 +1223,29 @@ impl NostrWalletConnect {
             return Err(Error::BudgetExceeded);
         }
 
-        // Pay invoice through wallet
-        let service = self.service_state.lock().await;
-        let payment_result = service
-            .pay_bolt11_invoice(invoice)
+        // Pay invoice through wallet, without holding the service lock while
+        // the payment waits for approval
+        let payments = self.service_state.lock().await.payments();
+        let payment_result = payments
+            .pay_bolt11_invoice(invoice, origin, false)
             .await
-            .map_err(|e| Error::Wallet(format!("Failed to pay invoice: {}", e)))?;
+            .map_err(|e| Error::payment("Failed to pay invoice", e))?;
 
         Ok((payment_result, amount_msats))
     }
 
     /// Receives a cashu token.
-    async fn receive_cashu(&self, token: &str) -> Result<CashuReceiveResult, Error> {
+    async fn receive_cashu(
+        &self,
+        token: &str,
+        origin: &Origin,
+    ) -> Result<CashuReceiveResult, Error> {
         log::info!("Receiving cashu token via NWC");
 
         // Receive token through wallet
         let service = self.service_state.lock().await;
         let receive_result = service
-            .receive_cashu_token(token)
+            .receive_cashu_token(token, origin, false)
             .await
             .map_err(|e| Error::Wallet(format!("Failed to receive cashu token: {}", e)))?;
 
@@ -1096,15 +1263,17 @@ impl NostrWalletConnect {
         &self,
         payment_request: &str,
         amount: Option<u64>,
+        origin: &Origin,
     ) -> Result<PayNut18Result, Error> {
         log::info!("Paying cashu payment request via NWC");
+        self.ensure_unlocked()?;
 
         // Pay payment request through wallet
-        let service = self.service_state.lock().await;
-        let pay_result = service
-            .pay_nut18_payment_request_with_token(payment_request, amount)
+        let payments = self.service_state.lock().await.payments();
+        let pay_result = payments
+            .pay_nut18_payment_request_with_token(payment_request, amount, origin, false)
             .await
-            .map_err(|e| Error::Wallet(format!("Failed to pay cashu payment request: {}", e)))?;
+            .map_err(|e| Error::payment("Failed to pay cashu payment request", e))?;
 
         if pay_result.token.is_some() {
             log::info!(
@@ -1179,7 +1348,7 @@ impl NostrWalletConnect {
             "secret": secret,
             "pubkey": connection.keys.public_key().to_hex(),
             "commands": ["pay_invoice", "make_invoice", "get_balance", "receive_cashu", "pay_cashu_request"],
-            "relay": REMOTE_RELAY_URL,
+            "relay": self.relay_url,
             "lud16": lud16,
         });
 
@@ -1200,7 +1369,7 @@ impl NostrWalletConnect {
             .sign_with_keys(&connection.keys)?;
         // Add specified relays if they're different from our default
         for relay_url in relays {
-            if relay_url != REMOTE_RELAY_URL {
+            if relay_url != self.relay_url {
                 if let Err(e) = self.client.add_relay(&relay_url).await {
                     log::warn!("Failed to add relay {}: {}", relay_url, e);
                 }
@@ -1373,7 +1542,7 @@ impl Default for ConnectionBudget {
         Self {
             renewal_period: BudgetRenewalPeriod::Daily,
             renews_at: None,
-            total_budget_msats: NWC_BUDGET_MSATS,
+            total_budget_msats: config::current().nwc.default_budget_sats * 1000,
             used_budget_msats: 0,
         }
     }
@@ -1397,6 +1566,9 @@ pub enum BudgetRenewalPeriod {
 /// NWC error types.
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
+    #[error("Payment approval timed out")]
+    ApprovalTimedOut,
+
     #[error("Budget exceeded")]
     BudgetExceeded,
 
@@ -1424,9 +1596,15 @@ pub enum Error {
     #[error("Key error: {0}")]
     Key(#[from] nostr_sdk::key::Error),
 
+    #[error("Wallet is locked")]
+    Locked,
+
     #[error("Missing service key in event")]
     MissingServiceKey,
 
+    #[error("{0}")]
+    PaymentRefused(String),
+
     #[error("NIP-04 error: {0}")]
     Nip04(#[from] nip04::Error),
 
@@ -1440,6 +1618,20 @@ pub enum Error {
     Wallet(String),
 }
 
+impl Error {
+    /// Wallet payment error, keeping spending policy refusals distinct so
+    /// clients get a meaningful NIP-47 code
+    fn payment(context: &str, err: TollGateError) -> Self {
+        match err {
+            TollGateError::SpendApprovalTimedOut => Error::ApprovalTimedOut,
+            TollGateError::SpendDenied(_)
+            | TollGateError::SpendRejected(_)
+            | TollGateError::SpendApprovalRequired(_) => Error::PaymentRefused(err.to_string()),
+            err => Error::Wallet(format!("{}: {}", context, err)),
+        }
+    }
+}
+
 impl From<lightning_invoice::ParseOrSemanticError> for Error {
     fn from(err: lightning_invoice::ParseOrSemanticError) -> Self {
         Error::InvoiceParse(format!("{:?}", err))
@@ -1457,6 +1649,18 @@ impl From<Error> for nip47::NIP47Error {
                 code: nip47::ErrorCode::Other,
                 message: "Invalid invoice".to_string(),
             },
+            Error::ApprovalTimedOut => nip47::NIP47Error {
+                code: nip47::ErrorCode::Restricted,
+                message: "Payment was not approved in time".to_string(),
+            },
+            Error::Locked => nip47::NIP47Error {
+                code: nip47::ErrorCode::Restricted,
+                message: "Wallet is locked".to_string(),
+            },
+            Error::PaymentRefused(message) => nip47::NIP47Error {
+                code: nip47::ErrorCode::Restricted,
+                message,
+            },
             e => nip47::NIP47Error {
                 code: nip47::ErrorCode::Internal,
                 message: e.to_string(),
@@ -1477,7 +1681,7 @@ mod tests {
 
         // Step 1: Create TollGate service
         println!("Step 1: Creating TollGate service...");
-        let service = TollGateService::new()
+        let service = TollGateService::new(Arc::default())
             .await
             .expect("Failed to create TollGate service");
         let service_state = Arc::new(Mutex::new(service));
@@ -1597,7 +1801,7 @@ mod tests {
 
         // Step 1: Create TollGate service
         println!("Step 1: Creating TollGate service...");
-        let service = TollGateService::new()
+        let service = TollGateService::new(Arc::default())
             .await
             .expect("Failed to create TollGate service");
         let service_state = Arc::new(Mutex::new(service));
@@ -1686,7 +1890,7 @@ mod tests {
 
         // Step 1: Create TollGate service
         println!("Step 1: Creating TollGate service...");
-        let service = TollGateService::new()
+        let service = TollGateService::new(Arc::default())
             .await
             .expect("Failed to create TollGate service");
         let service_state = Arc::new(Mutex::new(service));
@@ -1720,7 +1924,10 @@ mod tests {
         // Note: Using a test token that may already be spent in testing environment
         let token = "cashuBo2FteCJodHRwczovL25vZmVlcy50ZXN0bnV0LmNhc2h1LnNwYWNlYXVjc2F0YXSBomFpSAC0zSfYhhpEYXCFpGFhCGFzeEE5YmViNTE0ZTE2MjFkM2RkYTY0MjgyNDg4Zjg5ZTBkZTk4Y2IyNmM3NGI2MjNmNjllZGMwYWMxOTA3ZTAxMjA1YWNYIQMw7UppJvgL0Ixr7brd2QUSiZ_BkkWgkpmo_ojPa-W5wGFko2FlWCDgFHEyX6D2iU-Mam3xrcfzMHTXP2QFuDALk8BKQqxhIWFzWCDD81s4-_savlVBT05zsXEYv59_DT9G_VuSHzgMUU081GFyWCDIs4v0uSoV9dlp09FeFE7iNG1RGmbd7n4zwkBotSS0_6RhYQhhc3hAMDg5NTg4M2Y4NjQwMzMwY2Q1ODY1ODc0MTE5ZGRkZWExODJiZWYxNmU1ZWI5YzliODk3YjUxNzI4NjgzMzdmM2FjWCECwXXD_aWRi1ZY4VAw4QC_3WAd-dzIO16wsP0448PSZfZhZKNhZVggI96r12eU2NmET3Y9iuvRB_BHA8yTKJ0ovVqXpAVXnTFhc1gg25yD6mRI9PMP70IqAje3BDgiQOsnGrsM5vSJbOm8slVhclgg8jW7TRtey7xrQfv762Fx9aGICHfeFQ1UTaj5MPi6IAmkYWECYXN4QDhhOWEyNmM0ZDg4ZWYyY2E2MDlkYjJjNjY3MWQ1YTU3OWZhMDhkYjU1ODI3YmVjZGJiMmNlNTNiOGEyZWVjMGVhY1ghApZZIz1vpxeW6zrSv44msnU3Ky0M0Ad8kCbxfCW9F8GqYWSjYWVYIAD_aln-jTz31V1v3Jcp8zLZoIHmKGCwJcsZrHmbvqAaYXNYIC7lL1yomkctyPMfGjPj6hsm6ZTs5gyJkiUtuxSan1BMYXJYIDf4xrFqo6s200g1AOLP8CZqFjgRUBqL8St5tF_1PGRQpGFhAmFzeEE0YzAzMTI1ZDRhZTU3NWM2MTBiNzBmMWYwN2VlMTNiMjkwN2E2MWQ4NzgwOWRkMjM2MTA2NmJjNjAwNzVmZDQ3YWNYIQNXh9p03x9bqCAj4picnMqOpqY9m8S3W3502ayAaqGvJmFko2FlWCBM--Kr27PYSt-xNng4q5a8w_3moX8V2JybosGthPnzrGFzWCCcrJS0WuLvD3b_Y0g_8OImwA9Ly2rKwp2bRvAskjegKGFyWCBZfFAv0nqKNBC_FM8QzSu3eOV4NkA3eSD40CVMiCi5rKRhYQFhc3hAMGZhMjM4M2Y1YjUzZTA0MWQzOWIxMDQ4YWVlZWQ3NjRmMTU1MDBkMzE4YmI1ZGU4MzNiOTJkZjUzMjBkZjM2NGFjWCEC6_oMe4HmiKrmyukKGez4sOaA-m2I7MloMXqE9zbFoDJhZKNhZVggagzjRZB-jJ9xJ1KZzbyRCH2C39Utiole54pyD0fnIvBhc1ggulky-qM3PRpNCg_tZoSWPDFnpSqdB0SX6M4KvINWmeZhclggbMnmAC1Pe3KPY07KJqTPh84IsgrmqmcjNYHMsp3wCFQ";
 
-        match nwc.receive_cashu(token).await {
+        let origin = Origin::Nwc {
+            connection: nwc.service_pubkey().to_hex(),
+        };
+        match nwc.receive_cashu(token, &origin).await {
             Ok(result) => {
                 println!("✓ Successfully received cashu token!");
                 println!("  Amount: {} sats", result.amount);
@@ -1769,7 +1976,7 @@ mod tests {
 
         // Step 1: Create TollGate service
         println!("Step 1: Creating TollGate service...");
-        let service = TollGateService::new()
+        let service = TollGateService::new(Arc::default())
             .await
             .expect("Failed to create TollGate service");
         let service_state = Arc::new(Mutex::new(service));
@@ -1790,7 +1997,10 @@ mod tests {
         println!("\nStep 3: Paying cashu payment request...");
         let payment_request = "creqApWF0gaNhdGVub3N0cmFheKlucHJvZmlsZTFxeTI4d3VtbjhnaGo3dW45ZDNzaGp0bnl2OWtoMnVld2Q5aHN6OW1od2RlbjV0ZTB3ZmprY2N0ZTljdXJ4dmVuOWVlaHFjdHJ2NWhzenJ0aHdkZW41dGUwZGVoaHh0bnZkYWtxcWd6Z21yMnB0MDk0OTV0ZG5sbXduZ3NmdTN5NjR1cDh4ODVmcnM5c2h5a3lwYzU0dm5ranNneTU2enNtYWeBgmFuYjE3YWloNWVmYzE3ZWZhYQVhdWNzYXRhbYF4Imh0dHBzOi8vbm9mZWVzLnRlc3RudXQuY2FzaHUuc3BhY2U=";
 
-        match nwc.pay_cashu_request(payment_request, None).await {
+        match nwc
+            .pay_cashu_request(payment_request, None, &Origin::Ui)
+            .await
+        {
             Ok(result) => {
                 println!("✓ Successfully processed cashu payment request!");
                 println!("  Amount: {} sats", result.amount);
@@ -1842,7 +2052,7 @@ mod tests {
 
         // Step 1: Create TollGate service
         println!("Step 1: Creating TollGate service...");
-        let service = TollGateService::new()
+        let service = TollGateService::new(Arc::default())
             .await
             .expect("Failed to create TollGate service");
         let service_state = Arc::new(Mutex::new(service));
@@ -1894,7 +2104,10 @@ mod tests {
 
         // Step 5: Pay the cashu request (should return a token since there's no transport)
         println!("\nStep 5: Paying cashu payment request with no transport...");
-        match nwc.pay_cashu_request(&payment_request, None).await {
+        match nwc
+            .pay_cashu_request(&payment_request, None, &Origin::Ui)
+            .await
+        {
             Ok(result) => {
                 println!("✓ Successfully processed cashu payment request!");
                 println!("  Amount: {} sats", result.amount);
@@ -1949,7 +2162,10 @@ mod tests {
 
         // Step 7: Pay the amount-less request with a custom amount
         println!("\nStep 7: Paying amount-less payment request with custom amount of 10 sats...");
-        match nwc.pay_cashu_request(&amountless_request, Some(10)).await {
+        match nwc
+            .pay_cashu_request(&amountless_request, Some(10), &Origin::Ui)
+            .await
+        {
             Ok(result) => {
                 println!(
                     "✓ Successfully processed amount-less payment request with custom amount!"
@@ -1984,7 +2200,10 @@ mod tests {
 
         // Step 8: Test that amount-less request fails without custom amount
         println!("\nStep 8: Testing that amount-less request fails without custom amount...");
-        match nwc.pay_cashu_request(&amountless_request, None).await {
+        match nwc
+            .pay_cashu_request(&amountless_request, None, &Origin::Ui)
+            .await
+        {
             Ok(_) => {
                 println!(
                     "✗ Unexpectedly succeeded paying amount-less request without custom amount!"
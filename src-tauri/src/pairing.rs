@@ -0,0 +1,550 @@
+//! Pairing of clients with the connection server
+//!
+//! Creating NWC connection requests and using the Routstr proxy need a
+//! client token in the `X-Wally-Token` header, because `Authorization` is
+//! passed on to the proxied provider. A web page or tool without a token
+//! asks for one with `POST /pair`; once the user approves, it collects the
+//! token from `GET /pair/:id`, exactly once. Tokens issued this way have the
+//! `connect` and `proxy` scopes and are bound to the page's origin.
+//!
+//! CORS only admits the origins of paired clients and the configured
+//! allow-list, and pairing and connection requests are rate limited, so a
+//! page the user happens to visit cannot flood them with prompts.
+
+use crate::api_clients::{ApiClient, ApiClientError, ApiClients, ApiClientsState, ApiScope};
+use crate::connection_server::ConnectionServerState;
+use crate::events::{self, BackendEvent};
+use crate::local_api::ApiAuth;
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Header carrying a client token on the connection and proxy routes
+pub const CLIENT_TOKEN_HEADER: &str = "x-wally-token";
+
+/// How long a pairing request waits for the user
+const PAIRING_TTL_SECS: u64 = 600;
+/// Pairing requests waiting at once, across all origins
+const MAX_PENDING_PAIRINGS: usize = 16;
+const MAX_NAME_LEN: usize = 64;
+/// Pairing requests per origin per minute
+const PAIRING_RATE: usize = 3;
+/// Connection requests per client per minute
+const CONNECTION_RATE: usize = 10;
+
+pub type PairingsState = Arc<Pairings>;
+
+#[derive(Debug, Error)]
+pub enum PairingError {
+    #[error("Client name must be 1 to {MAX_NAME_LEN} characters")]
+    InvalidName,
+    #[error("Too many requests, try again later")]
+    RateLimited,
+    #[error("Too many pairing requests are waiting for the user")]
+    TooManyPending,
+    #[error("Pairing request not found: {0}")]
+    NotFound(String),
+    #[error(transparent)]
+    Client(#[from] ApiClientError),
+}
+
+impl PairingError {
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            PairingError::InvalidName => StatusCode::BAD_REQUEST,
+            PairingError::RateLimited | PairingError::TooManyPending => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            PairingError::NotFound(_) => StatusCode::NOT_FOUND,
+            PairingError::Client(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// A client asking to pair, shown to the user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairingRequest {
+    pub id: String,
+    pub name: String,
+    /// Origin of the page that asked; `None` for tools outside a browser
+    pub origin: Option<String>,
+    pub requested_at: u64,
+    pub expires_at: u64,
+}
+
+/// What a pairing client sees when it polls
+#[derive(Debug)]
+pub enum PairingStatus {
+    Pending,
+    Approved { client: ApiClient, token: String },
+    Rejected,
+    NotFound,
+}
+
+enum PairingState {
+    Pending,
+    Approved { client: ApiClient, token: String },
+    Rejected,
+}
+
+struct PairingEntry {
+    request: PairingRequest,
+    state: PairingState,
+}
+
+/// Sliding-window limit on requests per key
+struct RateLimiter {
+    max: usize,
+    window: Duration,
+    hits: Mutex<HashMap<String, VecDeque<Instant>>>,
+}
+
+impl RateLimiter {
+    fn new(max: usize, window: Duration) -> Self {
+        Self {
+            max,
+            window,
+            hits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Record a request for `key`, or refuse it when over the limit
+    fn check(&self, key: &str) -> bool {
+        let now = Instant::now();
+        let mut hits = self.hits.lock().expect("rate limiter poisoned");
+        hits.retain(|_, times| {
+            while matches!(times.front(), Some(at) if now.duration_since(*at) >= self.window) {
+                times.pop_front();
+            }
+            !times.is_empty()
+        });
+
+        let times = hits.entry(key.to_string()).or_default();
+        if times.len() >= self.max {
+            return false;
+        }
+        times.push_back(now);
+        true
+    }
+}
+
+pub struct Pairings {
+    /// Origins admitted by CORS besides those of paired clients
+    allowed_origins: Vec<String>,
+    pending: Mutex<HashMap<String, PairingEntry>>,
+    pairing_limit: RateLimiter,
+    connection_limit: RateLimiter,
+}
+
+impl Default for Pairings {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+fn now_secs() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+impl Pairings {
+    pub fn new(allowed_origins: Vec<String>) -> Self {
+        let minute = Duration::from_secs(60);
+        Self {
+            allowed_origins,
+            pending: Mutex::new(HashMap::new()),
+            pairing_limit: RateLimiter::new(PAIRING_RATE, minute),
+            connection_limit: RateLimiter::new(CONNECTION_RATE, minute),
+        }
+    }
+
+    /// Whether browsers may call the server from `origin`
+    pub fn origin_allowed(&self, origin: &str, clients: &ApiClients) -> bool {
+        self.allowed_origins.iter().any(|allowed| allowed == origin)
+            || clients.has_web_origin(origin)
+    }
+
+    /// Count a connection request by `caller` against its rate limit
+    pub fn check_connection_rate(&self, caller: &str) -> Result<(), PairingError> {
+        if self.connection_limit.check(caller) {
+            Ok(())
+        } else {
+            Err(PairingError::RateLimited)
+        }
+    }
+
+    fn prune(pending: &mut HashMap<String, PairingEntry>, now: u64) {
+        pending.retain(|_, entry| entry.request.expires_at > now);
+    }
+
+    /// Ask the user to pair a client
+    pub fn request(
+        &self,
+        name: &str,
+        origin: Option<String>,
+    ) -> Result<PairingRequest, PairingError> {
+        let name = name.trim();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LEN {
+            return Err(PairingError::InvalidName);
+        }
+        if !self
+            .pairing_limit
+            .check(origin.as_deref().unwrap_or("local"))
+        {
+            return Err(PairingError::RateLimited);
+        }
+
+        let now = now_secs();
+        let mut pending = self.pending.lock().expect("pairings poisoned");
+        Self::prune(&mut pending, now);
+        if pending.len() >= MAX_PENDING_PAIRINGS {
+            return Err(PairingError::TooManyPending);
+        }
+
+        let request = PairingRequest {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            origin,
+            requested_at: now,
+            expires_at: now + PAIRING_TTL_SECS,
+        };
+        pending.insert(
+            request.id.clone(),
+            PairingEntry {
+                request: request.clone(),
+                state: PairingState::Pending,
+            },
+        );
+        log::info!(
+            "Pairing request {} from {} ({})",
+            request.id,
+            request.name,
+            request.origin.as_deref().unwrap_or("no origin")
+        );
+        Ok(request)
+    }
+
+    /// Requests waiting for the user, oldest first
+    pub fn list(&self) -> Vec<PairingRequest> {
+        let mut pending = self.pending.lock().expect("pairings poisoned");
+        Self::prune(&mut pending, now_secs());
+        let mut requests: Vec<_> = pending
+            .values()
+            .filter(|entry| matches!(entry.state, PairingState::Pending))
+            .map(|entry| entry.request.clone())
+            .collect();
+        requests.sort_by_key(|request| request.requested_at);
+        requests
+    }
+
+    /// Issue the client's token, to be collected by the client
+    pub fn approve(&self, id: &str, clients: &ApiClients) -> Result<ApiClient, PairingError> {
+        let mut pending = self.pending.lock().expect("pairings poisoned");
+        Self::prune(&mut pending, now_secs());
+        let entry = pending
+            .get_mut(id)
+            .filter(|entry| matches!(entry.state, PairingState::Pending))
+            .ok_or_else(|| PairingError::NotFound(id.to_string()))?;
+
+        let issued = clients.issue(
+            &entry.request.name,
+            vec![ApiScope::Connect, ApiScope::Proxy],
+            entry.request.origin.clone(),
+        )?;
+        entry.state = PairingState::Approved {
+            client: issued.client.clone(),
+            token: issued.token,
+        };
+        Ok(issued.client)
+    }
+
+    pub fn reject(&self, id: &str) -> Result<(), PairingError> {
+        let mut pending = self.pending.lock().expect("pairings poisoned");
+        let entry = pending
+            .get_mut(id)
+            .filter(|entry| matches!(entry.state, PairingState::Pending))
+            .ok_or_else(|| PairingError::NotFound(id.to_string()))?;
+        entry.state = PairingState::Rejected;
+        log::info!("Pairing request {} rejected", id);
+        Ok(())
+    }
+
+    /// Status of a pairing for the client polling from `origin`. An approved
+    /// token is handed out once, after which the request is gone.
+    pub fn collect(&self, id: &str, origin: Option<&str>) -> PairingStatus {
+        let mut pending = self.pending.lock().expect("pairings poisoned");
+        Self::prune(&mut pending, now_secs());
+        let answered = match pending.get(id) {
+            Some(entry) if entry.request.origin.as_deref() == origin => {
+                !matches!(entry.state, PairingState::Pending)
+            }
+            _ => return PairingStatus::NotFound,
+        };
+        if !answered {
+            return PairingStatus::Pending;
+        }
+
+        match pending.remove(id).map(|entry| entry.state) {
+            Some(PairingState::Approved { client, token }) => {
+                log::info!("Pairing {} collected by {}", id, client.name);
+                PairingStatus::Approved { client, token }
+            }
+            Some(PairingState::Rejected) => PairingStatus::Rejected,
+            _ => PairingStatus::NotFound,
+        }
+    }
+}
+
+/// Identity of a caller, used for rate limits and to tie connection
+/// requests to the client that made them
+pub(crate) fn caller_id(auth: &ApiAuth) -> String {
+    match auth {
+        ApiAuth::Owner => "owner".to_string(),
+        ApiAuth::Client(client) => client.id.clone(),
+    }
+}
+
+/// Check a request to the connection or proxy routes. It needs the owner
+/// token or a client token with `scope`, and a page may only use a token
+/// paired from its own origin.
+pub(crate) fn authorize(
+    state: &ConnectionServerState,
+    headers: &HeaderMap,
+    scope: ApiScope,
+) -> Result<ApiAuth, (StatusCode, String)> {
+    let token = headers
+        .get(CLIENT_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    if token.is_empty() {
+        return Err((
+            StatusCode::UNAUTHORIZED,
+            format!(
+                "Pair with the wallet first (POST /pair) and send the token in {}",
+                CLIENT_TOKEN_HEADER
+            ),
+        ));
+    }
+    let auth = ApiAuth::from_token(state, token)
+        .ok_or_else(|| (StatusCode::UNAUTHORIZED, "Invalid client token".to_string()))?;
+
+    if let Some(origin) = origin(headers) {
+        let paired_from = match &auth {
+            ApiAuth::Client(client) => client.web_origin.as_deref(),
+            ApiAuth::Owner => None,
+        };
+        if paired_from != Some(origin.as_str()) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                format!("Token was not paired from {}", origin),
+            ));
+        }
+    }
+
+    if let ApiAuth::Client(client) = &auth {
+        if !client.has_scope(scope) {
+            return Err((
+                StatusCode::FORBIDDEN,
+                format!("Token lacks the {:?} scope", scope).to_lowercase(),
+            ));
+        }
+    }
+    Ok(auth)
+}
+
+fn origin(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::ORIGIN)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string)
+}
+
+fn failure(status: StatusCode, message: impl Into<String>) -> Response {
+    (
+        status,
+        Json(json!({
+            "success": false,
+            "error": message.into()
+        })),
+    )
+        .into_response()
+}
+
+#[derive(Deserialize)]
+pub(crate) struct PairBody {
+    name: String,
+}
+
+/// Handler for POST /pair - Ask the user to pair a client
+pub(crate) async fn request_pairing(
+    State(state): State<ConnectionServerState>,
+    headers: HeaderMap,
+    Json(body): Json<PairBody>,
+) -> Response {
+    match state.pairings.request(&body.name, origin(&headers)) {
+        Ok(pairing) => {
+            let response = Json(json!({
+                "success": true,
+                "pairing_id": pairing.id,
+                "expires_at": pairing.expires_at,
+                "poll_url": format!("/pair/{}", pairing.id)
+            }))
+            .into_response();
+            events::publish(BackendEvent::PairingRequested { pairing });
+            response
+        }
+        Err(e) => failure(e.status(), e.to_string()),
+    }
+}
+
+/// Handler for GET /pair/:id - Collect the token once the user approved
+pub(crate) async fn poll_pairing(
+    State(state): State<ConnectionServerState>,
+    headers: HeaderMap,
+    Path(id): Path<String>,
+) -> Response {
+    match state.pairings.collect(&id, origin(&headers).as_deref()) {
+        PairingStatus::Pending => Json(json!({
+            "status": "pending",
+            "message": "Waiting for user approval"
+        }))
+        .into_response(),
+        PairingStatus::Approved { client, token } => Json(json!({
+            "status": "approved",
+            "client_id": client.id,
+            "scopes": client.scopes,
+            "token": token,
+            "token_header": CLIENT_TOKEN_HEADER
+        }))
+        .into_response(),
+        PairingStatus::Rejected => Json(json!({
+            "status": "rejected",
+            "message": "Pairing was rejected by user"
+        }))
+        .into_response(),
+        PairingStatus::NotFound => (
+            StatusCode::NOT_FOUND,
+            Json(json!({
+                "status": "not_found",
+                "error": "Pairing request not found or expired"
+            })),
+        )
+            .into_response(),
+    }
+}
+
+#[tauri::command]
+pub async fn list_pairing_requests(
+    pairings: tauri::State<'_, PairingsState>,
+    lock: tauri::State<'_, crate::app_lock::AppLockState>,
+) -> Result<Vec<PairingRequest>, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
+    Ok(pairings.list())
+}
+
+#[tauri::command]
+pub async fn approve_pairing(
+    id: String,
+    pairings: tauri::State<'_, PairingsState>,
+    clients: tauri::State<'_, ApiClientsState>,
+    lock: tauri::State<'_, crate::app_lock::AppLockState>,
+) -> Result<ApiClient, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    pairings.approve(&id, &clients).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn reject_pairing(
+    id: String,
+    pairings: tauri::State<'_, PairingsState>,
+) -> Result<(), String> {
+    pairings.reject(&id).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        assert!(limiter.check("a"));
+        assert!(limiter.check("a"));
+        assert!(!limiter.check("a"));
+        assert!(limiter.check("b"));
+
+        let limiter = RateLimiter::new(1, Duration::ZERO);
+        assert!(limiter.check("a"));
+        assert!(limiter.check("a"));
+    }
+
+    #[test]
+    fn test_pairing_flow() {
+        let clients = ApiClients::with_clients(None, Vec::new());
+        let pairings = Pairings::new(vec!["https://allowed.example".to_string()]);
+        let origin = "https://app.example";
+
+        assert!(matches!(
+            pairings.request(" ", None),
+            Err(PairingError::InvalidName)
+        ));
+        let request = pairings
+            .request("Web app", Some(origin.to_string()))
+            .unwrap();
+        assert_eq!(pairings.list().len(), 1);
+        assert!(matches!(
+            pairings.collect(&request.id, Some(origin)),
+            PairingStatus::Pending
+        ));
+        // Another page cannot see or collect it
+        assert!(matches!(
+            pairings.collect(&request.id, Some("https://evil.example")),
+            PairingStatus::NotFound
+        ));
+
+        assert!(!pairings.origin_allowed(origin, &clients));
+        assert!(pairings.origin_allowed("https://allowed.example", &clients));
+        let client = pairings.approve(&request.id, &clients).unwrap();
+        assert_eq!(client.web_origin.as_deref(), Some(origin));
+        assert!(pairings.origin_allowed(origin, &clients));
+        assert!(pairings.list().is_empty());
+
+        let PairingStatus::Approved { token, .. } = pairings.collect(&request.id, Some(origin))
+        else {
+            panic!("pairing should be approved");
+        };
+        let paired = clients.authenticate(&token).unwrap();
+        assert!(paired.has_scope(ApiScope::Proxy));
+        assert!(!paired.has_scope(ApiScope::Spend));
+        // The token is handed out once
+        assert!(matches!(
+            pairings.collect(&request.id, Some(origin)),
+            PairingStatus::NotFound
+        ));
+
+        let rejected = pairings.request("Tool", None).unwrap();
+        pairings.reject(&rejected.id).unwrap();
+        assert!(pairings.approve(&rejected.id, &clients).is_err());
+        assert!(matches!(
+            pairings.collect(&rejected.id, None),
+            PairingStatus::Rejected
+        ));
+
+        // Requests without an origin share one rate limit
+        for _ in 1..PAIRING_RATE {
+            pairings.request("Tool", None).unwrap();
+        }
+        assert!(matches!(
+            pairings.request("Tool", None),
+            Err(PairingError::RateLimited)
+        ));
+    }
+}
@@ -0,0 +1,453 @@
+//! Classify and decode arbitrary payment input
+//!
+//! Takes whatever the user pasted or scanned and returns a typed preview,
+//! so the frontend does not have to guess which command to call.
+//!
+//! Supported inputs:
+//! - BOLT11 invoices
+//! - Cashu tokens (v3 `cashuA`, v4 `cashuB`)
+//! - NUT-18 payment requests (`creqA`)
+//! - LNURLs and Lightning addresses
+//! - Nostr Wallet Connect URIs (`nostr+walletconnect://`)
+//! - Nostr Wallet Auth URIs (`nostr+walletauth://`)
+
+use crate::connection_server::parse_nwa_uri;
+use crate::lnurl;
+use crate::tollgate::wallet::WalletBalance;
+use cdk::nuts::nut18::payment_request::PaymentRequest;
+use cdk::nuts::Token;
+use lightning_invoice::{Bolt11Invoice, Bolt11InvoiceDescriptionRef};
+use nostr_sdk::nips::nip47::NostrWalletConnectURI;
+use serde::Serialize;
+use std::str::FromStr;
+
+/// Kind of payment input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentInputKind {
+    Bolt11,
+    CashuToken,
+    Nut18Request,
+    Lnurl,
+    LightningAddress,
+    NwcUri,
+    NwaUri,
+}
+
+/// What the wallet would do with the input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentAction {
+    Pay,
+    Receive,
+    Connect,
+}
+
+/// Decoded preview of a payment input
+#[derive(Debug, Clone, Serialize)]
+pub struct PaymentInputPreview {
+    pub kind: PaymentInputKind,
+    pub action: PaymentAction,
+    /// Input with URI prefixes such as `lightning:` removed
+    pub normalized: String,
+    pub amount: Option<u64>,
+    pub unit: Option<String>,
+    /// Mints the payment is restricted to (or issued by, for tokens)
+    pub mints: Vec<String>,
+    /// Expiry as unix timestamp in seconds
+    pub expiry: Option<u64>,
+    pub description: Option<String>,
+    /// Whether the wallet has enough funds at a suitable mint to pay
+    pub can_pay: bool,
+    /// Kind-specific fields (relays, LNURL endpoint, token version, ...)
+    pub details: serde_json::Value,
+}
+
+impl PaymentInputPreview {
+    fn new(kind: PaymentInputKind, action: PaymentAction, normalized: String) -> Self {
+        Self {
+            kind,
+            action,
+            normalized,
+            amount: None,
+            unit: None,
+            mints: Vec::new(),
+            expiry: None,
+            description: None,
+            can_pay: false,
+            details: serde_json::Value::Null,
+        }
+    }
+
+    /// Fill in `can_pay` from the current wallet balances
+    pub fn apply_balances(&mut self, balances: &[WalletBalance]) {
+        if self.action != PaymentAction::Pay {
+            self.can_pay = false;
+            return;
+        }
+
+        let expired = self
+            .details
+            .get("expired")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false);
+        if expired {
+            self.can_pay = false;
+            return;
+        }
+
+        let mut usable = balances.iter().filter(|balance| {
+            let mint_url = balance.mint_url.trim_end_matches('/');
+            self.mints.is_empty()
+                || self
+                    .mints
+                    .iter()
+                    .any(|mint| mint.trim_end_matches('/') == mint_url)
+        });
+
+        self.can_pay = match self.amount {
+            Some(amount) => usable.any(|balance| balance.balance >= amount),
+            None => usable.any(|balance| balance.balance > 0),
+        };
+    }
+}
+
+/// Strip common URI schemes so the payload itself can be parsed
+fn normalize_input(input: &str) -> String {
+    let trimmed = input.trim();
+    let lower = trimmed.to_lowercase();
+
+    // BIP21 URIs may carry a BOLT11 invoice in the `lightning` parameter
+    if lower.starts_with("bitcoin:") {
+        if let Some((_, query)) = trimmed.split_once('?') {
+            for pair in query.split('&') {
+                if let Some((key, value)) = pair.split_once('=') {
+                    if key.eq_ignore_ascii_case("lightning") {
+                        return urlencoding::decode(value)
+                            .map(|v| v.to_string())
+                            .unwrap_or_else(|_| value.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    for prefix in ["lightning:", "cashu:", "lnurl:", "lnurlp://"] {
+        if lower.starts_with(prefix) {
+            let rest = &trimmed[prefix.len()..];
+            // `lnurlp://` (LUD-17) is a plain URL with a different scheme
+            if prefix == "lnurlp://" {
+                return format!("https://{}", rest);
+            }
+            return rest.trim_start_matches("//").to_string();
+        }
+    }
+
+    trimmed.to_string()
+}
+
+/// Classify and decode a payment input without network access
+pub fn parse_payment_input(input: &str) -> Result<PaymentInputPreview, String> {
+    let normalized = normalize_input(input);
+    let lower = normalized.to_lowercase();
+
+    if lower.is_empty() {
+        return Err("Input is empty".to_string());
+    }
+
+    if lower.starts_with("nostr+walletauth://") {
+        return parse_nwa(normalized);
+    }
+
+    if lower.starts_with("nostr+walletconnect://") {
+        return parse_nwc(normalized);
+    }
+
+    if lower.starts_with("creqa") {
+        return parse_nut18(normalized);
+    }
+
+    if lower.starts_with("cashua") || lower.starts_with("cashub") {
+        return parse_cashu_token(normalized);
+    }
+
+    if lower.starts_with("lnurl1") {
+        let url = lnurl::decode_lnurl(&normalized)?;
+        let mut preview =
+            PaymentInputPreview::new(PaymentInputKind::Lnurl, PaymentAction::Pay, normalized);
+        preview.details = serde_json::json!({ "url": url });
+        return Ok(preview);
+    }
+
+    if lower.starts_with("https://") && lower.contains("lightning=lnurl1") {
+        // Fallback scheme of LUD-01: https://example.com?lightning=LNURL1...
+        if let Some(start) = lower.find("lightning=") {
+            let encoded: String = normalized[start + "lightning=".len()..]
+                .chars()
+                .take_while(|c| c.is_ascii_alphanumeric())
+                .collect();
+            return parse_payment_input(&encoded);
+        }
+    }
+
+    if lower.starts_with("https://") && lower.contains("/.well-known/lnurlp/") {
+        let mut preview = PaymentInputPreview::new(
+            PaymentInputKind::Lnurl,
+            PaymentAction::Pay,
+            normalized.clone(),
+        );
+        preview.details = serde_json::json!({ "url": normalized });
+        return Ok(preview);
+    }
+
+    if lnurl::is_lightning_address(&normalized) {
+        let url = lnurl::lightning_address_url(&normalized)?;
+        let mut preview = PaymentInputPreview::new(
+            PaymentInputKind::LightningAddress,
+            PaymentAction::Pay,
+            normalized,
+        );
+        preview.details = serde_json::json!({ "url": url });
+        return Ok(preview);
+    }
+
+    if lower.starts_with("lnbc") || lower.starts_with("lntb") || lower.starts_with("lnsb") {
+        return parse_bolt11(normalized);
+    }
+
+    Err("Unrecognized payment input".to_string())
+}
+
+fn parse_bolt11(normalized: String) -> Result<PaymentInputPreview, String> {
+    let invoice = Bolt11Invoice::from_str(&normalized)
+        .map_err(|e| format!("Invalid BOLT11 invoice: {}", e))?;
+
+    let mut preview =
+        PaymentInputPreview::new(PaymentInputKind::Bolt11, PaymentAction::Pay, normalized);
+    preview.amount = invoice.amount_milli_satoshis().map(|msat| msat.div_ceil(1000));
+    preview.unit = Some("sat".to_string());
+    preview.expiry = invoice.expires_at().map(|expiry| expiry.as_secs());
+    preview.description = match invoice.description() {
+        Bolt11InvoiceDescriptionRef::Direct(description) => Some(description.to_string()),
+        Bolt11InvoiceDescriptionRef::Hash(_) => None,
+    };
+    preview.details = serde_json::json!({
+        "payment_hash": invoice.payment_hash().to_string(),
+        "network": invoice.network().to_string(),
+        "amount_msats": invoice.amount_milli_satoshis(),
+        "expired": invoice.is_expired(),
+    });
+
+    Ok(preview)
+}
+
+fn parse_cashu_token(normalized: String) -> Result<PaymentInputPreview, String> {
+    let token =
+        Token::from_str(&normalized).map_err(|e| format!("Invalid cashu token: {}", e))?;
+
+    let version = match token {
+        Token::TokenV3(_) => 3,
+        Token::TokenV4(_) => 4,
+    };
+    let mint_url = token
+        .mint_url()
+        .map_err(|e| format!("Failed to get mint URL: {}", e))?
+        .to_string();
+
+    let mut preview = PaymentInputPreview::new(
+        PaymentInputKind::CashuToken,
+        PaymentAction::Receive,
+        normalized,
+    );
+    preview.amount = Some(
+        token
+            .value()
+            .map_err(|e| format!("Invalid token amount: {}", e))?
+            .into(),
+    );
+    preview.unit = token.unit().map(|unit| unit.to_string());
+    preview.mints = vec![mint_url];
+    preview.description = token.memo().clone();
+    preview.details = serde_json::json!({ "version": version });
+
+    Ok(preview)
+}
+
+fn parse_nut18(normalized: String) -> Result<PaymentInputPreview, String> {
+    let request = PaymentRequest::from_str(&normalized)
+        .map_err(|e| format!("Invalid payment request: {}", e))?;
+
+    let mut preview = PaymentInputPreview::new(
+        PaymentInputKind::Nut18Request,
+        PaymentAction::Pay,
+        normalized,
+    );
+    preview.amount = request.amount.map(u64::from);
+    preview.unit = request.unit.as_ref().map(|unit| unit.to_string());
+    preview.mints = request
+        .mints
+        .as_ref()
+        .map(|mints| mints.iter().map(|mint| mint.to_string()).collect())
+        .unwrap_or_default();
+    preview.description = request.description.clone();
+    preview.details = serde_json::json!({
+        "payment_id": request.payment_id,
+        "single_use": request.single_use,
+        "has_transport": !request.transports.is_empty(),
+    });
+
+    Ok(preview)
+}
+
+fn parse_nwc(normalized: String) -> Result<PaymentInputPreview, String> {
+    let uri = NostrWalletConnectURI::from_str(&normalized)
+        .map_err(|e| format!("Invalid NWC URI: {}", e))?;
+
+    let mut preview =
+        PaymentInputPreview::new(PaymentInputKind::NwcUri, PaymentAction::Connect, normalized);
+    preview.details = serde_json::json!({
+        "wallet_pubkey": uri.public_key.to_hex(),
+        "relays": uri.relays.iter().map(|relay| relay.to_string()).collect::<Vec<_>>(),
+        "lud16": uri.lud16,
+    });
+
+    Ok(preview)
+}
+
+fn parse_nwa(normalized: String) -> Result<PaymentInputPreview, String> {
+    let request = parse_nwa_uri(&normalized)?;
+
+    let mut preview =
+        PaymentInputPreview::new(PaymentInputKind::NwaUri, PaymentAction::Connect, normalized);
+    preview.details = serde_json::json!({
+        "app_pubkey": request.app_pubkey,
+        "relays": request.relays,
+        "required_commands": request.required_commands,
+        "optional_commands": request.optional_commands,
+        "budget": request.budget,
+        "identity": request.identity,
+    });
+
+    Ok(preview)
+}
+
+/// Resolve LNURL-pay limits and description for LNURL and Lightning address
+/// previews. Failures are recorded in `details.error` instead of failing.
+pub async fn resolve_lnurl(preview: &mut PaymentInputPreview) {
+    if !matches!(
+        preview.kind,
+        PaymentInputKind::Lnurl | PaymentInputKind::LightningAddress
+    ) {
+        return;
+    }
+
+    let Some(url) = preview
+        .details
+        .get("url")
+        .and_then(|v| v.as_str())
+        .map(|s| s.to_string())
+    else {
+        return;
+    };
+
+    match lnurl::fetch_pay_params(&url).await {
+        Ok(params) => {
+            preview.description = params.description();
+            // Amounts are shown in sats, LNURL uses msats
+            if params.min_sendable == params.max_sendable {
+                preview.amount = Some(params.min_sendable.div_ceil(1000));
+            }
+            preview.unit = Some("sat".to_string());
+            preview.details["min_sendable_msats"] = params.min_sendable.into();
+            preview.details["max_sendable_msats"] = params.max_sendable.into();
+            preview.details["comment_allowed"] = params.comment_allowed.into();
+        }
+        Err(e) => {
+            log::warn!("Failed to resolve LNURL {}: {}", url, e);
+            preview.details["error"] = e.into();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bolt11() {
+        // BOLT11 spec example: 2500 uBTC for "1 cup coffee"
+        let invoice = "lightning:lnbc2500u1pvjluezsp5zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zygspp5qqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqqqsyqcyq5rqwzqfqypqdq5xysxxatsyp3k7enxv4jsxqzpu9qrsgquk0rl77nj30yxdy8j9vdx85fkpmdla2087ne0xh8nhedh8w27kyke0lp53ut353s06fv3qfegext0eh0ymjpf39tuven09sam30g4vgpfna3rh";
+        let preview = parse_payment_input(invoice).unwrap();
+
+        assert_eq!(preview.kind, PaymentInputKind::Bolt11);
+        assert_eq!(preview.action, PaymentAction::Pay);
+        assert_eq!(preview.amount, Some(250_000));
+        assert_eq!(preview.description.as_deref(), Some("1 cup coffee"));
+        assert!(preview.normalized.starts_with("lnbc"));
+    }
+
+    #[test]
+    fn test_parse_lightning_address_and_lnurl() {
+        let preview = parse_payment_input("alice@example.com").unwrap();
+        assert_eq!(preview.kind, PaymentInputKind::LightningAddress);
+        assert_eq!(
+            preview.details["url"],
+            "https://example.com/.well-known/lnurlp/alice"
+        );
+
+        let preview = parse_payment_input("lightning:LNURL1DP68GURN8GHJ7UM9WFMXJCM99E3K7MF0V9CXJ0M385EKVCENXC6R2C35XVUKXEFCV5MKVV34X5EKZD3EV56NYD3HXQURZEPEXEJXXEPNXSCRVWFNV9NXZCN9XQ6XYEFHVGCXXCMYXYMNSERXFQ5FNS").unwrap();
+        assert_eq!(preview.kind, PaymentInputKind::Lnurl);
+        assert_eq!(
+            preview.details["url"],
+            "https://service.com/api?q=3fc3645b439ce8e7f2553a69e5267081d96dcd340693afabe04be7b0ccd178df"
+        );
+    }
+
+    #[test]
+    fn test_parse_nwa_uri() {
+        let uri = "nostr+walletauth://b889ff5b1513b641e2a139f661a661364979c5beee91842f8f0ef42ab558e9d4?relay=wss%3A%2F%2Frelay.damus.io&secret=abc123&request_methods=pay_invoice+get_balance";
+        let preview = parse_payment_input(uri).unwrap();
+
+        assert_eq!(preview.kind, PaymentInputKind::NwaUri);
+        assert_eq!(preview.action, PaymentAction::Connect);
+        assert_eq!(preview.details["relays"][0], "wss://relay.damus.io");
+    }
+
+    #[test]
+    fn test_can_pay_respects_mints_and_amount() {
+        let mut preview = PaymentInputPreview::new(
+            PaymentInputKind::Nut18Request,
+            PaymentAction::Pay,
+            "creqA".to_string(),
+        );
+        preview.amount = Some(100);
+        preview.mints = vec!["https://mint.example.com/".to_string()];
+
+        let balances = vec![
+            WalletBalance {
+                mint_url: "https://other.example.com".to_string(),
+                balance: 1000,
+                unit: "sat".to_string(),
+                pending: 0,
+            },
+            WalletBalance {
+                mint_url: "https://mint.example.com".to_string(),
+                balance: 50,
+                unit: "sat".to_string(),
+                pending: 0,
+            },
+        ];
+        preview.apply_balances(&balances);
+        assert!(!preview.can_pay);
+
+        preview.mints.clear();
+        preview.apply_balances(&balances);
+        assert!(preview.can_pay);
+    }
+
+    #[test]
+    fn test_unrecognized_input() {
+        assert!(parse_payment_input("hello world").is_err());
+        assert!(parse_payment_input("   ").is_err());
+    }
+}
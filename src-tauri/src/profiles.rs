@@ -0,0 +1,285 @@
+//! Wallet profiles
+//!
+//! Each profile has its own data directory with its own seed, mint
+//! databases, NWC connections and Routstr configuration. The `default`
+//! profile uses the top-level data directory so installs from before
+//! profiles keep their data. Other profiles live in `profiles/<name>/`.
+
+use crate::app_lock::AppLockState;
+use crate::tollgate::wallet::stored_holdings;
+use chrono::{DateTime, Utc};
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use tauri::State;
+
+pub const DEFAULT_PROFILE: &str = "default";
+const PROFILES_FILE: &str = "profiles.json";
+const PROFILES_DIR: &str = "profiles";
+const MAX_NAME_LEN: usize = 32;
+
+/// Active profile, cached after the first lookup
+static ACTIVE_PROFILE: RwLock<Option<String>> = RwLock::new(None);
+/// Data directory chosen by the daemon config instead of the platform default
+static ROOT_OVERRIDE: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileInfo {
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct StoredProfiles {
+    active: Option<String>,
+    #[serde(default)]
+    profiles: Vec<ProfileInfo>,
+}
+
+/// Profile list for the UI
+#[derive(Debug, Clone, Serialize)]
+pub struct ProfileList {
+    pub active: String,
+    pub profiles: Vec<ProfileInfo>,
+}
+
+/// Top-level data directory shared by all profiles
+pub(crate) fn root_dir() -> io::Result<PathBuf> {
+    if let Some(root) = ROOT_OVERRIDE.read().expect("profile lock poisoned").clone() {
+        return Ok(root);
+    }
+    let project_dirs = ProjectDirs::from("com", "Tollgate", "TollgateApp")
+        .ok_or_else(|| io::Error::other("Unable to determine storage directory"))?;
+    Ok(project_dirs.data_dir().to_path_buf())
+}
+
+/// Use `root` as the top-level data directory. Must be called before any
+/// service is started.
+pub(crate) fn set_root_dir(root: PathBuf) -> io::Result<()> {
+    fs::create_dir_all(&root)?;
+    *ROOT_OVERRIDE.write().expect("profile lock poisoned") = Some(root);
+    *ACTIVE_PROFILE.write().expect("profile lock poisoned") = None;
+    Ok(())
+}
+
+fn profile_dir_in(root: &Path, name: &str) -> PathBuf {
+    if name == DEFAULT_PROFILE {
+        root.to_path_buf()
+    } else {
+        root.join(PROFILES_DIR).join(name)
+    }
+}
+
+pub fn validate_profile_name(name: &str) -> Result<(), String> {
+    if name.is_empty() || name.len() > MAX_NAME_LEN {
+        return Err(format!(
+            "Profile names must be 1-{} characters long",
+            MAX_NAME_LEN
+        ));
+    }
+    if !name
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+    {
+        return Err("Profile names may only contain a-z, 0-9, '-' and '_'".to_string());
+    }
+    Ok(())
+}
+
+fn load_profiles(root: &Path) -> StoredProfiles {
+    let mut stored: StoredProfiles = fs::read(root.join(PROFILES_FILE))
+        .ok()
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default();
+
+    if !stored.profiles.iter().any(|p| p.name == DEFAULT_PROFILE) {
+        stored.profiles.insert(
+            0,
+            ProfileInfo {
+                name: DEFAULT_PROFILE.to_string(),
+                created_at: Utc::now(),
+            },
+        );
+    }
+    stored
+}
+
+fn save_profiles(root: &Path, stored: &StoredProfiles) -> io::Result<()> {
+    fs::create_dir_all(root)?;
+    fs::write(root.join(PROFILES_FILE), serde_json::to_vec_pretty(stored)?)
+}
+
+/// Name of the active profile
+pub fn active_profile() -> String {
+    if let Some(active) = ACTIVE_PROFILE
+        .read()
+        .expect("profile lock poisoned")
+        .clone()
+    {
+        return active;
+    }
+
+    let active = root_dir()
+        .ok()
+        .and_then(|root| load_profiles(&root).active)
+        .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+    *ACTIVE_PROFILE.write().expect("profile lock poisoned") = Some(active.clone());
+    active
+}
+
+/// Data directory of the active profile
+pub fn data_dir() -> io::Result<PathBuf> {
+    let dir = profile_dir_in(&root_dir()?, &active_profile());
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+pub fn list_profiles() -> io::Result<ProfileList> {
+    let root = root_dir()?;
+    Ok(ProfileList {
+        active: active_profile(),
+        profiles: load_profiles(&root).profiles,
+    })
+}
+
+pub fn create_profile(name: &str) -> Result<ProfileInfo, String> {
+    validate_profile_name(name)?;
+    let root = root_dir().map_err(|e| e.to_string())?;
+    let mut stored = load_profiles(&root);
+    if stored.profiles.iter().any(|p| p.name == name) {
+        return Err(format!("Profile already exists: {}", name));
+    }
+
+    fs::create_dir_all(profile_dir_in(&root, name)).map_err(|e| e.to_string())?;
+    let profile = ProfileInfo {
+        name: name.to_string(),
+        created_at: Utc::now(),
+    };
+    stored.profiles.push(profile.clone());
+    save_profiles(&root, &stored).map_err(|e| e.to_string())?;
+
+    log::info!("Created profile {}", name);
+    Ok(profile)
+}
+
+fn check_deletable(name: &str) -> Result<(), String> {
+    if name == DEFAULT_PROFILE {
+        return Err("The default profile cannot be deleted".to_string());
+    }
+    if name == active_profile() {
+        return Err("Switch to another profile before deleting this one".to_string());
+    }
+    validate_profile_name(name)
+}
+
+/// Delete a profile and all of its data
+pub fn delete_profile(name: &str) -> Result<(), String> {
+    check_deletable(name)?;
+
+    let root = root_dir().map_err(|e| e.to_string())?;
+    let mut stored = load_profiles(&root);
+    stored.profiles.retain(|p| p.name != name);
+    save_profiles(&root, &stored).map_err(|e| e.to_string())?;
+
+    let dir = profile_dir_in(&root, name);
+    if dir.exists() {
+        fs::remove_dir_all(&dir).map_err(|e| e.to_string())?;
+    }
+
+    log::info!("Deleted profile {}", name);
+    Ok(())
+}
+
+/// Use `name` as the active profile in this process only, leaving the
+/// profile the app opens with unchanged
+pub fn use_profile(name: &str) -> Result<(), String> {
+    let root = root_dir().map_err(|e| e.to_string())?;
+    if !load_profiles(&root).profiles.iter().any(|p| p.name == name) {
+        return Err(format!("Unknown profile: {}", name));
+    }
+    *ACTIVE_PROFILE.write().expect("profile lock poisoned") = Some(name.to_string());
+    Ok(())
+}
+
+/// Make `name` the active profile. Services must be rebuilt afterwards.
+pub fn set_active_profile(name: &str) -> Result<(), String> {
+    let root = root_dir().map_err(|e| e.to_string())?;
+    let mut stored = load_profiles(&root);
+    if !stored.profiles.iter().any(|p| p.name == name) {
+        return Err(format!("Unknown profile: {}", name));
+    }
+
+    stored.active = Some(name.to_string());
+    save_profiles(&root, &stored).map_err(|e| e.to_string())?;
+    *ACTIVE_PROFILE.write().expect("profile lock poisoned") = Some(name.to_string());
+
+    log::info!("Active profile is now {}", name);
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn list_wallet_profiles() -> Result<ProfileList, String> {
+    list_profiles().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn create_wallet_profile(name: String) -> Result<ProfileInfo, String> {
+    create_profile(name.trim())
+}
+
+#[tauri::command]
+pub async fn delete_wallet_profile(
+    name: String,
+    force: Option<bool>,
+    lock: State<'_, AppLockState>,
+) -> Result<(), String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    check_deletable(&name)?;
+
+    // Deleting the data directory deletes the funds in it
+    if !force.unwrap_or(false) {
+        let dir = profile_dir_in(&root_dir().map_err(|e| e.to_string())?, &name);
+        let holdings = stored_holdings(&dir).await.map_err(|e| e.to_string())?;
+        if holdings.damaged_files > 0 {
+            return Err(format!(
+                "Profile {} has {} unreadable pending-token files that may hold tokens. Recover them first or delete it with force",
+                name, holdings.damaged_files
+            ));
+        }
+        if !holdings.is_empty() {
+            return Err(format!(
+                "Profile {} still holds a balance of {} and {} pending tokens. Move them to another profile first or delete it with force",
+                name, holdings.balance, holdings.pending_tokens
+            ));
+        }
+    }
+    delete_profile(&name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_names() {
+        assert!(validate_profile_name("work").is_ok());
+        assert!(validate_profile_name("test_2").is_ok());
+        assert!(validate_profile_name("").is_err());
+        assert!(validate_profile_name("Work").is_err());
+        assert!(validate_profile_name("../evil").is_err());
+        assert!(validate_profile_name(&"a".repeat(MAX_NAME_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn test_default_profile_uses_root_dir() {
+        let root = Path::new("/data");
+        assert_eq!(profile_dir_in(root, DEFAULT_PROFILE), root);
+        assert_eq!(
+            profile_dir_in(root, "work"),
+            Path::new("/data/profiles/work")
+        );
+    }
+}
@@ -1,9 +1,10 @@
+use crate::api_clients::ApiScope;
 use crate::connection_server::ConnectionServerState;
 use crate::proxy::onion::{
     construct_url_with_protocol, create_onion_client, get_onion_error_message, log_onion_timing,
     start_onion_timing,
 };
-use crate::routstr::RoutstrService;
+use crate::tollgate::origin::Origin;
 use axum::{
     body::Body,
     extract::{Path, Request, State},
@@ -12,7 +13,6 @@ use axum::{
     Json,
 };
 use serde_json::json;
-use tauri::Manager;
 
 #[derive(serde::Deserialize)]
 struct OpenAIRequest {
@@ -93,13 +93,25 @@ async fn forward_request_impl(
     server_state: State<ConnectionServerState>,
     is_streaming: bool,
 ) -> Response<Body> {
-    // Get routstr config from the app state
-    let routstr_state = server_state
-        .app_handle
-        .state::<std::sync::Arc<tokio::sync::Mutex<RoutstrService>>>();
+    // The proxy pays for requests, so callers need a paired token
+    if let Err((status, message)) =
+        crate::pairing::authorize(&server_state, &original_headers, ApiScope::Proxy)
+    {
+        return (
+            status,
+            Json(json!({
+                "error": {
+                    "message": message,
+                    "type": "authentication_error",
+                    "code": "wallet_token_required"
+                }
+            })),
+        )
+            .into_response();
+    }
 
     let (config, max_cost_msats, selected_mint) = {
-        let service = routstr_state.lock().await;
+        let service = server_state.routstr.lock().await;
 
         let target_url = if let Some(url) = &service.target_service_url {
             url.clone()
@@ -148,7 +160,12 @@ async fn forward_request_impl(
     let endpoint_url = construct_url_with_protocol(&config.target_url, &path);
     log::info!("Forwarding request to: {}", endpoint_url);
 
-    let timeout_secs = if is_streaming { 300 } else { 60 };
+    let timeouts = &crate::config::current().timeouts;
+    let timeout_secs = if is_streaming {
+        timeouts.proxy_stream_secs
+    } else {
+        timeouts.proxy_secs
+    };
     let client = match create_onion_client(&endpoint_url, config.use_onion, Some(timeout_secs)) {
         Ok(client) => client,
         Err(e) => {
@@ -192,7 +209,14 @@ async fn forward_request_impl(
     }
 
     let payment_token = if max_cost_msats > 0 {
-        (create_payment_token(max_cost_msats, selected_mint, &server_state.app_handle).await).ok()
+        create_payment_token(
+            max_cost_msats,
+            selected_mint,
+            &config.target_url,
+            &server_state.tollgate,
+        )
+        .await
+        .ok()
     } else {
         None
     };
@@ -201,19 +225,29 @@ async fn forward_request_impl(
         req_builder = req_builder.header("X-Cashu", &payment_token);
     }
 
+    let model = body
+        .as_ref()
+        .and_then(|body| body.get("model"))
+        .and_then(|model| model.as_str())
+        .map(str::to_string);
     let start_time = start_onion_timing(&endpoint_url);
+    let sent_at = std::time::Instant::now();
 
     match req_builder.send().await {
         Ok(resp) => {
             log_onion_timing(start_time, &endpoint_url, "proxy");
+            crate::metrics::PROXY_LATENCY
+                .observe(&[model.as_deref().unwrap_or("unknown")], sent_at.elapsed());
             let status = resp.status();
             let headers = resp.headers().clone();
 
+            let mut refunded_sats = 0;
             if status != reqwest::StatusCode::OK {
                 if let Some(payment_token) = payment_token.clone() {
-                    let app_handle_clone = server_state.app_handle.clone();
-                    if let Err(e) = redeem_change_token(&payment_token, &app_handle_clone).await {
-                        log::error!("Failed to redeem change token in background: {}", e);
+                    let tollgate_clone = server_state.tollgate.clone();
+                    match redeem_change_token(&payment_token, &tollgate_clone).await {
+                        Ok(amount) => refunded_sats += amount,
+                        Err(e) => log::error!("Failed to redeem change token in background: {}", e),
                     }
                 }
             }
@@ -221,14 +255,25 @@ async fn forward_request_impl(
             println!("{:?}", headers);
             if let Some(change_token) = headers.get("X-Cashu") {
                 if let Ok(token_str) = change_token.to_str() {
-                    let app_handle_clone = server_state.app_handle.clone();
+                    let tollgate_clone = server_state.tollgate.clone();
                     let token_str_owned = token_str.to_string();
-                    if let Err(e) = redeem_change_token(&token_str_owned, &app_handle_clone).await {
-                        log::error!("Failed to redeem change token in background: {}", e);
+                    match redeem_change_token(&token_str_owned, &tollgate_clone).await {
+                        Ok(amount) => refunded_sats += amount,
+                        Err(e) => log::error!("Failed to redeem change token in background: {}", e),
                     }
                 }
             }
 
+            if payment_token.is_some() {
+                crate::events::publish(crate::events::BackendEvent::ProxyRequestBilled {
+                    target_url: config.target_url.clone(),
+                    model: model.clone(),
+                    status: status.as_u16(),
+                    paid_sats: max_cost_msats,
+                    refunded_sats,
+                });
+            }
+
             let mut response = Response::builder().status(
                 StatusCode::from_u16(status.as_u16()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
             );
@@ -262,9 +307,8 @@ async fn forward_request_impl(
                 }),
                 Err(e) => {
                     if let Some(payment_token) = payment_token {
-                        let app_handle_clone = server_state.app_handle.clone();
-                        if let Err(e) = redeem_change_token(&payment_token, &app_handle_clone).await
-                        {
+                        let tollgate_clone = server_state.tollgate.clone();
+                        if let Err(e) = redeem_change_token(&payment_token, &tollgate_clone).await {
                             log::error!("Failed to redeem change token in background: {}", e);
                         }
                     }
@@ -288,8 +332,8 @@ async fn forward_request_impl(
         Err(error) => {
             log::error!("Error forwarding request: {}", error);
             if let Some(payment_token) = payment_token {
-                let app_handle_clone = server_state.app_handle.clone();
-                if let Err(e) = redeem_change_token(&payment_token, &app_handle_clone).await {
+                let tollgate_clone = server_state.tollgate.clone();
+                if let Err(e) = redeem_change_token(&payment_token, &tollgate_clone).await {
                     log::error!("Failed to redeem change token in background: {}", e);
                 }
             }
@@ -314,7 +358,8 @@ async fn forward_request_impl(
 async fn create_payment_token(
     amount_msats: u64,
     selected_mint_url: Option<String>,
-    app_handle: &tauri::AppHandle,
+    target_url: &str,
+    tollgate_state: &crate::TollGateState,
 ) -> Result<String, String> {
     log::info!(
         "Creating payment token for {} sats using mint: {:?}",
@@ -322,11 +367,16 @@ async fn create_payment_token(
         selected_mint_url
     );
 
-    let tollgate_state = app_handle.state::<crate::TollGateState>();
-    let service = tollgate_state.lock().await;
+    let payments = tollgate_state.lock().await.payments();
 
-    match service
-        .create_external_token(amount_msats, selected_mint_url)
+    match payments
+        .create_external_token(
+            amount_msats,
+            selected_mint_url,
+            &Origin::Proxy,
+            Some(target_url.to_string()),
+            false,
+        )
         .await
     {
         Ok(token) => {
@@ -345,21 +395,23 @@ async fn create_payment_token(
 
 async fn redeem_change_token(
     change_token: &str,
-    app_handle: &tauri::AppHandle,
-) -> Result<(), String> {
+    tollgate_state: &crate::TollGateState,
+) -> Result<u64, String> {
     log::info!("Redeeming change token: {}", change_token);
 
-    let tollgate_state = app_handle.state::<crate::TollGateState>();
     let service = tollgate_state.lock().await;
 
-    match service.receive_cashu_token(change_token).await {
+    match service
+        .receive_cashu_token(change_token, &Origin::Proxy, false)
+        .await
+    {
         Ok(result) => {
             log::info!(
                 "Successfully redeemed change token: {} sats from mint {}",
                 result.amount,
                 result.mint_url
             );
-            Ok(())
+            Ok(result.amount)
         }
         Err(e) => {
             log::error!("Failed to redeem change token: {}", e);
@@ -22,8 +22,8 @@ pub fn construct_url_with_protocol(base_url: &str, path: &str) -> String {
 }
 
 pub fn configure_tor_proxy_url(endpoint_url: &str) -> String {
-    let tor_proxy_url =
-        std::env::var("TOR_SOCKS_PROXY").unwrap_or_else(|_| "socks5h://127.0.0.1:9050".to_string());
+    let tor_proxy_url = std::env::var("TOR_SOCKS_PROXY")
+        .unwrap_or_else(|_| crate::config::current().tor.socks_proxy.clone());
 
     if endpoint_url.contains(".onion") && tor_proxy_url.starts_with("socks5://") {
         tor_proxy_url.replace("socks5://", "socks5h://")
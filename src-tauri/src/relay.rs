@@ -1,20 +1,43 @@
 //! Simple local Nostr relay for NWC communication
 //!
 //! This module provides a lightweight, in-process Nostr relay that allows
-//! the NWC service to communicate with external applications.
-
+//! the NWC service to communicate with external applications. It listens on
+//! loopback, and in LAN mode also over TLS on a network interface; every
+//! listener shares the same events. When the configured port is taken the
+//! relay moves to a free one, see [`local_relay_url`]. The loopback accept
+//! loop runs under the supervisor.
+
+use crate::metrics;
+use crate::supervisor::{supervisor, Shutdown};
 use nostr::{Event, Filter};
 use nostr_sdk::filter::MatchEventOptions;
 use std::collections::HashMap;
 use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU16, Ordering};
 use std::sync::Arc;
-// Note: AsyncReadExt and AsyncWriteExt are not used directly but are required for tokio-tungstenite
-use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
 use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinHandle;
+use tokio_rustls::TlsAcceptor;
 
-/// Default port for the local relay
+/// Default port for the local relay, `ports.relay` in the config
 pub const DEFAULT_RELAY_PORT: u16 = 4869;
 
+/// Name of the accept loop under the supervisor
+const RELAY_SERVICE: &str = "relay";
+
+/// Port the loopback relay listens on, once started
+static LOCAL_RELAY_PORT: AtomicU16 = AtomicU16::new(DEFAULT_RELAY_PORT);
+
+/// URL of the loopback relay, with the port actually in use
+pub fn local_relay_url() -> String {
+    format!(
+        "ws://localhost:{}",
+        LOCAL_RELAY_PORT.load(Ordering::Relaxed)
+    )
+}
+
 /// Simple in-memory event store with broadcast support
 #[derive(Clone)]
 struct EventStore {
@@ -36,6 +59,7 @@ impl EventStore {
         // Check if event already exists
         if !events.iter().any(|e| e.id == event.id) {
             events.push(event.clone());
+            metrics::RELAY_STORED_EVENTS.set(&[], events.len() as f64);
             // Broadcast to all subscribers
             let _ = self.broadcast_tx.send(event);
         }
@@ -64,64 +88,140 @@ impl EventStore {
     }
 }
 
-/// Start the local Nostr relay server
-pub async fn start_relay_server(port: u16) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let addr = SocketAddr::from(([127, 0, 0, 1], port));
+/// Running relay, used to serve it on more addresses
+#[derive(Clone)]
+pub struct RelayHandle {
+    store: EventStore,
+    port: u16,
+}
 
-    log::info!("Attempting to bind local Nostr relay to {}", addr);
-    let listener = match TcpListener::bind(addr).await {
-        Ok(l) => {
-            log::info!("Successfully bound to {}", addr);
-            l
-        }
+impl RelayHandle {
+    /// Loopback port of the relay
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Serve the relay over TLS on `addr`. Abort the returned task to stop
+    /// accepting connections. Must be called from within the runtime.
+    pub fn serve_tls(
+        &self,
+        addr: SocketAddr,
+        acceptor: TlsAcceptor,
+    ) -> std::io::Result<JoinHandle<()>> {
+        let listener = std::net::TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        let listener = TcpListener::from_std(listener)?;
+        log::info!("Local Nostr relay listening on wss://{}", addr);
+
+        let store = self.store.clone();
+        Ok(tokio::spawn(async move {
+            loop {
+                let (stream, peer_addr) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        log::error!("Failed to accept LAN relay connection: {}", e);
+                        continue;
+                    }
+                };
+                log::info!("New LAN relay connection from: {}", peer_addr);
+                let acceptor = acceptor.clone();
+                let store = store.clone();
+                tokio::spawn(async move {
+                    let stream = match acceptor.accept(stream).await {
+                        Ok(stream) => stream,
+                        Err(e) => {
+                            log::warn!("TLS handshake with {} failed: {}", peer_addr, e);
+                            return;
+                        }
+                    };
+                    if let Err(e) = handle_connection(stream, store).await {
+                        log::error!("LAN relay connection error from {}: {}", peer_addr, e);
+                    }
+                });
+            }
+        }))
+    }
+}
+
+/// Start the local Nostr relay server on `port`, or on a free port when it
+/// is taken
+pub async fn start_relay_server(
+    port: u16,
+) -> Result<RelayHandle, Box<dyn std::error::Error + Send + Sync>> {
+    log::info!("Attempting to bind local Nostr relay to port {}", port);
+    let listener = match crate::runtime::bind_loopback(port).and_then(TcpListener::from_std) {
+        Ok(l) => l,
         Err(e) => {
-            log::error!("Failed to bind relay server to {}: {}", addr, e);
+            log::error!("Failed to bind relay server to port {}: {}", port, e);
             return Err(Box::new(e));
         }
     };
+    let addr = listener.local_addr()?;
+    LOCAL_RELAY_PORT.store(addr.port(), Ordering::Relaxed);
 
     log::info!("✓ Local Nostr relay listening on ws://{}", addr);
 
     let event_store = EventStore::new();
+    let handle = RelayHandle {
+        store: event_store.clone(),
+        port: addr.port(),
+    };
 
-    tokio::spawn(async move {
-        log::info!("Relay server task started, waiting for connections...");
-        let mut connection_count = 0;
-        loop {
-            match listener.accept().await {
-                Ok((stream, peer_addr)) => {
-                    connection_count += 1;
-                    log::info!(
-                        "✓ New relay connection #{} from: {}",
-                        connection_count,
-                        peer_addr
-                    );
-                    let store = event_store.clone();
-                    let conn_id = connection_count;
-                    tokio::spawn(async move {
-                        log::debug!("[Conn #{}] Starting WebSocket handshake", conn_id);
-                        if let Err(e) = handle_connection(stream, store).await {
-                            log::error!("[Conn #{}] Relay connection error: {}", conn_id, e);
-                        } else {
-                            log::info!("[Conn #{}] Connection closed gracefully", conn_id);
-                        }
-                    });
-                }
-                Err(e) => {
-                    log::error!("Failed to accept relay connection: {}", e);
-                }
-            }
-        }
+    let listener = Arc::new(listener);
+    supervisor().spawn(RELAY_SERVICE, move |shutdown| {
+        accept_connections(listener.clone(), event_store.clone(), shutdown)
     });
 
-    Ok(())
+    Ok(handle)
+}
+
+/// Accept loopback connections until shutdown
+async fn accept_connections(
+    listener: Arc<TcpListener>,
+    event_store: EventStore,
+    mut shutdown: Shutdown,
+) -> Result<(), String> {
+    log::info!("Relay server task started, waiting for connections...");
+    let mut connection_count = 0;
+    loop {
+        let accepted = tokio::select! {
+            accepted = listener.accept() => accepted,
+            _ = shutdown.wait() => return Ok(()),
+        };
+        match accepted {
+            Ok((stream, peer_addr)) => {
+                connection_count += 1;
+                log::info!(
+                    "✓ New relay connection #{} from: {}",
+                    connection_count,
+                    peer_addr
+                );
+                let store = event_store.clone();
+                let conn_id = connection_count;
+                tokio::spawn(async move {
+                    log::debug!("[Conn #{}] Starting WebSocket handshake", conn_id);
+                    if let Err(e) = handle_connection(stream, store).await {
+                        log::error!("[Conn #{}] Relay connection error: {}", conn_id, e);
+                    } else {
+                        log::info!("[Conn #{}] Connection closed gracefully", conn_id);
+                    }
+                });
+            }
+            Err(e) => {
+                log::error!("Failed to accept relay connection: {}", e);
+            }
+        }
+    }
 }
 
 /// Handle a single WebSocket connection
-async fn handle_connection(
-    stream: TcpStream,
+async fn handle_connection<S>(
+    stream: S,
     event_store: EventStore,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
     use futures_util::{SinkExt, StreamExt};
     use tokio_tungstenite::{accept_async, tungstenite::Message};
 
@@ -139,6 +239,7 @@ async fn handle_connection(
 
     let (mut write, mut read) = ws_stream.split();
     log::debug!("WebSocket connection established, ready to receive messages");
+    metrics::RELAY_CONNECTIONS.inc(&[]);
 
     // Track active subscriptions for this connection
     let subscriptions: Arc<RwLock<HashMap<String, Vec<Filter>>>> =
@@ -200,19 +301,25 @@ async fn handle_connection(
         }
     }
 
+    metrics::RELAY_CONNECTIONS.dec(&[]);
+    let open = subscriptions.read().await.len();
+    metrics::RELAY_SUBSCRIPTIONS.add(&[], -(open as f64));
     Ok(())
 }
 
 /// Handle a single WebSocket message
-async fn handle_message(
+async fn handle_message<S>(
     msg: tokio_tungstenite::tungstenite::Message,
     write: &mut futures_util::stream::SplitSink<
-        tokio_tungstenite::WebSocketStream<TcpStream>,
+        tokio_tungstenite::WebSocketStream<S>,
         tokio_tungstenite::tungstenite::Message,
     >,
     event_store: &EventStore,
     subscriptions: &Arc<RwLock<HashMap<String, Vec<Filter>>>>,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
     use futures_util::SinkExt;
     use tokio_tungstenite::tungstenite::Message;
 
@@ -297,7 +404,9 @@ async fn handle_message(
                                 // Store subscription
                                 {
                                     let mut subs = subscriptions.write().await;
-                                    subs.insert(sub_id.to_string(), filters.clone());
+                                    if subs.insert(sub_id.to_string(), filters.clone()).is_none() {
+                                        metrics::RELAY_SUBSCRIPTIONS.inc(&[]);
+                                    }
                                 }
 
                                 // Send matching events
@@ -326,7 +435,9 @@ async fn handle_message(
                             // ["CLOSE", <subscription_id>]
                             if let Some(sub_id) = message.get(1).and_then(|v| v.as_str()) {
                                 let mut subs = subscriptions.write().await;
-                                subs.remove(sub_id);
+                                if subs.remove(sub_id).is_some() {
+                                    metrics::RELAY_SUBSCRIPTIONS.dec(&[]);
+                                }
                                 log::debug!("Closed subscription: {}", sub_id);
                             }
                         }
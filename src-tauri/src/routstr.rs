@@ -1,9 +1,13 @@
+use crate::profiles;
+use crate::supervisor::{supervisor, Shutdown};
 use anyhow::{anyhow, Result};
-use directories::ProjectDirs;
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::path::PathBuf;
 
+/// Name of the model price updates under the supervisor
+const AUTO_UPDATE_SERVICE: &str = "routstr_auto_update";
+
 #[derive(Debug, Clone)]
 pub struct RoutstrStoragePaths {
     pub config_file: PathBuf,
@@ -11,10 +15,7 @@ pub struct RoutstrStoragePaths {
 
 impl RoutstrStoragePaths {
     fn new() -> Result<Self> {
-        let project_dirs = ProjectDirs::from("com", "Tollgate", "TollgateApp")
-            .ok_or_else(|| anyhow!("Unable to determine Routstr storage directory"))?;
-
-        let base_dir = project_dirs.data_dir().join("routstr");
+        let base_dir = profiles::data_dir()?.join("routstr");
         let config_file = base_dir.join("config.json");
 
         // Ensure directories exist
@@ -48,6 +49,17 @@ pub struct RoutstrStoredConfig {
     pub selected_mint_url: Option<String>,
 }
 
+/// Provider choice, synced with the settings backup
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RoutstrProviderSelection {
+    pub base_url: Option<String>,
+    pub use_manual_url: bool,
+    pub selected_provider_id: Option<String>,
+    pub service_mode: String,
+    pub selected_mint_url: Option<String>,
+    pub use_onion: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Architecture {
     pub modality: Option<String>,
@@ -162,7 +174,6 @@ pub struct RoutstrService {
     pub selected_mint_url: Option<String>,
     client: reqwest::Client,
     storage: RoutstrStoragePaths,
-    auto_update_handle: Option<tokio::task::JoinHandle<()>>,
 }
 
 impl Clone for RoutstrService {
@@ -183,7 +194,6 @@ impl Clone for RoutstrService {
             selected_mint_url: self.selected_mint_url.clone(),
             client: self.client.clone(),
             storage: self.storage.clone(),
-            auto_update_handle: None, // Don't clone the handle
         }
     }
 }
@@ -222,7 +232,6 @@ impl RoutstrService {
             selected_mint_url: None,
             client: reqwest::Client::new(),
             storage,
-            auto_update_handle: None,
         };
 
         // Load existing configuration
@@ -247,7 +256,7 @@ impl RoutstrService {
         let response = self
             .client
             .get(&models_url)
-            .timeout(std::time::Duration::from_secs(10))
+            .timeout(crate::config::current().timeouts.request())
             .send()
             .await?;
 
@@ -277,39 +286,17 @@ impl RoutstrService {
         Ok(())
     }
 
+    /// Refresh model prices under the supervisor, replacing any running
+    /// update task
     pub fn start_auto_update(&mut self, state: RoutstrState) {
-        // Cancel any existing auto-update task
-        self.stop_auto_update();
-
-        let handle = tokio::spawn(async move {
-            let mut interval = interval(Duration::from_secs(300));
-
-            loop {
-                interval.tick().await;
-
-                let mut service = state.lock().await;
-                if service.base_url.is_some() {
-                    if let Err(e) = service.refresh_models().await {
-                        log::warn!("Auto-refresh models failed: {}", e);
-                    } else {
-                        log::debug!("Auto-refreshed models successfully");
-                    }
-                } else {
-                    // If no longer connected, stop the auto-update task
-                    log::debug!("No longer connected, stopping auto-update task");
-                    break;
-                }
-                drop(service); // Release the lock
-            }
+        supervisor().spawn(AUTO_UPDATE_SERVICE, move |shutdown| {
+            run_auto_update(state.clone(), shutdown)
         });
-
-        self.auto_update_handle = Some(handle);
-        log::info!("Started automatic model price updates (every 60 seconds)");
+        log::info!("Started automatic model price updates (every 5 minutes)");
     }
 
     pub fn stop_auto_update(&mut self) {
-        if let Some(handle) = self.auto_update_handle.take() {
-            handle.abort();
+        if supervisor().stop(AUTO_UPDATE_SERVICE) {
             log::info!("Stopped automatic model price updates");
         }
     }
@@ -338,7 +325,7 @@ impl RoutstrService {
         let response = self
             .client
             .get(&models_url)
-            .timeout(std::time::Duration::from_secs(10))
+            .timeout(crate::config::current().timeouts.request())
             .send()
             .await?;
 
@@ -462,7 +449,7 @@ impl RoutstrService {
             .client
             .get(&create_url)
             .query(&[("initial_balance_token", &cashu_token)])
-            .timeout(std::time::Duration::from_secs(30))
+            .timeout(crate::config::current().timeouts.payment())
             .send()
             .await?;
 
@@ -520,7 +507,7 @@ impl RoutstrService {
             .client
             .get(&create_url)
             .query(&[("initial_balance_token", &cashu_token)])
-            .timeout(std::time::Duration::from_secs(30))
+            .timeout(crate::config::current().timeouts.payment())
             .send()
             .await?;
 
@@ -574,7 +561,7 @@ impl RoutstrService {
             .client
             .get(&balance_url)
             .header("Authorization", format!("Bearer {}", api_key))
-            .timeout(std::time::Duration::from_secs(10))
+            .timeout(crate::config::current().timeouts.request())
             .send()
             .await?;
 
@@ -647,7 +634,7 @@ impl RoutstrService {
             .header("Authorization", format!("Bearer {}", api_key))
             .header("Content-Type", "application/json")
             .json(&request_body)
-            .timeout(std::time::Duration::from_secs(30))
+            .timeout(crate::config::current().timeouts.payment())
             .send()
             .await?;
 
@@ -685,7 +672,7 @@ impl RoutstrService {
             .post(&refund_url)
             .header("Authorization", format!("Bearer {}", api_key))
             .header("Content-Type", "application/json")
-            .timeout(std::time::Duration::from_secs(30))
+            .timeout(crate::config::current().timeouts.payment())
             .send()
             .await?;
 
@@ -709,6 +696,36 @@ impl RoutstrService {
         Ok(refund_response)
     }
 
+    pub fn provider_selection(&self) -> RoutstrProviderSelection {
+        RoutstrProviderSelection {
+            base_url: self.base_url.clone(),
+            use_manual_url: self.use_manual_url,
+            selected_provider_id: self.selected_provider_id.clone(),
+            service_mode: self.service_mode.clone(),
+            selected_mint_url: self.selected_mint_url.clone(),
+            use_onion: self.use_onion,
+        }
+    }
+
+    /// Apply a synced provider selection. Models are fetched on the next
+    /// refresh.
+    pub fn apply_provider_selection(&mut self, selection: RoutstrProviderSelection) -> Result<()> {
+        self.base_url = selection.base_url;
+        self.use_manual_url = selection.use_manual_url;
+        self.selected_provider_id = selection.selected_provider_id;
+        if !selection.service_mode.is_empty() {
+            self.service_mode = selection.service_mode;
+        }
+        self.selected_mint_url = selection.selected_mint_url;
+        self.use_onion = selection.use_onion;
+        self.save_config()
+    }
+
+    /// Reload the configuration from disk, e.g. after a restore
+    pub fn reload_config(&mut self) -> Result<()> {
+        self.load_config()
+    }
+
     fn load_config(&mut self) -> Result<()> {
         if self.storage.config_file.exists() {
             let data = fs::read(&self.storage.config_file)?;
@@ -800,6 +817,30 @@ use tokio::time::{interval, Duration};
 
 pub type RoutstrState = Arc<Mutex<RoutstrService>>;
 
+/// Refresh model prices until disconnected or shut down
+async fn run_auto_update(state: RoutstrState, mut shutdown: Shutdown) -> Result<(), String> {
+    let mut interval = interval(Duration::from_secs(300));
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = shutdown.wait() => return Ok(()),
+        }
+
+        let mut service = state.lock().await;
+        if service.base_url.is_none() {
+            // If no longer connected, stop the auto-update task
+            log::debug!("No longer connected, stopping auto-update task");
+            return Ok(());
+        }
+        if let Err(e) = service.refresh_models().await {
+            log::warn!("Auto-refresh models failed: {}", e);
+        } else {
+            log::debug!("Auto-refreshed models successfully");
+        }
+    }
+}
+
 pub async fn initialize_routstr_auto_update(state: RoutstrState) {
     let mut service = state.lock().await;
     if service.is_connected() {
@@ -882,7 +923,9 @@ pub async fn routstr_create_wallet(
     url: String,
     cashu_token: String,
     state: tauri::State<'_, RoutstrState>,
+    lock: tauri::State<'_, crate::app_lock::AppLockState>,
 ) -> Result<RoutstrCreateResponse, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
     let mut service = state.lock().await;
     let result = service
         .create_wallet(url, cashu_token)
@@ -899,7 +942,9 @@ pub async fn routstr_create_wallet(
 pub async fn routstr_create_balance_with_token(
     cashu_token: String,
     state: tauri::State<'_, RoutstrState>,
+    lock: tauri::State<'_, crate::app_lock::AppLockState>,
 ) -> Result<RoutstrCreateResponse, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
     let mut service = state.lock().await;
     service
         .create_balance_with_token(cashu_token)
@@ -910,7 +955,9 @@ pub async fn routstr_create_balance_with_token(
 #[tauri::command]
 pub async fn routstr_get_all_api_keys(
     state: tauri::State<'_, RoutstrState>,
+    lock: tauri::State<'_, crate::app_lock::AppLockState>,
 ) -> Result<Vec<ApiKeyEntry>, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
     let service = state.lock().await;
     Ok(service.get_api_keys().clone())
 }
@@ -918,7 +965,9 @@ pub async fn routstr_get_all_api_keys(
 #[tauri::command]
 pub async fn routstr_get_all_wallet_balances(
     state: tauri::State<'_, RoutstrState>,
+    lock: tauri::State<'_, crate::app_lock::AppLockState>,
 ) -> Result<Vec<RoutstrWalletBalance>, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
     let service = state.lock().await;
     Ok(service.get_all_wallet_balances().await)
 }
@@ -927,7 +976,9 @@ pub async fn routstr_get_all_wallet_balances(
 pub async fn routstr_get_wallet_balance_for_key(
     api_key: String,
     state: tauri::State<'_, RoutstrState>,
+    lock: tauri::State<'_, crate::app_lock::AppLockState>,
 ) -> Result<RoutstrWalletBalance, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
     let service = state.lock().await;
     service
         .get_wallet_balance_for_key(&api_key)
@@ -940,7 +991,9 @@ pub async fn routstr_top_up_wallet_for_key(
     api_key: String,
     cashu_token: String,
     state: tauri::State<'_, RoutstrState>,
+    lock: tauri::State<'_, crate::app_lock::AppLockState>,
 ) -> Result<RoutstrTopUpResponse, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
     let service = state.lock().await;
     service
         .top_up_wallet_for_key(&api_key, cashu_token)
@@ -964,7 +1017,10 @@ pub async fn routstr_refund_wallet_for_key(
 
     if let Some(ref token) = refund_response.token {
         let tollgate_service = tollgate_state.lock().await;
-        match tollgate_service.receive_cashu_token(token).await {
+        match tollgate_service
+            .receive_cashu_token(token, &crate::tollgate::origin::Origin::Proxy, false)
+            .await
+        {
             Ok(result) => {
                 log::info!(
                     "Successfully received refunded token into local wallet: {} sats from {}",
@@ -1097,7 +1153,6 @@ mod tests {
             storage: RoutstrStoragePaths {
                 config_file: config_file.clone(),
             },
-            auto_update_handle: None,
         };
 
         // Load configuration (should succeed with defaults)
@@ -0,0 +1,207 @@
+//! Ports in use and the discovery file
+//!
+//! The relay and the connection server prefer their configured ports but
+//! move to a free one when it is taken, so a second instance or another
+//! program on the port does not leave the wallet half started. The ports
+//! actually in use and an API token are written to `runtime.json` in the
+//! top-level data directory, where local tools such as wally-cli find the
+//! running instance.
+
+use crate::daemon::write_private;
+use crate::profiles;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const RUNTIME_FILE: &str = "runtime.json";
+/// Ports after the preferred one tried before asking the OS for any port
+const PORT_FALLBACK_ATTEMPTS: u16 = 10;
+
+pub type RuntimeState = Arc<RuntimeInfo>;
+
+/// Bind a loopback listener on `preferred`, or on a free port when it is
+/// taken. The listener is non-blocking, ready for tokio.
+pub fn bind_loopback(preferred: u16) -> io::Result<TcpListener> {
+    // Port 0 already means any free port
+    let candidates = (0..=PORT_FALLBACK_ATTEMPTS)
+        .filter(|_| preferred != 0)
+        .filter_map(|offset| preferred.checked_add(offset))
+        .chain(std::iter::once(0));
+
+    let mut last_error = None;
+    for port in candidates {
+        match TcpListener::bind(SocketAddr::from((Ipv4Addr::LOCALHOST, port))) {
+            Ok(listener) => {
+                if preferred != 0 && port != preferred {
+                    log::warn!(
+                        "Port {} is in use, using {} instead",
+                        preferred,
+                        listener.local_addr()?.port()
+                    );
+                }
+                listener.set_nonblocking(true)?;
+                return Ok(listener);
+            }
+            Err(e) if e.kind() == io::ErrorKind::AddrInUse => last_error = Some(e),
+            Err(e) => return Err(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::from(io::ErrorKind::AddrInUse)))
+}
+
+/// Generate a random hex token
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// How to reach the running instance
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuntimeInfo {
+    pub pid: u32,
+    /// Unix timestamp in seconds
+    pub started_at: u64,
+    /// None when the relay failed to start
+    pub relay_port: Option<u16>,
+    pub relay_url: Option<String>,
+    pub connection_port: u16,
+    /// Base URL of the local API
+    pub api_url: String,
+    /// Owner token for the local API. Left out when shown in the app.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+fn runtime_path() -> io::Result<PathBuf> {
+    Ok(profiles::root_dir()?.join(RUNTIME_FILE))
+}
+
+impl RuntimeInfo {
+    pub fn new(relay_port: Option<u16>, connection_port: u16, token: Option<String>) -> Self {
+        Self {
+            pid: std::process::id(),
+            started_at: chrono::Utc::now().timestamp() as u64,
+            relay_port,
+            relay_url: relay_port.map(|port| format!("ws://localhost:{}", port)),
+            connection_port,
+            api_url: format!("http://127.0.0.1:{}/api/v1", connection_port),
+            token,
+        }
+    }
+
+    /// The same info without the token
+    pub fn public(&self) -> Self {
+        Self {
+            token: None,
+            ..self.clone()
+        }
+    }
+
+    /// Base URL of the connection server
+    pub fn server_url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.connection_port)
+    }
+
+    /// Write the discovery file, readable only by the current user
+    pub fn write(&self) -> io::Result<()> {
+        let path = runtime_path()?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_private(&path, serde_json::to_vec_pretty(self)?)?;
+        log::info!("Wrote discovery file {}", path.display());
+        Ok(())
+    }
+
+    /// The discovery file of the last instance started, which may have exited
+    pub fn read() -> Option<Self> {
+        let data = fs::read(runtime_path().ok()?).ok()?;
+        serde_json::from_slice(&data).ok()
+    }
+
+    /// Whether the instance that wrote this is still running
+    pub fn is_alive(&self) -> bool {
+        self.pid == std::process::id() || process_alive(self.pid)
+    }
+
+    /// Remove the discovery file, unless another instance has replaced it
+    pub fn remove(&self) {
+        if Self::read().is_some_and(|current| current.pid == self.pid) {
+            if let Ok(path) = runtime_path() {
+                let _ = fs::remove_file(path);
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn process_alive(pid: u32) -> bool {
+    std::path::Path::new(&format!("/proc/{}", pid)).exists()
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn process_alive(pid: u32) -> bool {
+    std::process::Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+#[cfg(windows)]
+fn process_alive(pid: u32) -> bool {
+    std::process::Command::new("tasklist")
+        .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+        .output()
+        .is_ok_and(|output| String::from_utf8_lossy(&output.stdout).contains(&pid.to_string()))
+}
+
+#[tauri::command]
+pub async fn get_runtime_info(
+    runtime: tauri::State<'_, RuntimeState>,
+) -> Result<RuntimeInfo, String> {
+    Ok(runtime.public())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bind_falls_back_when_taken() {
+        let taken = bind_loopback(0).unwrap();
+        let port = taken.local_addr().unwrap().port();
+
+        let listener = bind_loopback(port).unwrap();
+        let fallback = listener.local_addr().unwrap().port();
+        assert_ne!(fallback, port);
+        assert_ne!(fallback, 0);
+    }
+
+    #[test]
+    fn test_runtime_info() {
+        let info = RuntimeInfo::new(Some(4870), 3738, Some("secret".to_string()));
+        assert_eq!(info.relay_url.as_deref(), Some("ws://localhost:4870"));
+        assert_eq!(info.api_url, "http://127.0.0.1:3738/api/v1");
+        assert_eq!(info.server_url(), "http://127.0.0.1:3738");
+
+        let public = serde_json::to_value(info.public()).unwrap();
+        assert!(public.get("token").is_none());
+        let parsed: RuntimeInfo =
+            serde_json::from_value(serde_json::to_value(&info).unwrap()).unwrap();
+        assert_eq!(parsed, info);
+    }
+
+    #[test]
+    fn test_runtime_info_liveness() {
+        let mut info = RuntimeInfo::new(None, 3738, None);
+        assert!(info.is_alive());
+        info.pid = u32::MAX;
+        assert!(!info.is_alive());
+    }
+}
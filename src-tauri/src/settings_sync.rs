@@ -0,0 +1,408 @@
+//! Settings backup to Nostr (NIP-78 app data)
+//!
+//! Publishes the wallet configuration as a kind 30078 event authored by the
+//! wallet's Nostr key, with the content NIP-44 encrypted to that same key.
+//! Restoring a wallet from its mnemonic pulls the latest event and applies
+//! it, so the configuration comes back along with the seed.
+//!
+//! NWC connections are synced as metadata only. Their secret keys never
+//! leave the device, so a connection seen on another device is reported for
+//! re-issuing rather than cloned.
+
+use crate::app_lock::AppLockState;
+use crate::config;
+use crate::nwc::{BudgetRenewalPeriod, NostrWalletConnect};
+use crate::routstr::{RoutstrProviderSelection, RoutstrService, RoutstrState};
+use crate::tollgate::TollGateService;
+use crate::{NwcState, TollGateState};
+use nostr_sdk::nips::nip44::{self, Version};
+use nostr_sdk::{Client, EventBuilder, Filter, Keys, Kind, Tag, Timestamp};
+use serde::{Deserialize, Serialize};
+use tauri::State;
+
+/// `d` tag of the settings event
+const SETTINGS_IDENTIFIER: &str = "wally/settings";
+const SETTINGS_VERSION: u32 = 1;
+
+/// NWC connection metadata as stored in the settings event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncedNwcConnection {
+    /// Public key of the connection
+    pub pubkey: String,
+    pub name: String,
+    pub renewal_period: BudgetRenewalPeriod,
+    pub total_budget_msats: u64,
+    pub app_pubkey: Option<String>,
+}
+
+/// Configuration carried in the settings event
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncedSettings {
+    pub version: u32,
+    pub mints: Vec<String>,
+    pub default_mint: Option<String>,
+    pub routstr: RoutstrProviderSelection,
+    pub nwc_connections: Vec<SyncedNwcConnection>,
+}
+
+/// Result of publishing settings
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingsPublishSummary {
+    pub event_id: String,
+    pub relays: Vec<String>,
+}
+
+/// What was applied from a settings event
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct SettingsApplySummary {
+    pub published_at: u64,
+    pub mints_added: Vec<String>,
+    pub mints_failed: Vec<String>,
+    pub nwc_connections_updated: usize,
+    /// Names of synced connections that do not exist here and have to be
+    /// issued again
+    pub nwc_connections_to_reissue: Vec<String>,
+    /// The NWC service key changed; NWC only picks it up after a restart
+    pub restart_required: bool,
+}
+
+async fn collect_settings(
+    service: &TollGateService,
+    nwc: Option<&NostrWalletConnect>,
+    routstr: &RoutstrService,
+) -> SyncedSettings {
+    let (mints, default_mint) = service.mint_settings().await;
+    let nwc_connections = match nwc {
+        Some(nwc) => nwc
+            .get_connections()
+            .await
+            .into_iter()
+            .map(|connection| SyncedNwcConnection {
+                pubkey: connection.keys.public_key().to_hex(),
+                name: connection.name,
+                renewal_period: connection.budget.renewal_period,
+                total_budget_msats: connection.budget.total_budget_msats,
+                app_pubkey: connection.app_pubkey.map(|pk| pk.to_hex()),
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+
+    SyncedSettings {
+        version: SETTINGS_VERSION,
+        mints,
+        default_mint,
+        routstr: routstr.provider_selection(),
+        nwc_connections,
+    }
+}
+
+async fn connect_client() -> Client {
+    let client = Client::default();
+    for relay in &config::current().relays.discovery {
+        if let Err(e) = client.add_relay(relay).await {
+            log::warn!("Failed to add relay {}: {}", relay, e);
+        }
+    }
+    client.connect().await;
+    client
+}
+
+/// Publish `settings` as an encrypted app-data event
+async fn publish_settings(
+    keys: &Keys,
+    settings: &SyncedSettings,
+) -> Result<SettingsPublishSummary, String> {
+    let plaintext = serde_json::to_string(settings).map_err(|e| e.to_string())?;
+    let content = nip44::encrypt(
+        keys.secret_key(),
+        &keys.public_key(),
+        plaintext,
+        Version::default(),
+    )
+    .map_err(|e| format!("Failed to encrypt settings: {}", e))?;
+
+    let event = EventBuilder::new(Kind::ApplicationSpecificData, content)
+        .tags(vec![Tag::identifier(SETTINGS_IDENTIFIER)])
+        .sign_with_keys(keys)
+        .map_err(|e| format!("Failed to sign settings event: {}", e))?;
+
+    let client = connect_client().await;
+    let output = client.send_event(&event).await;
+    client.disconnect().await;
+    let output = output.map_err(|e| format!("Failed to publish settings: {}", e))?;
+
+    log::info!(
+        "Published settings event {} to {} relays",
+        event.id,
+        output.success.len()
+    );
+
+    Ok(SettingsPublishSummary {
+        event_id: event.id.to_hex(),
+        relays: output.success.iter().map(|url| url.to_string()).collect(),
+    })
+}
+
+/// Fetch and decrypt the latest settings event
+async fn fetch_settings(keys: &Keys) -> Result<Option<(Timestamp, SyncedSettings)>, String> {
+    let filter = Filter::new()
+        .kind(Kind::ApplicationSpecificData)
+        .author(keys.public_key())
+        .identifier(SETTINGS_IDENTIFIER);
+
+    let client = connect_client().await;
+    let events = client
+        .fetch_events(filter, config::current().timeouts.request())
+        .await;
+    client.disconnect().await;
+    let events = events.map_err(|e| format!("Failed to fetch settings: {}", e))?;
+
+    let Some(event) = events.into_iter().max_by_key(|event| event.created_at) else {
+        return Ok(None);
+    };
+
+    let plaintext = nip44::decrypt(keys.secret_key(), &keys.public_key(), &event.content)
+        .map_err(|e| format!("Failed to decrypt settings: {}", e))?;
+    let settings: SyncedSettings =
+        serde_json::from_str(&plaintext).map_err(|e| format!("Invalid settings: {}", e))?;
+    if settings.version > SETTINGS_VERSION {
+        return Err(format!(
+            "Settings version {} is newer than supported version {}",
+            settings.version, SETTINGS_VERSION
+        ));
+    }
+
+    Ok(Some((event.created_at, settings)))
+}
+
+/// Update the names and budgets of connections that exist here. The others
+/// are listed in the summary, since their keys are not synced.
+async fn apply_nwc_connections(
+    nwc: &NostrWalletConnect,
+    connections: &[SyncedNwcConnection],
+    summary: &mut SettingsApplySummary,
+) {
+    let existing = nwc.get_connections().await;
+
+    for synced in connections {
+        if !existing
+            .iter()
+            .any(|conn| conn.keys.public_key().to_hex() == synced.pubkey)
+        {
+            summary.nwc_connections_to_reissue.push(synced.name.clone());
+            continue;
+        }
+
+        let pubkey = &synced.pubkey;
+        let budget_sats = synced.total_budget_msats / 1_000;
+        if let Err(e) = nwc
+            .update_connection_budget(pubkey, budget_sats, synced.renewal_period)
+            .await
+        {
+            log::warn!("Failed to update NWC budget for {}: {}", pubkey, e);
+        }
+        if let Err(e) = nwc.update_connection_name(pubkey, &synced.name).await {
+            log::warn!("Failed to update NWC name for {}: {}", pubkey, e);
+        }
+        summary.nwc_connections_updated += 1;
+    }
+}
+
+/// Apply settings to every subsystem. Mints that cannot be reached are
+/// reported and skipped.
+async fn apply_settings(
+    published_at: Timestamp,
+    settings: &SyncedSettings,
+    service: &TollGateService,
+    nwc: Option<&NostrWalletConnect>,
+    routstr: &mut RoutstrService,
+) -> Result<SettingsApplySummary, String> {
+    let mut summary = SettingsApplySummary {
+        published_at: published_at.as_u64(),
+        ..Default::default()
+    };
+
+    let (known_mints, _) = service.mint_settings().await;
+    for mint_url in &settings.mints {
+        if known_mints.contains(mint_url) {
+            continue;
+        }
+        match service.add_mint(mint_url).await {
+            Ok(()) => summary.mints_added.push(mint_url.clone()),
+            Err(e) => {
+                log::warn!("Failed to restore mint {}: {}", mint_url, e);
+                summary.mints_failed.push(mint_url.clone());
+            }
+        }
+    }
+    if let Some(default_mint) = &settings.default_mint {
+        if !summary.mints_failed.contains(default_mint) {
+            service
+                .set_default_mint(default_mint)
+                .await
+                .map_err(|e| e.to_string())?;
+        }
+    }
+
+    routstr
+        .apply_provider_selection(settings.routstr.clone())
+        .map_err(|e| e.to_string())?;
+
+    if let Some(nwc) = nwc {
+        apply_nwc_connections(nwc, &settings.nwc_connections, &mut summary).await;
+        summary.restart_required =
+            nwc.service_pubkey() != service.get_nwc_service_keys().await.public_key();
+    }
+
+    log::info!(
+        "Applied settings: {} mints added, {} NWC connections to re-issue",
+        summary.mints_added.len(),
+        summary.nwc_connections_to_reissue.len()
+    );
+
+    Ok(summary)
+}
+
+async fn pull_and_apply(
+    state: &TollGateState,
+    nwc_state: &NwcState,
+    routstr_state: &RoutstrState,
+) -> Result<Option<SettingsApplySummary>, String> {
+    let keys = state.lock().await.get_wallet_keys().await;
+
+    let Some((published_at, settings)) = fetch_settings(&keys).await? else {
+        log::info!("No settings event found for this wallet");
+        return Ok(None);
+    };
+
+    let service = state.lock().await;
+    let nwc = nwc_state.lock().await;
+    let mut routstr = routstr_state.lock().await;
+    apply_settings(
+        published_at,
+        &settings,
+        &service,
+        nwc.as_ref(),
+        &mut routstr,
+    )
+    .await
+    .map(Some)
+}
+
+#[tauri::command]
+pub async fn publish_settings_to_nostr(
+    state: State<'_, TollGateState>,
+    nwc_state: State<'_, NwcState>,
+    routstr_state: State<'_, RoutstrState>,
+    lock: State<'_, AppLockState>,
+) -> Result<SettingsPublishSummary, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    let (keys, settings) = {
+        let service = state.lock().await;
+        let nwc = nwc_state.lock().await;
+        let routstr = routstr_state.lock().await;
+        let settings = collect_settings(&service, nwc.as_ref(), &routstr).await;
+        (service.get_wallet_keys().await, settings)
+    };
+
+    publish_settings(&keys, &settings).await
+}
+
+#[tauri::command]
+pub async fn pull_settings_from_nostr(
+    state: State<'_, TollGateState>,
+    nwc_state: State<'_, NwcState>,
+    routstr_state: State<'_, RoutstrState>,
+    lock: State<'_, AppLockState>,
+) -> Result<Option<SettingsApplySummary>, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    pull_and_apply(&state, &nwc_state, &routstr_state).await
+}
+
+/// Replace the wallet seed with `mnemonic`, then pull and apply the settings
+/// published for it. Refused while the current wallet holds funds.
+#[tauri::command]
+pub async fn restore_wallet_from_mnemonic(
+    mnemonic: String,
+    state: State<'_, TollGateState>,
+    nwc_state: State<'_, NwcState>,
+    routstr_state: State<'_, RoutstrState>,
+    lock: State<'_, AppLockState>,
+) -> Result<Option<SettingsApplySummary>, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    {
+        let service = state.lock().await;
+        service
+            .restore_from_mnemonic(&mnemonic)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    pull_and_apply(&state, &nwc_state, &routstr_state).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settings_round_trip_through_nip44() {
+        let keys = Keys::generate();
+        let settings = SyncedSettings {
+            version: SETTINGS_VERSION,
+            mints: vec!["https://mint.example.com".to_string()],
+            default_mint: Some("https://mint.example.com".to_string()),
+            routstr: RoutstrProviderSelection::default(),
+            nwc_connections: vec![SyncedNwcConnection {
+                pubkey: Keys::generate().public_key().to_hex(),
+                name: "Alby".to_string(),
+                renewal_period: BudgetRenewalPeriod::Weekly,
+                total_budget_msats: 50_000,
+                app_pubkey: None,
+            }],
+        };
+
+        let content = nip44::encrypt(
+            keys.secret_key(),
+            &keys.public_key(),
+            serde_json::to_string(&settings).unwrap(),
+            Version::default(),
+        )
+        .unwrap();
+        let decrypted: SyncedSettings = serde_json::from_str(
+            &nip44::decrypt(keys.secret_key(), &keys.public_key(), &content).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(decrypted.mints, settings.mints);
+        assert_eq!(decrypted.nwc_connections[0].name, "Alby");
+    }
+
+    #[test]
+    fn test_synced_connections_carry_no_secrets() {
+        let settings = SyncedSettings {
+            version: SETTINGS_VERSION,
+            mints: Vec::new(),
+            default_mint: None,
+            routstr: RoutstrProviderSelection::default(),
+            nwc_connections: vec![SyncedNwcConnection {
+                pubkey: Keys::generate().public_key().to_hex(),
+                name: "Alby".to_string(),
+                renewal_period: BudgetRenewalPeriod::Never,
+                total_budget_msats: 0,
+                app_pubkey: None,
+            }],
+        };
+        let json = serde_json::to_value(&settings).unwrap();
+        let connection = json["nwc_connections"][0].as_object().unwrap();
+        assert!(!connection.contains_key("connection_secret"));
+        assert!(!connection.contains_key("secret"));
+
+        // Events published by older versions still parse
+        let mut legacy = json;
+        legacy["nwc_connections"] = serde_json::json!([]);
+        legacy["nwc_legacy_service_key"] = serde_json::json!(false);
+        assert!(serde_json::from_value::<SyncedSettings>(legacy).is_ok());
+    }
+}
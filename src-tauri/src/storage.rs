@@ -0,0 +1,130 @@
+//! Reading and writing the JSON files in the data directory
+//!
+//! Files are written to a temporary file that is synced and then renamed
+//! over the original, so a crash leaves either the old or the new contents.
+//! A file that cannot be parsed is moved aside instead of being read as
+//! empty, so the next save does not overwrite what may be the only copy.
+
+use serde::de::DeserializeOwned;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Replace `path` with `contents` without ever leaving it half written. The
+/// file is only readable by the current user.
+pub(crate) fn write_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let tmp = sibling(path, "tmp");
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(&tmp)?;
+    file.write_all(contents.as_ref())?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp, path)?;
+
+    // Make the rename itself durable
+    #[cfg(unix)]
+    if let Some(parent) = path.parent() {
+        fs::File::open(parent)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Read and parse `path`, returning `None` when it does not exist. A file
+/// that cannot be parsed is moved aside and reported as an error.
+pub(crate) fn read_json<T: DeserializeOwned>(path: &Path) -> io::Result<Option<T>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    match serde_json::from_slice(&data) {
+        Ok(value) => Ok(Some(value)),
+        Err(e) => {
+            let aside = sibling(path, &format!("corrupt-{}", chrono::Utc::now().timestamp()));
+            fs::rename(path, &aside)?;
+            Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{} could not be parsed and was moved to {}: {}",
+                    path.display(),
+                    aside.display(),
+                    e
+                ),
+            ))
+        }
+    }
+}
+
+/// Copies of `path` that `read_json` moved aside, oldest first
+pub(crate) fn corrupt_copies(path: &Path) -> Vec<PathBuf> {
+    let (Some(name), Some(parent)) = (path.file_name(), path.parent()) else {
+        return Vec::new();
+    };
+    let prefix = format!("{}.corrupt-", name.to_string_lossy());
+    let Ok(entries) = fs::read_dir(parent) else {
+        return Vec::new();
+    };
+
+    let mut copies: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|copy| {
+            copy.file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+        })
+        .collect();
+    copies.sort();
+    copies
+}
+
+/// `path` with `suffix` appended to its file name
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_and_read() {
+        let dir = std::env::temp_dir().join(format!("wally-storage-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("state.json");
+        assert!(read_json::<Vec<u32>>(&path).unwrap().is_none());
+
+        write_atomic(&path, b"[1, 2]").unwrap();
+        assert_eq!(read_json::<Vec<u32>>(&path).unwrap(), Some(vec![1, 2]));
+        assert!(!sibling(&path, "tmp").exists());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_corrupt_file_is_moved_aside() {
+        let dir = std::env::temp_dir().join(format!("wally-storage-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("state.json");
+        write_atomic(&path, b"[1, 2").unwrap();
+
+        let error = read_json::<Vec<u32>>(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(!path.exists());
+        let kept: Vec<_> = fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(kept.len(), 1);
+        assert_eq!(corrupt_copies(&path).len(), 1);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
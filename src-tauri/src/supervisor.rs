@@ -0,0 +1,362 @@
+//! Supervision of background services
+//!
+//! Long-running loops (the relay accept loop, the NWC event loop, the
+//! connection server, Routstr model updates and provider discovery) run
+//! under the supervisor rather than as detached tasks. A service that
+//! returns an error or panics is restarted after a delay that doubles with
+//! each failure, up to a minute, and starts over at one second once the
+//! service has stayed up for a minute. A service that returns `Ok` has
+//! finished and is left stopped.
+//!
+//! On shutdown every service sees its [`Shutdown`] signal and gets a few
+//! seconds to finish before it is aborted.
+//!
+//! The supervisor is process-wide, like the event bus, so services rebuilt
+//! on profile switches are replaced by name.
+
+use futures_util::FutureExt;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+
+/// Delay before the first restart of a failed service
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Uptime after which a failure counts as the first one again
+const HEALTHY_AFTER: Duration = Duration::from_secs(60);
+/// How long services get to finish on shutdown
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(5);
+
+static SUPERVISOR: OnceLock<Supervisor> = OnceLock::new();
+
+/// The process-wide supervisor
+pub fn supervisor() -> &'static Supervisor {
+    SUPERVISOR.get_or_init(|| Supervisor::new(INITIAL_BACKOFF))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServiceState {
+    Running,
+    /// Failed, and waiting to be restarted
+    Restarting,
+    /// Finished, stopped or shut down
+    Stopped,
+}
+
+/// Health of one supervised service
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServiceStatus {
+    pub name: String,
+    pub state: ServiceState,
+    /// Unix timestamp in seconds when the current or last run started
+    pub started_at: u64,
+    /// Restarts after failures since the service was spawned
+    pub restarts: u32,
+    pub last_error: Option<String>,
+    /// Unix timestamp of the next restart, while restarting
+    pub restart_at: Option<u64>,
+}
+
+/// Resolves when the supervisor shuts down, so a service can finish cleanly
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    /// Wait until shutdown has started
+    pub async fn wait(&mut self) {
+        let _ = self.0.wait_for(|stopping| *stopping).await;
+    }
+
+    pub fn is_set(&self) -> bool {
+        *self.0.borrow()
+    }
+}
+
+type ServiceFuture = Pin<Box<dyn Future<Output = Result<(), String>> + Send>>;
+type ServiceFactory = Arc<dyn Fn(Shutdown) -> ServiceFuture + Send + Sync>;
+type SharedStatus = Arc<Mutex<ServiceStatus>>;
+
+struct Service {
+    status: SharedStatus,
+    /// Runs the service and restarts it; None once shut down
+    driver: Option<JoinHandle<()>>,
+}
+
+pub struct Supervisor {
+    services: Mutex<BTreeMap<String, Service>>,
+    shutdown: watch::Sender<bool>,
+    initial_backoff: Duration,
+}
+
+impl Supervisor {
+    fn new(initial_backoff: Duration) -> Self {
+        Self {
+            services: Mutex::new(BTreeMap::new()),
+            shutdown: watch::channel(false).0,
+            initial_backoff,
+        }
+    }
+
+    fn services(&self) -> std::sync::MutexGuard<'_, BTreeMap<String, Service>> {
+        self.services.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Run `factory`'s service under `name`, replacing a service already
+    /// running under that name. Must be called from within the runtime.
+    pub fn spawn<F, Fut>(&self, name: &str, factory: F)
+    where
+        F: Fn(Shutdown) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let factory: ServiceFactory =
+            Arc::new(move |shutdown| -> ServiceFuture { Box::pin(factory(shutdown)) });
+        let status = Arc::new(Mutex::new(ServiceStatus {
+            name: name.to_string(),
+            state: ServiceState::Running,
+            started_at: now_secs(),
+            restarts: 0,
+            last_error: None,
+            restart_at: None,
+        }));
+        let driver = tokio::spawn(drive(
+            name.to_string(),
+            factory,
+            status.clone(),
+            Shutdown(self.shutdown.subscribe()),
+            self.initial_backoff,
+        ));
+
+        let replaced = self.services().insert(
+            name.to_string(),
+            Service {
+                status,
+                driver: Some(driver),
+            },
+        );
+        if let Some(driver) = replaced.and_then(|service| service.driver) {
+            driver.abort();
+        }
+    }
+
+    /// Abort the service running under `name`. Returns whether it was
+    /// running.
+    pub fn stop(&self, name: &str) -> bool {
+        let mut services = self.services();
+        let Some(service) = services.get_mut(name) else {
+            return false;
+        };
+        let Some(driver) = service.driver.take() else {
+            return false;
+        };
+        driver.abort();
+        let mut status = lock(&service.status);
+        let was_running = status.state != ServiceState::Stopped;
+        status.state = ServiceState::Stopped;
+        status.restart_at = None;
+        was_running
+    }
+
+    /// Every service spawned so far, by name
+    pub fn status(&self) -> Vec<ServiceStatus> {
+        self.services()
+            .values()
+            .map(|service| lock(&service.status).clone())
+            .collect()
+    }
+
+    /// Signal every service to stop, wait for them to finish, and abort
+    /// those still running after a grace period
+    pub async fn shutdown(&self) {
+        let _ = self.shutdown.send(true);
+        let drivers: Vec<_> = self
+            .services()
+            .iter_mut()
+            .filter_map(|(name, service)| Some((name.clone(), service.driver.take()?)))
+            .collect();
+
+        let deadline = tokio::time::Instant::now() + SHUTDOWN_GRACE;
+        for (name, mut driver) in drivers {
+            if tokio::time::timeout_at(deadline, &mut driver)
+                .await
+                .is_err()
+            {
+                log::warn!("Service {} did not stop in time, aborting it", name);
+                driver.abort();
+            }
+        }
+        for service in self.services().values() {
+            let mut status = lock(&service.status);
+            status.state = ServiceState::Stopped;
+            status.restart_at = None;
+        }
+        log::info!("All services stopped");
+    }
+}
+
+fn lock(status: &SharedStatus) -> std::sync::MutexGuard<'_, ServiceStatus> {
+    status.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn now_secs() -> u64 {
+    chrono::Utc::now().timestamp() as u64
+}
+
+fn panic_message(panic: &(dyn std::any::Any + Send)) -> String {
+    let message = panic
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| panic.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown cause");
+    format!("Panicked: {}", message)
+}
+
+/// Run a service until it finishes or shutdown starts, restarting it when
+/// it fails
+async fn drive(
+    name: String,
+    factory: ServiceFactory,
+    status: SharedStatus,
+    mut shutdown: Shutdown,
+    initial_backoff: Duration,
+) {
+    let mut backoff = initial_backoff;
+    loop {
+        let started = Instant::now();
+        {
+            let mut status = lock(&status);
+            status.state = ServiceState::Running;
+            status.started_at = now_secs();
+            status.restart_at = None;
+        }
+
+        let result = AssertUnwindSafe(factory(shutdown.clone()))
+            .catch_unwind()
+            .await;
+        let error = match result {
+            _ if shutdown.is_set() => None,
+            Ok(Ok(())) => {
+                log::info!("Service {} finished", name);
+                None
+            }
+            Ok(Err(e)) => Some(e),
+            Err(panic) => Some(panic_message(panic.as_ref())),
+        };
+        let Some(error) = error else {
+            lock(&status).state = ServiceState::Stopped;
+            return;
+        };
+
+        if started.elapsed() >= HEALTHY_AFTER {
+            backoff = initial_backoff;
+        }
+        log::error!(
+            "Service {} failed: {}. Restarting in {:?}",
+            name,
+            error,
+            backoff
+        );
+        {
+            let mut status = lock(&status);
+            status.state = ServiceState::Restarting;
+            status.restarts += 1;
+            status.last_error = Some(error);
+            status.restart_at = Some(now_secs() + backoff.as_secs());
+        }
+
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            _ = shutdown.wait() => {
+                lock(&status).state = ServiceState::Stopped;
+                return;
+            }
+        }
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+#[tauri::command]
+pub async fn get_service_status() -> Result<Vec<ServiceStatus>, String> {
+    Ok(supervisor().status())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+    async fn wait_for(supervisor: &Supervisor, check: impl Fn(&ServiceStatus) -> bool) {
+        for _ in 0..200 {
+            if supervisor.status().iter().any(&check) {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("service never reached the expected state");
+    }
+
+    #[tokio::test]
+    async fn test_failed_service_is_restarted() {
+        let supervisor = Supervisor::new(Duration::from_millis(10));
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = runs.clone();
+        supervisor.spawn("flaky", move |mut shutdown| {
+            let run = counter.fetch_add(1, Ordering::SeqCst);
+            async move {
+                match run {
+                    0 => Err("relay went away".to_string()),
+                    1 => panic!("boom"),
+                    _ => {
+                        shutdown.wait().await;
+                        Ok(())
+                    }
+                }
+            }
+        });
+
+        wait_for(&supervisor, |status| {
+            status.state == ServiceState::Running && status.restarts == 2
+        })
+        .await;
+        let status = &supervisor.status()[0];
+        assert_eq!(status.name, "flaky");
+        assert_eq!(status.last_error.as_deref(), Some("Panicked: boom"));
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_finished_service_is_not_restarted() {
+        let supervisor = Supervisor::new(Duration::from_millis(10));
+        supervisor.spawn("once", |_| async { Ok(()) });
+        wait_for(&supervisor, |status| status.state == ServiceState::Stopped).await;
+        assert_eq!(supervisor.status()[0].restarts, 0);
+        assert!(!supervisor.stop("once"));
+        assert!(!supervisor.stop("missing"));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_lets_services_finish() {
+        let supervisor = Supervisor::new(Duration::from_millis(10));
+        let flushed = Arc::new(AtomicBool::new(false));
+        let flag = flushed.clone();
+        supervisor.spawn("store", move |mut shutdown| {
+            let flag = flag.clone();
+            async move {
+                shutdown.wait().await;
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                flag.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+        });
+
+        supervisor.shutdown().await;
+        assert!(flushed.load(Ordering::SeqCst));
+        assert_eq!(supervisor.status()[0].state, ServiceState::Stopped);
+    }
+}
@@ -0,0 +1,244 @@
+//! Human-in-the-loop approval of outgoing payments
+//!
+//! When the spending policy answers "ask" for a payment that no user
+//! started (NWC, the Routstr proxy, TollGate renewals, scheduled payments),
+//! the payment is parked here until the user approves or rejects it in the
+//! app, or until the prompt times out. Prompts are published on a broadcast
+//! channel which the backend republishes on the event bus.
+
+use crate::tollgate::spending_policy::SpendRequest;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{broadcast, oneshot};
+
+/// How long a payment waits for an answer before it is refused, unless
+/// `timeouts.approval_secs` in the config says otherwise
+pub const DEFAULT_APPROVAL_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Shared approval broker, managed by the app and handed to each profile's
+/// wallet service
+pub type ApprovalsState = Arc<ApprovalBroker>;
+
+/// A payment waiting for the user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingApproval {
+    pub id: String,
+    pub origin: String,
+    pub counterparty: Option<String>,
+    pub mint_url: Option<String>,
+    pub amount_sats: u64,
+    /// Why the policy asked, usually the matching rule's name
+    pub reason: String,
+    pub requested_at: u64,
+    pub expires_at: u64,
+}
+
+/// Published whenever the set of pending approvals changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ApprovalEvent {
+    Requested(PendingApproval),
+    Resolved {
+        id: String,
+        outcome: ApprovalOutcome,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApprovalOutcome {
+    Approved,
+    Rejected,
+    TimedOut,
+    /// Nobody is listening for prompts, so nobody could be asked
+    Unavailable,
+}
+
+struct Waiter {
+    approval: PendingApproval,
+    respond: oneshot::Sender<bool>,
+}
+
+pub struct ApprovalBroker {
+    pending: Mutex<HashMap<String, Waiter>>,
+    events: broadcast::Sender<ApprovalEvent>,
+    /// Fixed timeout; `None` reads `timeouts.approval_secs` for each prompt
+    timeout: Option<Duration>,
+}
+
+impl Default for ApprovalBroker {
+    fn default() -> Self {
+        Self::new(DEFAULT_APPROVAL_TIMEOUT)
+    }
+}
+
+impl ApprovalBroker {
+    pub fn new(timeout: Duration) -> Self {
+        Self::with_timeout(Some(timeout))
+    }
+
+    /// Broker that follows the approval timeout in the config, so changes
+    /// apply to the next prompt
+    pub fn from_config() -> Self {
+        Self::with_timeout(None)
+    }
+
+    fn with_timeout(timeout: Option<Duration>) -> Self {
+        let (events, _) = broadcast::channel(64);
+        Self {
+            pending: Mutex::new(HashMap::new()),
+            events,
+            timeout,
+        }
+    }
+
+    fn timeout(&self) -> Duration {
+        self.timeout
+            .unwrap_or_else(|| crate::config::current().timeouts.approval())
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<ApprovalEvent> {
+        self.events.subscribe()
+    }
+
+    /// Payments currently waiting, oldest first
+    pub fn pending(&self) -> Vec<PendingApproval> {
+        let mut pending: Vec<_> = self
+            .pending
+            .lock()
+            .expect("approvals poisoned")
+            .values()
+            .map(|waiter| waiter.approval.clone())
+            .collect();
+        pending.sort_by_key(|approval| approval.requested_at);
+        pending
+    }
+
+    /// Ask the user about `request` and wait for the answer
+    pub async fn request(&self, request: &SpendRequest, reason: &str) -> ApprovalOutcome {
+        if self.events.receiver_count() == 0 {
+            return ApprovalOutcome::Unavailable;
+        }
+
+        let timeout = self.timeout();
+        let now = now_secs();
+        let approval = PendingApproval {
+            id: uuid::Uuid::new_v4().to_string(),
+            origin: request.origin.to_string(),
+            counterparty: request.counterparty.clone(),
+            mint_url: request.mint_url.clone(),
+            amount_sats: request.amount_sats,
+            reason: reason.to_string(),
+            requested_at: now,
+            expires_at: now + timeout.as_secs(),
+        };
+        let id = approval.id.clone();
+
+        let (respond, answer) = oneshot::channel();
+        self.pending.lock().expect("approvals poisoned").insert(
+            id.clone(),
+            Waiter {
+                approval: approval.clone(),
+                respond,
+            },
+        );
+        log::info!(
+            "Waiting for approval {} of {} sats from {}",
+            id,
+            approval.amount_sats,
+            approval.origin
+        );
+        let _ = self.events.send(ApprovalEvent::Requested(approval));
+
+        let outcome = match tokio::time::timeout(timeout, answer).await {
+            Ok(Ok(true)) => ApprovalOutcome::Approved,
+            Ok(Ok(false)) | Ok(Err(_)) => ApprovalOutcome::Rejected,
+            Err(_) => {
+                self.pending.lock().expect("approvals poisoned").remove(&id);
+                ApprovalOutcome::TimedOut
+            }
+        };
+
+        log::info!("Approval {} finished: {:?}", id, outcome);
+        let _ = self.events.send(ApprovalEvent::Resolved { id, outcome });
+        outcome
+    }
+
+    /// Answer a pending approval. Returns false if it no longer exists.
+    pub fn resolve(&self, id: &str, approved: bool) -> bool {
+        let waiter = self.pending.lock().expect("approvals poisoned").remove(id);
+        match waiter {
+            Some(waiter) => waiter.respond.send(approved).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Reject everything still waiting, e.g. when the wallet is locked or
+    /// the profile changes
+    pub fn reject_all(&self) {
+        let waiters: Vec<_> = self
+            .pending
+            .lock()
+            .expect("approvals poisoned")
+            .drain()
+            .collect();
+        for (_, waiter) in waiters {
+            let _ = waiter.respond.send(false);
+        }
+    }
+}
+
+fn now_secs() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tollgate::origin::Origin;
+    use crate::tollgate::spend_ledger::SpendKind;
+
+    fn request() -> SpendRequest {
+        SpendRequest {
+            kind: SpendKind::Token,
+            origin: Origin::Proxy,
+            counterparty: None,
+            amount_sats: 500,
+            mint_url: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_approve_and_timeout() {
+        let broker = Arc::new(ApprovalBroker::new(Duration::from_millis(200)));
+        assert_eq!(
+            broker.request(&request(), "cap").await,
+            ApprovalOutcome::Unavailable
+        );
+
+        let mut events = broker.subscribe();
+        let responder = broker.clone();
+        tokio::spawn(async move {
+            if let Ok(ApprovalEvent::Requested(approval)) = events.recv().await {
+                assert_eq!(approval.amount_sats, 500);
+                assert!(responder.resolve(&approval.id, true));
+            }
+        });
+        assert_eq!(
+            broker.request(&request(), "cap").await,
+            ApprovalOutcome::Approved
+        );
+
+        let _listener = broker.subscribe();
+        assert_eq!(
+            broker.request(&request(), "cap").await,
+            ApprovalOutcome::TimedOut
+        );
+        assert!(broker.pending().is_empty());
+    }
+}
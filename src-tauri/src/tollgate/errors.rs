@@ -39,6 +39,30 @@ pub enum TollGateError {
     #[error("Invalid gateway IP: {0}")]
     InvalidGatewayIp(String),
 
+    #[error("Mint is not trusted: {0}")]
+    UntrustedMint(String),
+
+    #[error("Mint requires approval: {0}")]
+    MintApprovalRequired(String),
+
+    #[error("Token cannot be redeemed: {0}")]
+    TokenRejected(String),
+
+    #[error("Payment denied by spending policy: {0}")]
+    SpendDenied(String),
+
+    #[error("Payment requires approval: {0}")]
+    SpendApprovalRequired(String),
+
+    #[error("Payment rejected by user: {0}")]
+    SpendRejected(String),
+
+    #[error("Payment approval timed out")]
+    SpendApprovalTimedOut,
+
+    #[error("Storage error: {0}")]
+    Storage(#[from] rusqlite::Error),
+
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 }
@@ -0,0 +1,176 @@
+//! Trust policy for mints of incoming tokens
+//!
+//! Decides whether a token from a mint we have not configured yet may be
+//! kept as-is, needs to be confirmed by the user first, or is untrusted.
+//! Untrusted tokens are either rejected or melted into the default mint.
+
+use crate::tollgate::origin::Origin;
+use serde::{Deserialize, Serialize};
+
+/// How tokens from mints that are not configured yet are treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MintTrustMode {
+    /// Any mint is trusted and added automatically
+    #[default]
+    Open,
+    /// Only configured and allow-listed mints are trusted
+    AllowList,
+    /// The user is asked before an unknown mint is added
+    Ask,
+}
+
+/// What happens to a token from an untrusted mint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UntrustedMintAction {
+    /// Refuse the token
+    Reject,
+    /// Melt the token over Lightning into the default mint
+    #[default]
+    SwapToDefault,
+}
+
+/// Mint trust policy, persisted alongside the mint list
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct MintTrustPolicy {
+    #[serde(default)]
+    pub mode: MintTrustMode,
+    #[serde(default)]
+    pub allowed_mints: Vec<String>,
+    #[serde(default)]
+    pub denied_mints: Vec<String>,
+    #[serde(default)]
+    pub untrusted_action: UntrustedMintAction,
+}
+
+/// Outcome of evaluating the policy for one token
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MintTrustDecision {
+    /// Keep the token at its own mint
+    Trusted,
+    /// Ask the user before adding the mint
+    NeedsApproval,
+    /// Apply the configured `UntrustedMintAction`
+    Untrusted,
+}
+
+/// Normalize a mint URL for comparison
+pub fn normalize_mint_url(mint_url: &str) -> String {
+    mint_url.trim().trim_end_matches('/').to_lowercase()
+}
+
+impl MintTrustPolicy {
+    fn contains(list: &[String], mint_url: &str) -> bool {
+        let target = normalize_mint_url(mint_url);
+        list.iter().any(|entry| normalize_mint_url(entry) == target)
+    }
+
+    pub fn is_denied(&self, mint_url: &str) -> bool {
+        Self::contains(&self.denied_mints, mint_url)
+    }
+
+    pub fn is_allowed(&self, mint_url: &str) -> bool {
+        Self::contains(&self.allowed_mints, mint_url)
+    }
+
+    /// Evaluate the policy for a token from `mint_url`.
+    ///
+    /// `known` is true when the mint is already configured in the wallet and
+    /// `approved` is true when the user explicitly confirmed this mint.
+    pub fn evaluate(
+        &self,
+        mint_url: &str,
+        known: bool,
+        approved: bool,
+        origin: &Origin,
+    ) -> MintTrustDecision {
+        if self.is_denied(mint_url) {
+            return MintTrustDecision::Untrusted;
+        }
+
+        if known || self.is_allowed(mint_url) {
+            return MintTrustDecision::Trusted;
+        }
+
+        if approved && origin.is_interactive() {
+            return MintTrustDecision::Trusted;
+        }
+
+        match self.mode {
+            MintTrustMode::Open => MintTrustDecision::Trusted,
+            MintTrustMode::AllowList => MintTrustDecision::Untrusted,
+            MintTrustMode::Ask if origin.is_interactive() => MintTrustDecision::NeedsApproval,
+            MintTrustMode::Ask => MintTrustDecision::Untrusted,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINT: &str = "https://mint.example.com";
+
+    fn nwc() -> Origin {
+        Origin::Nwc {
+            connection: "abc".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_denied_mint_is_never_trusted() {
+        let policy = MintTrustPolicy {
+            denied_mints: vec!["https://MINT.example.com/".to_string()],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            policy.evaluate(MINT, true, true, &Origin::Ui),
+            MintTrustDecision::Untrusted
+        );
+    }
+
+    #[test]
+    fn test_allow_list_mode() {
+        let policy = MintTrustPolicy {
+            mode: MintTrustMode::AllowList,
+            allowed_mints: vec![MINT.to_string()],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            policy.evaluate(MINT, false, false, &nwc()),
+            MintTrustDecision::Trusted
+        );
+        assert_eq!(
+            policy.evaluate("https://other.example.com", false, false, &Origin::Proxy),
+            MintTrustDecision::Untrusted
+        );
+        assert_eq!(
+            policy.evaluate("https://other.example.com", true, false, &Origin::Proxy),
+            MintTrustDecision::Trusted
+        );
+    }
+
+    #[test]
+    fn test_ask_mode_only_prompts_interactive_callers() {
+        let policy = MintTrustPolicy {
+            mode: MintTrustMode::Ask,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            policy.evaluate(MINT, false, false, &Origin::Ui),
+            MintTrustDecision::NeedsApproval
+        );
+        assert_eq!(
+            policy.evaluate(MINT, false, true, &Origin::Ui),
+            MintTrustDecision::Trusted
+        );
+        assert_eq!(
+            policy.evaluate(MINT, false, true, &nwc()),
+            MintTrustDecision::Untrusted
+        );
+    }
+}
@@ -3,11 +3,20 @@
 //! This module handles all TollGate protocol operations, session management,
 //! and background purchasing logic.
 
+pub mod approvals;
 pub mod errors;
+pub mod mint_policy;
 pub mod network;
+pub mod origin;
+pub mod pending_tokens;
 pub mod protocol;
+pub mod scheduler;
 pub mod service;
 pub mod session;
+pub mod spend_ledger;
+pub mod spending_policy;
+pub mod split_strategy;
+pub mod token_pool;
 pub mod wallet;
 
 pub use service::TollGateService;
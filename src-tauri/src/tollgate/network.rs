@@ -10,7 +10,6 @@ use crate::tollgate::errors::{TollGateError, TollGateResult};
 use crate::tollgate::protocol::{TollGateAdvertisement, TollGateProtocol};
 use serde::{Deserialize, Serialize};
 use std::net::IpAddr;
-use std::time::Duration;
 
 /// Network information for a detected TollGate
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,6 +21,7 @@ pub struct NetworkInfo {
 }
 
 /// Network detector for TollGate networks
+#[derive(Clone)]
 pub struct NetworkDetector {
     client: reqwest::Client,
     protocol: TollGateProtocol,
@@ -31,7 +31,7 @@ impl NetworkDetector {
     pub fn new() -> Self {
         Self {
             client: reqwest::Client::builder()
-                .timeout(Duration::from_secs(5))
+                .timeout(crate::config::current().timeouts.gateway())
                 .build()
                 .unwrap(),
             protocol: TollGateProtocol::new(),
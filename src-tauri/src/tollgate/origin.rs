@@ -0,0 +1,61 @@
+//! Origin of a wallet operation
+//!
+//! Identifies which subsystem triggered a receive or spend so that policies
+//! can treat interactive UI actions differently from automated callers.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Subsystem that triggered a wallet operation
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Origin {
+    /// The user acting through the app UI
+    Ui,
+    /// A Nostr Wallet Connect client, identified by its connection pubkey
+    Nwc { connection: String },
+    /// The Routstr proxy (payment tokens, change and refunds)
+    Proxy,
+    /// Automatic TollGate purchases and renewals
+    TollGate,
+    /// A recurring scheduled payment, identified by its schedule id
+    Scheduled { schedule: String },
+    /// The command-line client, run by the user at a terminal
+    Cli,
+    /// A local API client, identified by its client id
+    Api { client: String },
+}
+
+impl Origin {
+    /// Whether a user is present to answer a prompt for this operation
+    pub fn is_interactive(&self) -> bool {
+        matches!(self, Origin::Ui | Origin::Cli)
+    }
+
+    /// Part of the app the operation came from, without the identifier
+    pub fn subsystem(&self) -> &'static str {
+        match self {
+            Origin::Ui => "ui",
+            Origin::Nwc { .. } => "nwc",
+            Origin::Proxy => "proxy",
+            Origin::TollGate => "tollgate",
+            Origin::Scheduled { .. } => "scheduled",
+            Origin::Cli => "cli",
+            Origin::Api { .. } => "api",
+        }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Origin::Ui => write!(f, "ui"),
+            Origin::Nwc { connection } => write!(f, "nwc:{}", connection),
+            Origin::Proxy => write!(f, "proxy"),
+            Origin::TollGate => write!(f, "tollgate"),
+            Origin::Scheduled { schedule } => write!(f, "scheduled:{}", schedule),
+            Origin::Cli => write!(f, "cli"),
+            Origin::Api { client } => write!(f, "api:{}", client),
+        }
+    }
+}
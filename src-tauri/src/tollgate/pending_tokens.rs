@@ -0,0 +1,331 @@
+//! Store for incoming cashu tokens that were accepted offline
+//!
+//! On a captive TollGate network the mint is usually unreachable, so a
+//! received token cannot be swapped right away. The token is validated
+//! locally, persisted here as "unredeemed incoming" and redeemed by the
+//! background service once the mint can be reached again.
+//!
+//! Outgoing tokens that were paid for but could not be delivered are kept
+//! here too, so the only copy of them is not lost. So are tokens from
+//! untrusted mints while they are melted into the default mint, so a swap
+//! that stops halfway can be finished later.
+//!
+//! A store file that cannot be parsed is moved aside and the store starts
+//! empty. The copy is listed by `damaged_files` until the user removes it,
+//! so the tokens in it can still be redeemed by hand.
+
+use crate::storage;
+use crate::tollgate::errors::TollGateResult;
+use crate::tollgate::origin::Origin;
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::PathBuf;
+
+/// Longest delay between two redemption attempts
+const MAX_RETRY_DELAY_SECS: i64 = 600;
+/// Delay after the first failed attempt, doubled on each further failure
+const BASE_RETRY_DELAY_SECS: i64 = 10;
+
+/// Redemption state of a pending token
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PendingTokenStatus {
+    /// Waiting for the mint to become reachable
+    Pending,
+    /// Swapped with the mint and added to the balance
+    Redeemed,
+    /// Permanently failed (e.g. already spent or untrusted mint)
+    Failed,
+    /// Outgoing token that could not be delivered to its recipient. It is
+    /// never redeemed here, since it may be locked to the recipient.
+    Undelivered,
+}
+
+/// Incoming token that has not been redeemed with its mint yet
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingToken {
+    pub id: String,
+    pub token: String,
+    pub amount: u64,
+    pub unit: String,
+    pub mint_url: String,
+    pub origin: Origin,
+    /// User approved an unknown mint when the token was accepted
+    #[serde(default)]
+    pub mint_approved: bool,
+    /// DLEQ proofs were checked against cached mint keys
+    pub dleq_verified: bool,
+    pub status: PendingTokenStatus,
+    pub received_at: DateTime<Utc>,
+    pub attempts: u32,
+    pub last_attempt_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub redeemed_at: Option<DateTime<Utc>>,
+    /// Swap into the default mint of a token from an untrusted mint, set
+    /// once the untrusted mint has taken the token
+    #[serde(default)]
+    pub swap: Option<PendingSwap>,
+}
+
+/// Progress of melting a token from an untrusted mint into the default mint
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct PendingSwap {
+    /// Amount redeemed at the untrusted mint
+    pub received: u64,
+    /// Mint quote at the default mint paid by the melt
+    pub quote: Option<SwapQuote>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SwapQuote {
+    pub mint_url: String,
+    pub quote_id: String,
+    /// Invoice of the quote, paid from the untrusted mint
+    pub request: String,
+    pub amount: u64,
+}
+
+impl PendingToken {
+    /// Whether a redemption attempt is due at `now`
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        if self.status != PendingTokenStatus::Pending {
+            return false;
+        }
+
+        match self.last_attempt_at {
+            None => true,
+            Some(last) => {
+                let exponent = self.attempts.saturating_sub(1).min(16);
+                let delay = (BASE_RETRY_DELAY_SECS << exponent).min(MAX_RETRY_DELAY_SECS);
+                now >= last + ChronoDuration::seconds(delay)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct StoredPendingTokens {
+    tokens: Vec<PendingToken>,
+}
+
+/// JSON-backed list of pending incoming tokens
+#[derive(Debug)]
+pub struct PendingTokenStore {
+    path: PathBuf,
+    tokens: Vec<PendingToken>,
+}
+
+impl PendingTokenStore {
+    /// Load the store from `path`, starting empty if the file does not exist
+    /// or cannot be parsed. An unparsable file is moved aside and kept.
+    pub fn load(path: PathBuf) -> TollGateResult<Self> {
+        let tokens = match storage::read_json::<StoredPendingTokens>(&path) {
+            Ok(stored) => stored.unwrap_or_default().tokens,
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                log::error!("Starting with no pending tokens: {}", e);
+                Vec::new()
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self { path, tokens })
+    }
+
+    /// Store files that could not be parsed and were moved aside. The tokens
+    /// in them are not redeemed automatically.
+    pub fn damaged_files(&self) -> Vec<PathBuf> {
+        storage::corrupt_copies(&self.path)
+    }
+
+    fn save(&self) -> TollGateResult<()> {
+        let stored = StoredPendingTokens {
+            tokens: self.tokens.clone(),
+        };
+        storage::write_atomic(&self.path, serde_json::to_vec_pretty(&stored)?)?;
+        Ok(())
+    }
+
+    pub fn list(&self) -> Vec<PendingToken> {
+        self.tokens.clone()
+    }
+
+    pub fn contains_token(&self, token: &str) -> bool {
+        self.tokens.iter().any(|entry| entry.token == token)
+    }
+
+    pub fn get(&self, id: &str) -> Option<PendingToken> {
+        self.tokens.iter().find(|entry| entry.id == id).cloned()
+    }
+
+    pub fn find_token(&self, token: &str) -> Option<PendingToken> {
+        self.tokens
+            .iter()
+            .find(|entry| entry.token == token)
+            .cloned()
+    }
+
+    /// Tokens whose next redemption attempt is due
+    pub fn due(&self, now: DateTime<Utc>) -> Vec<PendingToken> {
+        self.tokens
+            .iter()
+            .filter(|entry| entry.is_due(now))
+            .cloned()
+            .collect()
+    }
+
+    pub fn insert(&mut self, token: PendingToken) -> TollGateResult<()> {
+        self.tokens.push(token);
+        self.save()
+    }
+
+    pub fn mark_redeemed(&mut self, id: &str) -> TollGateResult<()> {
+        if let Some(entry) = self.tokens.iter_mut().find(|entry| entry.id == id) {
+            let now = Utc::now();
+            entry.status = PendingTokenStatus::Redeemed;
+            entry.attempts += 1;
+            entry.last_attempt_at = Some(now);
+            entry.redeemed_at = Some(now);
+            entry.last_error = None;
+        }
+        self.save()
+    }
+
+    /// Record a failed attempt; `permanent` failures are not retried
+    pub fn mark_failed(&mut self, id: &str, error: String, permanent: bool) -> TollGateResult<()> {
+        if let Some(entry) = self.tokens.iter_mut().find(|entry| entry.id == id) {
+            entry.attempts += 1;
+            entry.last_attempt_at = Some(Utc::now());
+            entry.last_error = Some(error);
+            if permanent {
+                entry.status = PendingTokenStatus::Failed;
+            }
+        }
+        self.save()
+    }
+
+    /// Record how far the swap of a token into the default mint got
+    pub fn set_swap(&mut self, id: &str, swap: PendingSwap) -> TollGateResult<()> {
+        if let Some(entry) = self.tokens.iter_mut().find(|entry| entry.id == id) {
+            entry.swap = Some(swap);
+        }
+        self.save()
+    }
+
+    /// Reset a failed or waiting token so it is retried on the next pass
+    pub fn retry(&mut self, id: &str) -> TollGateResult<bool> {
+        let Some(entry) = self.tokens.iter_mut().find(|entry| {
+            entry.id == id
+                && matches!(
+                    entry.status,
+                    PendingTokenStatus::Pending | PendingTokenStatus::Failed
+                )
+        }) else {
+            return Ok(false);
+        };
+        entry.status = PendingTokenStatus::Pending;
+        entry.last_attempt_at = None;
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Remove a token from the store, returning it
+    pub fn remove(&mut self, id: &str) -> TollGateResult<Option<PendingToken>> {
+        let position = self.tokens.iter().position(|entry| entry.id == id);
+        let removed = position.map(|index| self.tokens.remove(index));
+        if removed.is_some() {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn sample(id: &str) -> PendingToken {
+        PendingToken {
+            id: id.to_string(),
+            token: format!("cashuB{}", id),
+            amount: 21,
+            unit: "sat".to_string(),
+            mint_url: "https://mint.example.com".to_string(),
+            origin: Origin::Ui,
+            mint_approved: false,
+            dleq_verified: true,
+            status: PendingTokenStatus::Pending,
+            received_at: Utc::now(),
+            attempts: 0,
+            last_attempt_at: None,
+            last_error: None,
+            redeemed_at: None,
+            swap: None,
+        }
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let now = Utc::now();
+        let mut token = sample("a");
+        assert!(token.is_due(now));
+
+        token.attempts = 1;
+        token.last_attempt_at = Some(now);
+        assert!(!token.is_due(now + ChronoDuration::seconds(5)));
+        assert!(token.is_due(now + ChronoDuration::seconds(10)));
+
+        token.attempts = 20;
+        assert!(token.is_due(now + ChronoDuration::seconds(MAX_RETRY_DELAY_SECS)));
+
+        token.status = PendingTokenStatus::Failed;
+        assert!(!token.is_due(now + ChronoDuration::days(1)));
+        token.status = PendingTokenStatus::Undelivered;
+        assert!(!token.is_due(now + ChronoDuration::days(1)));
+    }
+
+    #[test]
+    fn test_store_round_trip() {
+        let path = std::env::temp_dir().join(format!(
+            "pending-tokens-test-{}.json",
+            uuid::Uuid::new_v4()
+        ));
+        let mut store = PendingTokenStore::load(path.clone()).unwrap();
+        store.insert(sample("a")).unwrap();
+        store.insert(sample("b")).unwrap();
+        store
+            .mark_failed("a", "token already spent".to_string(), true)
+            .unwrap();
+        store.mark_redeemed("b").unwrap();
+
+        let reloaded = PendingTokenStore::load(path.clone()).unwrap();
+        let tokens = reloaded.list();
+        assert_eq!(tokens.len(), 2);
+        assert_eq!(tokens[0].status, PendingTokenStatus::Failed);
+        assert_eq!(tokens[1].status, PendingTokenStatus::Redeemed);
+        assert!(reloaded.due(Utc::now()).is_empty());
+
+        let _ = fs::remove_file(path);
+    }
+
+    #[test]
+    fn test_corrupt_store_is_kept_aside() {
+        let dir =
+            std::env::temp_dir().join(format!("pending-tokens-test-{}", uuid::Uuid::new_v4()));
+        let path = dir.join("pending_tokens.json");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(&path, b"{\"tokens\": [").unwrap();
+
+        let store = PendingTokenStore::load(path.clone()).unwrap();
+        assert!(store.list().is_empty());
+        // The unreadable file is kept next to where it was, also after the
+        // empty store is saved and loaded again
+        store.save().unwrap();
+        let store = PendingTokenStore::load(path.clone()).unwrap();
+        assert_eq!(store.damaged_files().len(), 1);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -30,6 +30,13 @@ pub struct PricingOption {
     pub min_steps: u64,      // Minimum steps to purchase
 }
 
+impl PricingOption {
+    /// Price of `steps`, or `None` when the advertised price overflows
+    pub fn cost(&self, steps: u64) -> Option<u64> {
+        self.price_per_step.checked_mul(steps)
+    }
+}
+
 /// Payment event to send to TollGate (kind 21000)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PaymentEvent {
@@ -72,7 +79,7 @@ impl TollGateProtocol {
         let response = self
             .client
             .get(&advertisement_url)
-            .timeout(std::time::Duration::from_secs(5))
+            .timeout(crate::config::current().timeouts.gateway())
             .send()
             .await?;
 
@@ -200,7 +207,7 @@ impl TollGateProtocol {
         let response = self
             .client
             .get(&whoami_url)
-            .timeout(std::time::Duration::from_secs(5))
+            .timeout(crate::config::current().timeouts.gateway())
             .send()
             .await?;
 
@@ -265,7 +272,7 @@ impl TollGateProtocol {
             .post(&payment_url)
             .header("Content-Type", "application/json")
             .json(payment_event)
-            .timeout(std::time::Duration::from_secs(10))
+            .timeout(crate::config::current().timeouts.request())
             .send()
             .await?;
 
@@ -433,7 +440,7 @@ impl TollGateProtocol {
 
     /// Calculate total cost for a purchase
     pub fn calculate_cost(&self, option: &PricingOption, steps: u64) -> u64 {
-        steps * option.price_per_step
+        option.cost(steps).unwrap_or(u64::MAX)
     }
 
     /// Calculate allotment for given steps
@@ -485,6 +492,7 @@ mod tests {
         };
 
         assert_eq!(protocol.calculate_cost(&option, 100), 500);
+        assert_eq!(option.cost(u64::MAX / 2), None);
     }
 
     #[test]
@@ -0,0 +1,1011 @@
+//! Recurring scheduled payments
+//!
+//! Pays a fixed amount on a fixed schedule to a Lightning address, an LNURL,
+//! a NUT-18 payment request with a transport, or a nostr user (as a P2PK
+//! locked token delivered by encrypted DM). Schedules and their run history
+//! are persisted in SQLite so missed runs can be handled when the app starts
+//! again.
+
+use crate::config;
+use crate::lnurl;
+use crate::profiles;
+use crate::tollgate::errors::{TollGateError, TollGateResult};
+use crate::tollgate::origin::Origin;
+use crate::tollgate::spend_ledger::SpendKind;
+use crate::tollgate::spending_policy::{SpendRequest, SpendTicket, SpendingPolicyEngine};
+use crate::tollgate::wallet::TollGateWallet;
+use cdk::nuts::nut18::payment_request::PaymentRequest;
+use chrono::{DateTime, Duration as ChronoDuration, Months, TimeZone, Utc};
+use nostr_sdk::prelude::FromBech32;
+use nostr_sdk::{nips::nip04, Client, EventBuilder, Kind, PublicKey, Tag, TagStandard};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Runs scheduled further back than this count as missed
+const MISSED_RUN_GRACE_SECS: i64 = 300;
+/// Upper bound on runs executed for one schedule in a single catch-up
+const MAX_CATCH_UP_RUNS: usize = 24;
+/// Shortest allowed custom interval
+const MIN_INTERVAL_SECS: u64 = 60;
+
+/// Where a scheduled payment goes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ScheduleTarget {
+    /// LUD-16 Lightning address (`name@domain`)
+    LightningAddress { address: String },
+    /// LNURL-pay endpoint that issues BOLT11 invoices
+    Lnurl { lnurl: String },
+    /// NUT-18 payment request with a transport
+    Nut18 { request: String },
+    /// P2PK-locked token sent to an npub by encrypted DM
+    Nostr {
+        npub: String,
+        #[serde(default)]
+        relays: Vec<String>,
+    },
+}
+
+/// How often a schedule runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SchedulePeriod {
+    Daily,
+    Weekly,
+    Monthly,
+    Custom { seconds: u64 },
+}
+
+impl SchedulePeriod {
+    /// Next run time after `time`
+    pub fn next_after(&self, time: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            SchedulePeriod::Daily => time + ChronoDuration::days(1),
+            SchedulePeriod::Weekly => time + ChronoDuration::weeks(1),
+            SchedulePeriod::Monthly => time
+                .checked_add_months(Months::new(1))
+                .unwrap_or(time + ChronoDuration::days(30)),
+            SchedulePeriod::Custom { seconds } => {
+                time + ChronoDuration::seconds((*seconds).max(MIN_INTERVAL_SECS) as i64)
+            }
+        }
+    }
+}
+
+/// What to do with runs that were missed while the app was closed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum CatchUpPolicy {
+    /// Record missed runs as skipped
+    Skip,
+    /// Pay once for all missed runs
+    #[default]
+    RunOnce,
+    /// Pay every missed run (bounded by `MAX_CATCH_UP_RUNS`)
+    RunAll,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ScheduleStatus {
+    Active,
+    Paused,
+    /// A cap was reached; the schedule will not run again
+    Completed,
+}
+
+/// A recurring payment
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledPayment {
+    pub id: String,
+    pub name: String,
+    pub target: ScheduleTarget,
+    pub amount_sats: u64,
+    /// Mint to take tokens from for nostr targets; the default mint when not set
+    pub mint_url: Option<String>,
+    pub period: SchedulePeriod,
+    pub catch_up: CatchUpPolicy,
+    /// Stop after this much has been paid in total
+    pub max_total_sats: Option<u64>,
+    /// Stop after this many successful runs
+    pub max_runs: Option<u32>,
+    pub status: ScheduleStatus,
+    pub next_run_at: DateTime<Utc>,
+    pub total_paid_sats: u64,
+    pub run_count: u32,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Parameters for creating a schedule
+#[derive(Debug, Clone, Deserialize)]
+pub struct NewScheduledPayment {
+    pub name: String,
+    pub target: ScheduleTarget,
+    pub amount_sats: u64,
+    pub mint_url: Option<String>,
+    pub period: SchedulePeriod,
+    #[serde(default)]
+    pub catch_up: CatchUpPolicy,
+    pub max_total_sats: Option<u64>,
+    pub max_runs: Option<u32>,
+    /// First run; now when not set
+    pub start_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunStatus {
+    Succeeded,
+    Failed,
+    Skipped,
+    /// Paid for, but the token did not reach the recipient. It is kept with
+    /// the pending tokens.
+    Undelivered,
+}
+
+/// One execution (or skipped execution) of a schedule
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleRun {
+    pub id: i64,
+    pub schedule_id: String,
+    pub scheduled_for: DateTime<Utc>,
+    pub executed_at: DateTime<Utc>,
+    pub status: RunStatus,
+    pub amount_sats: u64,
+    pub fee_sats: u64,
+    /// Preimage, token or other payment reference
+    pub reference: Option<String>,
+    pub error: Option<String>,
+}
+
+impl ScheduleRun {
+    fn new(
+        schedule: &ScheduledPayment,
+        scheduled_for: DateTime<Utc>,
+        status: RunStatus,
+        fee_sats: u64,
+        reference: Option<String>,
+        error: Option<String>,
+    ) -> Self {
+        Self {
+            id: 0,
+            schedule_id: schedule.id.clone(),
+            scheduled_for,
+            executed_at: Utc::now(),
+            status,
+            // Undelivered tokens were paid for, so they count too
+            amount_sats: match status {
+                RunStatus::Succeeded | RunStatus::Undelivered => schedule.amount_sats,
+                RunStatus::Failed | RunStatus::Skipped => 0,
+            },
+            fee_sats,
+            reference,
+            error,
+        }
+    }
+}
+
+/// Runs to execute and skip for one schedule at a given time
+#[derive(Debug, Clone, PartialEq)]
+pub struct DuePlan {
+    pub run: Vec<DateTime<Utc>>,
+    pub skip: Vec<DateTime<Utc>>,
+    pub next_run_at: DateTime<Utc>,
+}
+
+impl ScheduledPayment {
+    /// Work out which runs are due at `now`, applying the catch-up policy
+    pub fn due_plan(&self, now: DateTime<Utc>) -> DuePlan {
+        let mut due = Vec::new();
+        let mut next = self.next_run_at;
+        while next <= now {
+            due.push(next);
+            next = self.period.next_after(next);
+        }
+
+        let grace = ChronoDuration::seconds(MISSED_RUN_GRACE_SECS);
+        let (on_time, missed): (Vec<_>, Vec<_>) =
+            due.into_iter().partition(|time| now - *time <= grace);
+
+        let (mut run, mut skip) = match self.catch_up {
+            CatchUpPolicy::Skip => (on_time, missed),
+            CatchUpPolicy::RunOnce => {
+                let mut all: Vec<_> = missed.into_iter().chain(on_time).collect();
+                let last = all.pop();
+                (last.into_iter().collect(), all)
+            }
+            CatchUpPolicy::RunAll => (missed.into_iter().chain(on_time).collect(), Vec::new()),
+        };
+
+        if run.len() > MAX_CATCH_UP_RUNS {
+            let excess = run.len() - MAX_CATCH_UP_RUNS;
+            skip.extend(run.drain(..excess));
+        }
+
+        DuePlan {
+            run,
+            skip,
+            next_run_at: next,
+        }
+    }
+
+    /// Whether one more run would exceed a cap
+    fn cap_reached(&self) -> bool {
+        if let Some(max_runs) = self.max_runs {
+            if self.run_count >= max_runs {
+                return true;
+            }
+        }
+        if let Some(max_total) = self.max_total_sats {
+            if self.total_paid_sats + self.amount_sats > max_total {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+fn validate_target(target: &ScheduleTarget) -> TollGateResult<()> {
+    match target {
+        ScheduleTarget::LightningAddress { address } => {
+            lnurl::lightning_address_url(address).map_err(TollGateError::wallet)?;
+        }
+        ScheduleTarget::Lnurl { lnurl: encoded } => {
+            lnurl::decode_lnurl(encoded).map_err(TollGateError::wallet)?;
+        }
+        ScheduleTarget::Nut18 { request } => {
+            let request = PaymentRequest::from_str(request)
+                .map_err(|e| TollGateError::wallet(format!("Invalid payment request: {}", e)))?;
+            if request.transports.is_empty() {
+                return Err(TollGateError::wallet(
+                    "Scheduled NUT-18 requests need a transport",
+                ));
+            }
+        }
+        ScheduleTarget::Nostr { npub, .. } => {
+            PublicKey::from_bech32(npub)
+                .map_err(|e| TollGateError::wallet(format!("Invalid npub: {}", e)))?;
+        }
+    }
+    Ok(())
+}
+
+/// SQLite storage for schedules and their run history
+pub struct ScheduleStore {
+    db_path: PathBuf,
+}
+
+impl ScheduleStore {
+    pub fn new() -> TollGateResult<Self> {
+        let base_dir = profiles::data_dir()?;
+
+        let store = Self {
+            db_path: base_dir.join("schedules.sqlite"),
+        };
+        store.init_database()?;
+        Ok(store)
+    }
+
+    fn init_database(&self) -> TollGateResult<()> {
+        let conn = Connection::open(&self.db_path)?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS schedules (
+                id TEXT PRIMARY KEY,
+                name TEXT NOT NULL,
+                target TEXT NOT NULL,
+                amount_sats INTEGER NOT NULL,
+                mint_url TEXT,
+                period TEXT NOT NULL,
+                catch_up TEXT NOT NULL,
+                max_total_sats INTEGER,
+                max_runs INTEGER,
+                status TEXT NOT NULL,
+                next_run_at INTEGER NOT NULL,
+                total_paid_sats INTEGER NOT NULL,
+                run_count INTEGER NOT NULL,
+                created_at INTEGER NOT NULL
+            )",
+            [],
+        )?;
+
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS schedule_runs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                schedule_id TEXT NOT NULL,
+                scheduled_for INTEGER NOT NULL,
+                executed_at INTEGER NOT NULL,
+                status TEXT NOT NULL,
+                amount_sats INTEGER NOT NULL,
+                fee_sats INTEGER NOT NULL,
+                reference TEXT,
+                error TEXT
+            )",
+            [],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn save_schedule(&self, schedule: &ScheduledPayment) -> TollGateResult<()> {
+        let conn = Connection::open(&self.db_path)?;
+        Self::write_schedule(&conn, schedule)
+    }
+
+    /// Save `schedule` and add `run` in one transaction, so the totals and
+    /// the run history cannot disagree
+    pub fn save_run(&self, schedule: &ScheduledPayment, run: &ScheduleRun) -> TollGateResult<()> {
+        let mut conn = Connection::open(&self.db_path)?;
+        let tx = conn.transaction()?;
+        Self::write_schedule(&tx, schedule)?;
+        Self::write_run(&tx, run)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn write_schedule(conn: &Connection, schedule: &ScheduledPayment) -> TollGateResult<()> {
+        conn.execute(
+            "INSERT OR REPLACE INTO schedules
+             (id, name, target, amount_sats, mint_url, period, catch_up, max_total_sats,
+              max_runs, status, next_run_at, total_paid_sats, run_count, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+            params![
+                schedule.id,
+                schedule.name,
+                serde_json::to_string(&schedule.target)?,
+                schedule.amount_sats as i64,
+                schedule.mint_url,
+                serde_json::to_string(&schedule.period)?,
+                serde_json::to_string(&schedule.catch_up)?,
+                schedule.max_total_sats.map(|v| v as i64),
+                schedule.max_runs,
+                serde_json::to_string(&schedule.status)?,
+                schedule.next_run_at.timestamp(),
+                schedule.total_paid_sats as i64,
+                schedule.run_count,
+                schedule.created_at.timestamp(),
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn load_schedules(&self) -> TollGateResult<Vec<ScheduledPayment>> {
+        let conn = Connection::open(&self.db_path)?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, name, target, amount_sats, mint_url, period, catch_up, max_total_sats,
+                    max_runs, status, next_run_at, total_paid_sats, run_count, created_at
+             FROM schedules
+             ORDER BY created_at ASC",
+        )?;
+
+        let schedules = stmt.query_map([], Self::row_to_schedule)?;
+
+        let mut result = Vec::new();
+        for schedule in schedules {
+            match schedule {
+                Ok(schedule) => result.push(schedule),
+                Err(e) => log::warn!("Failed to load schedule from database: {}", e),
+            }
+        }
+        Ok(result)
+    }
+
+    pub fn get_schedule(&self, id: &str) -> TollGateResult<Option<ScheduledPayment>> {
+        let conn = Connection::open(&self.db_path)?;
+
+        let schedule = conn
+            .query_row(
+                "SELECT id, name, target, amount_sats, mint_url, period, catch_up, max_total_sats,
+                        max_runs, status, next_run_at, total_paid_sats, run_count, created_at
+                 FROM schedules WHERE id = ?1",
+                params![id],
+                Self::row_to_schedule,
+            )
+            .optional()?;
+        Ok(schedule)
+    }
+
+    fn row_to_schedule(row: &Row) -> rusqlite::Result<ScheduledPayment> {
+        fn json<T: serde::de::DeserializeOwned>(value: String) -> rusqlite::Result<T> {
+            serde_json::from_str(&value)
+                .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
+        }
+        fn timestamp(secs: i64) -> DateTime<Utc> {
+            Utc.timestamp_opt(secs, 0).single().unwrap_or_default()
+        }
+
+        Ok(ScheduledPayment {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            target: json(row.get(2)?)?,
+            amount_sats: row.get::<_, i64>(3)? as u64,
+            mint_url: row.get(4)?,
+            period: json(row.get(5)?)?,
+            catch_up: json(row.get(6)?)?,
+            max_total_sats: row.get::<_, Option<i64>>(7)?.map(|v| v as u64),
+            max_runs: row.get(8)?,
+            status: json(row.get(9)?)?,
+            next_run_at: timestamp(row.get(10)?),
+            total_paid_sats: row.get::<_, i64>(11)? as u64,
+            run_count: row.get(12)?,
+            created_at: timestamp(row.get(13)?),
+        })
+    }
+
+    pub fn delete_schedule(&self, id: &str) -> TollGateResult<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute("DELETE FROM schedules WHERE id = ?1", params![id])?;
+        conn.execute(
+            "DELETE FROM schedule_runs WHERE schedule_id = ?1",
+            params![id],
+        )?;
+        Ok(())
+    }
+
+    pub fn insert_run(&self, run: &ScheduleRun) -> TollGateResult<()> {
+        let conn = Connection::open(&self.db_path)?;
+        Self::write_run(&conn, run)
+    }
+
+    fn write_run(conn: &Connection, run: &ScheduleRun) -> TollGateResult<()> {
+        conn.execute(
+            "INSERT INTO schedule_runs
+             (schedule_id, scheduled_for, executed_at, status, amount_sats, fee_sats, reference, error)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                run.schedule_id,
+                run.scheduled_for.timestamp(),
+                run.executed_at.timestamp(),
+                serde_json::to_string(&run.status)?,
+                run.amount_sats as i64,
+                run.fee_sats as i64,
+                run.reference,
+                run.error,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn load_runs(&self, schedule_id: &str, limit: u32) -> TollGateResult<Vec<ScheduleRun>> {
+        let conn = Connection::open(&self.db_path)?;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, schedule_id, scheduled_for, executed_at, status, amount_sats, fee_sats,
+                    reference, error
+             FROM schedule_runs
+             WHERE schedule_id = ?1
+             ORDER BY scheduled_for DESC, id DESC
+             LIMIT ?2",
+        )?;
+
+        let runs = stmt.query_map(params![schedule_id, limit], |row| {
+            let status: String = row.get(4)?;
+            Ok(ScheduleRun {
+                id: row.get(0)?,
+                schedule_id: row.get(1)?,
+                scheduled_for: Utc
+                    .timestamp_opt(row.get(2)?, 0)
+                    .single()
+                    .unwrap_or_default(),
+                executed_at: Utc
+                    .timestamp_opt(row.get(3)?, 0)
+                    .single()
+                    .unwrap_or_default(),
+                status: serde_json::from_str(&status)
+                    .map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?,
+                amount_sats: row.get::<_, i64>(5)? as u64,
+                fee_sats: row.get::<_, i64>(6)? as u64,
+                reference: row.get(7)?,
+                error: row.get(8)?,
+            })
+        })?;
+
+        Ok(runs.collect::<Result<Vec<_>, _>>()?)
+    }
+}
+
+/// Outcome of a payment that left the wallet
+struct PaymentOutcome {
+    fee_sats: u64,
+    reference: Option<String>,
+    /// Why the payment did not reach the recipient
+    undelivered: Option<String>,
+}
+
+/// Executes due schedules against the wallet
+pub struct Scheduler {
+    store: ScheduleStore,
+    wallet: Arc<Mutex<TollGateWallet>>,
+    spending: Arc<SpendingPolicyEngine>,
+    /// Prevents overlapping passes from paying the same run twice
+    running: Mutex<()>,
+}
+
+impl Scheduler {
+    pub fn new(
+        wallet: Arc<Mutex<TollGateWallet>>,
+        spending: Arc<SpendingPolicyEngine>,
+    ) -> TollGateResult<Self> {
+        Ok(Self {
+            store: ScheduleStore::new()?,
+            wallet,
+            spending,
+            running: Mutex::new(()),
+        })
+    }
+
+    pub fn create_schedule(&self, new: NewScheduledPayment) -> TollGateResult<ScheduledPayment> {
+        if new.amount_sats == 0 {
+            return Err(TollGateError::wallet("Scheduled amount must be positive"));
+        }
+        if let SchedulePeriod::Custom { seconds } = new.period {
+            if seconds < MIN_INTERVAL_SECS {
+                return Err(TollGateError::wallet(format!(
+                    "Interval must be at least {} seconds",
+                    MIN_INTERVAL_SECS
+                )));
+            }
+        }
+        validate_target(&new.target)?;
+
+        let now = Utc::now();
+        let schedule = ScheduledPayment {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: new.name,
+            target: new.target,
+            amount_sats: new.amount_sats,
+            mint_url: new.mint_url,
+            period: new.period,
+            catch_up: new.catch_up,
+            max_total_sats: new.max_total_sats,
+            max_runs: new.max_runs,
+            status: ScheduleStatus::Active,
+            next_run_at: new.start_at.unwrap_or(now),
+            total_paid_sats: 0,
+            run_count: 0,
+            created_at: now,
+        };
+        self.store.save_schedule(&schedule)?;
+
+        log::info!(
+            "Created schedule {} ({} sats, {:?})",
+            schedule.id,
+            schedule.amount_sats,
+            schedule.period
+        );
+        Ok(schedule)
+    }
+
+    pub fn list_schedules(&self) -> TollGateResult<Vec<ScheduledPayment>> {
+        self.store.load_schedules()
+    }
+
+    pub fn list_runs(&self, schedule_id: &str, limit: u32) -> TollGateResult<Vec<ScheduleRun>> {
+        self.store.load_runs(schedule_id, limit)
+    }
+
+    fn get(&self, id: &str) -> TollGateResult<ScheduledPayment> {
+        self.store
+            .get_schedule(id)?
+            .ok_or_else(|| TollGateError::wallet(format!("Schedule not found: {}", id)))
+    }
+
+    pub fn pause_schedule(&self, id: &str) -> TollGateResult<ScheduledPayment> {
+        let mut schedule = self.get(id)?;
+        if schedule.status == ScheduleStatus::Active {
+            schedule.status = ScheduleStatus::Paused;
+            self.store.save_schedule(&schedule)?;
+        }
+        Ok(schedule)
+    }
+
+    /// Resume a paused schedule. Runs that fell due while paused are not
+    /// caught up; the next run is the first one after now.
+    pub fn resume_schedule(&self, id: &str) -> TollGateResult<ScheduledPayment> {
+        let mut schedule = self.get(id)?;
+        if schedule.status == ScheduleStatus::Paused {
+            let now = Utc::now();
+            while schedule.next_run_at < now {
+                schedule.next_run_at = schedule.period.next_after(schedule.next_run_at);
+            }
+            schedule.status = ScheduleStatus::Active;
+            self.store.save_schedule(&schedule)?;
+        }
+        Ok(schedule)
+    }
+
+    pub fn delete_schedule(&self, id: &str) -> TollGateResult<()> {
+        self.store.delete_schedule(id)
+    }
+
+    /// Execute every run that is due now
+    pub async fn run_due(&self) -> TollGateResult<()> {
+        let Ok(_guard) = self.running.try_lock() else {
+            return Ok(());
+        };
+
+        let now = Utc::now();
+        for mut schedule in self.store.load_schedules()? {
+            if schedule.status != ScheduleStatus::Active || schedule.next_run_at > now {
+                continue;
+            }
+
+            // Move past the skipped runs before recording them, so a failure
+            // part way through is not followed by the same runs again
+            let plan = schedule.due_plan(now);
+            schedule.next_run_at = plan.run.first().copied().unwrap_or(plan.next_run_at);
+            self.store.save_schedule(&schedule)?;
+            for scheduled_for in plan.skip {
+                self.record(
+                    &schedule,
+                    scheduled_for,
+                    RunStatus::Skipped,
+                    0,
+                    None,
+                    Some("Missed while the app was not running".to_string()),
+                )?;
+            }
+
+            for scheduled_for in plan.run {
+                if schedule.cap_reached() {
+                    schedule.status = ScheduleStatus::Completed;
+                    log::info!("Schedule {} reached its cap", schedule.id);
+                    break;
+                }
+
+                // The schedule is saved past this run before paying it, so a
+                // crash or error after the payment never pays the run twice
+                schedule.next_run_at = schedule.period.next_after(scheduled_for);
+                self.store.save_schedule(&schedule)?;
+
+                let result = match self.authorize(&schedule).await {
+                    Ok(ticket) => {
+                        let result = self.pay(&schedule).await;
+                        self.spending.complete(ticket, result.is_ok());
+                        result
+                    }
+                    Err(e) => Err(e),
+                };
+
+                match result {
+                    Ok(outcome) => {
+                        self.wallet.lock().await.publish_balance().await;
+                        schedule.total_paid_sats += schedule.amount_sats;
+                        schedule.run_count += 1;
+                        let status = match &outcome.undelivered {
+                            Some(_) => RunStatus::Undelivered,
+                            None => RunStatus::Succeeded,
+                        };
+                        self.store.save_run(
+                            &schedule,
+                            &ScheduleRun::new(
+                                &schedule,
+                                scheduled_for,
+                                status,
+                                outcome.fee_sats,
+                                outcome.reference,
+                                outcome.undelivered,
+                            ),
+                        )?;
+                    }
+                    Err(e) => {
+                        log::warn!("Scheduled payment {} failed: {}", schedule.id, e);
+                        self.record(
+                            &schedule,
+                            scheduled_for,
+                            RunStatus::Failed,
+                            0,
+                            None,
+                            Some(e.to_string()),
+                        )?;
+                    }
+                }
+            }
+
+            if schedule.status == ScheduleStatus::Active && schedule.cap_reached() {
+                schedule.status = ScheduleStatus::Completed;
+            }
+            schedule.next_run_at = plan.next_run_at;
+            self.store.save_schedule(&schedule)?;
+        }
+
+        Ok(())
+    }
+
+    fn record(
+        &self,
+        schedule: &ScheduledPayment,
+        scheduled_for: DateTime<Utc>,
+        status: RunStatus,
+        fee_sats: u64,
+        reference: Option<String>,
+        error: Option<String>,
+    ) -> TollGateResult<()> {
+        self.store.insert_run(&ScheduleRun::new(
+            schedule,
+            scheduled_for,
+            status,
+            fee_sats,
+            reference,
+            error,
+        ))
+    }
+
+    /// Check one run against the spending policy
+    async fn authorize(&self, schedule: &ScheduledPayment) -> TollGateResult<SpendTicket> {
+        let origin = Origin::Scheduled {
+            schedule: schedule.id.clone(),
+        };
+        let wallet = self.wallet.lock().await;
+        let spend = match &schedule.target {
+            ScheduleTarget::Nut18 { request } => {
+                let payment_request = PaymentRequest::from_str(request).map_err(|e| {
+                    TollGateError::wallet(format!("Invalid payment request: {}", e))
+                })?;
+                SpendRequest::nut18(
+                    origin,
+                    &payment_request,
+                    Some(schedule.amount_sats),
+                    wallet.payment_request_mint(&payment_request),
+                )?
+            }
+            ScheduleTarget::LightningAddress {
+                address: counterparty,
+            }
+            | ScheduleTarget::Lnurl {
+                lnurl: counterparty,
+            }
+            | ScheduleTarget::Nostr {
+                npub: counterparty, ..
+            } => SpendRequest {
+                // Nostr recipients are sent a token, the others are paid over Lightning
+                kind: match schedule.target {
+                    ScheduleTarget::Nostr { .. } => SpendKind::Token,
+                    _ => SpendKind::Melt,
+                },
+                origin,
+                counterparty: Some(counterparty.clone()),
+                amount_sats: schedule.amount_sats,
+                mint_url: schedule
+                    .mint_url
+                    .clone()
+                    .or_else(|| wallet.bolt11_payment_mint()),
+            },
+        };
+        drop(wallet);
+
+        self.spending.authorize(&spend, false).await
+    }
+
+    async fn pay(&self, schedule: &ScheduledPayment) -> TollGateResult<PaymentOutcome> {
+        match &schedule.target {
+            ScheduleTarget::LightningAddress { address } => {
+                let url = lnurl::lightning_address_url(address).map_err(TollGateError::wallet)?;
+                self.pay_lnurl(&url, schedule).await
+            }
+            ScheduleTarget::Lnurl { lnurl: encoded } => {
+                let url = lnurl::decode_lnurl(encoded).map_err(TollGateError::wallet)?;
+                self.pay_lnurl(&url, schedule).await
+            }
+            ScheduleTarget::Nut18 { request } => {
+                let wallet = self.wallet.lock().await;
+                wallet
+                    .pay_nut18_payment_request(request, Some(schedule.amount_sats))
+                    .await?;
+                Ok(PaymentOutcome {
+                    fee_sats: 0,
+                    reference: None,
+                    undelivered: None,
+                })
+            }
+            ScheduleTarget::Nostr { npub, relays } => self.pay_nostr(npub, relays, schedule).await,
+        }
+    }
+
+    async fn pay_lnurl(
+        &self,
+        url: &str,
+        schedule: &ScheduledPayment,
+    ) -> TollGateResult<PaymentOutcome> {
+        let params = lnurl::fetch_pay_params(url)
+            .await
+            .map_err(TollGateError::wallet)?;
+        let invoice =
+            lnurl::request_invoice(&params, schedule.amount_sats * 1000, Some(&schedule.name))
+                .await
+                .map_err(TollGateError::wallet)?;
+
+        let wallet = self.wallet.lock().await;
+        let result = wallet.pay_bolt11_invoice(&invoice).await?;
+        Ok(PaymentOutcome {
+            fee_sats: result.fee_paid,
+            reference: result.preimage,
+            undelivered: None,
+        })
+    }
+
+    async fn pay_nostr(
+        &self,
+        npub: &str,
+        relays: &[String],
+        schedule: &ScheduledPayment,
+    ) -> TollGateResult<PaymentOutcome> {
+        let recipient = PublicKey::from_bech32(npub)
+            .map_err(|e| TollGateError::wallet(format!("Invalid npub: {}", e)))?;
+
+        let (token, keys) = {
+            let wallet = self.wallet.lock().await;
+            let token = wallet
+                .create_p2pk_token(
+                    schedule.amount_sats,
+                    schedule.mint_url.as_deref(),
+                    &recipient,
+                )
+                .await?;
+            (token, wallet.get_keys())
+        };
+
+        let message = format!("{}\n\n{}", schedule.name, token.token);
+        let content = nip04::encrypt(keys.secret_key(), &recipient, message)
+            .map_err(|e| TollGateError::wallet(format!("Failed to encrypt DM: {}", e)))?;
+        let event = EventBuilder::new(Kind::EncryptedDirectMessage, content)
+            .tags(vec![Tag::from_standardized(TagStandard::public_key(
+                recipient,
+            ))])
+            .sign_with_keys(&keys)?;
+
+        let client = Client::default();
+        let relay_urls: Vec<String> = if relays.is_empty() {
+            config::current().relays.discovery.clone()
+        } else {
+            relays.to_vec()
+        };
+        for relay in &relay_urls {
+            if let Err(e) = client.add_relay(relay).await {
+                log::warn!("Failed to add relay {}: {}", relay, e);
+            }
+        }
+        client.connect().await;
+        let sent = client.send_event(&event).await;
+        client.disconnect().await;
+
+        // The token is already spent from our wallet, so an undelivered one
+        // is kept with the pending tokens rather than dropped
+        let undelivered = match sent {
+            Ok(_) => None,
+            Err(e) => {
+                log::error!("Failed to deliver scheduled token to {}: {}", npub, e);
+                let error = format!("Delivery failed: {}", e);
+                let origin = Origin::Scheduled {
+                    schedule: schedule.id.clone(),
+                };
+                if let Err(e) =
+                    self.wallet
+                        .lock()
+                        .await
+                        .keep_undelivered_token(&token, &origin, error.clone())
+                {
+                    log::error!("Failed to keep undelivered token: {}", e);
+                }
+                Some(error)
+            }
+        };
+
+        Ok(PaymentOutcome {
+            fee_sats: 0,
+            reference: Some(token.token),
+            undelivered,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule(catch_up: CatchUpPolicy, next_run_at: DateTime<Utc>) -> ScheduledPayment {
+        ScheduledPayment {
+            id: "s".to_string(),
+            name: "test".to_string(),
+            target: ScheduleTarget::LightningAddress {
+                address: "alice@example.com".to_string(),
+            },
+            amount_sats: 100,
+            mint_url: None,
+            period: SchedulePeriod::Daily,
+            catch_up,
+            max_total_sats: None,
+            max_runs: None,
+            status: ScheduleStatus::Active,
+            next_run_at,
+            total_paid_sats: 0,
+            run_count: 0,
+            created_at: next_run_at,
+        }
+    }
+
+    #[test]
+    fn test_on_time_run() {
+        let now = Utc::now();
+        let plan = schedule(CatchUpPolicy::Skip, now - ChronoDuration::seconds(10)).due_plan(now);
+
+        assert_eq!(plan.run.len(), 1);
+        assert!(plan.skip.is_empty());
+        assert!(plan.next_run_at > now);
+    }
+
+    #[test]
+    fn test_catch_up_policies() {
+        let now = Utc::now();
+        let start = now - ChronoDuration::days(3) + ChronoDuration::hours(1);
+
+        // Missed runs 71h, 47h and 23h ago; the next one is in an hour
+        let skip = schedule(CatchUpPolicy::Skip, start).due_plan(now);
+        assert!(skip.run.is_empty());
+        assert_eq!(skip.skip.len(), 3);
+
+        let once = schedule(CatchUpPolicy::RunOnce, start).due_plan(now);
+        assert_eq!(once.run, vec![start + ChronoDuration::days(2)]);
+        assert_eq!(once.skip.len(), 2);
+
+        let all = schedule(CatchUpPolicy::RunAll, start).due_plan(now);
+        assert_eq!(all.run.len(), 3);
+        assert!(all.skip.is_empty());
+        assert_eq!(all.next_run_at, start + ChronoDuration::days(3));
+    }
+
+    #[test]
+    fn test_caps() {
+        let mut payment = schedule(CatchUpPolicy::RunOnce, Utc::now());
+        payment.max_total_sats = Some(250);
+        payment.total_paid_sats = 100;
+        assert!(!payment.cap_reached());
+        payment.total_paid_sats = 200;
+        assert!(payment.cap_reached());
+
+        payment.max_total_sats = None;
+        payment.max_runs = Some(2);
+        payment.run_count = 2;
+        assert!(payment.cap_reached());
+    }
+
+    #[test]
+    fn test_undelivered_run_counts_as_paid() {
+        let payment = schedule(CatchUpPolicy::RunOnce, Utc::now());
+        let undelivered = ScheduleRun::new(
+            &payment,
+            payment.next_run_at,
+            RunStatus::Undelivered,
+            0,
+            None,
+            Some("Delivery failed".to_string()),
+        );
+        assert_eq!(undelivered.amount_sats, 100);
+        let failed = ScheduleRun::new(
+            &payment,
+            payment.next_run_at,
+            RunStatus::Failed,
+            0,
+            None,
+            None,
+        );
+        assert_eq!(failed.amount_sats, 0);
+    }
+
+    #[test]
+    fn test_monthly_period() {
+        let jan_31 = Utc.with_ymd_and_hms(2025, 1, 31, 12, 0, 0).unwrap();
+        assert_eq!(
+            SchedulePeriod::Monthly.next_after(jan_31),
+            Utc.with_ymd_and_hms(2025, 2, 28, 12, 0, 0).unwrap()
+        );
+    }
+}
@@ -6,23 +6,43 @@
 //! - Network detection and auto-connection
 //! - Wallet integration and payments
 
+use crate::events::{self, BackendEvent};
+use crate::tollgate::approvals::ApprovalsState;
 use crate::tollgate::errors::{TollGateError, TollGateResult};
+use crate::tollgate::mint_policy::MintTrustPolicy;
 use crate::tollgate::network::{NetworkDetector, NetworkInfo};
-use crate::tollgate::protocol::{PaymentEvent, TollGateProtocol};
+use crate::tollgate::origin::Origin;
+use crate::tollgate::pending_tokens::PendingToken;
+use crate::tollgate::protocol::{PaymentEvent, PricingOption, TollGateProtocol};
+use crate::tollgate::scheduler::{NewScheduledPayment, ScheduleRun, ScheduledPayment, Scheduler};
 use crate::tollgate::session::{Session, SessionManager, SessionStatus};
+use crate::tollgate::spend_ledger::{LedgerAnchor, LedgerEntry, LedgerVerification, SpendKind};
+use crate::tollgate::spending_policy::{
+    SpendAuditEntry, SpendRequest, SpendingPolicy, SpendingPolicyEngine,
+};
+use crate::tollgate::split_strategy::SplitStrategy;
+use crate::tollgate::token_pool::{count_denominations, TokenPoolConfig, TokenPoolStatus};
 use crate::tollgate::wallet::{
-    Bolt11InvoiceInfo, Bolt11PaymentResult, CashuReceiveResult, Nut18PaymentRequestInfo,
-    PayNut18Result, TollGateWallet, WalletSummary, WalletTransactionEntry,
+    write_secrets_from_mnemonic, Bolt11InvoiceInfo, Bolt11PaymentResult, CashuReceiveResult,
+    ConsolidationResult, Nut18PaymentRequestInfo, PayNut18Result, TollGateWallet, WalletSummary,
+    WalletTransactionEntry,
 };
 use cdk::amount::SplitTarget;
+use cdk::nuts::nut18::payment_request::PaymentRequest;
 use chrono::{DateTime, Utc};
 use nostr::Keys;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{watch, Mutex, RwLock};
 use tokio::time::interval;
 
+/// Number of background ticks between token pool refills
+const TOKEN_POOL_REFILL_TICKS: u64 = 6;
+/// How long the background task gets to finish its current pass on shutdown
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(10);
+
 /// Service status information
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ServiceStatus {
@@ -46,6 +66,21 @@ pub struct SessionInfo {
     pub total_spent: u64,
 }
 
+impl From<&Session> for SessionInfo {
+    fn from(session: &Session) -> Self {
+        Self {
+            id: session.id.clone(),
+            tollgate_pubkey: session.tollgate_pubkey.clone(),
+            gateway_ip: session.gateway_ip.clone(),
+            status: session.status.clone(),
+            usage_percentage: session.usage_percentage(),
+            remaining_time_seconds: session.remaining_time_seconds(),
+            remaining_data_bytes: session.remaining_data_bytes(),
+            total_spent: session.total_spent,
+        }
+    }
+}
+
 /// Main TollGate service
 pub struct TollGateService {
     /// Global enable/disable state
@@ -54,28 +89,39 @@ pub struct TollGateService {
     session_manager: Arc<Mutex<SessionManager>>,
     /// Cashu wallet
     wallet: Arc<Mutex<TollGateWallet>>,
+    /// Recurring payments
+    scheduler: Arc<Scheduler>,
+    /// Checks every outgoing payment
+    spending: Arc<SpendingPolicyEngine>,
     /// Network detector
     network_detector: NetworkDetector,
     /// Protocol handler
     protocol: TollGateProtocol,
     /// Current network information
     current_network: Arc<RwLock<Option<NetworkInfo>>>,
-    /// Background task handle
-    background_task: Option<tokio::task::JoinHandle<()>>,
+    /// Background task handle, and the signal that stops it
+    background_task: Option<(watch::Sender<bool>, tokio::task::JoinHandle<()>)>,
 }
 
 impl TollGateService {
-    /// Create a new TollGate service
-    pub async fn new() -> TollGateResult<Self> {
+    /// Create a new TollGate service, asking for payment approvals through
+    /// `approvals`
+    pub async fn new(approvals: ApprovalsState) -> TollGateResult<Self> {
         let mut wallet = TollGateWallet::new()?;
 
         // Load existing mints from previous sessions
         wallet.load_existing_mints().await?;
 
+        let spending = Arc::new(SpendingPolicyEngine::new(&wallet.wallet_seed(), approvals)?);
+        let wallet = Arc::new(Mutex::new(wallet));
+        let scheduler = Arc::new(Scheduler::new(wallet.clone(), spending.clone())?);
+
         let service = Self {
             auto_tollgate_enabled: Arc::new(RwLock::new(false)),
             session_manager: Arc::new(Mutex::new(SessionManager::new())),
-            wallet: Arc::new(Mutex::new(wallet)),
+            wallet,
+            scheduler,
+            spending,
             network_detector: NetworkDetector::new(),
             protocol: TollGateProtocol::new(),
             current_network: Arc::new(RwLock::new(None)),
@@ -97,14 +143,47 @@ impl TollGateService {
         let auto_enabled = self.auto_tollgate_enabled.clone();
         let session_manager = self.session_manager.clone();
         let wallet = self.wallet.clone();
+        let scheduler = self.scheduler.clone();
+        let spending = self.spending.clone();
         let current_network = self.current_network.clone();
         let protocol = self.protocol.clone();
+        let (stop, mut stopping) = watch::channel(false);
 
         let task = tokio::spawn(async move {
             let mut interval = interval(Duration::from_secs(10));
+            let mut ticks: u64 = 0;
 
             loop {
-                interval.tick().await;
+                // A pass that has started is finished before stopping
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = stopping.wait_for(|stop| *stop) => break,
+                }
+                ticks += 1;
+
+                // Redeem tokens that were accepted while the mint was unreachable
+                {
+                    let mut wallet = wallet.lock().await;
+                    match wallet.redeem_pending_tokens().await {
+                        Ok(0) => {}
+                        Ok(_) => wallet.publish_balance().await,
+                        Err(e) => log::error!("Error redeeming pending tokens: {}", e),
+                    }
+
+                    // Top up pre-split proofs once a minute
+                    if ticks % TOKEN_POOL_REFILL_TICKS == 0 {
+                        if let Err(e) = wallet.refill_token_pool().await {
+                            log::warn!("Error refilling token pool: {}", e);
+                        }
+                    }
+
+                    // Payments only record usage in memory
+                    wallet.flush_token_pool();
+                }
+
+                if let Err(e) = scheduler.run_due().await {
+                    log::error!("Error running scheduled payments: {}", e);
+                }
 
                 // Only run if auto-tollgate is enabled
                 if !*auto_enabled.read().await {
@@ -115,6 +194,7 @@ impl TollGateService {
                 if let Err(e) = Self::check_sessions_for_renewal(
                     &session_manager,
                     &wallet,
+                    &spending,
                     &current_network,
                     &protocol,
                 )
@@ -127,7 +207,11 @@ impl TollGateService {
                 {
                     let mut manager = session_manager.lock().await;
                     manager.update_time_based_usage();
-                    manager.cleanup_expired_sessions();
+                    for session in manager.cleanup_expired_sessions() {
+                        events::publish(BackendEvent::SessionExpired {
+                            session: SessionInfo::from(&session),
+                        });
+                    }
                 }
 
                 // Persist state periodically
@@ -137,11 +221,31 @@ impl TollGateService {
             }
         });
 
-        self.background_task = Some(task);
+        self.background_task = Some((stop, task));
         log::info!("Background service started");
         Ok(())
     }
 
+    /// Stop the background task once its current pass is done and write out
+    /// session state, before exit or a profile switch
+    pub async fn shutdown(&mut self) {
+        if let Some((stop, mut task)) = self.background_task.take() {
+            let _ = stop.send(true);
+            if tokio::time::timeout(SHUTDOWN_GRACE, &mut task)
+                .await
+                .is_err()
+            {
+                log::warn!("Background service did not stop in time, aborting it");
+                task.abort();
+            }
+        }
+        self.wallet.lock().await.flush_token_pool();
+        if let Err(e) = Self::persist_state(&self.session_manager).await {
+            log::error!("Failed to persist sessions on shutdown: {}", e);
+        }
+        log::info!("Background service stopped");
+    }
+
     /// Enable or disable auto-tollgate functionality
     pub async fn set_auto_tollgate_enabled(&self, enabled: bool) -> TollGateResult<()> {
         *self.auto_tollgate_enabled.write().await = enabled;
@@ -162,16 +266,7 @@ impl TollGateService {
         let active_sessions: Vec<SessionInfo> = session_manager
             .get_active_sessions()
             .iter()
-            .map(|session| SessionInfo {
-                id: session.id.clone(),
-                tollgate_pubkey: session.tollgate_pubkey.clone(),
-                gateway_ip: session.gateway_ip.clone(),
-                status: session.status.clone(),
-                usage_percentage: session.usage_percentage(),
-                remaining_time_seconds: session.remaining_time_seconds(),
-                remaining_data_bytes: session.remaining_data_bytes(),
-                total_spent: session.total_spent,
-            })
+            .map(|session| SessionInfo::from(*session))
             .collect();
 
         let wallet_balance = wallet
@@ -191,45 +286,6 @@ impl TollGateService {
         })
     }
 
-    /// Handle network connection event
-    pub async fn handle_network_connected(
-        &self,
-        gateway_ip: String,
-        mac_address: String,
-    ) -> TollGateResult<()> {
-        log::info!(
-            "Network connected: gateway={}, mac={}",
-            gateway_ip,
-            mac_address
-        );
-
-        // Detect if this is a TollGate network
-        let network_info = self
-            .network_detector
-            .detect_tollgate(&gateway_ip, &mac_address)
-            .await?;
-
-        // Update current network
-        *self.current_network.write().await = Some(network_info.clone());
-
-        if !network_info.is_tollgate {
-            log::debug!("Network {} is not a TollGate", gateway_ip);
-            return Ok(());
-        }
-
-        log::info!("TollGate detected on network {}", gateway_ip);
-
-        // If auto-tollgate is enabled, start a session
-        if *self.auto_tollgate_enabled.read().await {
-            if let Some(advertisement) = &network_info.advertisement.clone() {
-                self.start_tollgate_session(network_info, advertisement.clone())
-                    .await?;
-            }
-        }
-
-        Ok(())
-    }
-
     /// Handle network disconnection event
     pub async fn handle_network_disconnected(&self) -> TollGateResult<()> {
         log::info!("Network disconnected");
@@ -237,7 +293,7 @@ impl TollGateService {
         // Clear current network
         *self.current_network.write().await = None;
 
-        // Mark all sessions as expired (they'll be cleaned up by background service)
+        // Expire all sessions, they cannot be used without the network
         let mut session_manager = self.session_manager.lock().await;
         for session in session_manager.get_all_sessions_mut() {
             if session.is_active() {
@@ -248,111 +304,12 @@ impl TollGateService {
                 );
             }
         }
-
-        Ok(())
-    }
-
-    /// Start a new TollGate session
-    async fn start_tollgate_session(
-        &self,
-        network_info: NetworkInfo,
-        advertisement: crate::tollgate::protocol::TollGateAdvertisement,
-    ) -> TollGateResult<()> {
-        let mut session_manager = self.session_manager.lock().await;
-        let wallet = self.wallet.lock().await;
-
-        // Check if we already have an active session for this TollGate
-        if let Some(existing_session) = session_manager.get_session(&advertisement.tollgate_pubkey)
-        {
-            if existing_session.is_active() {
-                log::info!(
-                    "Already have active session for TollGate {}",
-                    advertisement.tollgate_pubkey
-                );
-                return Ok(());
-            }
+        for session in session_manager.cleanup_expired_sessions() {
+            events::publish(BackendEvent::SessionExpired {
+                session: SessionInfo::from(&session),
+            });
         }
 
-        // Calculate initial purchase (minimum steps or 5 minutes, whichever is larger)
-        let min_steps = advertisement
-            .pricing_options
-            .iter()
-            .map(|opt| opt.min_steps)
-            .min()
-            .unwrap_or(1);
-
-        let five_minutes_steps = if advertisement.metric == "milliseconds" {
-            300000 / advertisement.step_size // 5 minutes in milliseconds
-        } else {
-            1024 * 1024 * 10 / advertisement.step_size // 10MB for data-based
-        };
-
-        let initial_steps = min_steps.max(five_minutes_steps);
-
-        // Select best pricing option
-        let pricing_option = wallet
-            .select_best_pricing_option(&advertisement.pricing_options, initial_steps)
-            .await?;
-
-        // Create payment token
-        let payment_token = wallet
-            .create_payment_token(&pricing_option, initial_steps)
-            .await?;
-
-        // Get device identifier from TollGate
-        let (device_type, device_value) = self
-            .protocol
-            .get_device_identifier(&network_info.gateway_ip)
-            .await?;
-
-        // Create payment event
-        let customer_keys = Keys::generate();
-        let payment = PaymentEvent {
-            tollgate_pubkey: advertisement.tollgate_pubkey.clone(),
-            mac_address: device_value.clone(),
-            cashu_token: payment_token.token.clone(),
-            steps: initial_steps,
-        };
-
-        let payment_event = self
-            .protocol
-            .create_payment_event(&payment, &customer_keys, &device_type, &device_value)
-            .await?;
-
-        // Send payment and get session response
-        let session_response = self
-            .protocol
-            .send_payment(&network_info.gateway_ip, &payment_event)
-            .await?;
-
-        // Calculate allotment and session end
-        let allotment = self
-            .protocol
-            .calculate_allotment(initial_steps, advertisement.step_size);
-        let cost = self.protocol.calculate_cost(&pricing_option, initial_steps);
-
-        // Create session
-        let mut session = Session::new(super::session::SessionParams {
-            tollgate_pubkey: advertisement.tollgate_pubkey.clone(),
-            gateway_ip: network_info.gateway_ip.clone(),
-            mac_address: device_value,
-            pricing_option,
-            advertisement: advertisement.clone(),
-            initial_allotment: allotment,
-            session_end: session_response.session_end,
-            initial_cost: cost,
-        })?;
-
-        // Update session with response
-        session.update_from_response(&session_response)?;
-
-        // Add to session manager
-        session_manager.add_session(session);
-
-        log::info!(
-            "Successfully started TollGate session for {}",
-            advertisement.tollgate_pubkey
-        );
         Ok(())
     }
 
@@ -360,6 +317,7 @@ impl TollGateService {
     async fn check_sessions_for_renewal(
         session_manager: &Arc<Mutex<SessionManager>>,
         wallet: &Arc<Mutex<TollGateWallet>>,
+        spending: &SpendingPolicyEngine,
         _current_network: &Arc<RwLock<Option<NetworkInfo>>>,
         protocol: &TollGateProtocol,
     ) -> TollGateResult<()> {
@@ -373,9 +331,14 @@ impl TollGateService {
         };
 
         for session in sessions_needing_renewal {
-            if let Err(e) =
-                Self::renew_session(session_manager, wallet, protocol, &session.tollgate_pubkey)
-                    .await
+            if let Err(e) = Self::renew_session(
+                session_manager,
+                wallet,
+                spending,
+                protocol,
+                &session.tollgate_pubkey,
+            )
+            .await
             {
                 log::error!("Failed to renew session {}: {}", session.id, e);
 
@@ -394,6 +357,7 @@ impl TollGateService {
     async fn renew_session(
         session_manager: &Arc<Mutex<SessionManager>>,
         wallet: &Arc<Mutex<TollGateWallet>>,
+        spending: &SpendingPolicyEngine,
         protocol: &TollGateProtocol,
         tollgate_pubkey: &str,
     ) -> TollGateResult<()> {
@@ -403,11 +367,12 @@ impl TollGateService {
                 .get_session(tollgate_pubkey)
                 .ok_or_else(|| TollGateError::session("Session not found for renewal"))?;
 
-            // Calculate renewal steps (same as initial or based on remaining time)
+            // Calculate renewal steps from the configured renewal purchase
+            let config = crate::config::current();
             let renewal_steps = if session.advertisement.metric == "milliseconds" {
-                300000 / session.advertisement.step_size // 5 more minutes
+                config.tollgate.renewal_purchase_secs * 1000 / session.advertisement.step_size
             } else {
-                1024 * 1024 * 5 / session.advertisement.step_size // 5MB more
+                config.tollgate.renewal_purchase_bytes / session.advertisement.step_size
             };
 
             (session.clone(), renewal_steps)
@@ -422,11 +387,23 @@ impl TollGateService {
         }
 
         // Create renewal payment
+        let ticket = spending
+            .authorize(
+                &Self::tollgate_spend(
+                    &session_clone.tollgate_pubkey,
+                    &session_clone.pricing_option,
+                    renewal_steps,
+                )?,
+                false,
+            )
+            .await?;
         let wallet_guard = wallet.lock().await;
         let payment_token = wallet_guard
             .create_payment_token(&session_clone.pricing_option, renewal_steps)
-            .await?;
+            .await;
         drop(wallet_guard);
+        spending.complete(ticket, payment_token.is_ok());
+        let payment_token = payment_token?;
 
         // Get device identifier
         let (device_type, device_value) = protocol
@@ -462,6 +439,9 @@ impl TollGateService {
 
                 session.mark_renewed(additional_allotment, additional_cost);
                 session.session_end = session_response.session_end;
+                events::publish(BackendEvent::SessionRenewed {
+                    session: SessionInfo::from(&*session),
+                });
 
                 log::info!(
                     "Successfully renewed session {} with {} additional allotment",
@@ -470,37 +450,20 @@ impl TollGateService {
                 );
             }
         }
+        wallet.lock().await.publish_balance().await;
 
         Ok(())
     }
 
-    /// Force renewal of current session
-    pub async fn force_renewal(&self, tollgate_pubkey: &str) -> TollGateResult<()> {
-        Self::renew_session(
-            &self.session_manager,
-            &self.wallet,
-            &self.protocol,
-            tollgate_pubkey,
-        )
-        .await
-    }
-
     /// Get current session information
     pub async fn get_current_session(&self) -> TollGateResult<Option<SessionInfo>> {
         let manager = self.session_manager.lock().await;
         let active_sessions = manager.get_active_sessions();
 
         // Return the most recent active session
-        let session_info = active_sessions.first().map(|session| SessionInfo {
-            id: session.id.clone(),
-            tollgate_pubkey: session.tollgate_pubkey.clone(),
-            gateway_ip: session.gateway_ip.clone(),
-            status: session.status.clone(),
-            usage_percentage: session.usage_percentage(),
-            remaining_time_seconds: session.remaining_time_seconds(),
-            remaining_data_bytes: session.remaining_data_bytes(),
-            total_spent: session.total_spent,
-        });
+        let session_info = active_sessions
+            .first()
+            .map(|session| SessionInfo::from(*session));
 
         Ok(session_info)
     }
@@ -592,9 +555,12 @@ impl TollGateService {
                     break;
                 }
 
-                let mint_wallet = {
+                let (mint_wallet, split_strategy) = {
                     let guard = wallet.lock().await;
-                    guard.clone_wallet_for_mint(&mint_url)
+                    (
+                        guard.clone_wallet_for_mint(&mint_url),
+                        guard.split_strategy(&mint_url),
+                    )
                 };
 
                 let Some(mint_wallet) = mint_wallet else {
@@ -609,8 +575,18 @@ impl TollGateService {
                 match mint_wallet.mint_quote_state(&quote_id).await {
                     Ok(status) => {
                         if status.state == cdk::nuts::MintQuoteState::Paid {
+                            let amount = status.amount.map(u64::from).unwrap_or(0);
+                            let split = match mint_wallet.get_unspent_proofs().await {
+                                Ok(proofs) => split_strategy.split_target(
+                                    amount,
+                                    &count_denominations(
+                                        proofs.iter().map(|proof| u64::from(proof.amount)),
+                                    ),
+                                ),
+                                Err(_) => SplitTarget::None,
+                            };
                             match mint_wallet
-                                .mint(&status.quote, SplitTarget::default(), None)
+                                .mint(&status.quote, split, None)
                                 .await
                             {
                                 Ok(_) => {
@@ -619,6 +595,12 @@ impl TollGateService {
                                         quote_id,
                                         mint_url
                                     );
+                                    events::publish(BackendEvent::MintQuotePaid {
+                                        mint_url: mint_url.clone(),
+                                        quote_id: quote_id.clone(),
+                                        amount_sats: amount,
+                                    });
+                                    wallet.lock().await.publish_balance().await;
                                     break;
                                 }
                                 Err(err) => {
@@ -647,49 +629,87 @@ impl TollGateService {
         });
     }
 
-    /// Pay a Nut18 payment request
-    pub async fn pay_nut18_payment_request(
-        &self,
-        request: &str,
-        custom_amount: Option<u64>,
-    ) -> TollGateResult<()> {
-        let wallet = self.wallet.lock().await;
-        wallet
-            .pay_nut18_payment_request(request, custom_amount)
-            .await
+    /// Spend request for a TollGate purchase. Prices come from the gate, so
+    /// an offer whose total overflows is rejected.
+    fn tollgate_spend(
+        tollgate_pubkey: &str,
+        pricing_option: &PricingOption,
+        steps: u64,
+    ) -> TollGateResult<SpendRequest> {
+        let amount_sats = pricing_option.cost(steps).ok_or_else(|| {
+            TollGateError::InvalidAdvertisement(format!(
+                "Price of {} steps at {} per step is too large",
+                steps, pricing_option.price_per_step
+            ))
+        })?;
+        Ok(SpendRequest {
+            kind: SpendKind::Token,
+            origin: Origin::TollGate,
+            counterparty: Some(tollgate_pubkey.to_string()),
+            amount_sats,
+            mint_url: Some(pricing_option.mint_url.clone()),
+        })
     }
 
-    /// Pay a Nut18 payment request, returning a Token if no transport is defined
-    pub async fn pay_nut18_payment_request_with_token(
+    /// Most recent runs of a schedule, newest first
+    pub fn list_schedule_runs(&self, id: &str, limit: u32) -> TollGateResult<Vec<ScheduleRun>> {
+        self.scheduler.list_runs(id, limit)
+    }
+
+    /// Handle for paying without holding the service lock
+    pub fn payments(&self) -> Payments {
+        Payments {
+            wallet: self.wallet.clone(),
+            spending: self.spending.clone(),
+        }
+    }
+
+    /// Handle for starting and renewing sessions without holding the
+    /// service lock
+    pub fn sessions(&self) -> Sessions {
+        Sessions {
+            auto_tollgate_enabled: self.auto_tollgate_enabled.clone(),
+            session_manager: self.session_manager.clone(),
+            wallet: self.wallet.clone(),
+            spending: self.spending.clone(),
+            network_detector: self.network_detector.clone(),
+            protocol: self.protocol.clone(),
+            current_network: self.current_network.clone(),
+        }
+    }
+
+    pub fn get_spending_policy(&self) -> SpendingPolicy {
+        self.spending.policy()
+    }
+
+    pub fn set_spending_policy(&self, policy: SpendingPolicy) -> TollGateResult<SpendingPolicy> {
+        self.spending.set_policy(policy)
+    }
+
+    /// Ask before payments from `origin` above `threshold_sats`
+    pub fn set_spend_approval_threshold(
         &self,
-        request: &str,
-        custom_amount: Option<u64>,
-    ) -> TollGateResult<PayNut18Result> {
-        let wallet = self.wallet.lock().await;
-        wallet
-            .pay_nut18_payment_request_with_token(request, custom_amount)
-            .await
+        origin: &str,
+        threshold_sats: Option<u64>,
+    ) -> TollGateResult<SpendingPolicy> {
+        self.spending.set_approval_threshold(origin, threshold_sats)
     }
 
-    /// Pay a BOLT11 invoice
-    pub async fn pay_bolt11_invoice(&self, invoice: &str) -> TollGateResult<Bolt11PaymentResult> {
-        let wallet = self.wallet.lock().await;
-        wallet.pay_bolt11_invoice(invoice).await
+    /// Most recent spending decisions, newest first
+    pub fn list_spending_audit(&self, limit: u32) -> TollGateResult<Vec<SpendAuditEntry>> {
+        self.spending.audit_log(limit)
     }
 
-    /// Receive a cashu token
-    pub async fn receive_cashu_token(&self, token: &str) -> TollGateResult<CashuReceiveResult> {
-        let mut wallet = self.wallet.lock().await;
-        wallet.receive_cashu_token(token).await
+    /// Most recent settled spends from the tamper-evident ledger
+    pub fn list_spend_ledger(&self, limit: u32) -> TollGateResult<Vec<LedgerEntry>> {
+        self.spending.ledger(limit)
     }
 
-    pub async fn create_external_token(
+    pub fn verify_spend_ledger(
         &self,
-        amount_sats: u64,
-        mint_url: Option<String>,
-    ) -> TollGateResult<String> {
-        let wallet = self.wallet.lock().await;
-        wallet.create_external_token(amount_sats, mint_url).await
+        anchor: Option<&LedgerAnchor>,
+    ) -> TollGateResult<LedgerVerification> {
+        self.spending.verify_ledger(anchor)
     }
 
     /// Detect if current network is a TollGate
@@ -709,26 +729,83 @@ impl TollGateService {
         let active_sessions: Vec<SessionInfo> = manager
             .get_active_sessions()
             .iter()
-            .map(|session| SessionInfo {
-                id: session.id.clone(),
-                tollgate_pubkey: session.tollgate_pubkey.clone(),
-                gateway_ip: session.gateway_ip.clone(),
-                status: session.status.clone(),
-                usage_percentage: session.usage_percentage(),
-                remaining_time_seconds: session.remaining_time_seconds(),
-                remaining_data_bytes: session.remaining_data_bytes(),
-                total_spent: session.total_spent,
-            })
+            .map(|session| SessionInfo::from(*session))
             .collect();
         Ok(active_sessions)
     }
 
+    /// Run `apply` with the wallet locked, then reload the wallet from disk.
+    /// Used when the files behind the wallet are replaced, e.g. on restore.
+    pub async fn reload_wallet_with<T>(
+        &self,
+        apply: impl FnOnce() -> TollGateResult<T>,
+    ) -> TollGateResult<T> {
+        let mut wallet = self.wallet.lock().await;
+        let result = apply()?;
+
+        let mut reloaded = TollGateWallet::new()?;
+        reloaded.load_existing_mints().await?;
+        *wallet = reloaded;
+
+        log::info!("Reloaded wallet from storage");
+        Ok(result)
+    }
+
     /// Get the wallet's Nostr keys
     pub async fn get_wallet_keys(&self) -> nostr::Keys {
         let wallet = self.wallet.lock().await;
         wallet.get_keys()
     }
 
+    /// Configured mints and the default mint
+    pub async fn mint_settings(&self) -> (Vec<String>, Option<String>) {
+        let wallet = self.wallet.lock().await;
+        wallet.mint_settings()
+    }
+
+    /// Whether NWC still uses the pre-NIP-06 key
+    pub async fn uses_legacy_nwc_key(&self) -> bool {
+        let wallet = self.wallet.lock().await;
+        wallet.legacy_keys().is_some()
+    }
+
+    /// Replace the wallet seed with `phrase`. Refused while the current
+    /// wallet holds funds, since they would no longer be recoverable.
+    pub async fn restore_from_mnemonic(&self, phrase: &str) -> TollGateResult<()> {
+        let balance: u64 = {
+            let wallet = self.wallet.lock().await;
+            wallet
+                .get_all_balances()
+                .await?
+                .iter()
+                .map(|balance| balance.balance)
+                .sum()
+        };
+        if balance > 0 {
+            return Err(TollGateError::wallet(format!(
+                "Wallet still holds {} sats; empty it before restoring another seed",
+                balance
+            )));
+        }
+
+        // The previous install may have used the legacy NWC key; synced
+        // settings retire it when it is not needed
+        self.reload_wallet_with(|| write_secrets_from_mnemonic(phrase, true))
+            .await
+    }
+
+    /// Keys the NWC service should listen on
+    pub async fn get_nwc_service_keys(&self) -> nostr::Keys {
+        let wallet = self.wallet.lock().await;
+        wallet.nwc_service_keys()
+    }
+
+    /// Finish the migration to the NIP-06 identity
+    pub async fn retire_legacy_nostr_key(&self) -> TollGateResult<()> {
+        let mut wallet = self.wallet.lock().await;
+        wallet.retire_legacy_nostr_key()
+    }
+
     /// Load persisted state from storage
     async fn load_persisted_state(&self) -> TollGateResult<()> {
         // TODO: Implement persistence loading from file/database
@@ -747,12 +824,494 @@ impl TollGateService {
         log::debug!("Persisting state with {} sessions", manager.session_count());
         Ok(())
     }
+
+    /// Receive a cashu token, subject to the mint trust policy
+    pub async fn receive_cashu_token(
+        &self,
+        token: &str,
+        origin: &Origin,
+        approve_mint: bool,
+    ) -> TollGateResult<CashuReceiveResult> {
+        let mut wallet = self.wallet.lock().await;
+        let result = wallet
+            .receive_cashu_token(token, origin, approve_mint)
+            .await;
+        if result.is_ok() {
+            wallet.publish_balance().await;
+        }
+        result
+    }
+
+    /// Accept a cashu token without contacting the mint, to be redeemed later
+    pub async fn receive_cashu_token_offline(
+        &self,
+        token: &str,
+        origin: &Origin,
+        approve_mint: bool,
+    ) -> TollGateResult<CashuReceiveResult> {
+        let mut wallet = self.wallet.lock().await;
+        wallet.accept_token_offline(token, origin, approve_mint).await
+    }
+
+    /// List tokens accepted offline
+    pub async fn list_pending_tokens(&self) -> Vec<PendingToken> {
+        let wallet = self.wallet.lock().await;
+        wallet.list_pending_tokens()
+    }
+
+    /// Pending-token files that could not be read
+    pub async fn damaged_pending_token_files(&self) -> Vec<String> {
+        let wallet = self.wallet.lock().await;
+        wallet.damaged_pending_token_files()
+    }
+
+    /// Retry redeeming a pending token right away
+    pub async fn retry_pending_token(&self, id: &str) -> TollGateResult<usize> {
+        let mut wallet = self.wallet.lock().await;
+        wallet.retry_pending_token(id)?;
+        let redeemed = wallet.redeem_pending_tokens().await?;
+        if redeemed > 0 {
+            wallet.publish_balance().await;
+        }
+        Ok(redeemed)
+    }
+
+    /// Drop a pending token, returning it so the user can keep a copy
+    pub async fn discard_pending_token(&self, id: &str) -> TollGateResult<PendingToken> {
+        let mut wallet = self.wallet.lock().await;
+        wallet.discard_pending_token(id)
+    }
+
+    pub async fn get_split_strategy(&self, mint_url: &str) -> SplitStrategy {
+        let wallet = self.wallet.lock().await;
+        wallet.split_strategy(mint_url)
+    }
+
+    pub async fn set_split_strategy(
+        &self,
+        mint_url: &str,
+        strategy: SplitStrategy,
+    ) -> TollGateResult<()> {
+        let mut wallet = self.wallet.lock().await;
+        wallet.set_split_strategy(mint_url, strategy)
+    }
+
+    /// Consolidate proofs at one mint, or at every mint when `mint_url` is None
+    pub async fn consolidate_proofs(
+        &self,
+        mint_url: Option<String>,
+    ) -> TollGateResult<Vec<ConsolidationResult>> {
+        let wallet = self.wallet.lock().await;
+        let mints = match mint_url {
+            Some(mint_url) => vec![mint_url],
+            None => wallet
+                .get_all_balances()
+                .await?
+                .into_iter()
+                .map(|balance| balance.mint_url)
+                .collect(),
+        };
+
+        let mut results = Vec::new();
+        for mint_url in mints {
+            results.push(wallet.consolidate_proofs(&mint_url).await?);
+        }
+        Ok(results)
+    }
+
+    /// Token pool targets and current denominations per mint
+    pub async fn get_token_pool_status(&self) -> TollGateResult<Vec<TokenPoolStatus>> {
+        let wallet = self.wallet.lock().await;
+        wallet.token_pool_status().await
+    }
+
+    pub async fn get_token_pool_config(&self) -> TokenPoolConfig {
+        let wallet = self.wallet.lock().await;
+        wallet.token_pool_config()
+    }
+
+    pub async fn set_token_pool_config(&self, config: TokenPoolConfig) -> TollGateResult<()> {
+        let wallet = self.wallet.lock().await;
+        wallet.set_token_pool_config(config)
+    }
+
+    /// Get the mint trust policy
+    pub async fn get_mint_trust_policy(&self) -> MintTrustPolicy {
+        let wallet = self.wallet.lock().await;
+        wallet.trust_policy().clone()
+    }
+
+    /// Replace the mint trust policy
+    pub async fn set_mint_trust_policy(&self, policy: MintTrustPolicy) -> TollGateResult<()> {
+        let mut wallet = self.wallet.lock().await;
+        wallet.set_trust_policy(policy)
+    }
+
+    pub fn create_schedule(
+        &self,
+        schedule: NewScheduledPayment,
+    ) -> TollGateResult<ScheduledPayment> {
+        self.scheduler.create_schedule(schedule)
+    }
+
+    pub fn list_schedules(&self) -> TollGateResult<Vec<ScheduledPayment>> {
+        self.scheduler.list_schedules()
+    }
+
+    pub fn pause_schedule(&self, id: &str) -> TollGateResult<ScheduledPayment> {
+        self.scheduler.pause_schedule(id)
+    }
+
+    pub fn resume_schedule(&self, id: &str) -> TollGateResult<ScheduledPayment> {
+        self.scheduler.resume_schedule(id)
+    }
+
+    pub fn delete_schedule(&self, id: &str) -> TollGateResult<()> {
+        self.scheduler.delete_schedule(id)
+    }
+}
+
+/// Payments subject to the spending policy.
+///
+/// A payment can wait minutes for the user to approve it, so callers take
+/// this handle from the service and release the service lock before paying.
+#[derive(Clone)]
+pub struct Payments {
+    wallet: Arc<Mutex<TollGateWallet>>,
+    spending: Arc<SpendingPolicyEngine>,
+}
+
+impl Payments {
+    fn nut18_spend(
+        wallet: &TollGateWallet,
+        request: &str,
+        custom_amount: Option<u64>,
+        origin: &Origin,
+    ) -> TollGateResult<SpendRequest> {
+        let payment_request = PaymentRequest::from_str(request)
+            .map_err(|e| TollGateError::wallet(format!("Invalid payment request: {}", e)))?;
+        SpendRequest::nut18(
+            origin.clone(),
+            &payment_request,
+            custom_amount,
+            wallet.payment_request_mint(&payment_request),
+        )
+    }
+
+    /// Pay a Nut18 payment request, subject to the spending policy
+    pub async fn pay_nut18_payment_request(
+        &self,
+        request: &str,
+        custom_amount: Option<u64>,
+        origin: &Origin,
+        approved: bool,
+    ) -> TollGateResult<()> {
+        let spend = {
+            let wallet = self.wallet.lock().await;
+            Self::nut18_spend(&wallet, request, custom_amount, origin)?
+        };
+        // The wallet is not held while the user is asked for approval
+        let ticket = self.spending.authorize(&spend, approved).await?;
+        let wallet = self.wallet.lock().await;
+        let result = wallet
+            .pay_nut18_payment_request(request, custom_amount)
+            .await;
+        self.spending.complete(ticket, result.is_ok());
+        if result.is_ok() {
+            wallet.publish_balance().await;
+        }
+        result
+    }
+
+    /// Pay a Nut18 payment request, returning a Token if no transport is defined
+    pub async fn pay_nut18_payment_request_with_token(
+        &self,
+        request: &str,
+        custom_amount: Option<u64>,
+        origin: &Origin,
+        approved: bool,
+    ) -> TollGateResult<PayNut18Result> {
+        let spend = {
+            let wallet = self.wallet.lock().await;
+            Self::nut18_spend(&wallet, request, custom_amount, origin)?
+        };
+        // The wallet is not held while the user is asked for approval
+        let ticket = self.spending.authorize(&spend, approved).await?;
+        let wallet = self.wallet.lock().await;
+        let result = wallet
+            .pay_nut18_payment_request_with_token(request, custom_amount)
+            .await;
+        self.spending.complete(ticket, result.is_ok());
+        if result.is_ok() {
+            wallet.publish_balance().await;
+        }
+        result
+    }
+
+    /// Pay a BOLT11 invoice, subject to the spending policy
+    pub async fn pay_bolt11_invoice(
+        &self,
+        invoice: &str,
+        origin: &Origin,
+        approved: bool,
+    ) -> TollGateResult<Bolt11PaymentResult> {
+        let mint_url = self.wallet.lock().await.bolt11_payment_mint();
+        let spend = SpendRequest::bolt11(origin.clone(), invoice, mint_url)?;
+        let ticket = self.spending.authorize(&spend, approved).await?;
+        let wallet = self.wallet.lock().await;
+        let result = wallet.pay_bolt11_invoice(invoice).await;
+        self.spending.complete(ticket, result.is_ok());
+        if result.is_ok() {
+            wallet.publish_balance().await;
+        }
+        result
+    }
+
+    /// Create a token to hand to `counterparty`, subject to the spending policy
+    pub async fn create_external_token(
+        &self,
+        amount_sats: u64,
+        mint_url: Option<String>,
+        origin: &Origin,
+        counterparty: Option<String>,
+        approved: bool,
+    ) -> TollGateResult<String> {
+        let mint_url = match mint_url {
+            Some(mint_url) => mint_url,
+            None => {
+                self.wallet
+                    .lock()
+                    .await
+                    .select_mint_for_amount(amount_sats)
+                    .await?
+            }
+        };
+        let ticket = self
+            .spending
+            .authorize(
+                &SpendRequest {
+                    kind: SpendKind::Token,
+                    origin: origin.clone(),
+                    counterparty,
+                    amount_sats,
+                    mint_url: Some(mint_url.clone()),
+                },
+                approved,
+            )
+            .await?;
+        let wallet = self.wallet.lock().await;
+        let result = wallet
+            .create_external_token(amount_sats, Some(mint_url))
+            .await;
+        self.spending.complete(ticket, result.is_ok());
+        if result.is_ok() {
+            wallet.publish_balance().await;
+        }
+        result
+    }
+}
+
+/// Session purchases, which like `Payments` may wait for the user to
+/// approve them
+#[derive(Clone)]
+pub struct Sessions {
+    auto_tollgate_enabled: Arc<RwLock<bool>>,
+    session_manager: Arc<Mutex<SessionManager>>,
+    wallet: Arc<Mutex<TollGateWallet>>,
+    spending: Arc<SpendingPolicyEngine>,
+    network_detector: NetworkDetector,
+    protocol: TollGateProtocol,
+    current_network: Arc<RwLock<Option<NetworkInfo>>>,
+}
+
+impl Sessions {
+    /// Handle network connection event
+    pub async fn handle_network_connected(
+        &self,
+        gateway_ip: String,
+        mac_address: String,
+    ) -> TollGateResult<()> {
+        log::info!(
+            "Network connected: gateway={}, mac={}",
+            gateway_ip,
+            mac_address
+        );
+
+        // Detect if this is a TollGate network
+        let network_info = self
+            .network_detector
+            .detect_tollgate(&gateway_ip, &mac_address)
+            .await?;
+
+        // Update current network
+        *self.current_network.write().await = Some(network_info.clone());
+
+        if !network_info.is_tollgate {
+            log::debug!("Network {} is not a TollGate", gateway_ip);
+            return Ok(());
+        }
+
+        log::info!("TollGate detected on network {}", gateway_ip);
+
+        // If auto-tollgate is enabled, start a session
+        if *self.auto_tollgate_enabled.read().await {
+            if let Some(advertisement) = &network_info.advertisement.clone() {
+                self.start_tollgate_session(network_info, advertisement.clone())
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Start a new TollGate session
+    async fn start_tollgate_session(
+        &self,
+        network_info: NetworkInfo,
+        advertisement: crate::tollgate::protocol::TollGateAdvertisement,
+    ) -> TollGateResult<()> {
+        // Check if we already have an active session for this TollGate. The
+        // session manager is not held while paying, so renewals and other
+        // commands go on while the user is asked for approval.
+        let already_active = self
+            .session_manager
+            .lock()
+            .await
+            .get_session(&advertisement.tollgate_pubkey)
+            .is_some_and(|session| session.is_active());
+        if already_active {
+            log::info!(
+                "Already have active session for TollGate {}",
+                advertisement.tollgate_pubkey
+            );
+            return Ok(());
+        }
+
+        // Calculate initial purchase (minimum steps or the configured
+        // purchase, whichever is larger)
+        let min_steps = advertisement
+            .pricing_options
+            .iter()
+            .map(|opt| opt.min_steps)
+            .min()
+            .unwrap_or(1);
+
+        let config = crate::config::current();
+        let purchase_steps = if advertisement.metric == "milliseconds" {
+            config.tollgate.initial_purchase_secs * 1000 / advertisement.step_size
+        } else {
+            config.tollgate.initial_purchase_bytes / advertisement.step_size
+        };
+
+        let initial_steps = min_steps.max(purchase_steps);
+
+        // Select best pricing option
+        let pricing_option = self
+            .wallet
+            .lock()
+            .await
+            .select_best_pricing_option(&advertisement.pricing_options, initial_steps)
+            .await?;
+
+        // Create payment token. The wallet is not held while the user is
+        // asked for approval.
+        let ticket = self
+            .spending
+            .authorize(
+                &TollGateService::tollgate_spend(
+                    &advertisement.tollgate_pubkey,
+                    &pricing_option,
+                    initial_steps,
+                )?,
+                false,
+            )
+            .await?;
+        let payment_token = self
+            .wallet
+            .lock()
+            .await
+            .create_payment_token(&pricing_option, initial_steps)
+            .await;
+        self.spending.complete(ticket, payment_token.is_ok());
+        let payment_token = payment_token?;
+
+        // Get device identifier from TollGate
+        let (device_type, device_value) = self
+            .protocol
+            .get_device_identifier(&network_info.gateway_ip)
+            .await?;
+
+        // Create payment event
+        let customer_keys = Keys::generate();
+        let payment = PaymentEvent {
+            tollgate_pubkey: advertisement.tollgate_pubkey.clone(),
+            mac_address: device_value.clone(),
+            cashu_token: payment_token.token.clone(),
+            steps: initial_steps,
+        };
+
+        let payment_event = self
+            .protocol
+            .create_payment_event(&payment, &customer_keys, &device_type, &device_value)
+            .await?;
+
+        // Send payment and get session response
+        let session_response = self
+            .protocol
+            .send_payment(&network_info.gateway_ip, &payment_event)
+            .await?;
+
+        // Calculate allotment and session end
+        let allotment = self
+            .protocol
+            .calculate_allotment(initial_steps, advertisement.step_size);
+        let cost = self.protocol.calculate_cost(&pricing_option, initial_steps);
+
+        // Create session
+        let mut session = Session::new(super::session::SessionParams {
+            tollgate_pubkey: advertisement.tollgate_pubkey.clone(),
+            gateway_ip: network_info.gateway_ip.clone(),
+            mac_address: device_value,
+            pricing_option,
+            advertisement: advertisement.clone(),
+            initial_allotment: allotment,
+            session_end: session_response.session_end,
+            initial_cost: cost,
+        })?;
+
+        // Update session with response
+        session.update_from_response(&session_response)?;
+
+        // Add to session manager
+        let info = SessionInfo::from(&session);
+        self.session_manager.lock().await.add_session(session);
+        events::publish(BackendEvent::SessionStarted { session: info });
+        self.wallet.lock().await.publish_balance().await;
+
+        log::info!(
+            "Successfully started TollGate session for {}",
+            advertisement.tollgate_pubkey
+        );
+        Ok(())
+    }
+
+    /// Force renewal of current session
+    pub async fn force_renewal(&self, tollgate_pubkey: &str) -> TollGateResult<()> {
+        TollGateService::renew_session(
+            &self.session_manager,
+            &self.wallet,
+            &self.spending,
+            &self.protocol,
+            tollgate_pubkey,
+        )
+        .await
+    }
 }
 
 impl Drop for TollGateService {
     fn drop(&mut self) {
-        // Stop background service when service is dropped
-        if let Some(task) = self.background_task.take() {
+        // Stop background service when service is dropped without shutdown
+        if let Some((_, task)) = self.background_task.take() {
             task.abort();
         }
     }
@@ -764,13 +1323,13 @@ mod tests {
 
     #[tokio::test]
     async fn test_service_creation() {
-        let service = TollGateService::new().await;
+        let service = TollGateService::new(Arc::default()).await;
         assert!(service.is_ok());
     }
 
     #[tokio::test]
     async fn test_auto_tollgate_toggle() {
-        let service = TollGateService::new().await.unwrap();
+        let service = TollGateService::new(Arc::default()).await.unwrap();
 
         // Initially disabled
         let status = service.get_status().await.unwrap();
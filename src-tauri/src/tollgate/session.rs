@@ -94,7 +94,7 @@ impl Session {
             total_allotment: params.initial_allotment,
             current_usage: 0,
             session_end: params.session_end,
-            renewal_threshold: 0.8, // 80%
+            renewal_threshold: crate::config::current().tollgate.renewal_threshold,
             created_at: Utc::now(),
             last_renewal: None,
             total_spent: params.initial_cost,
@@ -244,9 +244,18 @@ impl SessionManager {
         self.sessions.values_mut()
     }
 
-    /// Clean up expired sessions
-    pub fn cleanup_expired_sessions(&mut self) {
-        self.sessions.retain(|_, session| !session.is_expired());
+    /// Remove sessions that ran out or were marked expired, returning them
+    pub fn cleanup_expired_sessions(&mut self) -> Vec<Session> {
+        let expired: Vec<String> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.is_expired() || session.status == SessionStatus::Expired)
+            .map(|(pubkey, _)| pubkey.clone())
+            .collect();
+        expired
+            .iter()
+            .filter_map(|pubkey| self.sessions.remove(pubkey))
+            .collect()
     }
 
     /// Get session count
@@ -365,4 +374,22 @@ mod tests {
         manager.remove_session(&pubkey);
         assert_eq!(manager.session_count(), 0);
     }
+
+    #[test]
+    fn test_cleanup_expired_sessions() {
+        let mut manager = SessionManager::new();
+        let mut session = create_test_session();
+        session.status = SessionStatus::Active;
+        manager.add_session(session);
+        assert!(manager.cleanup_expired_sessions().is_empty());
+
+        // Marked expired on disconnect, before its time is up
+        for session in manager.get_all_sessions_mut() {
+            session.status = SessionStatus::Expired;
+        }
+        let removed = manager.cleanup_expired_sessions();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].tollgate_pubkey, "test_pubkey");
+        assert_eq!(manager.session_count(), 0);
+    }
 }
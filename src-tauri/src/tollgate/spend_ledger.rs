@@ -0,0 +1,458 @@
+//! Tamper-evident ledger of outgoing payments
+//!
+//! Every settled spend (melts, tokens handed out, TollGate payments, proxy
+//! tokens, NWC payments, scheduled payments) is appended to a SQLite table
+//! whose rows form an HMAC chain: each entry's MAC covers its fields and the
+//! MAC of the entry before it. The key is derived from the wallet seed, so
+//! rewriting history requires the seed. The MAC of the newest entry is also
+//! kept in a separate head file, which is what exposes a truncated table. A
+//! row is only committed once the head file points at it.
+//!
+//! Unlike the spending audit trail, which tracks decisions and is updated as
+//! payments settle, rows here are never modified once written.
+
+use crate::storage;
+use crate::tollgate::errors::TollGateResult;
+use crate::tollgate::origin::Origin;
+use bitcoin_hashes::{hmac, sha256, Hash, HashEngine};
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub const LEDGER_DB: &str = "spend-ledger.sqlite";
+pub const LEDGER_HEAD: &str = "spend-ledger.head.json";
+/// MAC that the first entry chains from
+const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+/// Domain separation for the MAC key
+const KEY_CONTEXT: &[u8] = b"wally/spend-ledger/v1";
+
+/// How the funds left the wallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpendKind {
+    /// Lightning payment through a mint
+    Melt,
+    /// Ecash token handed to the counterparty
+    Token,
+}
+
+impl SpendKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SpendKind::Melt => "melt",
+            SpendKind::Token => "token",
+        }
+    }
+}
+
+/// One settled spend
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub kind: SpendKind,
+    /// What triggered the spend, as in `Origin`'s display form
+    pub origin: String,
+    pub counterparty: Option<String>,
+    pub mint_url: Option<String>,
+    pub amount_sats: u64,
+    pub prev_mac: String,
+    pub mac: String,
+}
+
+/// Position of the newest entry, to compare against a copy kept elsewhere
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LedgerAnchor {
+    pub seq: u64,
+    pub mac: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerVerification {
+    pub valid: bool,
+    pub entries: u64,
+    pub head: Option<LedgerAnchor>,
+    /// Everything that did not check out, empty when `valid`
+    pub problems: Vec<String>,
+}
+
+/// Fields covered by an entry's MAC, in a fixed order
+#[derive(Serialize)]
+struct MacInput<'a> {
+    seq: u64,
+    timestamp: i64,
+    kind: &'a str,
+    origin: &'a str,
+    counterparty: Option<&'a str>,
+    mint_url: Option<&'a str>,
+    amount_sats: u64,
+}
+
+pub struct SpendLedger {
+    db_path: PathBuf,
+    head_path: PathBuf,
+    key: [u8; 32],
+    /// Serializes appends so the chain never forks
+    append: Mutex<()>,
+}
+
+impl SpendLedger {
+    pub fn open(db_path: PathBuf, head_path: PathBuf, seed: &[u8]) -> TollGateResult<Self> {
+        let mut engine = sha256::Hash::engine();
+        engine.input(KEY_CONTEXT);
+        engine.input(seed);
+        let key = sha256::Hash::from_engine(engine).to_byte_array();
+
+        let conn = Connection::open(&db_path)?;
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS spend_ledger (
+                seq INTEGER PRIMARY KEY,
+                timestamp INTEGER NOT NULL,
+                kind TEXT NOT NULL,
+                origin TEXT NOT NULL,
+                counterparty TEXT,
+                mint_url TEXT,
+                amount_sats INTEGER NOT NULL,
+                prev_mac TEXT NOT NULL,
+                mac TEXT NOT NULL
+            );
+            CREATE TRIGGER IF NOT EXISTS spend_ledger_no_update
+                BEFORE UPDATE ON spend_ledger
+                BEGIN SELECT RAISE(ABORT, 'spend ledger is append-only'); END;
+            CREATE TRIGGER IF NOT EXISTS spend_ledger_no_delete
+                BEFORE DELETE ON spend_ledger
+                BEGIN SELECT RAISE(ABORT, 'spend ledger is append-only'); END;",
+        )?;
+
+        Ok(Self {
+            db_path,
+            head_path,
+            key,
+            append: Mutex::new(()),
+        })
+    }
+
+    /// Append a settled spend and move the head anchor to it
+    pub fn append(
+        &self,
+        kind: SpendKind,
+        origin: &Origin,
+        counterparty: Option<&str>,
+        mint_url: Option<&str>,
+        amount_sats: u64,
+    ) -> TollGateResult<LedgerEntry> {
+        let _guard = self.append.lock().expect("spend ledger poisoned");
+        let mut conn = Connection::open(&self.db_path)?;
+        // The row is only committed once the head file points at it
+        let tx = conn.transaction()?;
+
+        let last: Option<(u64, String)> = tx
+            .query_row(
+                "SELECT seq, mac FROM spend_ledger ORDER BY seq DESC LIMIT 1",
+                [],
+                |row| Ok((row.get::<_, i64>(0)? as u64, row.get(1)?)),
+            )
+            .optional()?;
+        let (seq, prev_mac) = match last {
+            Some((seq, mac)) => (seq + 1, mac),
+            None => (1, GENESIS.to_string()),
+        };
+
+        let timestamp = Utc::now().timestamp();
+        let subsystem = origin.subsystem();
+        let origin = origin.to_string();
+        let mac = self.mac(
+            &prev_mac,
+            &MacInput {
+                seq,
+                timestamp,
+                kind: kind.as_str(),
+                origin: &origin,
+                counterparty,
+                mint_url,
+                amount_sats,
+            },
+        )?;
+
+        tx.execute(
+            "INSERT INTO spend_ledger
+                (seq, timestamp, kind, origin, counterparty, mint_url, amount_sats, prev_mac, mac)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                seq as i64,
+                timestamp,
+                kind.as_str(),
+                origin,
+                counterparty,
+                mint_url,
+                amount_sats as i64,
+                prev_mac,
+                mac,
+            ],
+        )?;
+        self.write_head(&LedgerAnchor {
+            seq,
+            mac: mac.clone(),
+        })?;
+        if let Err(e) = tx.commit() {
+            // Point the head back at the last committed entry
+            let previous = (seq > 1).then(|| LedgerAnchor {
+                seq: seq - 1,
+                mac: prev_mac.clone(),
+            });
+            match previous {
+                Some(previous) => self.write_head(&previous)?,
+                None => fs::remove_file(&self.head_path)?,
+            }
+            return Err(e.into());
+        }
+        crate::metrics::SPENT_SATS.add(&[subsystem], amount_sats as f64);
+
+        Ok(LedgerEntry {
+            seq,
+            timestamp: Utc.timestamp_opt(timestamp, 0).single().unwrap_or_default(),
+            kind,
+            origin,
+            counterparty: counterparty.map(str::to_string),
+            mint_url: mint_url.map(str::to_string),
+            amount_sats,
+            prev_mac,
+            mac,
+        })
+    }
+
+    /// Most recent entries, newest first
+    pub fn list(&self, limit: u32) -> TollGateResult<Vec<LedgerEntry>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT seq, timestamp, kind, origin, counterparty, mint_url, amount_sats, prev_mac, mac
+             FROM spend_ledger ORDER BY seq DESC LIMIT ?1",
+        )?;
+        let entries = stmt
+            .query_map(params![limit], Self::row_to_entry)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(entries)
+    }
+
+    /// Recompute the whole chain. `anchor` is a head recorded earlier, e.g.
+    /// by an auditor; the head file kept next to the ledger is always checked.
+    pub fn verify(&self, anchor: Option<&LedgerAnchor>) -> TollGateResult<LedgerVerification> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT seq, timestamp, kind, origin, counterparty, mint_url, amount_sats, prev_mac, mac
+             FROM spend_ledger ORDER BY seq ASC",
+        )?;
+        let entries = stmt
+            .query_map([], Self::row_to_entry)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut problems = Vec::new();
+        let mut expected_prev = GENESIS.to_string();
+        for (index, entry) in entries.iter().enumerate() {
+            let expected_seq = index as u64 + 1;
+            if entry.seq != expected_seq {
+                problems.push(format!(
+                    "Entry {} missing (found {} instead)",
+                    expected_seq, entry.seq
+                ));
+            }
+            if entry.prev_mac != expected_prev {
+                problems.push(format!(
+                    "Entry {} does not chain to its predecessor",
+                    entry.seq
+                ));
+            }
+            if self.entry_mac(entry)? != entry.mac {
+                problems.push(format!("Entry {} was modified", entry.seq));
+            }
+            expected_prev = entry.mac.clone();
+        }
+
+        let head = entries.last().map(|entry| LedgerAnchor {
+            seq: entry.seq,
+            mac: entry.mac.clone(),
+        });
+
+        let stored_head = fs::read(&self.head_path)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<LedgerAnchor>(&data).ok());
+        match (&stored_head, &head) {
+            (Some(stored), _) => Self::check_anchor(&entries, stored, "head file", &mut problems),
+            (None, Some(_)) => problems.push("Head file is missing".to_string()),
+            (None, None) => {}
+        }
+        if let Some(anchor) = anchor {
+            Self::check_anchor(&entries, anchor, "given anchor", &mut problems);
+        }
+
+        Ok(LedgerVerification {
+            valid: problems.is_empty(),
+            entries: entries.len() as u64,
+            head,
+            problems,
+        })
+    }
+
+    /// An anchor must still be in the ledger with the same MAC
+    fn check_anchor(
+        entries: &[LedgerEntry],
+        anchor: &LedgerAnchor,
+        label: &str,
+        problems: &mut Vec<String>,
+    ) {
+        match entries.iter().find(|entry| entry.seq == anchor.seq) {
+            Some(entry) if entry.mac == anchor.mac => {}
+            Some(_) => problems.push(format!("Entry {} does not match the {}", anchor.seq, label)),
+            None => problems.push(format!(
+                "Ledger ends before entry {} recorded in the {}",
+                anchor.seq, label
+            )),
+        }
+    }
+
+    fn entry_mac(&self, entry: &LedgerEntry) -> TollGateResult<String> {
+        self.mac(
+            &entry.prev_mac,
+            &MacInput {
+                seq: entry.seq,
+                timestamp: entry.timestamp.timestamp(),
+                kind: entry.kind.as_str(),
+                origin: &entry.origin,
+                counterparty: entry.counterparty.as_deref(),
+                mint_url: entry.mint_url.as_deref(),
+                amount_sats: entry.amount_sats,
+            },
+        )
+    }
+
+    fn mac(&self, prev_mac: &str, input: &MacInput) -> TollGateResult<String> {
+        let mut engine = hmac::HmacEngine::<sha256::Hash>::new(&self.key);
+        engine.input(prev_mac.as_bytes());
+        engine.input(&serde_json::to_vec(input)?);
+        Ok(hmac::Hmac::<sha256::Hash>::from_engine(engine).to_string())
+    }
+
+    fn write_head(&self, anchor: &LedgerAnchor) -> TollGateResult<()> {
+        storage::write_atomic(&self.head_path, serde_json::to_vec(anchor)?)?;
+        Ok(())
+    }
+
+    fn row_to_entry(row: &Row) -> rusqlite::Result<LedgerEntry> {
+        let kind: String = row.get(2)?;
+        Ok(LedgerEntry {
+            seq: row.get::<_, i64>(0)? as u64,
+            timestamp: Utc
+                .timestamp_opt(row.get(1)?, 0)
+                .single()
+                .unwrap_or_default(),
+            kind: if kind == "melt" {
+                SpendKind::Melt
+            } else {
+                SpendKind::Token
+            },
+            origin: row.get(3)?,
+            counterparty: row.get(4)?,
+            mint_url: row.get(5)?,
+            amount_sats: row.get::<_, i64>(6)? as u64,
+            prev_mac: row.get(7)?,
+            mac: row.get(8)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_ledger() -> (SpendLedger, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("wally-ledger-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        let ledger =
+            SpendLedger::open(dir.join(LEDGER_DB), dir.join(LEDGER_HEAD), &[7u8; 64]).unwrap();
+        (ledger, dir)
+    }
+
+    fn append(ledger: &SpendLedger, amount_sats: u64) -> LedgerEntry {
+        ledger
+            .append(
+                SpendKind::Melt,
+                &Origin::Ui,
+                Some("shop"),
+                Some("https://mint.example.com"),
+                amount_sats,
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn test_chain_detects_edits_and_truncation() {
+        let (ledger, dir) = temp_ledger();
+        for amount in [100, 200, 300] {
+            append(&ledger, amount);
+        }
+        let verification = ledger.verify(None).unwrap();
+        assert!(verification.valid, "{:?}", verification.problems);
+        assert_eq!(verification.entries, 3);
+        let anchor = verification.head.unwrap();
+
+        // Writes through the app's own connection are refused outright
+        let conn = Connection::open(dir.join(LEDGER_DB)).unwrap();
+        assert!(conn
+            .execute("UPDATE spend_ledger SET amount_sats = 1 WHERE seq = 2", [])
+            .is_err());
+
+        // Someone bypassing the triggers still breaks the chain
+        conn.execute_batch(
+            "DROP TRIGGER spend_ledger_no_update;
+             DROP TRIGGER spend_ledger_no_delete;
+             UPDATE spend_ledger SET amount_sats = 1 WHERE seq = 2;",
+        )
+        .unwrap();
+        let verification = ledger.verify(None).unwrap();
+        assert!(!verification.valid);
+        assert!(verification.problems[0].contains("Entry 2 was modified"));
+
+        // Dropping the newest entry is caught by the head file and the anchor
+        conn.execute("DELETE FROM spend_ledger WHERE seq >= 2", [])
+            .unwrap();
+        let verification = ledger.verify(Some(&anchor)).unwrap();
+        assert!(!verification.valid);
+        assert_eq!(verification.problems.len(), 2);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_failed_head_write_rolls_back_entry() {
+        let (ledger, dir) = temp_ledger();
+        append(&ledger, 100);
+
+        // A directory in place of the head file makes the rename fail
+        let blocked =
+            SpendLedger::open(dir.join(LEDGER_DB), dir.join("blocked"), &[7u8; 64]).unwrap();
+        fs::create_dir_all(dir.join("blocked")).unwrap();
+        assert!(blocked
+            .append(SpendKind::Melt, &Origin::Ui, None, None, 200)
+            .is_err());
+
+        assert_eq!(ledger.list(10).unwrap().len(), 1);
+        assert!(ledger.verify(None).unwrap().valid);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_key_comes_from_seed() {
+        let (ledger, dir) = temp_ledger();
+        append(&ledger, 100);
+
+        let other =
+            SpendLedger::open(dir.join(LEDGER_DB), dir.join(LEDGER_HEAD), &[8u8; 64]).unwrap();
+        assert!(!other.verify(None).unwrap().valid);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,1119 @@
+//! Spending policy for outgoing payments
+//!
+//! Every outgoing payment (UI, NWC, the Routstr proxy, TollGate purchases and
+//! scheduled payments) is checked against one set of rules before any proofs
+//! leave the wallet. Rules match on origin, counterparty, mint and amount, and
+//! can carry a limit over a rolling time window. A rule without a limit
+//! applies whenever it matches; a rule with a limit only applies once the
+//! limit would be exceeded. When several rules apply the strictest outcome
+//! wins, so an allow rule never overrides a deny.
+//!
+//! An "ask" outcome prompts the user: interactive callers confirm up front,
+//! automated callers wait on the approval broker until the user answers.
+//!
+//! A policy file that cannot be read denies every payment until a policy is
+//! saved again, so a damaged file never lifts the limits. A file that cannot
+//! be parsed is moved aside and replaced by a deny-all policy, which keeps
+//! denying after a restart.
+//!
+//! Every decision is written to an audit table, which is also what the
+//! window limits are computed from. Settled payments are additionally
+//! appended to the tamper-evident spend ledger, and the audit row keeps the
+//! sequence number of its ledger entry so a missing entry shows up when the
+//! ledger is verified.
+
+use crate::profiles;
+use crate::storage;
+use crate::tollgate::approvals::{ApprovalOutcome, ApprovalsState};
+use crate::tollgate::errors::{TollGateError, TollGateResult};
+use crate::tollgate::mint_policy::normalize_mint_url;
+use crate::tollgate::origin::Origin;
+use crate::tollgate::spend_ledger::{
+    LedgerAnchor, LedgerEntry, LedgerVerification, SpendKind, SpendLedger, LEDGER_DB, LEDGER_HEAD,
+};
+use cdk::nuts::nut18::payment_request::PaymentRequest;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use lightning_invoice::Bolt11Invoice;
+use rusqlite::{params, Connection, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+const POLICY_FILE: &str = "spending-policy.json";
+const AUDIT_DB: &str = "spending-audit.sqlite";
+/// Longest window a limit may use (ten years)
+const MAX_WINDOW_SECS: u64 = 10 * 366 * 86_400;
+/// Id prefix of the rules managed by `set_approval_threshold`
+const APPROVAL_RULE_PREFIX: &str = "approve-above:";
+
+/// Outcome of a rule, ordered from most to least permissive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyOutcome {
+    #[default]
+    Allow,
+    /// Ask the user before paying
+    Ask,
+    Deny,
+}
+
+/// Which payments a rule applies to. Empty fields match anything.
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct RuleMatch {
+    /// Origin kind (`ui`, `nwc`, `proxy`, `tollgate`, `scheduled`) or a full
+    /// origin such as `nwc:<connection pubkey>`
+    #[serde(default)]
+    pub origin: Option<String>,
+    #[serde(default)]
+    pub counterparty: Option<String>,
+    #[serde(default)]
+    pub mint_url: Option<String>,
+    #[serde(default)]
+    pub min_amount_sats: Option<u64>,
+    #[serde(default)]
+    pub max_amount_sats: Option<u64>,
+}
+
+/// Total amount allowed over a rolling window
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SpendLimit {
+    pub window_secs: u64,
+    pub max_sats: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct SpendRule {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub matches: RuleMatch,
+    #[serde(default)]
+    pub limit: Option<SpendLimit>,
+    pub outcome: PolicyOutcome,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Spending policy, persisted per profile
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq)]
+pub struct SpendingPolicy {
+    /// Outcome when no rule applies
+    #[serde(default)]
+    pub default_outcome: PolicyOutcome,
+    #[serde(default)]
+    pub rules: Vec<SpendRule>,
+}
+
+/// One outgoing payment to be checked
+#[derive(Debug, Clone)]
+pub struct SpendRequest {
+    pub kind: SpendKind,
+    pub origin: Origin,
+    pub counterparty: Option<String>,
+    pub amount_sats: u64,
+    pub mint_url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyDecision {
+    pub outcome: PolicyOutcome,
+    pub rule_id: Option<String>,
+    pub reason: String,
+}
+
+/// Audit status of a decision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SpendStatus {
+    /// Allowed, payment in progress
+    Pending,
+    Completed,
+    Failed,
+    Denied,
+    ApprovalRequired,
+    /// Waiting for the user to answer a prompt
+    AwaitingApproval,
+    Rejected,
+    Expired,
+}
+
+impl SpendStatus {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SpendStatus::Pending => "pending",
+            SpendStatus::Completed => "completed",
+            SpendStatus::Failed => "failed",
+            SpendStatus::Denied => "denied",
+            SpendStatus::ApprovalRequired => "approval_required",
+            SpendStatus::AwaitingApproval => "awaiting_approval",
+            SpendStatus::Rejected => "rejected",
+            SpendStatus::Expired => "expired",
+        }
+    }
+
+    fn parse(value: &str) -> Self {
+        match value {
+            "pending" => SpendStatus::Pending,
+            "completed" => SpendStatus::Completed,
+            "failed" => SpendStatus::Failed,
+            "approval_required" => SpendStatus::ApprovalRequired,
+            "awaiting_approval" => SpendStatus::AwaitingApproval,
+            "rejected" => SpendStatus::Rejected,
+            "expired" => SpendStatus::Expired,
+            _ => SpendStatus::Denied,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SpendAuditEntry {
+    pub id: i64,
+    pub timestamp: DateTime<Utc>,
+    pub origin: String,
+    pub counterparty: Option<String>,
+    pub mint_url: Option<String>,
+    pub amount_sats: u64,
+    pub outcome: PolicyOutcome,
+    pub rule_id: Option<String>,
+    pub reason: String,
+    pub status: SpendStatus,
+}
+
+/// Handle for an allowed payment, to be passed to `complete`
+#[derive(Debug)]
+#[must_use = "complete the ticket once the payment has settled"]
+pub struct SpendTicket {
+    audit_id: i64,
+    request: SpendRequest,
+}
+
+impl SpendRequest {
+    /// Request for paying a BOLT11 invoice from `mint_url`
+    pub fn bolt11(origin: Origin, invoice: &str, mint_url: Option<String>) -> TollGateResult<Self> {
+        let invoice = Bolt11Invoice::from_str(invoice)
+            .map_err(|e| TollGateError::wallet(format!("Invalid invoice: {}", e)))?;
+        let amount_msats = invoice
+            .amount_milli_satoshis()
+            .ok_or_else(|| TollGateError::wallet("Invoice has no amount"))?;
+
+        Ok(Self {
+            kind: SpendKind::Melt,
+            origin,
+            counterparty: Some(invoice.recover_payee_pub_key().to_string()),
+            amount_sats: amount_msats.div_ceil(1000),
+            mint_url,
+        })
+    }
+
+    /// Request for paying a NUT-18 payment request from `mint_url`
+    pub fn nut18(
+        origin: Origin,
+        request: &PaymentRequest,
+        custom_amount: Option<u64>,
+        mint_url: Option<String>,
+    ) -> TollGateResult<Self> {
+        let amount_sats = match request.amount {
+            Some(amount) => amount.into(),
+            None => custom_amount.ok_or_else(|| {
+                TollGateError::wallet("Amount not specified in request and no custom amount")
+            })?,
+        };
+        let counterparty = request
+            .transports
+            .first()
+            .map(|transport| transport.target.clone())
+            .or_else(|| request.payment_id.clone());
+
+        Ok(Self {
+            kind: SpendKind::Token,
+            origin,
+            counterparty,
+            amount_sats,
+            mint_url,
+        })
+    }
+}
+
+impl RuleMatch {
+    fn matches_origin(&self, origin: &str) -> bool {
+        match &self.origin {
+            None => true,
+            Some(pattern) => {
+                origin == pattern
+                    || origin
+                        .strip_prefix(pattern.as_str())
+                        .is_some_and(|rest| rest.starts_with(':'))
+            }
+        }
+    }
+
+    /// Whether a payment falls in this rule's scope, ignoring the amount
+    fn matches_scope(&self, origin: &str, counterparty: Option<&str>, mint: Option<&str>) -> bool {
+        if !self.matches_origin(origin) {
+            return false;
+        }
+        if let Some(expected) = &self.counterparty {
+            if !counterparty.is_some_and(|c| c.eq_ignore_ascii_case(expected)) {
+                return false;
+            }
+        }
+        if let Some(expected) = &self.mint_url {
+            if !mint.is_some_and(|m| normalize_mint_url(m) == normalize_mint_url(expected)) {
+                return false;
+            }
+        }
+        true
+    }
+
+    fn matches(&self, request: &SpendRequest) -> bool {
+        self.matches_scope(
+            &request.origin.to_string(),
+            request.counterparty.as_deref(),
+            request.mint_url.as_deref(),
+        ) && self
+            .min_amount_sats
+            .is_none_or(|min| request.amount_sats >= min)
+            && self
+                .max_amount_sats
+                .is_none_or(|max| request.amount_sats <= max)
+    }
+}
+
+impl SpendingPolicy {
+    /// Evaluate the policy for `request`. `spent` returns the amount already
+    /// spent within a rule's scope over its limit window.
+    pub fn evaluate(
+        &self,
+        request: &SpendRequest,
+        spent: impl Fn(&SpendRule, &SpendLimit) -> u64,
+    ) -> PolicyDecision {
+        let mut decision: Option<PolicyDecision> = None;
+
+        for rule in self.rules.iter().filter(|rule| rule.enabled) {
+            if !rule.matches.matches(request) {
+                continue;
+            }
+
+            let reason = match &rule.limit {
+                None => rule.name.clone(),
+                Some(limit) => {
+                    let already = spent(rule, limit);
+                    if already.saturating_add(request.amount_sats) <= limit.max_sats {
+                        continue;
+                    }
+                    format!(
+                        "{}: {} sats spent, {} more would exceed {} sats per {}s",
+                        rule.name, already, request.amount_sats, limit.max_sats, limit.window_secs
+                    )
+                }
+            };
+
+            if decision
+                .as_ref()
+                .is_none_or(|current| rule.outcome > current.outcome)
+            {
+                decision = Some(PolicyDecision {
+                    outcome: rule.outcome,
+                    rule_id: Some(rule.id.clone()),
+                    reason,
+                });
+            }
+        }
+
+        decision.unwrap_or_else(|| PolicyDecision {
+            outcome: self.default_outcome,
+            rule_id: None,
+            reason: "Default policy".to_string(),
+        })
+    }
+
+    /// Amount above which payments from `origin` need approval, if set
+    pub fn approval_threshold(&self, origin: &str) -> Option<u64> {
+        let id = format!("{}{}", APPROVAL_RULE_PREFIX, origin);
+        self.rules
+            .iter()
+            .find(|rule| rule.id == id)
+            .and_then(|rule| rule.matches.min_amount_sats)
+            .map(|min| min.saturating_sub(1))
+    }
+
+    /// Ask before payments from `origin` (e.g. `nwc:<pubkey>`, `tollgate`,
+    /// `proxy`) above `threshold_sats`, or remove the threshold with `None`
+    pub fn set_approval_threshold(&mut self, origin: &str, threshold_sats: Option<u64>) {
+        let id = format!("{}{}", APPROVAL_RULE_PREFIX, origin);
+        self.rules.retain(|rule| rule.id != id);
+        if let Some(threshold) = threshold_sats {
+            self.rules.push(SpendRule {
+                id,
+                name: format!("Approval above {} sats for {}", threshold, origin),
+                enabled: true,
+                matches: RuleMatch {
+                    origin: Some(origin.to_string()),
+                    min_amount_sats: Some(threshold.saturating_add(1)),
+                    ..Default::default()
+                },
+                limit: None,
+                outcome: PolicyOutcome::Ask,
+            });
+        }
+    }
+
+    fn validate(&mut self) -> TollGateResult<()> {
+        for rule in &mut self.rules {
+            if rule.id.is_empty() {
+                rule.id = uuid::Uuid::new_v4().to_string();
+            }
+            if let Some(limit) = &rule.limit {
+                if limit.window_secs == 0 || limit.window_secs > MAX_WINDOW_SECS {
+                    return Err(TollGateError::wallet(format!(
+                        "Rule '{}' needs a window between 1 and {} seconds",
+                        rule.name, MAX_WINDOW_SECS
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// SQLite audit trail of spending decisions
+struct SpendAuditStore {
+    db_path: PathBuf,
+}
+
+impl SpendAuditStore {
+    fn new(db_path: PathBuf) -> TollGateResult<Self> {
+        let store = Self { db_path };
+        let conn = Connection::open(&store.db_path)?;
+        conn.execute(
+            "CREATE TABLE IF NOT EXISTS spend_audit (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                timestamp INTEGER NOT NULL,
+                origin TEXT NOT NULL,
+                counterparty TEXT,
+                mint_url TEXT,
+                amount_sats INTEGER NOT NULL,
+                outcome TEXT NOT NULL,
+                rule_id TEXT,
+                reason TEXT NOT NULL,
+                status TEXT NOT NULL,
+                ledger_seq INTEGER
+            )",
+            [],
+        )?;
+        // Rows completed before payments were linked to the ledger get 0
+        if conn
+            .execute("ALTER TABLE spend_audit ADD COLUMN ledger_seq INTEGER", [])
+            .is_ok()
+        {
+            conn.execute(
+                "UPDATE spend_audit SET ledger_seq = 0 WHERE status = 'completed'",
+                [],
+            )?;
+        }
+        conn.execute(
+            "CREATE INDEX IF NOT EXISTS idx_spend_audit_timestamp ON spend_audit(timestamp)",
+            [],
+        )?;
+        Ok(store)
+    }
+
+    fn insert(
+        &self,
+        request: &SpendRequest,
+        decision: &PolicyDecision,
+        status: SpendStatus,
+    ) -> TollGateResult<i64> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "INSERT INTO spend_audit
+                (timestamp, origin, counterparty, mint_url, amount_sats, outcome, rule_id,
+                 reason, status)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                Utc::now().timestamp(),
+                request.origin.to_string(),
+                request.counterparty,
+                request.mint_url,
+                request.amount_sats as i64,
+                serde_json::to_string(&decision.outcome)?,
+                decision.rule_id,
+                decision.reason,
+                status.as_str(),
+            ],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    fn set_status(&self, id: i64, status: SpendStatus) -> TollGateResult<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "UPDATE spend_audit SET status = ?1 WHERE id = ?2",
+            params![status.as_str(), id],
+        )?;
+        Ok(())
+    }
+
+    /// Mark a payment settled, linking it to its ledger entry if it has one
+    fn set_completed(&self, id: i64, ledger_seq: Option<u64>) -> TollGateResult<()> {
+        let conn = Connection::open(&self.db_path)?;
+        conn.execute(
+            "UPDATE spend_audit SET status = ?1, ledger_seq = ?2 WHERE id = ?3",
+            params![
+                SpendStatus::Completed.as_str(),
+                ledger_seq.map(|seq| seq as i64),
+                id
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Id, amount and ledger entry of every settled payment
+    fn completed(&self) -> TollGateResult<Vec<(i64, u64, Option<u64>)>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT id, amount_sats, ledger_seq FROM spend_audit
+             WHERE status = 'completed' ORDER BY id ASC",
+        )?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, i64>(1)? as u64,
+                    row.get::<_, Option<i64>>(2)?.map(|seq| seq as u64),
+                ))
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    }
+
+    /// Amount spent within `scope` since `since`, counting pending payments
+    fn spent_since(&self, scope: &RuleMatch, since: DateTime<Utc>) -> TollGateResult<u64> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT origin, counterparty, mint_url, amount_sats FROM spend_audit
+             WHERE timestamp >= ?1 AND status IN ('pending', 'completed')",
+        )?;
+        let rows = stmt.query_map(params![since.timestamp()], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<String>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, i64>(3)?,
+            ))
+        })?;
+
+        let mut total = 0u64;
+        for row in rows {
+            let (origin, counterparty, mint_url, amount) = row?;
+            if scope.matches_scope(&origin, counterparty.as_deref(), mint_url.as_deref()) {
+                total = total.saturating_add(amount as u64);
+            }
+        }
+        Ok(total)
+    }
+
+    fn list(&self, limit: u32) -> TollGateResult<Vec<SpendAuditEntry>> {
+        let conn = Connection::open(&self.db_path)?;
+        let mut stmt = conn.prepare(
+            "SELECT id, timestamp, origin, counterparty, mint_url, amount_sats, outcome, rule_id,
+                    reason, status
+             FROM spend_audit ORDER BY id DESC LIMIT ?1",
+        )?;
+        let entries = stmt
+            .query_map(params![limit], Self::row_to_entry)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(entries)
+    }
+
+    fn row_to_entry(row: &Row) -> rusqlite::Result<SpendAuditEntry> {
+        let outcome: String = row.get(6)?;
+        let status: String = row.get(9)?;
+        Ok(SpendAuditEntry {
+            id: row.get(0)?,
+            timestamp: Utc
+                .timestamp_opt(row.get(1)?, 0)
+                .single()
+                .unwrap_or_default(),
+            origin: row.get(2)?,
+            counterparty: row.get(3)?,
+            mint_url: row.get(4)?,
+            amount_sats: row.get::<_, i64>(5)? as u64,
+            outcome: serde_json::from_str(&outcome).unwrap_or(PolicyOutcome::Deny),
+            rule_id: row.get(7)?,
+            reason: row.get(8)?,
+            status: SpendStatus::parse(&status),
+        })
+    }
+}
+
+/// Checks payments against the policy and keeps the audit trail
+pub struct SpendingPolicyEngine {
+    policy_path: PathBuf,
+    store: SpendAuditStore,
+    /// Held across evaluation and recording so concurrent payments see
+    /// each other in the window totals
+    policy: Mutex<SpendingPolicy>,
+    /// Why the stored policy could not be loaded, until one is saved
+    load_error: Mutex<Option<String>>,
+    approvals: ApprovalsState,
+    ledger: SpendLedger,
+}
+
+impl SpendingPolicyEngine {
+    /// `seed` keys the spend ledger's MAC chain
+    pub fn new(seed: &[u8], approvals: ApprovalsState) -> TollGateResult<Self> {
+        let base_dir = profiles::data_dir()?;
+        let ledger = SpendLedger::open(base_dir.join(LEDGER_DB), base_dir.join(LEDGER_HEAD), seed)?;
+        Self::open(
+            base_dir.join(POLICY_FILE),
+            base_dir.join(AUDIT_DB),
+            approvals,
+            ledger,
+        )
+    }
+
+    fn open(
+        policy_path: PathBuf,
+        db_path: PathBuf,
+        approvals: ApprovalsState,
+        ledger: SpendLedger,
+    ) -> TollGateResult<Self> {
+        let (policy, load_error) = match storage::read_json(&policy_path) {
+            Ok(policy) => (policy.unwrap_or_default(), None),
+            Err(e) => {
+                log::error!(
+                    "Failed to load spending policy, denying payments until it is saved: {}",
+                    e
+                );
+                let deny_all = SpendingPolicy {
+                    default_outcome: PolicyOutcome::Deny,
+                    rules: Vec::new(),
+                };
+                // The damaged file was moved aside; without a replacement the
+                // next start would load the permissive default
+                if e.kind() == std::io::ErrorKind::InvalidData {
+                    if let Err(e) =
+                        storage::write_atomic(&policy_path, serde_json::to_vec_pretty(&deny_all)?)
+                    {
+                        log::error!("Failed to write deny-all spending policy: {}", e);
+                    }
+                }
+                (deny_all, Some(e.to_string()))
+            }
+        };
+
+        Ok(Self {
+            policy_path,
+            store: SpendAuditStore::new(db_path)?,
+            policy: Mutex::new(policy),
+            load_error: Mutex::new(load_error),
+            approvals,
+            ledger,
+        })
+    }
+
+    pub fn policy(&self) -> SpendingPolicy {
+        self.policy
+            .lock()
+            .expect("spending policy poisoned")
+            .clone()
+    }
+
+    pub fn set_policy(&self, mut policy: SpendingPolicy) -> TollGateResult<SpendingPolicy> {
+        policy.validate()?;
+        storage::write_atomic(&self.policy_path, serde_json::to_vec_pretty(&policy)?)?;
+        *self.policy.lock().expect("spending policy poisoned") = policy.clone();
+        *self.load_error.lock().expect("spending policy poisoned") = None;
+        log::info!("Spending policy updated ({} rules)", policy.rules.len());
+        Ok(policy)
+    }
+
+    pub fn set_approval_threshold(
+        &self,
+        origin: &str,
+        threshold_sats: Option<u64>,
+    ) -> TollGateResult<SpendingPolicy> {
+        let mut policy = self.policy();
+        policy.set_approval_threshold(origin, threshold_sats);
+        self.set_policy(policy)
+    }
+
+    /// Check `request` against the policy and record the decision.
+    ///
+    /// `approved` is an explicit user confirmation, which only counts for
+    /// interactive callers. Automated callers that need approval wait here
+    /// until the user answers the prompt.
+    pub async fn authorize(
+        &self,
+        request: &SpendRequest,
+        approved: bool,
+    ) -> TollGateResult<SpendTicket> {
+        // The policy lock is released before waiting on the user
+        let (decision, status, audit_id) = {
+            let policy = self.policy.lock().expect("spending policy poisoned");
+            let now = Utc::now();
+            let mut decision = policy.evaluate(request, |rule, limit| {
+                let since = now - Duration::seconds(limit.window_secs.min(MAX_WINDOW_SECS) as i64);
+                self.store
+                    .spent_since(&rule.matches, since)
+                    .unwrap_or_else(|e| {
+                        // Fail closed: an unreadable history counts as exhausted
+                        log::error!("Failed to read spending history: {}", e);
+                        u64::MAX
+                    })
+            });
+            if let Some(error) = &*self.load_error.lock().expect("spending policy poisoned") {
+                if decision.rule_id.is_none() {
+                    decision.reason = format!("Spending policy could not be loaded: {}", error);
+                }
+            }
+
+            let status = match decision.outcome {
+                PolicyOutcome::Allow => SpendStatus::Pending,
+                PolicyOutcome::Ask if approved && request.origin.is_interactive() => {
+                    SpendStatus::Pending
+                }
+                PolicyOutcome::Ask if request.origin.is_interactive() => {
+                    SpendStatus::ApprovalRequired
+                }
+                PolicyOutcome::Ask => SpendStatus::AwaitingApproval,
+                PolicyOutcome::Deny => SpendStatus::Denied,
+            };
+            let audit_id = self.store.insert(request, &decision, status)?;
+            (decision, status, audit_id)
+        };
+
+        match status {
+            SpendStatus::Pending => Ok(SpendTicket {
+                audit_id,
+                request: request.clone(),
+            }),
+            SpendStatus::AwaitingApproval => {
+                let outcome = self.approvals.request(request, &decision.reason).await;
+                let (status, result) = match outcome {
+                    ApprovalOutcome::Approved => (
+                        SpendStatus::Pending,
+                        Ok(SpendTicket {
+                            audit_id,
+                            request: request.clone(),
+                        }),
+                    ),
+                    ApprovalOutcome::Rejected => (
+                        SpendStatus::Rejected,
+                        Err(TollGateError::SpendRejected(decision.reason)),
+                    ),
+                    ApprovalOutcome::TimedOut => (
+                        SpendStatus::Expired,
+                        Err(TollGateError::SpendApprovalTimedOut),
+                    ),
+                    ApprovalOutcome::Unavailable => (
+                        SpendStatus::ApprovalRequired,
+                        Err(TollGateError::SpendApprovalRequired(decision.reason)),
+                    ),
+                };
+                self.store.set_status(audit_id, status)?;
+                result
+            }
+            SpendStatus::ApprovalRequired => {
+                log::info!(
+                    "Spend of {} sats from {} needs approval: {}",
+                    request.amount_sats,
+                    request.origin,
+                    decision.reason
+                );
+                Err(TollGateError::SpendApprovalRequired(decision.reason))
+            }
+            _ => {
+                log::warn!(
+                    "Spend of {} sats from {} denied: {}",
+                    request.amount_sats,
+                    request.origin,
+                    decision.reason
+                );
+                Err(TollGateError::SpendDenied(decision.reason))
+            }
+        }
+    }
+
+    /// Record how an allowed payment ended. Failed payments no longer count
+    /// towards window limits; settled ones go into the spend ledger.
+    pub fn complete(&self, ticket: SpendTicket, success: bool) {
+        let result = if success {
+            let request = &ticket.request;
+            // A settled payment without a ledger entry is still recorded as
+            // completed, which `verify_ledger` reports
+            let ledger_seq = match self.ledger.append(
+                request.kind,
+                &request.origin,
+                request.counterparty.as_deref(),
+                request.mint_url.as_deref(),
+                request.amount_sats,
+            ) {
+                Ok(entry) => Some(entry.seq),
+                Err(e) => {
+                    log::error!(
+                        "Failed to append spend {} to ledger: {}",
+                        ticket.audit_id,
+                        e
+                    );
+                    None
+                }
+            };
+            self.store.set_completed(ticket.audit_id, ledger_seq)
+        } else {
+            self.store.set_status(ticket.audit_id, SpendStatus::Failed)
+        };
+        if let Err(e) = result {
+            log::error!("Failed to update spend audit {}: {}", ticket.audit_id, e);
+        }
+    }
+
+    /// Most recent ledger entries, newest first
+    pub fn ledger(&self, limit: u32) -> TollGateResult<Vec<LedgerEntry>> {
+        self.ledger.list(limit)
+    }
+
+    /// Verify the ledger chain and that every settled payment in the audit
+    /// trail has its ledger entry
+    pub fn verify_ledger(
+        &self,
+        anchor: Option<&LedgerAnchor>,
+    ) -> TollGateResult<LedgerVerification> {
+        let mut verification = self.ledger.verify(anchor)?;
+
+        let amounts: HashMap<u64, u64> = self
+            .ledger
+            .list(u32::MAX)?
+            .into_iter()
+            .map(|entry| (entry.seq, entry.amount_sats))
+            .collect();
+        for (audit_id, amount_sats, ledger_seq) in self.store.completed()? {
+            match ledger_seq {
+                // Settled before payments were linked to the ledger
+                Some(0) => {}
+                Some(seq) if amounts.get(&seq) == Some(&amount_sats) => {}
+                Some(seq) => verification.problems.push(format!(
+                    "Payment {} does not match ledger entry {}",
+                    audit_id, seq
+                )),
+                None => verification
+                    .problems
+                    .push(format!("Payment {} is missing from the ledger", audit_id)),
+            }
+        }
+        verification.valid = verification.problems.is_empty();
+
+        Ok(verification)
+    }
+
+    /// Most recent decisions, newest first
+    pub fn audit_log(&self, limit: u32) -> TollGateResult<Vec<SpendAuditEntry>> {
+        self.store.list(limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tollgate::approvals::{ApprovalBroker, ApprovalEvent};
+    use std::fs;
+    use std::sync::Arc;
+
+    const MINT: &str = "https://mint.example.com";
+
+    fn request(origin: Origin, amount_sats: u64) -> SpendRequest {
+        SpendRequest {
+            kind: SpendKind::Melt,
+            origin,
+            counterparty: Some("shop".to_string()),
+            amount_sats,
+            mint_url: Some(MINT.to_string()),
+        }
+    }
+
+    fn rule(name: &str, matches: RuleMatch, outcome: PolicyOutcome) -> SpendRule {
+        SpendRule {
+            id: name.to_string(),
+            name: name.to_string(),
+            enabled: true,
+            matches,
+            limit: None,
+            outcome,
+        }
+    }
+
+    /// Engine in a fresh directory, which the test removes when done
+    fn temp_engine() -> (SpendingPolicyEngine, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("wally-spending-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        (engine_in(&dir), dir)
+    }
+
+    fn engine_in(dir: &std::path::Path) -> SpendingPolicyEngine {
+        let ledger =
+            SpendLedger::open(dir.join(LEDGER_DB), dir.join(LEDGER_HEAD), &[7u8; 64]).unwrap();
+        SpendingPolicyEngine::open(
+            dir.join(POLICY_FILE),
+            dir.join(AUDIT_DB),
+            Arc::new(ApprovalBroker::default()),
+            ledger,
+        )
+        .unwrap()
+    }
+
+    fn nwc() -> Origin {
+        Origin::Nwc {
+            connection: "abc".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_strictest_rule_wins() {
+        let policy = SpendingPolicy {
+            default_outcome: PolicyOutcome::Allow,
+            rules: vec![
+                rule(
+                    "nwc",
+                    RuleMatch {
+                        origin: Some("nwc".to_string()),
+                        ..Default::default()
+                    },
+                    PolicyOutcome::Allow,
+                ),
+                rule(
+                    "big",
+                    RuleMatch {
+                        min_amount_sats: Some(1000),
+                        ..Default::default()
+                    },
+                    PolicyOutcome::Ask,
+                ),
+                rule(
+                    "mint",
+                    RuleMatch {
+                        mint_url: Some("https://MINT.example.com/".to_string()),
+                        origin: Some("nwc:abc".to_string()),
+                        min_amount_sats: Some(5000),
+                        ..Default::default()
+                    },
+                    PolicyOutcome::Deny,
+                ),
+            ],
+        };
+
+        let decide = |origin, amount| policy.evaluate(&request(origin, amount), |_, _| 0);
+        assert_eq!(decide(nwc(), 10).outcome, PolicyOutcome::Allow);
+        assert_eq!(decide(Origin::Ui, 2000).outcome, PolicyOutcome::Ask);
+        assert_eq!(decide(Origin::Ui, 6000).outcome, PolicyOutcome::Ask);
+        assert_eq!(decide(nwc(), 6000).outcome, PolicyOutcome::Deny);
+        assert_eq!(decide(nwc(), 6000).rule_id.as_deref(), Some("mint"));
+    }
+
+    #[test]
+    fn test_origin_prefix_requires_separator() {
+        let matches = RuleMatch {
+            origin: Some("nwc".to_string()),
+            ..Default::default()
+        };
+        assert!(matches.matches_origin("nwc"));
+        assert!(matches.matches_origin("nwc:abc"));
+        assert!(!matches.matches_origin("nwcx"));
+        assert!(!matches.matches_origin("ui"));
+    }
+
+    #[tokio::test]
+    async fn test_daily_cap_from_audit_trail() {
+        let (engine, dir) = temp_engine();
+        engine
+            .set_policy(SpendingPolicy {
+                default_outcome: PolicyOutcome::Allow,
+                rules: vec![SpendRule {
+                    limit: Some(SpendLimit {
+                        window_secs: 86_400,
+                        max_sats: 5000,
+                    }),
+                    ..rule("daily", RuleMatch::default(), PolicyOutcome::Deny)
+                }],
+            })
+            .unwrap();
+
+        let first = engine
+            .authorize(&request(Origin::Ui, 3000), false)
+            .await
+            .unwrap();
+        engine.complete(first, true);
+
+        // A failed payment does not use up the allowance
+        let failed = engine
+            .authorize(&request(nwc(), 1500), false)
+            .await
+            .unwrap();
+        engine.complete(failed, false);
+
+        let second = engine
+            .authorize(&request(nwc(), 2000), false)
+            .await
+            .unwrap();
+        assert!(matches!(
+            engine.authorize(&request(Origin::Proxy, 1), false).await,
+            Err(TollGateError::SpendDenied(_))
+        ));
+        engine.complete(second, true);
+
+        let log = engine.audit_log(10).unwrap();
+        assert_eq!(log.len(), 4);
+        assert_eq!(log[0].status, SpendStatus::Denied);
+        assert_eq!(log[2].status, SpendStatus::Failed);
+
+        // Only the settled payments reach the ledger
+        let ledger = engine.ledger(10).unwrap();
+        assert_eq!(ledger.len(), 2);
+        assert_eq!(ledger[0].amount_sats, 2000);
+        assert!(engine.verify_ledger(None).unwrap().valid);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_missing_ledger_entry_fails_verification() {
+        let (engine, dir) = temp_engine();
+
+        let paid = engine
+            .authorize(&request(Origin::Ui, 100), false)
+            .await
+            .unwrap();
+        engine.complete(paid, true);
+        assert!(engine.verify_ledger(None).unwrap().valid);
+
+        // The ledger refuses the next append
+        Connection::open(dir.join(LEDGER_DB))
+            .unwrap()
+            .execute_batch(
+                "CREATE TRIGGER spend_ledger_broken BEFORE INSERT ON spend_ledger
+                 BEGIN SELECT RAISE(ABORT, 'disk full'); END;",
+            )
+            .unwrap();
+        let unrecorded = engine
+            .authorize(&request(Origin::Ui, 200), false)
+            .await
+            .unwrap();
+        engine.complete(unrecorded, true);
+
+        assert_eq!(
+            engine.audit_log(1).unwrap()[0].status,
+            SpendStatus::Completed
+        );
+        let verification = engine.verify_ledger(None).unwrap();
+        assert!(!verification.valid);
+        assert_eq!(verification.problems.len(), 1);
+        assert!(verification.problems[0].contains("missing from the ledger"));
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_policy_denies_everything() {
+        let dir = std::env::temp_dir().join(format!("wally-spending-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(POLICY_FILE), b"{\"rules\": [").unwrap();
+
+        let engine = engine_in(&dir);
+        assert_eq!(engine.policy().default_outcome, PolicyOutcome::Deny);
+        match engine.authorize(&request(Origin::Ui, 1), true).await {
+            Err(TollGateError::SpendDenied(reason)) => {
+                assert!(reason.contains("could not be loaded"))
+            }
+            _ => panic!("payment was not denied"),
+        }
+
+        // The damaged file is gone, but the policy stays deny-all
+        drop(engine);
+        let engine = engine_in(&dir);
+        assert_eq!(engine.policy().default_outcome, PolicyOutcome::Deny);
+        assert!(matches!(
+            engine.authorize(&request(Origin::Ui, 1), true).await,
+            Err(TollGateError::SpendDenied(_))
+        ));
+
+        // Saving a policy replaces the damaged one
+        engine.set_policy(SpendingPolicy::default()).unwrap();
+        let ticket = engine
+            .authorize(&request(Origin::Ui, 1), false)
+            .await
+            .unwrap();
+        engine.complete(ticket, true);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_ask_needs_interactive_approval() {
+        let (engine, dir) = temp_engine();
+        engine
+            .set_policy(SpendingPolicy {
+                default_outcome: PolicyOutcome::Ask,
+                rules: Vec::new(),
+            })
+            .unwrap();
+
+        assert!(matches!(
+            engine.authorize(&request(Origin::Ui, 10), false).await,
+            Err(TollGateError::SpendApprovalRequired(_))
+        ));
+        assert!(matches!(
+            engine.authorize(&request(nwc(), 10), true).await,
+            Err(TollGateError::SpendApprovalRequired(_))
+        ));
+        let ticket = engine
+            .authorize(&request(Origin::Ui, 10), true)
+            .await
+            .unwrap();
+        engine.complete(ticket, true);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_approval_threshold_waits_for_user() {
+        let (engine, dir) = temp_engine();
+        engine.set_approval_threshold("nwc:abc", Some(100)).unwrap();
+        assert_eq!(engine.policy().approval_threshold("nwc:abc"), Some(100));
+
+        let ticket = engine.authorize(&request(nwc(), 100), false).await.unwrap();
+        engine.complete(ticket, true);
+
+        let mut events = engine.approvals.subscribe();
+        let approvals = engine.approvals.clone();
+        tokio::spawn(async move {
+            while let Ok(event) = events.recv().await {
+                if let ApprovalEvent::Requested(approval) = event {
+                    approvals.resolve(&approval.id, approval.amount_sats < 1000);
+                }
+            }
+        });
+
+        let ticket = engine.authorize(&request(nwc(), 500), false).await.unwrap();
+        engine.complete(ticket, true);
+        assert!(matches!(
+            engine.authorize(&request(nwc(), 5000), false).await,
+            Err(TollGateError::SpendRejected(_))
+        ));
+        assert_eq!(
+            engine.audit_log(1).unwrap()[0].status,
+            SpendStatus::Rejected
+        );
+
+        engine.set_approval_threshold("nwc:abc", None).unwrap();
+        assert!(engine.policy().rules.is_empty());
+
+        let _ = fs::remove_dir_all(dir);
+    }
+}
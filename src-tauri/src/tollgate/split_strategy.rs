@@ -0,0 +1,94 @@
+//! Per-mint denomination strategy
+//!
+//! Controls how amounts are split into proofs when minting, receiving and
+//! redeeming change, instead of always using the mint's default split.
+
+use crate::tollgate::token_pool::missing_denominations;
+use cdk::{amount::SplitTarget, Amount};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// How new proofs from a mint are split
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SplitStrategy {
+    /// Fewest possible proofs (power-of-two decomposition)
+    #[default]
+    Default,
+    /// Split into proofs of this value where possible
+    Value { amount: u64 },
+    /// Keep at least `counts[d]` proofs of denomination `d`; the rest is
+    /// split into as few proofs as possible
+    KeepDenominations { counts: BTreeMap<u64, u64> },
+}
+
+impl SplitStrategy {
+    /// Split target for `amount` new value, given the proofs already held
+    pub fn split_target(&self, amount: u64, current: &BTreeMap<u64, u64>) -> SplitTarget {
+        match self {
+            SplitStrategy::Default => SplitTarget::None,
+            SplitStrategy::Value { amount: value } if *value > 0 => {
+                SplitTarget::Value(Amount::from(*value))
+            }
+            SplitStrategy::Value { .. } => SplitTarget::None,
+            SplitStrategy::KeepDenominations { counts } => {
+                let mut remaining = amount;
+                let mut values = Vec::new();
+                for denomination in missing_denominations(counts, current) {
+                    if denomination <= remaining {
+                        remaining -= denomination;
+                        values.push(Amount::from(denomination));
+                    }
+                }
+
+                if values.is_empty() {
+                    SplitTarget::None
+                } else {
+                    SplitTarget::Values(values)
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keep_denominations_fills_missing_proofs() {
+        let strategy = SplitStrategy::KeepDenominations {
+            counts: BTreeMap::from([(1, 2), (8, 3)]),
+        };
+        let current = BTreeMap::from([(8, 1)]);
+
+        assert_eq!(
+            strategy.split_target(20, &current),
+            SplitTarget::Values(vec![
+                Amount::from(1),
+                Amount::from(1),
+                Amount::from(8),
+                Amount::from(8)
+            ])
+        );
+        // Only what fits into the amount is requested
+        assert_eq!(
+            strategy.split_target(9, &current),
+            SplitTarget::Values(vec![Amount::from(1), Amount::from(1)])
+        );
+    }
+
+    #[test]
+    fn test_satisfied_strategy_uses_fewest_proofs() {
+        let strategy = SplitStrategy::KeepDenominations {
+            counts: BTreeMap::from([(4, 1)]),
+        };
+        let current = BTreeMap::from([(4, 2)]);
+
+        assert_eq!(strategy.split_target(100, &current), SplitTarget::None);
+        assert_eq!(
+            SplitStrategy::Default.split_target(100, &current),
+            SplitTarget::None
+        );
+    }
+}
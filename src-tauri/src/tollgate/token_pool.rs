@@ -0,0 +1,305 @@
+//! Pre-split token pool
+//!
+//! Tracks which amounts we pay most often per mint and unit, and keeps
+//! enough proofs of the matching denominations in the wallet so those
+//! payments can be served with an offline exact send instead of a swap
+//! with the mint on the hot path.
+
+use crate::storage;
+use crate::tollgate::errors::TollGateResult;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Once the payment count for a mint exceeds this, all counts are halved so
+/// recent behaviour dominates
+const USAGE_DECAY_THRESHOLD: u64 = 1000;
+
+/// Token pool settings
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct TokenPoolConfig {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// How many payments of each frequent amount to keep ready
+    #[serde(default = "default_depth")]
+    pub depth: u64,
+    /// How many distinct amounts to pre-split for
+    #[serde(default = "default_max_amounts")]
+    pub max_amounts: usize,
+    /// Upper bound on the share of a mint balance held in pool denominations
+    #[serde(default = "default_max_reserved_fraction")]
+    pub max_reserved_fraction: f64,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_depth() -> u64 {
+    3
+}
+
+fn default_max_amounts() -> usize {
+    4
+}
+
+fn default_max_reserved_fraction() -> f64 {
+    0.5
+}
+
+impl Default for TokenPoolConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_enabled(),
+            depth: default_depth(),
+            max_amounts: default_max_amounts(),
+            max_reserved_fraction: default_max_reserved_fraction(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+struct StoredTokenPool {
+    #[serde(default)]
+    config: TokenPoolConfig,
+    /// Payment counts keyed by `pool_key`, then by amount
+    #[serde(default)]
+    usage: BTreeMap<String, BTreeMap<u64, u64>>,
+}
+
+/// Snapshot of the pool for one mint and unit
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenPoolStatus {
+    pub mint_url: String,
+    pub unit: String,
+    /// Most frequent payment amounts and how often they were paid
+    pub frequent_amounts: Vec<(u64, u64)>,
+    /// Target number of proofs per denomination
+    pub target_denominations: BTreeMap<u64, u64>,
+    /// Number of unspent proofs per denomination
+    pub current_denominations: BTreeMap<u64, u64>,
+}
+
+/// Usage statistics and configuration for the token pool
+///
+/// Payment usage is only kept in memory until the next `flush`, so paying
+/// never waits on the disk.
+#[derive(Debug)]
+pub struct TokenPool {
+    path: PathBuf,
+    state: Mutex<StoredTokenPool>,
+    /// Usage changed since the last save
+    dirty: AtomicBool,
+}
+
+fn pool_key(mint_url: &str, unit: &str) -> String {
+    format!("{}|{}", mint_url, unit)
+}
+
+/// Split an amount into the power-of-two denominations a mint issues
+pub fn denominations(amount: u64) -> Vec<u64> {
+    (0..64)
+        .map(|bit| 1u64 << bit)
+        .filter(|denomination| amount & denomination != 0)
+        .collect()
+}
+
+/// Count proofs per denomination
+pub fn count_denominations(amounts: impl IntoIterator<Item = u64>) -> BTreeMap<u64, u64> {
+    let mut counts = BTreeMap::new();
+    for amount in amounts {
+        *counts.entry(amount).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Denominations needed so that each amount can be paid `depth` times
+pub fn target_denominations(amounts: &[u64], depth: u64) -> BTreeMap<u64, u64> {
+    let mut targets = BTreeMap::new();
+    for amount in amounts {
+        for denomination in denominations(*amount) {
+            *targets.entry(denomination).or_insert(0) += depth;
+        }
+    }
+    targets
+}
+
+/// Proofs missing from `current` to reach `targets`, as a list of amounts
+pub fn missing_denominations(
+    targets: &BTreeMap<u64, u64>,
+    current: &BTreeMap<u64, u64>,
+) -> Vec<u64> {
+    let mut missing = Vec::new();
+    for (denomination, target) in targets {
+        let have = current.get(denomination).copied().unwrap_or(0);
+        for _ in have..*target {
+            missing.push(*denomination);
+        }
+    }
+    missing
+}
+
+impl TokenPool {
+    /// Load pool state from `path`, starting empty if the file does not exist
+    /// or cannot be parsed. An unparsable file is moved aside.
+    pub fn load(path: PathBuf) -> TollGateResult<Self> {
+        let state = match storage::read_json(&path) {
+            Ok(state) => state.unwrap_or_default(),
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                log::warn!("Starting with an empty token pool: {}", e);
+                StoredTokenPool::default()
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(Self {
+            path,
+            state: Mutex::new(state),
+            dirty: AtomicBool::new(false),
+        })
+    }
+
+    fn save(&self, state: &StoredTokenPool) -> TollGateResult<()> {
+        storage::write_atomic(&self.path, serde_json::to_vec_pretty(state)?)?;
+        Ok(())
+    }
+
+    pub fn config(&self) -> TokenPoolConfig {
+        self.state.lock().expect("token pool poisoned").config.clone()
+    }
+
+    pub fn set_config(&self, config: TokenPoolConfig) -> TollGateResult<()> {
+        let mut state = self.state.lock().expect("token pool poisoned");
+        state.config = config;
+        self.save(&state)?;
+        self.dirty.store(false, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Write recorded usage to disk if it changed since the last save
+    pub fn flush(&self) -> TollGateResult<()> {
+        if !self.dirty.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        let state = self.state.lock().expect("token pool poisoned").clone();
+        let result = self.save(&state);
+        if result.is_err() {
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+        result
+    }
+
+    /// Record an outgoing payment of `amount` from `mint_url`. The change is
+    /// persisted by the next `flush`.
+    pub fn record_payment(&self, mint_url: &str, unit: &str, amount: u64) {
+        let mut state = self.state.lock().expect("token pool poisoned");
+        let usage = state.usage.entry(pool_key(mint_url, unit)).or_default();
+        *usage.entry(amount).or_insert(0) += 1;
+
+        if usage.values().sum::<u64>() > USAGE_DECAY_THRESHOLD {
+            usage.values_mut().for_each(|count| *count /= 2);
+            usage.retain(|_, count| *count > 0);
+        }
+
+        self.dirty.store(true, Ordering::SeqCst);
+    }
+
+    /// Most frequently paid amounts for a mint, most frequent first
+    pub fn frequent_amounts(&self, mint_url: &str, unit: &str) -> Vec<(u64, u64)> {
+        let state = self.state.lock().expect("token pool poisoned");
+        let mut amounts: Vec<(u64, u64)> = state
+            .usage
+            .get(&pool_key(mint_url, unit))
+            .map(|usage| usage.iter().map(|(a, c)| (*a, *c)).collect())
+            .unwrap_or_default();
+        amounts.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        amounts.truncate(state.config.max_amounts);
+        amounts
+    }
+
+    /// Target denominations for a mint, limited by the reserved fraction of
+    /// `balance`. The least frequent amounts are dropped first.
+    pub fn targets(&self, mint_url: &str, unit: &str, balance: u64) -> BTreeMap<u64, u64> {
+        let config = self.config();
+        let budget = (balance as f64 * config.max_reserved_fraction) as u64;
+        let mut amounts: Vec<u64> = self
+            .frequent_amounts(mint_url, unit)
+            .into_iter()
+            .map(|(amount, _)| amount)
+            .collect();
+
+        while !amounts.is_empty() {
+            let targets = target_denominations(&amounts, config.depth);
+            let reserved: u64 = targets.iter().map(|(d, n)| d * n).sum();
+            if reserved <= budget {
+                return targets;
+            }
+            amounts.pop();
+        }
+
+        BTreeMap::new()
+    }
+}
+
+impl Drop for TokenPool {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            log::warn!("Failed to persist token pool usage: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_denominations() {
+        assert_eq!(denominations(0), Vec::<u64>::new());
+        assert_eq!(denominations(21), vec![1, 4, 16]);
+        assert_eq!(denominations(64), vec![64]);
+    }
+
+    #[test]
+    fn test_missing_denominations() {
+        let targets = target_denominations(&[5, 4], 2);
+        assert_eq!(targets, BTreeMap::from([(1, 2), (4, 4)]));
+
+        let current = count_denominations([4, 4, 4, 1, 8]);
+        assert_eq!(missing_denominations(&targets, &current), vec![1, 4]);
+    }
+
+    #[test]
+    fn test_targets_respect_budget() {
+        let path =
+            std::env::temp_dir().join(format!("token-pool-test-{}.json", uuid::Uuid::new_v4()));
+        let pool = TokenPool::load(path.clone()).unwrap();
+        let mint = "https://mint.example.com";
+
+        for _ in 0..5 {
+            pool.record_payment(mint, "sat", 10);
+        }
+        pool.record_payment(mint, "sat", 100);
+
+        assert_eq!(pool.frequent_amounts(mint, "sat")[0], (10, 5));
+
+        // 3 x 10 + 3 x 100 = 330 needs a balance of at least 660
+        assert_eq!(pool.targets(mint, "sat", 1000).values().sum::<u64>(), 15);
+        // Only the 10 sat amount fits into half of 100
+        assert_eq!(pool.targets(mint, "sat", 100), BTreeMap::from([(2, 3), (8, 3)]));
+        assert!(pool.targets(mint, "sat", 10).is_empty());
+
+        // Usage is only written on flush
+        assert!(!path.exists());
+        pool.flush().unwrap();
+        let reloaded = TokenPool::load(path.clone()).unwrap();
+        assert_eq!(reloaded.frequent_amounts(mint, "sat")[0], (10, 5));
+
+        let _ = fs::remove_file(path);
+    }
+}
@@ -6,25 +6,37 @@
 //! - Balance management
 //! - Payment token generation
 
+use crate::profiles;
+use crate::storage;
 use crate::tollgate::errors::{TollGateError, TollGateResult};
+use crate::tollgate::mint_policy::{MintTrustDecision, MintTrustPolicy, UntrustedMintAction};
+use crate::tollgate::origin::Origin;
+use crate::tollgate::pending_tokens::{
+    PendingSwap, PendingToken, PendingTokenStatus, PendingTokenStore, SwapQuote,
+};
 use crate::tollgate::protocol::PricingOption;
+use crate::tollgate::split_strategy::SplitStrategy;
+use crate::tollgate::token_pool::{
+    count_denominations, missing_denominations, TokenPool, TokenPoolConfig, TokenPoolStatus,
+};
 use bip39::{Language, Mnemonic};
+use cdk::cdk_database::WalletDatabase;
 use cdk::mint_url::MintUrl;
 use cdk::nuts::nut18::payment_request::PaymentRequest;
-use cdk::nuts::CurrencyUnit;
+use cdk::nuts::{CurrencyUnit, Proofs, State};
 use cdk::wallet::{
     types::{Transaction, TransactionDirection},
-    MintQuote, SendOptions, Wallet,
+    MeltQuote, MintQuote, SendKind, SendOptions, Wallet,
 };
 use cdk::{amount::SplitTarget, Amount};
 use cdk_sqlite::wallet::WalletSqliteDatabase;
-use directories::ProjectDirs;
+use nostr::nips::nip06::FromMnemonic;
 use nostr::prelude::{Keys, SecretKey, ToBech32};
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
@@ -35,6 +47,10 @@ pub struct TollGateWallet {
     default_mint: Option<String>,
     storage: WalletStoragePaths,
     secrets: WalletSecrets,
+    trust_policy: MintTrustPolicy,
+    pending_tokens: PendingTokenStore,
+    token_pool: TokenPool,
+    split_strategies: HashMap<String, SplitStrategy>,
 }
 
 /// Payment token information
@@ -75,11 +91,37 @@ pub struct Bolt11PaymentResult {
     pub preimage: Option<String>,
 }
 
+/// How a received cashu token ended up in the wallet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CashuReceiveStatus {
+    /// Token was redeemed at its own mint
+    Received,
+    /// Token came from an untrusted mint and was melted into the default mint
+    SwappedToDefault,
+    /// Token was validated offline and is waiting to be redeemed
+    Pending,
+}
+
 /// Result of receiving a cashu token
 #[derive(Debug, Clone, Serialize)]
 pub struct CashuReceiveResult {
     pub amount: u64,
     pub mint_url: String,
+    pub status: CashuReceiveStatus,
+    /// Mint the token was issued by, when it differs from `mint_url`
+    pub source_mint_url: Option<String>,
+    /// Pending token id when the token was accepted offline
+    pub pending_id: Option<String>,
+}
+
+/// Result of consolidating the proofs of one mint
+#[derive(Debug, Clone, Serialize)]
+pub struct ConsolidationResult {
+    pub mint_url: String,
+    pub proofs_before: usize,
+    pub proofs_after: usize,
+    pub fee_paid: u64,
 }
 
 /// Result of paying a NUT18 payment request
@@ -96,6 +138,8 @@ pub struct WalletSummary {
     pub default_mint: Option<String>,
     pub balances: Vec<WalletBalance>,
     pub npub: Option<String>,
+    /// npub of the pre-NIP-06 key, while it is still in use
+    pub legacy_npub: Option<String>,
 }
 
 /// Flattened transaction entry suitable for the frontend
@@ -134,30 +178,26 @@ struct WalletStoragePaths {
     secrets_file: PathBuf,
     wallets_dir: PathBuf,
     mints_file: PathBuf,
+    pending_tokens_file: PathBuf,
+    token_pool_file: PathBuf,
 }
 
 impl WalletStoragePaths {
     fn new() -> TollGateResult<Self> {
-        let project_dirs = ProjectDirs::from("com", "Tollgate", "TollgateApp")
-            .ok_or_else(|| TollGateError::wallet("Unable to determine wallet storage directory"))?;
-
-        let base_dir = project_dirs.data_dir().to_path_buf();
-        let wallets_dir = base_dir.join("wallets");
+        let storage = Self::in_dir(&profiles::data_dir()?);
+        fs::create_dir_all(&storage.wallets_dir)?;
+        Ok(storage)
+    }
 
-        if let Some(parent) = base_dir.parent() {
-            fs::create_dir_all(parent)?;
+    /// Paths of the wallet stored in `base_dir`, without creating anything
+    fn in_dir(base_dir: &Path) -> Self {
+        Self {
+            secrets_file: base_dir.join("wallet-secrets.json"),
+            wallets_dir: base_dir.join("wallets"),
+            mints_file: base_dir.join("mints.json"),
+            pending_tokens_file: base_dir.join("pending-tokens.json"),
+            token_pool_file: base_dir.join("token-pool.json"),
         }
-        fs::create_dir_all(&base_dir)?;
-        fs::create_dir_all(&wallets_dir)?;
-
-        let secrets_file = base_dir.join("wallet-secrets.json");
-        let mints_file = base_dir.join("mints.json");
-
-        Ok(Self {
-            secrets_file,
-            wallets_dir,
-            mints_file,
-        })
     }
 
     fn mint_db_path(&self, mint_url: &str) -> TollGateResult<PathBuf> {
@@ -177,29 +217,132 @@ impl WalletStoragePaths {
     }
 }
 
+/// Funds still held by a wallet that is not open
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoredHoldings {
+    /// Proofs that are not known to be spent, summed over all mints and units
+    pub balance: u64,
+    /// Pending tokens that were not redeemed
+    pub pending_tokens: usize,
+    /// Pending-token files that could not be read and may hold tokens
+    pub damaged_files: usize,
+}
+
+impl StoredHoldings {
+    pub fn is_empty(&self) -> bool {
+        self.balance == 0 && self.pending_tokens == 0 && self.damaged_files == 0
+    }
+}
+
+/// Read what the wallet stored in `base_dir` still holds, straight from its
+/// mint databases and pending-token store
+pub async fn stored_holdings(base_dir: &Path) -> TollGateResult<StoredHoldings> {
+    let storage = WalletStoragePaths::in_dir(base_dir);
+    let mut holdings = StoredHoldings::default();
+
+    if storage.wallets_dir.exists() {
+        for entry in fs::read_dir(&storage.wallets_dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("sqlite") {
+                continue;
+            }
+            let localstore = WalletSqliteDatabase::new(path.as_path())
+                .await
+                .map_err(|e| {
+                    TollGateError::wallet(format!(
+                        "Failed to open wallet database {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })?;
+            let proofs = localstore
+                .get_proofs(
+                    None,
+                    None,
+                    Some(vec![
+                        State::Unspent,
+                        State::Pending,
+                        State::Reserved,
+                        State::PendingSpent,
+                    ]),
+                    None,
+                )
+                .await
+                .map_err(|e| {
+                    TollGateError::wallet(format!(
+                        "Failed to read proofs from {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })?;
+            holdings.balance += proofs
+                .iter()
+                .map(|info| u64::from(info.proof.amount))
+                .sum::<u64>();
+        }
+    }
+
+    let pending_tokens = PendingTokenStore::load(storage.pending_tokens_file)?;
+    holdings.pending_tokens = pending_tokens
+        .list()
+        .iter()
+        .filter(|token| token.status != PendingTokenStatus::Redeemed)
+        .count();
+    holdings.damaged_files = pending_tokens.damaged_files().len();
+
+    Ok(holdings)
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 struct StoredSecrets {
     mnemonic: Option<String>,
+    /// NIP-06 account index of the Nostr identity
+    #[serde(default)]
+    nostr_account: u32,
+    /// Keep the pre-NIP-06 key (`sha256(seed)`) for existing NWC connections
+    /// and TollGate history. Missing in secrets files written before NIP-06,
+    /// which therefore keep it.
+    #[serde(default = "default_keep_legacy_nostr_key")]
+    keep_legacy_nostr_key: bool,
+}
+
+fn default_keep_legacy_nostr_key() -> bool {
+    true
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 struct StoredMints {
     mints: Vec<String>,
     default_mint: Option<String>,
+    #[serde(default)]
+    trust_policy: MintTrustPolicy,
+    #[serde(default)]
+    split_strategies: HashMap<String, SplitStrategy>,
 }
 
 #[derive(Debug, Clone)]
 pub struct WalletSecrets {
     wallet_seed: [u8; 64],
+    /// NIP-06 identity (m/44'/1237'/account'/0/0)
     nostr_keys: Keys,
+    /// Key derived as `sha256(seed)` by earlier versions, kept until retired
+    legacy_nostr_keys: Option<Keys>,
+    stored: StoredSecrets,
 }
 
 impl WalletSecrets {
     fn load_or_create(paths: &WalletStoragePaths) -> TollGateResult<Self> {
         if paths.secrets_file.exists() {
             let data = fs::read(&paths.secrets_file)?;
-            let stored: StoredSecrets = serde_json::from_slice(&data)?;
-            Self::from_stored(stored)
+            let on_disk: serde_json::Value = serde_json::from_slice(&data)?;
+            let stored: StoredSecrets = serde_json::from_value(on_disk.clone())?;
+            let secrets = Self::from_stored(stored)?;
+            // Record the migration flag explicitly so it survives rewrites,
+            // without rewriting the only copy of the seed when nothing changed
+            if serde_json::to_value(&secrets.stored)? != on_disk {
+                secrets.persist(paths)?;
+            }
+            Ok(secrets)
         } else {
             // TODO(security): store mnemonic in platform secure storage rather than plaintext file.
             Self::generate_and_persist(paths)
@@ -209,44 +352,47 @@ impl WalletSecrets {
     fn generate_and_persist(paths: &WalletStoragePaths) -> TollGateResult<Self> {
         let mnemonic = Mnemonic::generate_in(Language::English, 12)
             .map_err(|e| TollGateError::wallet(format!("Failed to generate mnemonic: {}", e)))?;
-        let phrase = mnemonic.to_string();
-        let wallet_seed = mnemonic.to_seed("");
 
-        let nostr_keys = derive_nostr_keys_from_seed(&wallet_seed)?;
-        let secrets = Self {
-            wallet_seed,
-            nostr_keys,
-        };
-
-        let stored = StoredSecrets {
-            mnemonic: Some(phrase),
-        };
-
-        if let Some(parent) = paths.secrets_file.parent() {
-            fs::create_dir_all(parent)?;
-        }
-        fs::write(&paths.secrets_file, serde_json::to_vec_pretty(&stored)?)?;
+        // New wallets never had a legacy key
+        let secrets = Self::from_stored(StoredSecrets {
+            mnemonic: Some(mnemonic.to_string()),
+            nostr_account: 0,
+            keep_legacy_nostr_key: false,
+        })?;
+        secrets.persist(paths)?;
 
         Ok(secrets)
     }
 
-    fn from_stored(stored: StoredSecrets) -> TollGateResult<Self> {
-        if let Some(phrase) = stored.mnemonic {
-            Self::from_mnemonic(phrase)
-        } else {
-            Err(TollGateError::wallet("Wallet secrets file is empty"))
-        }
+    fn persist(&self, paths: &WalletStoragePaths) -> TollGateResult<()> {
+        storage::write_atomic(
+            &paths.secrets_file,
+            serde_json::to_vec_pretty(&self.stored)?,
+        )?;
+        Ok(())
     }
 
-    fn from_mnemonic(phrase: String) -> TollGateResult<Self> {
+    fn from_stored(stored: StoredSecrets) -> TollGateResult<Self> {
+        let Some(phrase) = stored.mnemonic.as_deref() else {
+            return Err(TollGateError::wallet("Wallet secrets file is empty"));
+        };
+
         let mnemonic = Mnemonic::parse_in(Language::English, phrase.trim())
             .map_err(|e| TollGateError::wallet(format!("Invalid mnemonic: {}", e)))?;
         let wallet_seed = mnemonic.to_seed("");
 
-        let nostr_keys = derive_nostr_keys_from_seed(&wallet_seed)?;
+        let nostr_keys = derive_nip06_keys(&mnemonic, stored.nostr_account)?;
+        let legacy_nostr_keys = if stored.keep_legacy_nostr_key {
+            Some(derive_legacy_nostr_keys(&wallet_seed)?)
+        } else {
+            None
+        };
+
         Ok(Self {
             wallet_seed,
             nostr_keys,
+            legacy_nostr_keys,
+            stored,
         })
     }
 
@@ -255,14 +401,63 @@ impl WalletSecrets {
     }
 
     pub(crate) fn nostr_npub(&self) -> TollGateResult<String> {
-        self.nostr_keys
-            .public_key()
-            .to_bech32()
-            .map_err(|e| TollGateError::wallet(format!("Failed to encode npub: {}", e)))
+        encode_npub(&self.nostr_keys)
+    }
+
+    pub(crate) fn legacy_nostr_npub(&self) -> Option<String> {
+        self.legacy_nostr_keys
+            .as_ref()
+            .and_then(|keys| encode_npub(keys).ok())
+    }
+
+    /// Key the NWC service listens on. Existing connection URIs embed the
+    /// legacy key, so it stays in use until it is retired.
+    fn nwc_service_keys(&self) -> &Keys {
+        self.legacy_nostr_keys.as_ref().unwrap_or(&self.nostr_keys)
+    }
+
+    fn retire_legacy_nostr_key(&mut self, paths: &WalletStoragePaths) -> TollGateResult<()> {
+        self.stored.keep_legacy_nostr_key = false;
+        self.legacy_nostr_keys = None;
+        self.persist(paths)
     }
 }
 
-fn derive_nostr_keys_from_seed(seed: &[u8; 64]) -> TollGateResult<Keys> {
+/// Replace the stored wallet secrets with `phrase`. The wallet must be
+/// reloaded afterwards.
+pub(crate) fn write_secrets_from_mnemonic(
+    phrase: &str,
+    keep_legacy_nostr_key: bool,
+) -> TollGateResult<()> {
+    let paths = WalletStoragePaths::new()?;
+    let secrets = WalletSecrets::from_stored(StoredSecrets {
+        mnemonic: Some(phrase.trim().to_string()),
+        nostr_account: 0,
+        keep_legacy_nostr_key,
+    })?;
+    secrets.persist(&paths)
+}
+
+/// Check that `data` is a usable wallet secrets file
+pub(crate) fn validate_secrets_file(data: &[u8]) -> TollGateResult<()> {
+    let stored: StoredSecrets = serde_json::from_slice(data)?;
+    WalletSecrets::from_stored(stored).map(|_| ())
+}
+
+fn encode_npub(keys: &Keys) -> TollGateResult<String> {
+    keys.public_key()
+        .to_bech32()
+        .map_err(|e| TollGateError::wallet(format!("Failed to encode npub: {}", e)))
+}
+
+/// NIP-06 key derivation, so the mnemonic yields the same npub in other clients
+fn derive_nip06_keys(mnemonic: &Mnemonic, account: u32) -> TollGateResult<Keys> {
+    Keys::from_mnemonic_advanced(mnemonic.to_string(), None, Some(account), Some(0), Some(0))
+        .map_err(|e| TollGateError::wallet(format!("Failed to derive nostr key: {}", e)))
+}
+
+/// Key derivation used before NIP-06
+fn derive_legacy_nostr_keys(seed: &[u8; 64]) -> TollGateResult<Keys> {
     let hash = Sha256::digest(seed);
     let secret_key = SecretKey::from_slice(hash.as_slice())
         .map_err(|e| TollGateError::wallet(format!("Failed to derive nostr key: {}", e)))?;
@@ -276,7 +471,7 @@ async fn discover_mint_keysets(mint_url: &str) -> TollGateResult<Vec<KeysetInfo>
 
     let response = client
         .get(&keys_url)
-        .timeout(Duration::from_secs(10))
+        .timeout(crate::config::current().timeouts.request())
         .send()
         .await
         .map_err(|e| {
@@ -329,6 +524,48 @@ async fn discover_mint_keysets(mint_url: &str) -> TollGateResult<Vec<KeysetInfo>
     Ok(keysets)
 }
 
+/// Error for a failed request to `wallet`'s mint, counted in the metrics
+fn mint_request_error(wallet: &Wallet, operation: &str, message: String) -> TollGateError {
+    crate::metrics::MINT_REQUEST_ERRORS.inc(&[&wallet.mint_url.to_string(), operation]);
+    TollGateError::Wallet(message)
+}
+
+/// Error for a failed receive at `wallet`'s mint. Tokens the mint will
+/// refuse on every attempt are reported as `TokenRejected`.
+fn receive_error(wallet: &Wallet, error: cdk::Error) -> TollGateError {
+    let message = format!("Failed to receive token: {}", error);
+    match error {
+        cdk::Error::TokenAlreadySpent
+        | cdk::Error::UnknownKeySet
+        | cdk::Error::CouldNotVerifyDleq
+        | cdk::Error::IncorrectMint
+        | cdk::Error::UnsupportedUnit => TollGateError::TokenRejected(message),
+        _ => mint_request_error(wallet, "receive", message),
+    }
+}
+
+/// Whether a failed receive will fail again no matter how often it is retried
+fn is_permanent_receive_error(error: &TollGateError) -> bool {
+    matches!(
+        error,
+        TollGateError::UntrustedMint(_)
+            | TollGateError::MintApprovalRequired(_)
+            | TollGateError::TokenRejected(_)
+    )
+}
+
+/// Split `proofs` into those the token pool keeps, up to the target count of
+/// each denomination, and the rest
+fn partition_pool_proofs(proofs: Proofs, targets: &BTreeMap<u64, u64>) -> (Proofs, Proofs) {
+    let mut kept = BTreeMap::new();
+    proofs.into_iter().partition(|proof| {
+        let amount = u64::from(proof.amount);
+        let count = kept.entry(amount).or_insert(0u64);
+        *count += 1;
+        *count <= targets.get(&amount).copied().unwrap_or(0)
+    })
+}
+
 /// Select the best currency unit from available keysets
 fn select_currency_unit(keysets: &[KeysetInfo]) -> CurrencyUnit {
     // FIXME: This is a simple implementation that prefers 'msat' over 'sat'.
@@ -358,12 +595,18 @@ impl TollGateWallet {
     pub fn new() -> TollGateResult<Self> {
         let storage = WalletStoragePaths::new()?;
         let secrets = WalletSecrets::load_or_create(&storage)?;
+        let pending_tokens = PendingTokenStore::load(storage.pending_tokens_file.clone())?;
+        let token_pool = TokenPool::load(storage.token_pool_file.clone())?;
 
         Ok(Self {
             wallets: HashMap::new(),
             default_mint: None,
             storage,
             secrets,
+            trust_policy: MintTrustPolicy::default(),
+            pending_tokens,
+            token_pool,
+            split_strategies: HashMap::new(),
         })
     }
 
@@ -383,6 +626,8 @@ impl TollGateWallet {
         let stored = StoredMints {
             mints: self.wallets.keys().cloned().collect(),
             default_mint: self.default_mint.clone(),
+            trust_policy: self.trust_policy.clone(),
+            split_strategies: self.split_strategies.clone(),
         };
 
         if let Some(parent) = self.storage.mints_file.parent() {
@@ -398,6 +643,8 @@ impl TollGateWallet {
     /// Load existing mints from storage on startup
     pub async fn load_existing_mints(&mut self) -> TollGateResult<()> {
         let stored_mints = self.load_mints_config()?;
+        self.trust_policy = stored_mints.trust_policy;
+        self.split_strategies = stored_mints.split_strategies;
 
         for mint_url in stored_mints.mints {
             if !self.wallets.contains_key(&mint_url) {
@@ -448,6 +695,18 @@ impl TollGateWallet {
         self.get_wallet_by_url(default_mint)
     }
 
+    /// Mint a BOLT11 invoice is paid from
+    pub fn bolt11_payment_mint(&self) -> Option<String> {
+        self.default_mint.clone()
+    }
+
+    /// Mint a NUT-18 payment request would be paid from
+    pub fn payment_request_mint(&self, request: &PaymentRequest) -> Option<String> {
+        self.wallet_for_payment_request(request)
+            .ok()
+            .map(|wallet| wallet.mint_url.to_string())
+    }
+
     /// Internal method to add a mint without persisting config
     async fn add_mint_internal(&mut self, mint_url: &str) -> TollGateResult<()> {
         if self.wallets.contains_key(mint_url) {
@@ -505,6 +764,114 @@ impl TollGateWallet {
         Ok(())
     }
 
+    /// Current mint trust policy
+    pub fn trust_policy(&self) -> &MintTrustPolicy {
+        &self.trust_policy
+    }
+
+    /// Replace the mint trust policy and persist it
+    pub fn set_trust_policy(&mut self, policy: MintTrustPolicy) -> TollGateResult<()> {
+        self.trust_policy = policy;
+        self.save_mints_config()
+    }
+
+    /// Split strategy configured for a mint
+    pub fn split_strategy(&self, mint_url: &str) -> SplitStrategy {
+        self.split_strategies
+            .get(mint_url)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// Set the split strategy for a mint and persist it
+    pub fn set_split_strategy(
+        &mut self,
+        mint_url: &str,
+        strategy: SplitStrategy,
+    ) -> TollGateResult<()> {
+        if strategy == SplitStrategy::Default {
+            self.split_strategies.remove(mint_url);
+        } else {
+            self.split_strategies.insert(mint_url.to_string(), strategy);
+        }
+        self.save_mints_config()
+    }
+
+    /// Split target for `amount` of new value at `mint_url`, based on the
+    /// mint's strategy and the proofs already held there
+    pub async fn split_target_for(&self, mint_url: &str, amount: u64) -> SplitTarget {
+        let strategy = self.split_strategy(mint_url);
+        if strategy == SplitStrategy::Default {
+            return SplitTarget::None;
+        }
+
+        let current = match self.wallets.get(mint_url) {
+            Some(wallet) => Self::proof_amounts(wallet)
+                .await
+                .map(count_denominations)
+                .unwrap_or_default(),
+            None => BTreeMap::new(),
+        };
+        strategy.split_target(amount, &current)
+    }
+
+    /// Swap many small proofs at a mint into as few proofs as the mint's
+    /// split strategy allows, reducing input fees and token size
+    pub async fn consolidate_proofs(&self, mint_url: &str) -> TollGateResult<ConsolidationResult> {
+        let wallet = self.get_wallet_by_url(mint_url)?;
+        let proofs = wallet
+            .get_unspent_proofs()
+            .await
+            .map_err(|e| TollGateError::wallet(format!("Failed to get proofs: {}", e)))?;
+        let proofs_before = proofs.len();
+        let balance: u64 = proofs.iter().map(|proof| u64::from(proof.amount)).sum();
+
+        // Pre-split proofs stay as they are, or the pool would be emptied
+        let targets = if self.token_pool.config().enabled {
+            self.token_pool
+                .targets(mint_url, &wallet.unit.to_string(), balance)
+        } else {
+            BTreeMap::new()
+        };
+        let (pooled, proofs) = partition_pool_proofs(proofs, &targets);
+        let total: u64 = proofs.iter().map(|proof| u64::from(proof.amount)).sum();
+
+        if proofs.len() <= total.count_ones() as usize {
+            return Ok(ConsolidationResult {
+                mint_url: mint_url.to_string(),
+                proofs_before,
+                proofs_after: proofs_before,
+                fee_paid: 0,
+            });
+        }
+
+        // Only the pooled proofs are still held once the rest are swapped
+        let held = count_denominations(pooled.iter().map(|proof| u64::from(proof.amount)));
+        let split = self.split_strategy(mint_url).split_target(total, &held);
+        wallet
+            .swap(None, split, proofs, None, false)
+            .await
+            .map_err(|e| TollGateError::wallet(format!("Failed to consolidate proofs: {}", e)))?;
+
+        let remaining = Self::proof_amounts(wallet).await?;
+        let remaining_total: u64 = remaining.iter().sum();
+
+        log::info!(
+            "Consolidated {} proofs into {} at mint {}, keeping {} in the token pool",
+            proofs_before,
+            remaining.len(),
+            mint_url,
+            pooled.len()
+        );
+
+        Ok(ConsolidationResult {
+            mint_url: mint_url.to_string(),
+            proofs_before,
+            proofs_after: remaining.len(),
+            fee_paid: balance.saturating_sub(remaining_total),
+        })
+    }
+
     /// Set the default mint
     pub async fn set_default_mint(&mut self, mint_url: &str) -> TollGateResult<()> {
         if !self.wallets.contains_key(mint_url) {
@@ -567,6 +934,36 @@ impl TollGateWallet {
         self.secrets.nostr_keys.clone()
     }
 
+    /// Seed of the wallet, for keys derived outside the wallet
+    pub(crate) fn wallet_seed(&self) -> [u8; 64] {
+        self.secrets.wallet_seed()
+    }
+
+    /// Configured mints and the default mint
+    pub fn mint_settings(&self) -> (Vec<String>, Option<String>) {
+        let mut mints: Vec<String> = self.wallets.keys().cloned().collect();
+        mints.sort();
+        (mints, self.default_mint.clone())
+    }
+
+    /// Pre-NIP-06 keys, while they are still kept
+    pub fn legacy_keys(&self) -> Option<nostr::Keys> {
+        self.secrets.legacy_nostr_keys.clone()
+    }
+
+    /// Keys for the NWC service
+    pub fn nwc_service_keys(&self) -> nostr::Keys {
+        self.secrets.nwc_service_keys().clone()
+    }
+
+    /// Stop using the pre-NIP-06 key. NWC connections created with it stop
+    /// working once the NWC service restarts with the NIP-06 key.
+    pub fn retire_legacy_nostr_key(&mut self) -> TollGateResult<()> {
+        self.secrets.retire_legacy_nostr_key(&self.storage)?;
+        log::info!("Retired legacy nostr key");
+        Ok(())
+    }
+
     /// Get balance for a specific mint
     pub async fn get_balance(&self, mint_url: &str) -> TollGateResult<u64> {
         let wallet = self
@@ -602,6 +999,16 @@ impl TollGateWallet {
         Ok(balances)
     }
 
+    /// Publish the total balance after it changed
+    pub async fn publish_balance(&self) {
+        match self.get_all_balances().await {
+            Ok(balances) => crate::events::publish(crate::events::BackendEvent::BalanceChanged {
+                total_sats: balances.iter().map(|b| b.balance).sum(),
+            }),
+            Err(e) => log::warn!("Failed to read balance for event: {}", e),
+        }
+    }
+
     /// Summarize balances and metadata for UI consumption
     pub async fn summary(&self) -> TollGateResult<WalletSummary> {
         let balances = self.get_all_balances().await?;
@@ -613,6 +1020,7 @@ impl TollGateWallet {
             default_mint: self.default_mint.clone(),
             balances,
             npub,
+            legacy_npub: self.secrets.legacy_nostr_npub(),
         })
     }
 
@@ -677,7 +1085,13 @@ impl TollGateWallet {
         let quote = wallet
             .mint_quote(Amount::from(amount), description.clone())
             .await
-            .map_err(|e| TollGateError::wallet(format!("Failed to request mint quote: {}", e)))?;
+            .map_err(|e| {
+                mint_request_error(
+                    wallet,
+                    "mint_quote",
+                    format!("Failed to request mint quote: {}", e),
+                )
+            })?;
 
         Ok(Bolt11InvoiceInfo {
             quote_id: quote.id.clone(),
@@ -794,12 +1208,17 @@ impl TollGateWallet {
         let quote = wallet
             .melt_quote(invoice.to_string(), None)
             .await
-            .map_err(|e| TollGateError::wallet(format!("Failed to request melt quote: {}", e)))?;
+            .map_err(|e| {
+                mint_request_error(
+                    wallet,
+                    "melt_quote",
+                    format!("Failed to request melt quote: {}", e),
+                )
+            })?;
 
-        let melted = wallet
-            .melt(&quote.id)
-            .await
-            .map_err(|e| TollGateError::wallet(format!("Failed to pay invoice: {}", e)))?;
+        let melted = wallet.melt(&quote.id).await.map_err(|e| {
+            mint_request_error(wallet, "melt", format!("Failed to pay invoice: {}", e))
+        })?;
         let amount: u64 = melted.amount.into();
         let fee_paid: u64 = melted.fee_paid.into();
 
@@ -810,11 +1229,21 @@ impl TollGateWallet {
         })
     }
 
-    /// Receive a cashu token and add it to the wallet
-    pub async fn receive_cashu_token(&mut self, token: &str) -> TollGateResult<CashuReceiveResult> {
+    /// Receive a cashu token, applying the mint trust policy.
+    ///
+    /// Tokens from trusted mints are redeemed at their own mint (adding it if
+    /// needed). Tokens from untrusted mints are rejected or melted into the
+    /// default mint, depending on the policy. `approve_mint` records an explicit
+    /// user confirmation for an unknown mint in `Ask` mode.
+    pub async fn receive_cashu_token(
+        &mut self,
+        token: &str,
+        origin: &Origin,
+        approve_mint: bool,
+    ) -> TollGateResult<CashuReceiveResult> {
         // Parse the token to determine which mint it belongs to
         let cashu_token = cdk::nuts::Token::from_str(token)
-            .map_err(|e| TollGateError::wallet(format!("Invalid cashu token: {}", e)))?;
+            .map_err(|e| TollGateError::TokenRejected(format!("Invalid cashu token: {}", e)))?;
 
         // Get the mint URL from the token
         let mint_url = cashu_token
@@ -822,9 +1251,34 @@ impl TollGateWallet {
             .map_err(|e| TollGateError::wallet(format!("Failed to get mint URL: {}", e)))?
             .to_string();
 
+        let known = self.wallets.contains_key(&mint_url);
+        match self
+            .trust_policy
+            .evaluate(&mint_url, known, approve_mint, origin)
+        {
+            MintTrustDecision::Trusted => {}
+            MintTrustDecision::NeedsApproval => {
+                return Err(TollGateError::MintApprovalRequired(mint_url));
+            }
+            MintTrustDecision::Untrusted => {
+                log::warn!(
+                    "Token from untrusted mint {} received via {}",
+                    mint_url,
+                    origin
+                );
+                return match self.trust_policy.untrusted_action {
+                    UntrustedMintAction::Reject => Err(TollGateError::UntrustedMint(mint_url)),
+                    UntrustedMintAction::SwapToDefault => {
+                        self.swap_token_to_default_mint(token, &mint_url, origin)
+                            .await
+                    }
+                };
+            }
+        }
+
         // Check if we have a wallet for this mint, if not add it automatically
         // Note: add_mint() now persists the config automatically
-        if !self.wallets.contains_key(&mint_url) {
+        if !known {
             log::info!("Mint {} not found, adding it automatically", mint_url);
             self.add_mint(&mint_url).await?;
         }
@@ -834,11 +1288,16 @@ impl TollGateWallet {
             TollGateError::wallet(format!("Failed to create wallet for mint: {}", mint_url))
         })?;
 
-        // Receive the token with default options
+        // Receive the token using the mint's split strategy
+        let token_value: u64 = cashu_token.value().map(u64::from).unwrap_or(0);
+        let receive_options = cdk::wallet::ReceiveOptions {
+            amount_split_target: self.split_target_for(&mint_url, token_value).await,
+            ..Default::default()
+        };
         let received_amount = wallet
-            .receive(token, cdk::wallet::ReceiveOptions::default())
+            .receive(token, receive_options)
             .await
-            .map_err(|e| TollGateError::wallet(format!("Failed to receive token: {}", e)))?;
+            .map_err(|e| receive_error(wallet, e))?;
 
         // Convert amount to u64
         let total_amount: u64 = received_amount.into();
@@ -851,9 +1310,507 @@ impl TollGateWallet {
         Ok(CashuReceiveResult {
             amount: total_amount,
             mint_url,
+            status: CashuReceiveStatus::Received,
+            source_mint_url: None,
+            pending_id: None,
+        })
+    }
+
+    /// Redeem a token at an untrusted mint and melt the value into the default
+    /// mint over Lightning. The source mint is never added to the mint list.
+    /// The token is kept in the pending store before any mint is contacted and
+    /// the mint quote is recorded before the melt, so a swap that stops
+    /// halfway is finished by `redeem_pending_tokens` instead of being lost.
+    async fn swap_token_to_default_mint(
+        &mut self,
+        token: &str,
+        source_mint_url: &str,
+        origin: &Origin,
+    ) -> TollGateResult<CashuReceiveResult> {
+        // Retried from the pending store, which records the outcome itself
+        if let Some(pending) = self.pending_tokens.find_token(token) {
+            return self.finish_swap(&pending).await;
+        }
+
+        let cashu_token = cdk::nuts::Token::from_str(token)
+            .map_err(|e| TollGateError::wallet(format!("Invalid cashu token: {}", e)))?;
+        let pending = PendingToken {
+            id: uuid::Uuid::new_v4().to_string(),
+            token: token.to_string(),
+            amount: cashu_token.value().map(u64::from).unwrap_or(0),
+            unit: cashu_token.unit().unwrap_or(CurrencyUnit::Sat).to_string(),
+            mint_url: source_mint_url.to_string(),
+            origin: origin.clone(),
+            mint_approved: false,
+            dleq_verified: false,
+            status: PendingTokenStatus::Pending,
+            received_at: chrono::Utc::now(),
+            attempts: 0,
+            last_attempt_at: None,
+            last_error: None,
+            redeemed_at: None,
+            swap: None,
+        };
+        self.pending_tokens.insert(pending.clone())?;
+
+        match self.finish_swap(&pending).await {
+            Ok(result) => {
+                self.pending_tokens.mark_redeemed(&pending.id)?;
+                Ok(result)
+            }
+            Err(e) => {
+                let taken = self
+                    .pending_tokens
+                    .get(&pending.id)
+                    .is_some_and(|entry| entry.swap.is_some());
+                if !taken {
+                    // The untrusted mint still honours the token, so hand the
+                    // error back with the token untouched
+                    self.pending_tokens.remove(&pending.id)?;
+                    return Err(e);
+                }
+
+                log::warn!(
+                    "Swap of token {} from {} stopped halfway, finishing it later: {}",
+                    pending.id,
+                    source_mint_url,
+                    e
+                );
+                self.pending_tokens
+                    .mark_failed(&pending.id, e.to_string(), false)?;
+                Ok(CashuReceiveResult {
+                    amount: pending.amount,
+                    mint_url: self.default_mint_url()?.clone(),
+                    status: CashuReceiveStatus::Pending,
+                    source_mint_url: Some(source_mint_url.to_string()),
+                    pending_id: Some(pending.id),
+                })
+            }
+        }
+    }
+
+    /// Take a pending token from an untrusted mint through the rest of its
+    /// swap into the default mint, recording each step in the pending store
+    async fn finish_swap(&mut self, pending: &PendingToken) -> TollGateResult<CashuReceiveResult> {
+        let source_mint_url = pending.mint_url.as_str();
+        let source_wallet = self.detached_wallet(source_mint_url).await?;
+
+        let mut swap = match &pending.swap {
+            Some(swap) => swap.clone(),
+            None => {
+                let received: u64 = source_wallet
+                    .receive(&pending.token, cdk::wallet::ReceiveOptions::default())
+                    .await
+                    .map_err(|e| receive_error(&source_wallet, e))?
+                    .into();
+                let swap = PendingSwap {
+                    received,
+                    quote: None,
+                };
+                self.pending_tokens.set_swap(&pending.id, swap.clone())?;
+                swap
+            }
+        };
+
+        let (quote, melt_quote) = match swap.quote.clone() {
+            Some(quote) => (quote, None),
+            None => {
+                let (quote, melt_quote) = self
+                    .request_swap_quotes(&source_wallet, source_mint_url, swap.received)
+                    .await?;
+                swap.quote = Some(quote.clone());
+                self.pending_tokens.set_swap(&pending.id, swap)?;
+                (quote, Some(melt_quote))
+            }
+        };
+
+        let target_wallet = self.wallets.get(&quote.mint_url).ok_or_else(|| {
+            TollGateError::wallet(format!(
+                "Mint {} of swap quote {} is no longer configured",
+                quote.mint_url, quote.quote_id
+            ))
+        })?;
+
+        let mut state = match melt_quote {
+            Some(_) => cdk::nuts::MintQuoteState::Unpaid,
+            None => {
+                target_wallet
+                    .mint_quote_state(&quote.quote_id)
+                    .await
+                    .map_err(|e| {
+                        mint_request_error(
+                            target_wallet,
+                            "mint_quote_state",
+                            format!("Failed to check mint quote: {}", e),
+                        )
+                    })?
+                    .state
+            }
+        };
+
+        if state == cdk::nuts::MintQuoteState::Unpaid {
+            let melt_quote = match melt_quote {
+                Some(melt_quote) => melt_quote,
+                None => source_wallet
+                    .melt_quote(quote.request.clone(), None)
+                    .await
+                    .map_err(|e| {
+                        mint_request_error(
+                            &source_wallet,
+                            "melt_quote",
+                            format!("Failed to request melt quote: {}", e),
+                        )
+                    })?,
+            };
+            source_wallet.melt(&melt_quote.id).await.map_err(|e| {
+                mint_request_error(
+                    &source_wallet,
+                    "melt",
+                    format!("Failed to melt token: {}", e),
+                )
+            })?;
+        }
+
+        // The mint may take a moment to see the incoming payment
+        for _ in 0..10 {
+            if state != cdk::nuts::MintQuoteState::Paid {
+                state = target_wallet
+                    .mint_quote_state(&quote.quote_id)
+                    .await
+                    .map_err(|e| {
+                        mint_request_error(
+                            target_wallet,
+                            "mint_quote_state",
+                            format!("Failed to check mint quote: {}", e),
+                        )
+                    })?
+                    .state;
+            }
+            if state == cdk::nuts::MintQuoteState::Paid {
+                let split = self.split_target_for(&quote.mint_url, quote.amount).await;
+                target_wallet
+                    .mint(&quote.quote_id, split, None)
+                    .await
+                    .map_err(|e| {
+                        mint_request_error(
+                            target_wallet,
+                            "mint",
+                            format!("Failed to mint tokens: {}", e),
+                        )
+                    })?;
+                state = cdk::nuts::MintQuoteState::Issued;
+            }
+            if state == cdk::nuts::MintQuoteState::Issued {
+                log::info!(
+                    "Swapped {} sats from untrusted mint {} into {}",
+                    quote.amount,
+                    source_mint_url,
+                    quote.mint_url
+                );
+                return Ok(CashuReceiveResult {
+                    amount: quote.amount,
+                    mint_url: quote.mint_url,
+                    status: CashuReceiveStatus::SwappedToDefault,
+                    source_mint_url: Some(source_mint_url.to_string()),
+                    pending_id: Some(pending.id.clone()),
+                });
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+
+        Err(TollGateError::wallet(format!(
+            "Melt succeeded but quote {} at {} is not paid yet",
+            quote.quote_id, quote.mint_url
+        )))
+    }
+
+    /// Request a mint quote at the default mint and a melt quote paying it
+    /// from `source_wallet`. The invoice is shrunk until amount plus the
+    /// source mint's fee reserve fits into `received`.
+    async fn request_swap_quotes(
+        &self,
+        source_wallet: &Wallet,
+        source_mint_url: &str,
+        received: u64,
+    ) -> TollGateResult<(SwapQuote, MeltQuote)> {
+        let default_mint_url = self.default_mint_url()?.clone();
+        let target_wallet = self.default_wallet()?;
+
+        let mut amount = received;
+        for _ in 0..3 {
+            if amount == 0 {
+                break;
+            }
+            let mint_quote = target_wallet
+                .mint_quote(
+                    Amount::from(amount),
+                    Some("Swap from untrusted mint".into()),
+                )
+                .await
+                .map_err(|e| {
+                    mint_request_error(
+                        target_wallet,
+                        "mint_quote",
+                        format!("Failed to request mint quote: {}", e),
+                    )
+                })?;
+            let melt_quote = source_wallet
+                .melt_quote(mint_quote.request.clone(), None)
+                .await
+                .map_err(|e| {
+                    mint_request_error(
+                        source_wallet,
+                        "melt_quote",
+                        format!("Failed to request melt quote: {}", e),
+                    )
+                })?;
+            let fee_reserve: u64 = melt_quote.fee_reserve.into();
+            if amount + fee_reserve <= received {
+                let quote = SwapQuote {
+                    mint_url: default_mint_url,
+                    quote_id: mint_quote.id,
+                    request: mint_quote.request,
+                    amount,
+                };
+                return Ok((quote, melt_quote));
+            }
+            amount = received.saturating_sub(fee_reserve);
+        }
+
+        Err(TollGateError::wallet(format!(
+            "Token of {} sats from {} is too small to cover Lightning fees",
+            received, source_mint_url
+        )))
+    }
+
+    /// Wallet for a mint that is not in the mint list, on the same database
+    /// the mint would use if it were added
+    async fn detached_wallet(&self, mint_url: &str) -> TollGateResult<Wallet> {
+        let keysets = discover_mint_keysets(mint_url).await?;
+        let currency_unit = select_currency_unit(&keysets);
+        let db_path = self.storage.mint_db_path(mint_url)?;
+        let localstore = WalletSqliteDatabase::new(db_path).await.map_err(|e| {
+            TollGateError::wallet(format!(
+                "Failed to open wallet database for mint {}: {}",
+                mint_url, e
+            ))
+        })?;
+        Wallet::new(
+            mint_url,
+            currency_unit,
+            Arc::new(localstore),
+            self.secrets.wallet_seed(),
+            None,
+        )
+        .map_err(|e| {
+            TollGateError::wallet(format!(
+                "Failed to create wallet for mint {}: {}",
+                mint_url, e
+            ))
         })
     }
 
+    /// Accept a cashu token without contacting its mint.
+    ///
+    /// The token is parsed, checked against the mint trust policy and, when
+    /// the mint's keys are cached, its DLEQ proofs are verified. It is then
+    /// stored as pending and redeemed later by `redeem_pending_tokens`.
+    pub async fn accept_token_offline(
+        &mut self,
+        token: &str,
+        origin: &Origin,
+        approve_mint: bool,
+    ) -> TollGateResult<CashuReceiveResult> {
+        let cashu_token = cdk::nuts::Token::from_str(token)
+            .map_err(|e| TollGateError::wallet(format!("Invalid cashu token: {}", e)))?;
+
+        let mint_url = cashu_token
+            .mint_url()
+            .map_err(|e| TollGateError::wallet(format!("Failed to get mint URL: {}", e)))?
+            .to_string();
+
+        let amount: u64 = cashu_token
+            .value()
+            .map_err(|e| TollGateError::wallet(format!("Invalid token amount: {}", e)))?
+            .into();
+        if amount == 0 {
+            return Err(TollGateError::wallet("Token has no value"));
+        }
+
+        if self.pending_tokens.contains_token(token) {
+            return Err(TollGateError::wallet("Token is already pending redemption"));
+        }
+
+        let unit = cashu_token
+            .unit()
+            .unwrap_or(CurrencyUnit::Sat)
+            .to_string();
+
+        let known = self.wallets.contains_key(&mint_url);
+        match self
+            .trust_policy
+            .evaluate(&mint_url, known, approve_mint, origin)
+        {
+            MintTrustDecision::Trusted => {}
+            MintTrustDecision::NeedsApproval => {
+                return Err(TollGateError::MintApprovalRequired(mint_url));
+            }
+            MintTrustDecision::Untrusted => {
+                // Swapping to the default mint needs Lightning, so only defer it
+                if self.trust_policy.untrusted_action == UntrustedMintAction::Reject {
+                    return Err(TollGateError::UntrustedMint(mint_url));
+                }
+            }
+        }
+
+        // Keys of configured mints are cached locally, so DLEQ can be checked offline
+        let dleq_verified = match self.wallets.get(&mint_url) {
+            Some(wallet) => {
+                wallet.verify_token_dleq(&cashu_token).await.map_err(|e| {
+                    TollGateError::wallet(format!("Token DLEQ verification failed: {}", e))
+                })?;
+                true
+            }
+            None => false,
+        };
+
+        let pending = PendingToken {
+            id: uuid::Uuid::new_v4().to_string(),
+            token: token.to_string(),
+            amount,
+            unit,
+            mint_url: mint_url.clone(),
+            origin: origin.clone(),
+            mint_approved: approve_mint,
+            dleq_verified,
+            status: PendingTokenStatus::Pending,
+            received_at: chrono::Utc::now(),
+            attempts: 0,
+            last_attempt_at: None,
+            last_error: None,
+            redeemed_at: None,
+            swap: None,
+        };
+        let pending_id = pending.id.clone();
+        self.pending_tokens.insert(pending)?;
+
+        log::info!(
+            "Accepted {} sats offline from mint {} (dleq verified: {})",
+            amount,
+            mint_url,
+            dleq_verified
+        );
+
+        Ok(CashuReceiveResult {
+            amount,
+            mint_url,
+            status: CashuReceiveStatus::Pending,
+            source_mint_url: None,
+            pending_id: Some(pending_id),
+        })
+    }
+
+    /// Try to redeem all pending tokens whose retry delay has elapsed.
+    /// Returns the number of tokens redeemed.
+    pub async fn redeem_pending_tokens(&mut self) -> TollGateResult<usize> {
+        let mut redeemed = 0;
+
+        for pending in self.pending_tokens.due(chrono::Utc::now()) {
+            let result = if pending.swap.is_some() {
+                // The untrusted mint already took this token
+                self.finish_swap(&pending).await
+            } else {
+                self.receive_cashu_token(&pending.token, &pending.origin, pending.mint_approved)
+                    .await
+            };
+            match result {
+                Ok(result) => {
+                    log::info!(
+                        "Redeemed pending token {} ({} sats into {})",
+                        pending.id,
+                        result.amount,
+                        result.mint_url
+                    );
+                    self.pending_tokens.mark_redeemed(&pending.id)?;
+                    redeemed += 1;
+                }
+                Err(e) => {
+                    let permanent = is_permanent_receive_error(&e);
+                    log::warn!(
+                        "Failed to redeem pending token {} (permanent: {}): {}",
+                        pending.id,
+                        permanent,
+                        e
+                    );
+                    self.pending_tokens
+                        .mark_failed(&pending.id, e.to_string(), permanent)?;
+                }
+            }
+        }
+
+        Ok(redeemed)
+    }
+
+    /// List tokens accepted offline, including redeemed and failed ones
+    pub fn list_pending_tokens(&self) -> Vec<PendingToken> {
+        self.pending_tokens.list()
+    }
+
+    /// Pending-token files that could not be read, to recover by hand
+    pub fn damaged_pending_token_files(&self) -> Vec<String> {
+        self.pending_tokens
+            .damaged_files()
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect()
+    }
+
+    /// Schedule a pending or failed token for another redemption attempt
+    pub fn retry_pending_token(&mut self, id: &str) -> TollGateResult<()> {
+        if self.pending_tokens.retry(id)? {
+            Ok(())
+        } else {
+            Err(TollGateError::wallet(format!("Pending token not found: {}", id)))
+        }
+    }
+
+    /// Remove a token from the pending list and return it so it is not lost
+    pub fn discard_pending_token(&mut self, id: &str) -> TollGateResult<PendingToken> {
+        self.pending_tokens
+            .remove(id)?
+            .ok_or_else(|| TollGateError::wallet(format!("Pending token not found: {}", id)))
+    }
+
+    /// Keep an outgoing token that was paid for but not delivered, so it can
+    /// still be handed over. Returns its id in the pending list.
+    pub fn keep_undelivered_token(
+        &mut self,
+        token: &PaymentToken,
+        origin: &Origin,
+        error: String,
+    ) -> TollGateResult<String> {
+        let pending = PendingToken {
+            id: uuid::Uuid::new_v4().to_string(),
+            token: token.token.clone(),
+            amount: token.amount,
+            unit: token.unit.clone(),
+            mint_url: token.mint_url.clone(),
+            origin: origin.clone(),
+            mint_approved: false,
+            dleq_verified: false,
+            status: PendingTokenStatus::Undelivered,
+            received_at: chrono::Utc::now(),
+            attempts: 1,
+            last_attempt_at: Some(chrono::Utc::now()),
+            last_error: Some(error),
+            redeemed_at: None,
+            swap: None,
+        };
+        let id = pending.id.clone();
+        self.pending_tokens.insert(pending)?;
+        Ok(id)
+    }
+
     /// List transactions across all configured mints
     pub async fn list_transactions(
         &self,
@@ -879,7 +1836,9 @@ impl TollGateWallet {
         pricing_option: &PricingOption,
         steps: u64,
     ) -> TollGateResult<bool> {
-        let required_amount = pricing_option.price_per_step * steps;
+        let Some(required_amount) = pricing_option.cost(steps) else {
+            return Ok(false);
+        };
         let balance = self.get_balance(&pricing_option.mint_url).await?;
 
         Ok(balance >= required_amount)
@@ -891,9 +1850,11 @@ impl TollGateWallet {
         pricing_option: &PricingOption,
         steps: u64,
     ) -> TollGateResult<PaymentToken> {
-        let amount = pricing_option.price_per_step * steps;
+        let amount = pricing_option
+            .cost(steps)
+            .ok_or_else(|| TollGateError::wallet("Price is too large"))?;
 
-        if amount < pricing_option.min_steps * pricing_option.price_per_step {
+        if steps < pricing_option.min_steps {
             return Err(TollGateError::wallet(format!(
                 "Amount {} is below minimum {} steps",
                 steps, pricing_option.min_steps
@@ -918,16 +1879,7 @@ impl TollGateWallet {
         }
 
         // Create the payment token
-        let prepared_send = wallet
-            .prepare_send(Amount::from(amount), SendOptions::default())
-            .await
-            .map_err(|e| {
-                TollGateError::wallet(format!("Failed to prepare payment token: {}", e))
-            })?;
-
-        let token = prepared_send.confirm(None).await.map_err(|e| {
-            TollGateError::wallet(format!("Failed to confirm payment token: {}", e))
-        })?;
+        let token = self.send_exact(wallet, amount).await?;
 
         Ok(PaymentToken {
             token: token.to_string(),
@@ -937,37 +1889,36 @@ impl TollGateWallet {
         })
     }
 
+    /// First mint holding at least `amount_sats`
+    pub async fn select_mint_for_amount(&self, amount_sats: u64) -> TollGateResult<String> {
+        let summary = self.summary().await?;
+        if summary.balances.is_empty() {
+            return Err(TollGateError::wallet("No mints configured".to_string()));
+        }
+
+        if let Some(balance) = summary
+            .balances
+            .iter()
+            .find(|balance| balance.balance >= amount_sats)
+        {
+            return Ok(balance.mint_url.clone());
+        }
+
+        let total_balance: u64 = summary.balances.iter().map(|b| b.balance).sum();
+        Err(TollGateError::wallet(format!(
+            "Insufficient balance: {} sats available across all mints, {} sats requested",
+            total_balance, amount_sats
+        )))
+    }
+
     pub async fn create_external_token(
         &self,
         amount_sats: u64,
         mint_url: Option<String>,
     ) -> TollGateResult<String> {
-        let target_mint = if let Some(mint) = mint_url {
-            mint
-        } else {
-            let summary = self.summary().await?;
-            if summary.balances.is_empty() {
-                return Err(TollGateError::wallet("No mints configured".to_string()));
-            }
-
-            let mut selected_mint = None;
-            for wallet_balance in &summary.balances {
-                if wallet_balance.balance >= amount_sats {
-                    selected_mint = Some(wallet_balance.mint_url.clone());
-                    break;
-                }
-            }
-
-            match selected_mint {
-                Some(mint) => mint,
-                None => {
-                    let total_balance: u64 = summary.balances.iter().map(|b| b.balance).sum();
-                    return Err(TollGateError::wallet(format!(
-                        "Insufficient balance: {} sats available across all mints, {} sats requested",
-                        total_balance, amount_sats
-                    )));
-                }
-            }
+        let target_mint = match mint_url {
+            Some(mint) => mint,
+            None => self.select_mint_for_amount(amount_sats).await?,
         };
 
         let balance = self.get_balance(&target_mint).await?;
@@ -982,25 +1933,207 @@ impl TollGateWallet {
             TollGateError::wallet(format!("Wallet not found for mint: {}", target_mint))
         })?;
 
+        let token = self.send_exact(wallet, amount_sats).await?;
+
+        log::info!(
+            "Created external token: {} sats from mint {}",
+            amount_sats,
+            target_mint
+        );
+        Ok(token.to_string())
+    }
+
+    /// Create a token locked to a nostr pubkey (NUT-11 P2PK).
+    ///
+    /// Nostr keys are x-only, so the lock uses the even-parity compressed key,
+    /// which is what nostr signers derive from the same secret.
+    pub async fn create_p2pk_token(
+        &self,
+        amount_sats: u64,
+        mint_url: Option<&str>,
+        recipient: &nostr::PublicKey,
+    ) -> TollGateResult<PaymentToken> {
+        let wallet = match mint_url {
+            Some(mint_url) => self.get_wallet_by_url(mint_url)?,
+            None => self.default_wallet()?,
+        };
+
+        let pubkey = cdk::nuts::PublicKey::from_hex(format!("02{}", recipient.to_hex()))
+            .map_err(|e| TollGateError::wallet(format!("Invalid recipient pubkey: {}", e)))?;
+        let options = SendOptions {
+            conditions: Some(cdk::nuts::SpendingConditions::new_p2pk(pubkey, None)),
+            ..Default::default()
+        };
+
         let prepared_send = wallet
-            .prepare_send(
-                cdk::Amount::from(amount_sats),
-                cdk::wallet::SendOptions::default(),
-            )
+            .prepare_send(Amount::from(amount_sats), options)
             .await
             .map_err(|e| TollGateError::wallet(format!("Failed to prepare token: {}", e)))?;
-
         let token = prepared_send
             .confirm(None)
             .await
             .map_err(|e| TollGateError::wallet(format!("Failed to create token: {}", e)))?;
 
-        log::info!(
-            "Created external token: {} sats from mint {}",
-            amount_sats,
-            target_mint
-        );
-        Ok(token.to_string())
+        Ok(PaymentToken {
+            token: token.to_string(),
+            amount: amount_sats,
+            mint_url: wallet.mint_url.to_string(),
+            unit: wallet.unit.to_string(),
+        })
+    }
+
+    /// Create a token of exactly `amount`, preferring pooled proofs so no swap
+    /// with the mint is needed. Falls back to an online send when the pool
+    /// cannot cover the amount.
+    async fn send_exact(&self, wallet: &Wallet, amount: u64) -> TollGateResult<cdk::nuts::Token> {
+        let mint_url = wallet.mint_url.to_string();
+        let unit = wallet.unit.to_string();
+        self.token_pool.record_payment(&mint_url, &unit, amount);
+
+        if self.token_pool.config().enabled {
+            let offline_options = SendOptions {
+                send_kind: SendKind::OfflineExact,
+                ..Default::default()
+            };
+            match wallet
+                .prepare_send(Amount::from(amount), offline_options)
+                .await
+            {
+                Ok(prepared_send) => {
+                    let token = prepared_send.confirm(None).await.map_err(|e| {
+                        TollGateError::wallet(format!("Failed to confirm payment token: {}", e))
+                    })?;
+                    log::debug!("Served {} from token pool at {}", amount, mint_url);
+                    return Ok(token);
+                }
+                Err(e) => {
+                    log::debug!(
+                        "Token pool miss for {} at {}, swapping online: {}",
+                        amount,
+                        mint_url,
+                        e
+                    );
+                }
+            }
+        }
+
+        let prepared_send = wallet
+            .prepare_send(Amount::from(amount), SendOptions::default())
+            .await
+            .map_err(|e| {
+                TollGateError::wallet(format!("Failed to prepare payment token: {}", e))
+            })?;
+
+        prepared_send.confirm(None).await.map_err(|e| {
+            TollGateError::wallet(format!("Failed to confirm payment token: {}", e))
+        })
+    }
+
+    /// Unspent proof amounts of a wallet
+    async fn proof_amounts(wallet: &Wallet) -> TollGateResult<Vec<u64>> {
+        let proofs = wallet
+            .get_unspent_proofs()
+            .await
+            .map_err(|e| TollGateError::wallet(format!("Failed to get proofs: {}", e)))?;
+        Ok(proofs.iter().map(|proof| u64::from(proof.amount)).collect())
+    }
+
+    /// Swap surplus proofs into the denominations the token pool is missing.
+    /// Returns the number of mints that were refilled.
+    pub async fn refill_token_pool(&self) -> TollGateResult<usize> {
+        if !self.token_pool.config().enabled {
+            return Ok(0);
+        }
+
+        let mut refilled = 0;
+        for (mint_url, wallet) in &self.wallets {
+            let unit = wallet.unit.to_string();
+            let proofs = wallet
+                .get_unspent_proofs()
+                .await
+                .map_err(|e| TollGateError::wallet(format!("Failed to get proofs: {}", e)))?;
+            let balance: u64 = proofs.iter().map(|proof| u64::from(proof.amount)).sum();
+
+            let targets = self.token_pool.targets(mint_url, &unit, balance);
+            let current = count_denominations(proofs.iter().map(|p| u64::from(p.amount)));
+            let missing = missing_denominations(&targets, &current);
+            if missing.is_empty() {
+                continue;
+            }
+            let missing_total: u64 = missing.iter().sum();
+
+            // Spend proofs the pool does not need, largest first
+            let (_, mut surplus) = partition_pool_proofs(proofs, &targets);
+            surplus.sort_by(|a, b| b.amount.cmp(&a.amount));
+
+            let mut inputs = Vec::new();
+            let mut input_total = 0u64;
+            for proof in surplus {
+                // Allow one unit of input fee per proof
+                if input_total >= missing_total + inputs.len() as u64 {
+                    break;
+                }
+                input_total += u64::from(proof.amount);
+                inputs.push(proof);
+            }
+            if input_total < missing_total + inputs.len() as u64 {
+                log::debug!(
+                    "Not enough surplus proofs at {} to refill token pool",
+                    mint_url
+                );
+                continue;
+            }
+
+            let split = SplitTarget::Values(missing.into_iter().map(Amount::from).collect());
+            wallet
+                .swap(None, split, inputs, None, false)
+                .await
+                .map_err(|e| {
+                    TollGateError::wallet(format!("Failed to refill token pool: {}", e))
+                })?;
+            log::info!(
+                "Refilled token pool at {} with {} {}",
+                mint_url,
+                missing_total,
+                unit
+            );
+            refilled += 1;
+        }
+
+        Ok(refilled)
+    }
+
+    /// Token pool targets and current denominations for every mint
+    pub async fn token_pool_status(&self) -> TollGateResult<Vec<TokenPoolStatus>> {
+        let mut statuses = Vec::new();
+        for (mint_url, wallet) in &self.wallets {
+            let unit = wallet.unit.to_string();
+            let amounts = Self::proof_amounts(wallet).await?;
+            let balance = amounts.iter().sum();
+            statuses.push(TokenPoolStatus {
+                mint_url: mint_url.clone(),
+                unit: unit.clone(),
+                frequent_amounts: self.token_pool.frequent_amounts(mint_url, &unit),
+                target_denominations: self.token_pool.targets(mint_url, &unit, balance),
+                current_denominations: count_denominations(amounts),
+            });
+        }
+        Ok(statuses)
+    }
+
+    pub fn token_pool_config(&self) -> TokenPoolConfig {
+        self.token_pool.config()
+    }
+
+    pub fn set_token_pool_config(&self, config: TokenPoolConfig) -> TollGateResult<()> {
+        self.token_pool.set_config(config)
+    }
+
+    /// Write token pool usage recorded since the last flush
+    pub fn flush_token_pool(&self) {
+        if let Err(e) = self.token_pool.flush() {
+            log::warn!("Failed to persist token pool usage: {}", e);
+        }
     }
 
     /// Request a mint quote for loading the wallet
@@ -1018,7 +2151,13 @@ impl TollGateWallet {
         let quote = wallet
             .mint_quote(Amount::from(amount), None)
             .await
-            .map_err(|e| TollGateError::wallet(format!("Failed to request mint quote: {}", e)))?;
+            .map_err(|e| {
+                mint_request_error(
+                    wallet,
+                    "mint_quote",
+                    format!("Failed to request mint quote: {}", e),
+                )
+            })?;
 
         Ok(quote)
     }
@@ -1031,17 +2170,24 @@ impl TollGateWallet {
             .get(mint_url)
             .ok_or_else(|| TollGateError::wallet(format!("Mint not found: {}", mint_url)))?;
 
-        let status = wallet
-            .mint_quote_state(quote_id)
-            .await
-            .map_err(|e| TollGateError::wallet(format!("Failed to check mint quote: {}", e)))?;
+        let status = wallet.mint_quote_state(quote_id).await.map_err(|e| {
+            mint_request_error(
+                wallet,
+                "mint_quote_state",
+                format!("Failed to check mint quote: {}", e),
+            )
+        })?;
 
         if status.state == cdk::nuts::MintQuoteState::Paid {
             // Mint the tokens
+            let amount = status.amount.map(u64::from).unwrap_or(0);
+            let split = self.split_target_for(mint_url, amount).await;
             wallet
-                .mint(&status.quote, SplitTarget::default(), None)
+                .mint(&status.quote, split, None)
                 .await
-                .map_err(|e| TollGateError::wallet(format!("Failed to mint tokens: {}", e)))?;
+                .map_err(|e| {
+                    mint_request_error(wallet, "mint", format!("Failed to mint tokens: {}", e))
+                })?;
 
             log::info!("Successfully minted tokens for quote {}", quote_id);
             return Ok(true);
@@ -1077,7 +2223,7 @@ impl TollGateWallet {
         // Select the option with the lowest total cost
         let best_option = compatible_options
             .into_iter()
-            .min_by_key(|option| option.price_per_step * steps)
+            .min_by_key(|option| option.cost(steps).unwrap_or(u64::MAX))
             .unwrap();
 
         Ok(best_option)
@@ -1125,6 +2271,72 @@ mod tests {
         // Basic wallet creation test
     }
 
+    #[test]
+    fn test_nip06_nostr_keys() {
+        // NIP-06 test vector
+        let phrase =
+            "leader monkey parrot ring guide accident before fence cannon height naive bean";
+        let stored = StoredSecrets {
+            mnemonic: Some(phrase.to_string()),
+            nostr_account: 0,
+            keep_legacy_nostr_key: false,
+        };
+        let secrets = WalletSecrets::from_stored(stored).expect("secrets");
+
+        assert_eq!(
+            secrets.nostr_keys.secret_key().to_secret_hex(),
+            "7f7ff03d123792d6ac594bfa67bf6d0c0ab55b6b1fdb6249303fe861f1ccba9a"
+        );
+        assert!(secrets.legacy_nostr_keys.is_none());
+        assert_eq!(
+            secrets.nwc_service_keys().public_key(),
+            secrets.nostr_keys.public_key()
+        );
+    }
+
+    #[test]
+    fn test_legacy_nostr_key_kept_for_existing_wallets() {
+        let phrase =
+            "leader monkey parrot ring guide accident before fence cannon height naive bean";
+        let stored: StoredSecrets =
+            serde_json::from_value(serde_json::json!({ "mnemonic": phrase })).unwrap();
+        assert!(stored.keep_legacy_nostr_key);
+
+        let secrets = WalletSecrets::from_stored(stored).expect("secrets");
+        let legacy = derive_legacy_nostr_keys(&secrets.wallet_seed()).unwrap();
+        assert_eq!(secrets.nwc_service_keys().public_key(), legacy.public_key());
+        assert_ne!(secrets.nostr_keys.public_key(), legacy.public_key());
+    }
+
+    #[test]
+    fn test_secrets_rewritten_only_when_changed() {
+        let dir = std::env::temp_dir().join(format!("wally-secrets-{}", uuid::Uuid::new_v4()));
+        let paths = WalletStoragePaths::in_dir(&dir);
+        let phrase =
+            "leader monkey parrot ring guide accident before fence cannon height naive bean";
+
+        // Files from before NIP-06 gain the migration flag
+        fs::create_dir_all(&dir).unwrap();
+        let legacy = serde_json::json!({ "mnemonic": phrase }).to_string();
+        fs::write(&paths.secrets_file, legacy).unwrap();
+        WalletSecrets::load_or_create(&paths).unwrap();
+        let migrated = fs::read_to_string(&paths.secrets_file).unwrap();
+        assert!(migrated.contains("keep_legacy_nostr_key"));
+
+        // An up to date file is left as it is
+        let compact = serde_json::to_string(&serde_json::json!({
+            "mnemonic": phrase,
+            "nostr_account": 0,
+            "keep_legacy_nostr_key": true,
+        }))
+        .unwrap();
+        fs::write(&paths.secrets_file, &compact).unwrap();
+        WalletSecrets::load_or_create(&paths).unwrap();
+        assert_eq!(fs::read_to_string(&paths.secrets_file).unwrap(), compact);
+
+        let _ = fs::remove_dir_all(dir);
+    }
+
     #[test]
     fn test_payment_token_creation() {
         let token = PaymentToken {
@@ -1150,4 +2362,41 @@ mod tests {
         assert_eq!(balance.balance, 1000);
         assert_eq!(balance.pending, 0);
     }
+
+    #[tokio::test]
+    async fn test_stored_holdings_count_unredeemed_tokens() {
+        let dir = std::env::temp_dir().join(format!("wally-holdings-{}", uuid::Uuid::new_v4()));
+        assert!(stored_holdings(&dir).await.unwrap().is_empty());
+
+        let storage = WalletStoragePaths::in_dir(&dir);
+        let mut pending = PendingTokenStore::load(storage.pending_tokens_file).unwrap();
+        pending
+            .insert(PendingToken {
+                id: "a".to_string(),
+                token: "cashuBa".to_string(),
+                amount: 21,
+                unit: "sat".to_string(),
+                mint_url: "https://mint.example.com".to_string(),
+                origin: Origin::Ui,
+                mint_approved: false,
+                dleq_verified: true,
+                status: PendingTokenStatus::Pending,
+                received_at: chrono::Utc::now(),
+                attempts: 0,
+                last_attempt_at: None,
+                last_error: None,
+                redeemed_at: None,
+                swap: None,
+            })
+            .unwrap();
+
+        let holdings = stored_holdings(&dir).await.unwrap();
+        assert_eq!(holdings.pending_tokens, 1);
+        assert!(!holdings.is_empty());
+
+        pending.mark_redeemed("a").unwrap();
+        assert!(stored_holdings(&dir).await.unwrap().is_empty());
+
+        let _ = fs::remove_dir_all(dir);
+    }
 }
@@ -1,14 +1,31 @@
 use crate::{
+    app_lock::AppLockState,
+    payment_input::{self, PaymentInputPreview},
+    tollgate::approvals::{ApprovalsState, PendingApproval},
+    tollgate::errors::TollGateError,
+    tollgate::mint_policy::MintTrustPolicy,
+    tollgate::origin::Origin,
+    tollgate::pending_tokens::PendingToken,
+    tollgate::scheduler::{NewScheduledPayment, ScheduleRun, ScheduledPayment},
+    tollgate::spend_ledger::{LedgerAnchor, LedgerEntry, LedgerVerification},
+    tollgate::spending_policy::{SpendAuditEntry, SpendingPolicy},
+    tollgate::split_strategy::SplitStrategy,
+    tollgate::token_pool::{TokenPoolConfig, TokenPoolStatus},
     tollgate::wallet::{
-        Bolt11InvoiceInfo, Bolt11PaymentResult, Nut18PaymentRequestInfo, WalletSummary,
-        WalletTransactionEntry,
+        Bolt11InvoiceInfo, Bolt11PaymentResult, ConsolidationResult, Nut18PaymentRequestInfo,
+        WalletSummary, WalletTransactionEntry,
     },
     TollGateState,
 };
 use tauri::State;
 
 #[tauri::command]
-pub async fn add_mint(mint_url: String, state: State<'_, TollGateState>) -> Result<(), String> {
+pub async fn add_mint(
+    mint_url: String,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<(), String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
     let service = state.lock().await;
     service.add_mint(&mint_url).await.map_err(|e| e.to_string())
 }
@@ -17,7 +34,9 @@ pub async fn add_mint(mint_url: String, state: State<'_, TollGateState>) -> Resu
 pub async fn set_default_mint(
     mint_url: String,
     state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
 ) -> Result<(), String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
     let service = state.lock().await;
     service
         .set_default_mint(&mint_url)
@@ -26,7 +45,12 @@ pub async fn set_default_mint(
 }
 
 #[tauri::command]
-pub async fn remove_mint(mint_url: String, state: State<'_, TollGateState>) -> Result<(), String> {
+pub async fn remove_mint(
+    mint_url: String,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<(), String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
     let service = state.lock().await;
     service
         .remove_mint(&mint_url)
@@ -35,7 +59,11 @@ pub async fn remove_mint(mint_url: String, state: State<'_, TollGateState>) -> R
 }
 
 #[tauri::command]
-pub async fn get_wallet_balance(state: State<'_, TollGateState>) -> Result<u64, String> {
+pub async fn get_wallet_balance(
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<u64, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
     let service = state.lock().await;
     service
         .get_wallet_balance()
@@ -73,11 +101,19 @@ pub async fn create_bolt11_invoice(
 pub async fn pay_nut18_payment_request(
     request: String,
     custom_amount: Option<u64>,
+    approve_spend: Option<bool>,
     state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
 ) -> Result<(), String> {
-    let service = state.lock().await;
-    service
-        .pay_nut18_payment_request(&request, custom_amount)
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    let payments = state.lock().await.payments();
+    payments
+        .pay_nut18_payment_request(
+            &request,
+            custom_amount,
+            &Origin::Ui,
+            approve_spend.unwrap_or(false),
+        )
         .await
         .map_err(|e| e.to_string())
 }
@@ -85,17 +121,24 @@ pub async fn pay_nut18_payment_request(
 #[tauri::command]
 pub async fn pay_bolt11_invoice(
     invoice: String,
+    approve_spend: Option<bool>,
     state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
 ) -> Result<Bolt11PaymentResult, String> {
-    let service = state.lock().await;
-    service
-        .pay_bolt11_invoice(&invoice)
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    let payments = state.lock().await.payments();
+    payments
+        .pay_bolt11_invoice(&invoice, &Origin::Ui, approve_spend.unwrap_or(false))
         .await
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-pub async fn get_wallet_summary(state: State<'_, TollGateState>) -> Result<WalletSummary, String> {
+pub async fn get_wallet_summary(
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<WalletSummary, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
     let service = state.lock().await;
     service
         .get_wallet_summary()
@@ -106,7 +149,9 @@ pub async fn get_wallet_summary(state: State<'_, TollGateState>) -> Result<Walle
 #[tauri::command]
 pub async fn list_wallet_transactions(
     state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
 ) -> Result<Vec<WalletTransactionEntry>, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
     let service = state.lock().await;
     service
         .list_wallet_transactions()
@@ -117,27 +162,412 @@ pub async fn list_wallet_transactions(
 #[tauri::command]
 pub async fn receive_cashu_token(
     token: String,
+    approve_mint: Option<bool>,
+    offline: Option<bool>,
     state: State<'_, TollGateState>,
 ) -> Result<serde_json::Value, String> {
     let service = state.lock().await;
-    match service.receive_cashu_token(&token).await {
+    let approve_mint = approve_mint.unwrap_or(false);
+    let result = if offline.unwrap_or(false) {
+        service
+            .receive_cashu_token_offline(&token, &Origin::Ui, approve_mint)
+            .await
+    } else {
+        service
+            .receive_cashu_token(&token, &Origin::Ui, approve_mint)
+            .await
+    };
+    match result {
         Ok(result) => Ok(serde_json::json!({
+            "status": result.status,
             "amount": result.amount,
             "mint_url": result.mint_url,
+            "source_mint_url": result.source_mint_url,
+            "pending_id": result.pending_id,
+        })),
+        Err(TollGateError::MintApprovalRequired(mint_url)) => Ok(serde_json::json!({
+            "status": "approval_required",
+            "mint_url": mint_url,
         })),
         Err(e) => Err(e.to_string()),
     }
 }
 
+#[tauri::command]
+pub async fn list_pending_tokens(
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<Vec<PendingToken>, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    Ok(service.list_pending_tokens().await)
+}
+
+/// Copies of the pending-token store that could not be read. The tokens in
+/// them can be pasted into the receive screen to redeem them.
+#[tauri::command]
+pub async fn list_damaged_pending_token_files(
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<Vec<String>, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    Ok(service.damaged_pending_token_files().await)
+}
+
+#[tauri::command]
+pub async fn retry_pending_token(
+    id: String,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<usize, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service
+        .retry_pending_token(&id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn discard_pending_token(
+    id: String,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<PendingToken, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service
+        .discard_pending_token(&id)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_split_strategy(
+    mint_url: String,
+    state: State<'_, TollGateState>,
+) -> Result<SplitStrategy, String> {
+    let service = state.lock().await;
+    Ok(service.get_split_strategy(&mint_url).await)
+}
+
+#[tauri::command]
+pub async fn set_split_strategy(
+    mint_url: String,
+    strategy: SplitStrategy,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<(), String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service
+        .set_split_strategy(&mint_url, strategy)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn consolidate_proofs(
+    mint_url: Option<String>,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<Vec<ConsolidationResult>, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service
+        .consolidate_proofs(mint_url)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_token_pool_status(
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<Vec<TokenPoolStatus>, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service
+        .get_token_pool_status()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_token_pool_config(
+    state: State<'_, TollGateState>,
+) -> Result<TokenPoolConfig, String> {
+    let service = state.lock().await;
+    Ok(service.get_token_pool_config().await)
+}
+
+#[tauri::command]
+pub async fn set_token_pool_config(
+    config: TokenPoolConfig,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<(), String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service
+        .set_token_pool_config(config)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn create_scheduled_payment(
+    schedule: NewScheduledPayment,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<ScheduledPayment, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service.create_schedule(schedule).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn list_scheduled_payments(
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<Vec<ScheduledPayment>, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service.list_schedules().map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn pause_scheduled_payment(
+    id: String,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<ScheduledPayment, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service.pause_schedule(&id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn resume_scheduled_payment(
+    id: String,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<ScheduledPayment, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service.resume_schedule(&id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn delete_scheduled_payment(
+    id: String,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<(), String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service.delete_schedule(&id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn list_scheduled_payment_runs(
+    id: String,
+    limit: Option<u32>,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<Vec<ScheduleRun>, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service
+        .list_schedule_runs(&id, limit.unwrap_or(50))
+        .map_err(|e| e.to_string())
+}
+
+/// Switch the NWC service to the NIP-06 key; takes effect on restart
+#[tauri::command]
+pub async fn retire_legacy_nostr_key(
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<(), String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service
+        .retire_legacy_nostr_key()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_mint_trust_policy(
+    state: State<'_, TollGateState>,
+) -> Result<MintTrustPolicy, String> {
+    let service = state.lock().await;
+    Ok(service.get_mint_trust_policy().await)
+}
+
+#[tauri::command]
+pub async fn set_mint_trust_policy(
+    policy: MintTrustPolicy,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<(), String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service
+        .set_mint_trust_policy(policy)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// Classify and decode arbitrary payment input, and check whether the wallet
+/// can pay it
+#[tauri::command]
+pub async fn parse_payment_input(
+    input: String,
+    state: State<'_, TollGateState>,
+) -> Result<PaymentInputPreview, String> {
+    let mut preview = payment_input::parse_payment_input(&input)?;
+    payment_input::resolve_lnurl(&mut preview).await;
+
+    let balances = {
+        let service = state.lock().await;
+        service
+            .get_wallet_summary()
+            .await
+            .map(|summary| summary.balances)
+            .unwrap_or_default()
+    };
+    preview.apply_balances(&balances);
+
+    Ok(preview)
+}
+
 #[tauri::command]
 pub async fn create_external_token(
     amount_sats: u64,
     mint_url: Option<String>,
+    approve_spend: Option<bool>,
     state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
 ) -> Result<String, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    let payments = state.lock().await.payments();
+    payments
+        .create_external_token(
+            amount_sats,
+            mint_url,
+            &Origin::Ui,
+            None,
+            approve_spend.unwrap_or(false),
+        )
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn get_spending_policy(
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<SpendingPolicy, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    Ok(service.get_spending_policy())
+}
+
+#[tauri::command]
+pub async fn set_spending_policy(
+    policy: SpendingPolicy,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<SpendingPolicy, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
     let service = state.lock().await;
     service
-        .create_external_token(amount_sats, mint_url)
-        .await
+        .set_spending_policy(policy)
         .map_err(|e| e.to_string())
 }
+
+#[tauri::command]
+pub async fn list_spending_audit(
+    limit: Option<u32>,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<Vec<SpendAuditEntry>, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service
+        .list_spending_audit(limit.unwrap_or(100))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn list_spend_ledger(
+    limit: Option<u32>,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<Vec<LedgerEntry>, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service
+        .list_spend_ledger(limit.unwrap_or(100))
+        .map_err(|e| e.to_string())
+}
+
+/// Check the spend ledger for edits and truncation. `anchor` is a head
+/// returned by an earlier verification and recorded elsewhere.
+#[tauri::command]
+pub async fn verify_spend_ledger(
+    anchor: Option<LedgerAnchor>,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<LedgerVerification, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service
+        .verify_spend_ledger(anchor.as_ref())
+        .map_err(|e| e.to_string())
+}
+
+/// Ask before payments from `origin` (`nwc:<pubkey>`, `tollgate`, `proxy`, ...)
+/// above `threshold_sats`; `None` removes the threshold
+#[tauri::command]
+pub async fn set_spend_approval_threshold(
+    origin: String,
+    threshold_sats: Option<u64>,
+    state: State<'_, TollGateState>,
+    lock: State<'_, AppLockState>,
+) -> Result<SpendingPolicy, String> {
+    lock.authorize_spend().map_err(|e| e.to_string())?;
+    let service = state.lock().await;
+    service
+        .set_spend_approval_threshold(&origin, threshold_sats)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn list_spend_approvals(
+    approvals: State<'_, ApprovalsState>,
+    lock: State<'_, AppLockState>,
+) -> Result<Vec<PendingApproval>, String> {
+    lock.authorize_read().map_err(|e| e.to_string())?;
+    Ok(approvals.pending())
+}
+
+/// Answer a payment waiting for approval
+#[tauri::command]
+pub async fn resolve_spend_approval(
+    id: String,
+    approved: bool,
+    approvals: State<'_, ApprovalsState>,
+    lock: State<'_, AppLockState>,
+) -> Result<(), String> {
+    if approved {
+        lock.authorize_spend().map_err(|e| e.to_string())?;
+    }
+    if approvals.resolve(&id, approved) {
+        Ok(())
+    } else {
+        Err(format!("Approval request not found: {}", id))
+    }
+}
@@ -18,8 +18,16 @@ import type { FeatureState, Period, StatusBadge } from "@/routes/types";
 import { periods } from "@/routes/types";
 import { HistoryScreen } from "@/routes/history-screen";
 import {
+  approvePairing,
   fetchWalletSummary,
   fetchWalletTransactions,
+  listPairingRequests,
+  listSpendApprovals,
+  rejectPairing,
+  resolveSpendApproval,
+  type BackendEvent,
+  type PairingRequest,
+  type PendingSpendApproval,
   type WalletSummary,
   type WalletTransactionEntry,
 } from "@/lib/wallet/api";
@@ -48,6 +56,8 @@ type PendingConnectionRequest = {
   nwc_uri: string | null;
   approved: boolean;
   rejected: boolean;
+  client_id: string | null;
+  expires_at: number;
 };
 
 const initialFeatures: FeatureState[] = [
@@ -119,6 +129,12 @@ function AppContent() {
   const [features, setFeatures] = useState<FeatureState[]>(initialFeatures);
   const [pendingConnection, setPendingConnection] =
     useState<PendingConnectionRequest | null>(null);
+  const [spendApprovals, setSpendApprovals] = useState<PendingSpendApproval[]>(
+    [],
+  );
+  const spendApproval = spendApprovals[0] ?? null;
+  const [pairings, setPairings] = useState<PairingRequest[]>([]);
+  const pairing = pairings[0] ?? null;
 
   const periodMeta = useCallback(
     (period: Period) =>
@@ -220,6 +236,52 @@ function AppContent() {
           },
         );
         listeners.push(nwcConnectionRequest);
+
+        const spendApprovalRequest = await listen(
+          "spend-approval-request",
+          (event: any) => {
+            if (!mounted) return;
+            const approval = event.payload as PendingSpendApproval;
+            setSpendApprovals((prev) => [...prev, approval]);
+          },
+        );
+        listeners.push(spendApprovalRequest);
+
+        const spendApprovalResolved = await listen(
+          "spend-approval-resolved",
+          (event: any) => {
+            if (!mounted) return;
+            const { id } = event.payload as { id: string };
+            setSpendApprovals((prev) => prev.filter((item) => item.id !== id));
+          },
+        );
+        listeners.push(spendApprovalResolved);
+
+        const backendEvent = await listen<BackendEvent>(
+          "backend-event",
+          async (event) => {
+            if (!mounted) return;
+            const { topic } = event.payload;
+            if (topic === "wallet" || topic === "session" || topic === "mint") {
+              await refreshStatus();
+            }
+            if (event.payload.type === "pairing_requested") {
+              const { pairing } = event.payload;
+              setPairings((prev) => [...prev, pairing]);
+            }
+          },
+        );
+        listeners.push(backendEvent);
+
+        // Prompts raised before the window was listening
+        const waiting = await listSpendApprovals().catch(() => []);
+        if (mounted && waiting.length) {
+          setSpendApprovals(waiting);
+        }
+        const waitingPairings = await listPairingRequests().catch(() => []);
+        if (mounted && waitingPairings.length) {
+          setPairings(waitingPairings);
+        }
       } catch (error) {
         console.warn("Failed to register listeners", error);
       }
@@ -311,6 +373,36 @@ function AppContent() {
       setLocation("/");
     }
   }, [pendingConnection, setLocation]);
+
+  const handleSpendApproval = useCallback(
+    async (approved: boolean) => {
+      if (!spendApproval) return;
+      setSpendApprovals((prev) =>
+        prev.filter((item) => item.id !== spendApproval.id),
+      );
+      try {
+        await resolveSpendApproval(spendApproval.id, approved);
+      } catch (error) {
+        console.error("Failed to answer payment approval:", error);
+      }
+    },
+    [spendApproval],
+  );
+
+  const handlePairing = useCallback(
+    async (approved: boolean) => {
+      if (!pairing) return;
+      setPairings((prev) => prev.filter((item) => item.id !== pairing.id));
+      try {
+        if (approved) await approvePairing(pairing.id);
+        else await rejectPairing(pairing.id);
+      } catch (error) {
+        console.error("Failed to answer pairing request:", error);
+      }
+    },
+    [pairing],
+  );
+
   const statusBadges: StatusBadge[] = useMemo(() => {
     const badges: StatusBadge[] = [];
 
@@ -609,6 +701,14 @@ function AppContent() {
                 </div>
               </div>
             )}
+            {pendingConnection?.expires_at ? (
+              <p className="text-xs text-muted-foreground">
+                Expires at{" "}
+                {new Date(
+                  pendingConnection.expires_at * 1000,
+                ).toLocaleTimeString()}
+              </p>
+            ) : null}
           </div>
 
           <DialogFooter>
@@ -619,6 +719,98 @@ function AppContent() {
           </DialogFooter>
         </DialogContent>
       </Dialog>
+
+      <Dialog
+        open={!!spendApproval && !pendingConnection}
+        onOpenChange={(open) => !open && handleSpendApproval(false)}
+      >
+        <DialogContent>
+          <DialogHeader>
+            <DialogTitle>Approve Payment</DialogTitle>
+            <DialogDescription>
+              {spendApproval
+                ? `${spendApproval.amount_sats.toLocaleString()} sats requested by ${spendApproval.origin}`
+                : null}
+            </DialogDescription>
+          </DialogHeader>
+
+          {spendApproval ? (
+            <div className="space-y-4">
+              {spendApproval.counterparty ? (
+                <div>
+                  <p className="mb-1 text-sm font-medium">Recipient</p>
+                  <p className="break-all font-mono text-xs text-muted-foreground">
+                    {spendApproval.counterparty}
+                  </p>
+                </div>
+              ) : null}
+              {spendApproval.mint_url ? (
+                <div>
+                  <p className="mb-1 text-sm font-medium">Mint</p>
+                  <p className="break-all font-mono text-xs text-muted-foreground">
+                    {spendApproval.mint_url}
+                  </p>
+                </div>
+              ) : null}
+              <div>
+                <p className="mb-1 text-sm font-medium">Reason</p>
+                <p className="text-xs text-muted-foreground">
+                  {spendApproval.reason}
+                </p>
+              </div>
+              <p className="text-xs text-muted-foreground">
+                Rejected automatically at{" "}
+                {new Date(spendApproval.expires_at * 1000).toLocaleTimeString()}
+              </p>
+            </div>
+          ) : null}
+
+          <DialogFooter>
+            <Button variant="outline" onClick={() => handleSpendApproval(false)}>
+              Reject
+            </Button>
+            <Button onClick={() => handleSpendApproval(true)}>Pay</Button>
+          </DialogFooter>
+        </DialogContent>
+      </Dialog>
+
+      <Dialog
+        open={!!pairing && !pendingConnection && !spendApproval}
+        onOpenChange={(open) => !open && handlePairing(false)}
+      >
+        <DialogContent>
+          <DialogHeader>
+            <DialogTitle>Pair Client</DialogTitle>
+            <DialogDescription>
+              {pairing
+                ? `${pairing.name} wants to request wallet connections and use the Routstr proxy`
+                : null}
+            </DialogDescription>
+          </DialogHeader>
+
+          {pairing ? (
+            <div className="space-y-4">
+              <div>
+                <p className="mb-1 text-sm font-medium">Origin</p>
+                <p className="break-all font-mono text-xs text-muted-foreground">
+                  {pairing.origin ?? "Local tool (no web page)"}
+                </p>
+              </div>
+              <p className="text-xs text-muted-foreground">
+                Only approve clients you just opened. Payments through the
+                proxy still follow your spending policy.
+              </p>
+            </div>
+          ) : null}
+
+          <DialogFooter>
+            <Button variant="outline" onClick={() => handlePairing(false)}>
+              Reject
+            </Button>
+            <Button onClick={() => handlePairing(true)}>Pair</Button>
+          </DialogFooter>
+        </DialogContent>
+      </Dialog>
     </div>
   );
 }
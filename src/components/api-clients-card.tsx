@@ -0,0 +1,190 @@
+import { CopyButton } from "@/components/copy-button";
+import { Button } from "@/components/ui/button";
+import { Card } from "@/components/ui/card";
+import { Checkbox } from "@/components/ui/checkbox";
+import {
+  Collapsible,
+  CollapsibleContent,
+  CollapsibleTrigger,
+} from "@/components/ui/collapsible";
+import { Input } from "@/components/ui/input";
+import { Label } from "@/components/ui/label";
+import type { ApiClient, ApiScope } from "@/lib/wallet/api";
+import {
+  createApiClient,
+  listApiClients,
+  revokeApiClient,
+} from "@/lib/wallet/api";
+import { ChevronDown, ChevronRight, Plus, Trash2 } from "lucide-react";
+import { useCallback, useEffect, useState } from "react";
+
+const scopes: { id: ApiScope; label: string }[] = [
+  { id: "read", label: "Read balances and history" },
+  { id: "receive", label: "Receive tokens" },
+  { id: "spend", label: "Spend (subject to spending policy)" },
+  { id: "manage", label: "Manage mints and NWC connections" },
+  { id: "connect", label: "Request NWC connections" },
+  { id: "proxy", label: "Use the Routstr proxy" },
+];
+
+/** Issue and revoke tokens for the local API (`/api/v1`) */
+export function ApiClientsCard() {
+  const [expanded, setExpanded] = useState(false);
+  const [clients, setClients] = useState<ApiClient[]>([]);
+  const [name, setName] = useState("");
+  const [selected, setSelected] = useState<Set<ApiScope>>(new Set(["read"]));
+  const [creating, setCreating] = useState(false);
+  const [issuedToken, setIssuedToken] = useState<string | null>(null);
+
+  const refresh = useCallback(async () => {
+    try {
+      setClients(await listApiClients());
+    } catch (error) {
+      console.error("Failed to load API clients:", error);
+    }
+  }, []);
+
+  useEffect(() => {
+    if (expanded) void refresh();
+  }, [expanded, refresh]);
+
+  const toggleScope = (scope: ApiScope) => {
+    setSelected((prev) => {
+      const next = new Set(prev);
+      if (next.has(scope)) next.delete(scope);
+      else next.add(scope);
+      return next;
+    });
+  };
+
+  const handleCreate = useCallback(async () => {
+    setCreating(true);
+    try {
+      const issued = await createApiClient(name.trim(), [...selected]);
+      setIssuedToken(issued.token);
+      setName("");
+      await refresh();
+    } catch (error) {
+      console.error("Failed to create API client:", error);
+      alert(`Failed to create API client: ${error}`);
+    } finally {
+      setCreating(false);
+    }
+  }, [name, selected, refresh]);
+
+  const handleRevoke = useCallback(
+    async (id: string) => {
+      try {
+        await revokeApiClient(id);
+        await refresh();
+      } catch (error) {
+        console.error("Failed to revoke API client:", error);
+        alert(`Failed to revoke API client: ${error}`);
+      }
+    },
+    [refresh],
+  );
+
+  return (
+    <Card className="mt-2 space-y-4 border border-dashed border-primary/20 bg-background/90 p-4">
+      <Collapsible open={expanded} onOpenChange={setExpanded}>
+        <CollapsibleTrigger className="flex w-full items-center justify-between text-left">
+          <div>
+            <h3 className="text-base font-semibold">Local API Clients</h3>
+            <p className="text-sm text-muted-foreground">
+              Tokens for local tools and wally-cli
+            </p>
+          </div>
+          {expanded ? (
+            <ChevronDown className="h-4 w-4" />
+          ) : (
+            <ChevronRight className="h-4 w-4" />
+          )}
+        </CollapsibleTrigger>
+
+        <CollapsibleContent className="space-y-4 overflow-hidden">
+          <div className="space-y-3 border-t pt-4">
+            <Label htmlFor="api-client-name">New Client</Label>
+            <Input
+              id="api-client-name"
+              value={name}
+              onChange={(event) => setName(event.target.value)}
+              placeholder="Test runner"
+              disabled={creating}
+            />
+            <div className="grid gap-2">
+              {scopes.map((scope) => (
+                <div key={scope.id} className="flex items-center gap-2">
+                  <Checkbox
+                    id={`api-scope-${scope.id}`}
+                    checked={selected.has(scope.id)}
+                    onCheckedChange={() => toggleScope(scope.id)}
+                  />
+                  <Label htmlFor={`api-scope-${scope.id}`} className="text-sm">
+                    {scope.label}
+                  </Label>
+                </div>
+              ))}
+            </div>
+            <Button
+              onClick={handleCreate}
+              disabled={!name.trim() || selected.size === 0 || creating}
+              size="sm"
+            >
+              <Plus className="h-4 w-4" />
+              {creating ? "Creating..." : "Create Token"}
+            </Button>
+            {issuedToken && (
+              <div className="space-y-2 rounded bg-primary/10 p-3">
+                <p className="text-xs text-muted-foreground">
+                  Copy this token now, it will not be shown again.
+                </p>
+                <p className="break-all font-mono text-xs">{issuedToken}</p>
+                <CopyButton
+                  onCopy={() => navigator.clipboard.writeText(issuedToken)}
+                  label="Copy"
+                  copiedLabel="Copied"
+                  size="sm"
+                  variant="outline"
+                />
+              </div>
+            )}
+          </div>
+
+          <div className="space-y-2">
+            <Label>Issued Tokens</Label>
+            {clients.length > 0 ? (
+              clients.map((client) => (
+                <div
+                  key={client.id}
+                  className="flex items-start justify-between border-b border-dashed border-border/50 py-2 last:border-b-0"
+                >
+                  <div className="min-w-0">
+                    <p className="truncate text-sm font-medium">{client.name}</p>
+                    <p className="text-xs text-muted-foreground">
+                      api:{client.id} · {client.scopes.join(", ")}
+                      {client.web_origin && ` · ${client.web_origin}`}
+                    </p>
+                  </div>
+                  <Button
+                    variant="ghost"
+                    size="sm"
+                    onClick={() => handleRevoke(client.id)}
+                    title="Revoke token"
+                    className="h-auto p-1 text-red-500 hover:bg-red-50 hover:text-red-600"
+                  >
+                    <Trash2 className="h-4 w-4" />
+                  </Button>
+                </div>
+              ))
+            ) : (
+              <p className="py-4 text-center text-sm text-muted-foreground">
+                No API clients yet.
+              </p>
+            )}
+          </div>
+        </CollapsibleContent>
+      </Collapsible>
+    </Card>
+  );
+}
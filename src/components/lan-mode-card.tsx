@@ -0,0 +1,192 @@
+import QRCode from "react-qr-code";
+import { CopyButton } from "@/components/copy-button";
+import { Button } from "@/components/ui/button";
+import { Card } from "@/components/ui/card";
+import {
+  Collapsible,
+  CollapsibleContent,
+  CollapsibleTrigger,
+} from "@/components/ui/collapsible";
+import { Label } from "@/components/ui/label";
+import {
+  Select,
+  SelectContent,
+  SelectItem,
+  SelectTrigger,
+  SelectValue,
+} from "@/components/ui/select";
+import { Switch } from "@/components/ui/switch";
+import type { LanPairingCode, LanStatus } from "@/lib/wallet/api";
+import {
+  createLanPairingCode,
+  getLanStatus,
+  setLanMode,
+} from "@/lib/wallet/api";
+import { ChevronDown, ChevronRight, QrCode } from "lucide-react";
+import { useCallback, useEffect, useState } from "react";
+
+/** Serve the relay and connection server to devices on the local network */
+export function LanModeCard() {
+  const [expanded, setExpanded] = useState(false);
+  const [status, setStatus] = useState<LanStatus | null>(null);
+  const [saving, setSaving] = useState(false);
+  const [pairingCode, setPairingCode] = useState<LanPairingCode | null>(null);
+
+  const refresh = useCallback(async () => {
+    try {
+      setStatus(await getLanStatus());
+    } catch (error) {
+      console.error("Failed to load LAN status:", error);
+    }
+  }, []);
+
+  useEffect(() => {
+    if (expanded) void refresh();
+  }, [expanded, refresh]);
+
+  const apply = useCallback(
+    async (enabled: boolean, iface: string | null) => {
+      setSaving(true);
+      setPairingCode(null);
+      try {
+        setStatus(await setLanMode(enabled, iface));
+      } catch (error) {
+        console.error("Failed to update LAN mode:", error);
+        alert(`Failed to update LAN mode: ${error}`);
+      } finally {
+        setSaving(false);
+      }
+    },
+    [],
+  );
+
+  const handleShowCode = useCallback(async () => {
+    try {
+      setPairingCode(await createLanPairingCode());
+    } catch (error) {
+      console.error("Failed to create pairing code:", error);
+      alert(`Failed to create pairing code: ${error}`);
+    }
+  }, []);
+
+  const selectedInterface =
+    status?.interface ?? status?.interfaces[0]?.name ?? null;
+  const running = Boolean(status?.address);
+
+  return (
+    <Card className="mt-2 space-y-4 border border-dashed border-primary/20 bg-background/90 p-4">
+      <Collapsible open={expanded} onOpenChange={setExpanded}>
+        <CollapsibleTrigger className="flex w-full items-center justify-between text-left">
+          <div>
+            <h3 className="text-base font-semibold">LAN Mode</h3>
+            <p className="text-sm text-muted-foreground">
+              Let phones and other devices on this network connect over NWC
+            </p>
+          </div>
+          {expanded ? (
+            <ChevronDown className="h-4 w-4" />
+          ) : (
+            <ChevronRight className="h-4 w-4" />
+          )}
+        </CollapsibleTrigger>
+
+        <CollapsibleContent className="space-y-4 overflow-hidden">
+          <div className="space-y-3 border-t pt-4">
+            <div className="flex items-center justify-between gap-2">
+              <Label htmlFor="lan-mode-enabled">Serve on the LAN</Label>
+              <Switch
+                id="lan-mode-enabled"
+                checked={status?.enabled ?? false}
+                disabled={!status || saving}
+                onCheckedChange={(enabled) =>
+                  apply(enabled, selectedInterface)
+                }
+              />
+            </div>
+
+            <div className="space-y-2">
+              <Label>Network Interface</Label>
+              {status && status.interfaces.length > 0 ? (
+                <Select
+                  value={selectedInterface ?? undefined}
+                  onValueChange={(iface) => apply(status.enabled, iface)}
+                  disabled={saving}
+                >
+                  <SelectTrigger className="w-full">
+                    <SelectValue placeholder="Choose an interface..." />
+                  </SelectTrigger>
+                  <SelectContent position="popper">
+                    {status.interfaces.map((iface) => (
+                      <SelectItem key={iface.name} value={iface.name}>
+                        {iface.name} ({iface.address})
+                      </SelectItem>
+                    ))}
+                  </SelectContent>
+                </Select>
+              ) : (
+                <p className="text-sm text-muted-foreground">
+                  No network interfaces found.
+                </p>
+              )}
+            </div>
+
+            {status?.error && (
+              <p className="text-sm text-red-500">{status.error}</p>
+            )}
+          </div>
+
+          {running && status && (
+            <div className="space-y-2 text-xs">
+              <div>
+                <Label>Relay</Label>
+                <p className="break-all font-mono">{status.relay_url}</p>
+              </div>
+              <div>
+                <Label>Connection Server</Label>
+                <p className="break-all font-mono">{status.server_url}</p>
+              </div>
+              <div>
+                <Label>Certificate Fingerprint</Label>
+                <p className="break-all font-mono">{status.fingerprint}</p>
+              </div>
+              <p className="text-muted-foreground">
+                Devices should check this fingerprint before trusting the
+                self-signed certificate.
+              </p>
+
+              <Button onClick={handleShowCode} size="sm" variant="outline">
+                <QrCode className="h-4 w-4" />
+                Show Pairing Code
+              </Button>
+              {pairingCode && (
+                <div className="space-y-2 rounded bg-primary/10 p-3">
+                  <div className="mx-auto h-40 w-40 bg-white p-2">
+                    <QRCode value={pairingCode.uri} className="h-full w-full" />
+                  </div>
+                  <p className="text-center font-mono text-lg tracking-widest">
+                    {pairingCode.code}
+                  </p>
+                  <p className="text-center text-muted-foreground">
+                    Works once, until{" "}
+                    {new Date(
+                      pairingCode.expires_at * 1000,
+                    ).toLocaleTimeString()}
+                  </p>
+                  <CopyButton
+                    onCopy={() =>
+                      navigator.clipboard.writeText(pairingCode.uri)
+                    }
+                    label="Copy Link"
+                    copiedLabel="Copied"
+                    size="sm"
+                    variant="outline"
+                  />
+                </div>
+              )}
+            </div>
+          )}
+        </CollapsibleContent>
+      </Collapsible>
+    </Card>
+  );
+}
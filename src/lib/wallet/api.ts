@@ -35,6 +35,7 @@ export type WalletSummary = {
   default_mint: string | null;
   balances: WalletBalance[];
   npub: string | null;
+  legacy_npub: string | null;
 };
 
 export type WalletTransactionEntry = {
@@ -88,25 +89,105 @@ export async function createBolt11Invoice(
 export async function payNut18PaymentRequest(
   request: string,
   customAmount: number | null,
+  approveSpend = false,
 ): Promise<void> {
   await invoke("pay_nut18_payment_request", {
     request,
     customAmount,
+    approveSpend,
   });
 }
 
-export async function payBolt11Invoice(invoice: string): Promise<Bolt11PaymentResult> {
+export async function payBolt11Invoice(
+  invoice: string,
+  approveSpend = false,
+): Promise<Bolt11PaymentResult> {
   return invoke<Bolt11PaymentResult>("pay_bolt11_invoice", {
     invoice,
+    approveSpend,
   });
 }
 
-export async function receiveCashuToken(token: string): Promise<{ amount: number; mint_url: string }> {
-  return invoke<{ amount: number; mint_url: string }>("receive_cashu_token", {
+export type CashuReceiveResult =
+  | {
+      status: "received" | "swapped_to_default" | "pending";
+      amount: number;
+      mint_url: string;
+      source_mint_url: string | null;
+      pending_id: string | null;
+    }
+  | { status: "approval_required"; mint_url: string };
+
+export type MintTrustPolicy = {
+  mode: "open" | "allow_list" | "ask";
+  allowed_mints: string[];
+  denied_mints: string[];
+  untrusted_action: "reject" | "swap_to_default";
+};
+
+export type PendingToken = {
+  id: string;
+  token: string;
+  amount: number;
+  unit: string;
+  mint_url: string;
+  dleq_verified: boolean;
+  status: "pending" | "redeemed" | "failed" | "undelivered";
+  received_at: string;
+  attempts: number;
+  last_attempt_at: string | null;
+  last_error: string | null;
+  redeemed_at: string | null;
+  swap: PendingSwap | null;
+};
+
+export type PendingSwap = {
+  received: number;
+  quote: {
+    mint_url: string;
+    quote_id: string;
+    request: string;
+    amount: number;
+  } | null;
+};
+
+export async function receiveCashuToken(
+  token: string,
+  approveMint = false,
+  offline = false,
+): Promise<CashuReceiveResult> {
+  return invoke<CashuReceiveResult>("receive_cashu_token", {
     token,
+    approveMint,
+    offline,
   });
 }
 
+export async function listPendingTokens(): Promise<PendingToken[]> {
+  return invoke<PendingToken[]>("list_pending_tokens");
+}
+
+export async function retryPendingToken(id: string): Promise<number> {
+  return invoke<number>("retry_pending_token", { id });
+}
+
+export async function discardPendingToken(id: string): Promise<PendingToken> {
+  return invoke<PendingToken>("discard_pending_token", { id });
+}
+
+/** Pending-token files that could not be read; the tokens in them can be redeemed by hand */
+export async function listDamagedPendingTokenFiles(): Promise<string[]> {
+  return invoke<string[]>("list_damaged_pending_token_files");
+}
+
+export async function getMintTrustPolicy(): Promise<MintTrustPolicy> {
+  return invoke<MintTrustPolicy>("get_mint_trust_policy");
+}
+
+export async function setMintTrustPolicy(policy: MintTrustPolicy): Promise<void> {
+  await invoke("set_mint_trust_policy", { policy });
+}
+
 export async function addMint(mintUrl: string): Promise<void> {
   await invoke("add_mint", {
     mintUrl,
@@ -118,3 +199,551 @@ export async function removeMint(mintUrl: string): Promise<void> {
     mintUrl,
   });
 }
+
+export type PaymentInputPreview = {
+  kind:
+    | "bolt11"
+    | "cashu_token"
+    | "nut18_request"
+    | "lnurl"
+    | "lightning_address"
+    | "nwc_uri"
+    | "nwa_uri";
+  action: "pay" | "receive" | "connect";
+  normalized: string;
+  amount: number | null;
+  unit: string | null;
+  mints: string[];
+  expiry: number | null;
+  description: string | null;
+  can_pay: boolean;
+  details: Record<string, unknown>;
+};
+
+export async function parsePaymentInput(input: string): Promise<PaymentInputPreview> {
+  return invoke<PaymentInputPreview>("parse_payment_input", { input });
+}
+
+export type ScheduleTarget =
+  | { type: "lightning_address"; address: string }
+  | { type: "lnurl"; lnurl: string }
+  | { type: "nut18"; request: string }
+  | { type: "nostr"; npub: string; relays?: string[] };
+
+export type SchedulePeriod =
+  | { type: "daily" }
+  | { type: "weekly" }
+  | { type: "monthly" }
+  | { type: "custom"; seconds: number };
+
+export type CatchUpPolicy = "skip" | "run_once" | "run_all";
+
+export type ScheduledPayment = {
+  id: string;
+  name: string;
+  target: ScheduleTarget;
+  amount_sats: number;
+  mint_url: string | null;
+  period: SchedulePeriod;
+  catch_up: CatchUpPolicy;
+  max_total_sats: number | null;
+  max_runs: number | null;
+  status: "active" | "paused" | "completed";
+  next_run_at: string;
+  total_paid_sats: number;
+  run_count: number;
+  created_at: string;
+};
+
+export type NewScheduledPayment = {
+  name: string;
+  target: ScheduleTarget;
+  amount_sats: number;
+  mint_url?: string | null;
+  period: SchedulePeriod;
+  catch_up?: CatchUpPolicy;
+  max_total_sats?: number | null;
+  max_runs?: number | null;
+  start_at?: string | null;
+};
+
+export type ScheduleRun = {
+  id: number;
+  schedule_id: string;
+  scheduled_for: string;
+  executed_at: string;
+  status: "succeeded" | "failed" | "skipped" | "undelivered";
+  amount_sats: number;
+  fee_sats: number;
+  reference: string | null;
+  error: string | null;
+};
+
+export async function createScheduledPayment(
+  schedule: NewScheduledPayment,
+): Promise<ScheduledPayment> {
+  return invoke<ScheduledPayment>("create_scheduled_payment", { schedule });
+}
+
+export async function listScheduledPayments(): Promise<ScheduledPayment[]> {
+  return invoke<ScheduledPayment[]>("list_scheduled_payments");
+}
+
+export async function pauseScheduledPayment(id: string): Promise<ScheduledPayment> {
+  return invoke<ScheduledPayment>("pause_scheduled_payment", { id });
+}
+
+export async function resumeScheduledPayment(id: string): Promise<ScheduledPayment> {
+  return invoke<ScheduledPayment>("resume_scheduled_payment", { id });
+}
+
+export async function deleteScheduledPayment(id: string): Promise<void> {
+  await invoke("delete_scheduled_payment", { id });
+}
+
+export async function listScheduledPaymentRuns(
+  id: string,
+  limit?: number,
+): Promise<ScheduleRun[]> {
+  return invoke<ScheduleRun[]>("list_scheduled_payment_runs", { id, limit });
+}
+
+export async function retireLegacyNostrKey(): Promise<void> {
+  await invoke("retire_legacy_nostr_key");
+}
+
+export type BackupSummary = {
+  path: string;
+  created_at: string;
+  files: string[];
+};
+
+export type RestoreSummary = {
+  created_at: string;
+  files: string[];
+  previous_state_dir: string;
+  restart_required: boolean;
+};
+
+export async function createBackup(path: string, passphrase: string): Promise<BackupSummary> {
+  return invoke<BackupSummary>("create_backup", { path, passphrase });
+}
+
+export async function restoreBackup(path: string, passphrase: string): Promise<RestoreSummary> {
+  return invoke<RestoreSummary>("restore_backup", { path, passphrase });
+}
+
+export type SettingsPublishSummary = {
+  event_id: string;
+  relays: string[];
+};
+
+export type SettingsApplySummary = {
+  published_at: number;
+  mints_added: string[];
+  mints_failed: string[];
+  nwc_connections_updated: number;
+  nwc_connections_to_reissue: string[];
+  restart_required: boolean;
+};
+
+export async function publishSettingsToNostr(): Promise<SettingsPublishSummary> {
+  return invoke<SettingsPublishSummary>("publish_settings_to_nostr");
+}
+
+export async function pullSettingsFromNostr(): Promise<SettingsApplySummary | null> {
+  return invoke<SettingsApplySummary | null>("pull_settings_from_nostr");
+}
+
+export async function restoreWalletFromMnemonic(
+  mnemonic: string,
+): Promise<SettingsApplySummary | null> {
+  return invoke<SettingsApplySummary | null>("restore_wallet_from_mnemonic", { mnemonic });
+}
+
+export type ProfileInfo = {
+  name: string;
+  created_at: string;
+};
+
+export type ProfileList = {
+  active: string;
+  profiles: ProfileInfo[];
+};
+
+export async function listWalletProfiles(): Promise<ProfileList> {
+  return invoke<ProfileList>("list_wallet_profiles");
+}
+
+export async function createWalletProfile(name: string): Promise<ProfileInfo> {
+  return invoke<ProfileInfo>("create_wallet_profile", { name });
+}
+
+export async function deleteWalletProfile(name: string, force = false): Promise<void> {
+  await invoke("delete_wallet_profile", { name, force });
+}
+
+export async function switchWalletProfile(name: string): Promise<ProfileList> {
+  return invoke<ProfileList>("switch_wallet_profile", { name });
+}
+
+export type AppLockStatus = {
+  enabled: boolean;
+  locked: boolean;
+  auto_lock_secs: number;
+  allow_reads_while_locked: boolean;
+  locks_in_secs: number | null;
+  /** Why the lock settings could not be read; the app stays locked */
+  damaged: string | null;
+};
+
+export async function getAppLockStatus(): Promise<AppLockStatus> {
+  return invoke<AppLockStatus>("get_app_lock_status");
+}
+
+export async function lockApp(): Promise<void> {
+  await invoke("lock_app");
+}
+
+export async function unlockApp(passphrase: string): Promise<AppLockStatus> {
+  return invoke<AppLockStatus>("unlock_app", { passphrase });
+}
+
+export async function appLockHeartbeat(): Promise<AppLockStatus> {
+  return invoke<AppLockStatus>("app_lock_heartbeat");
+}
+
+export async function setAppLockPassphrase(
+  passphrase: string,
+  current?: string,
+): Promise<AppLockStatus> {
+  return invoke<AppLockStatus>("set_app_lock_passphrase", {
+    passphrase,
+    current: current ?? null,
+  });
+}
+
+export async function disableAppLock(passphrase: string): Promise<AppLockStatus> {
+  return invoke<AppLockStatus>("disable_app_lock", { passphrase });
+}
+
+export async function setAppLockConfig(
+  autoLockSecs: number,
+  allowReadsWhileLocked: boolean,
+): Promise<AppLockStatus> {
+  return invoke<AppLockStatus>("set_app_lock_config", {
+    autoLockSecs,
+    allowReadsWhileLocked,
+  });
+}
+
+export type PolicyOutcome = "allow" | "ask" | "deny";
+
+export type SpendRule = {
+  id?: string;
+  name: string;
+  enabled?: boolean;
+  matches?: {
+    origin?: string | null;
+    counterparty?: string | null;
+    mint_url?: string | null;
+    min_amount_sats?: number | null;
+    max_amount_sats?: number | null;
+  };
+  limit?: { window_secs: number; max_sats: number } | null;
+  outcome: PolicyOutcome;
+};
+
+export type SpendingPolicy = {
+  default_outcome: PolicyOutcome;
+  rules: SpendRule[];
+};
+
+export type SpendAuditEntry = {
+  id: number;
+  timestamp: string;
+  origin: string;
+  counterparty: string | null;
+  mint_url: string | null;
+  amount_sats: number;
+  outcome: PolicyOutcome;
+  rule_id: string | null;
+  reason: string;
+  status:
+    | "pending"
+    | "completed"
+    | "failed"
+    | "denied"
+    | "approval_required"
+    | "awaiting_approval"
+    | "rejected"
+    | "expired";
+};
+
+export async function getSpendingPolicy(): Promise<SpendingPolicy> {
+  return invoke<SpendingPolicy>("get_spending_policy");
+}
+
+export async function setSpendingPolicy(policy: SpendingPolicy): Promise<SpendingPolicy> {
+  return invoke<SpendingPolicy>("set_spending_policy", { policy });
+}
+
+export async function listSpendingAudit(limit?: number): Promise<SpendAuditEntry[]> {
+  return invoke<SpendAuditEntry[]>("list_spending_audit", { limit: limit ?? null });
+}
+
+export type SpendLedgerEntry = {
+  seq: number;
+  timestamp: string;
+  kind: "melt" | "token";
+  origin: string;
+  counterparty: string | null;
+  mint_url: string | null;
+  amount_sats: number;
+  prev_mac: string;
+  mac: string;
+};
+
+export type SpendLedgerAnchor = {
+  seq: number;
+  mac: string;
+};
+
+export type SpendLedgerVerification = {
+  valid: boolean;
+  entries: number;
+  head: SpendLedgerAnchor | null;
+  problems: string[];
+};
+
+export async function listSpendLedger(limit?: number): Promise<SpendLedgerEntry[]> {
+  return invoke<SpendLedgerEntry[]>("list_spend_ledger", { limit: limit ?? null });
+}
+
+/** Verify the spend ledger, optionally against a head recorded earlier */
+export async function verifySpendLedger(
+  anchor?: SpendLedgerAnchor,
+): Promise<SpendLedgerVerification> {
+  return invoke<SpendLedgerVerification>("verify_spend_ledger", {
+    anchor: anchor ?? null,
+  });
+}
+
+/** Ask before payments from `origin` (e.g. `nwc:<pubkey>`, `tollgate`, `proxy`) above `thresholdSats` */
+export async function setSpendApprovalThreshold(
+  origin: string,
+  thresholdSats: number | null,
+): Promise<SpendingPolicy> {
+  return invoke<SpendingPolicy>("set_spend_approval_threshold", {
+    origin,
+    thresholdSats,
+  });
+}
+
+export type PendingSpendApproval = {
+  id: string;
+  origin: string;
+  counterparty: string | null;
+  mint_url: string | null;
+  amount_sats: number;
+  reason: string;
+  requested_at: number;
+  expires_at: number;
+};
+
+export async function listSpendApprovals(): Promise<PendingSpendApproval[]> {
+  return invoke<PendingSpendApproval[]>("list_spend_approvals");
+}
+
+export async function resolveSpendApproval(
+  id: string,
+  approved: boolean,
+): Promise<void> {
+  await invoke("resolve_spend_approval", { id, approved });
+}
+
+export type ApiScope =
+  | "read"
+  | "receive"
+  | "spend"
+  | "manage"
+  | "connect"
+  | "proxy";
+
+export type ApiClient = {
+  id: string;
+  name: string;
+  scopes: ApiScope[];
+  created_at: number;
+  /** Web origin the client was paired from */
+  web_origin?: string;
+};
+
+/** A new client; `token` is only returned here and never stored */
+export type IssuedApiClient = {
+  client: ApiClient;
+  token: string;
+};
+
+export async function listApiClients(): Promise<ApiClient[]> {
+  return invoke<ApiClient[]>("list_api_clients");
+}
+
+export async function createApiClient(
+  name: string,
+  scopes: ApiScope[],
+): Promise<IssuedApiClient> {
+  return invoke<IssuedApiClient>("create_api_client", { name, scopes });
+}
+
+export async function revokeApiClient(id: string): Promise<ApiClient> {
+  return invoke<ApiClient>("revoke_api_client", { id });
+}
+
+/** A web page or tool asking for a token, via `POST /pair` */
+export type PairingRequest = {
+  id: string;
+  name: string;
+  origin: string | null;
+  requested_at: number;
+  expires_at: number;
+};
+
+export async function listPairingRequests(): Promise<PairingRequest[]> {
+  return invoke<PairingRequest[]>("list_pairing_requests");
+}
+
+export async function approvePairing(id: string): Promise<ApiClient> {
+  return invoke<ApiClient>("approve_pairing", { id });
+}
+
+export async function rejectPairing(id: string): Promise<void> {
+  await invoke("reject_pairing", { id });
+}
+
+export type LanInterface = {
+  name: string;
+  address: string;
+};
+
+/** Whether the relay and connection server are also served on the LAN */
+export type LanStatus = {
+  enabled: boolean;
+  interface: string | null;
+  /** Address LAN mode listens on, when running */
+  address: string | null;
+  relay_url: string | null;
+  server_url: string | null;
+  /** SHA-256 fingerprint of the self-signed certificate */
+  fingerprint: string | null;
+  error: string | null;
+  interfaces: LanInterface[];
+};
+
+/** Single-use code a LAN device exchanges for an NWC connection */
+export type LanPairingCode = {
+  code: string;
+  expires_at: number;
+  /** `wally+lan://` URI with the code and fingerprint, for QR codes */
+  uri: string;
+  server_url: string;
+  fingerprint: string;
+};
+
+export async function getLanStatus(): Promise<LanStatus> {
+  return invoke<LanStatus>("get_lan_status");
+}
+
+export async function setLanMode(
+  enabled: boolean,
+  iface: string | null,
+): Promise<LanStatus> {
+  return invoke<LanStatus>("set_lan_mode", { enabled, interface: iface });
+}
+
+export async function createLanPairingCode(): Promise<LanPairingCode> {
+  return invoke<LanPairingCode>("create_lan_pairing_code");
+}
+
+/** Ports the relay and connection server ended up on */
+export type RuntimeInfo = {
+  pid: number;
+  started_at: number;
+  relay_port: number | null;
+  relay_url: string | null;
+  connection_port: number;
+  api_url: string;
+};
+
+export async function getRuntimeInfo(): Promise<RuntimeInfo> {
+  return invoke<RuntimeInfo>("get_runtime_info");
+}
+
+export type ServiceStatus = {
+  name: string;
+  state: "running" | "restarting" | "stopped";
+  started_at: number;
+  restarts: number;
+  last_error: string | null;
+  restart_at: number | null;
+};
+
+export async function getServiceStatus(): Promise<ServiceStatus[]> {
+  return invoke<ServiceStatus[]>("get_service_status");
+}
+
+export type BackendEventTopic =
+  | "wallet"
+  | "session"
+  | "nwc"
+  | "connection"
+  | "mint"
+  | "proxy"
+  | "approval";
+
+/** Emitted as the `backend-event` Tauri event and streamed by `/api/v1/events` */
+export type BackendEvent = {
+  seq: number;
+  at: number;
+  topic: BackendEventTopic;
+} & (
+  | { type: "balance_changed"; total_sats: number }
+  | {
+      type: "session_started" | "session_renewed" | "session_expired";
+      session: Record<string, unknown>;
+    }
+  | {
+      type: "nwc_request_handled";
+      connection: string;
+      method: string;
+      error: string | null;
+    }
+  | { type: "connection_requested"; request: Record<string, unknown> }
+  | {
+      type: "connection_approved";
+      request_id: string;
+      app_pubkey: string | null;
+    }
+  | { type: "pairing_requested"; pairing: PairingRequest }
+  | { type: "lan_device_paired"; name: string; connection_pubkey: string }
+  | {
+      type: "mint_quote_paid";
+      mint_url: string;
+      quote_id: string;
+      amount_sats: number;
+    }
+  | {
+      type: "proxy_request_billed";
+      target_url: string;
+      model: string | null;
+      status: number;
+      paid_sats: number;
+      refunded_sats: number;
+    }
+  | { type: "spend_approval_requested"; approval: PendingSpendApproval }
+  | {
+      type: "spend_approval_resolved";
+      id: string;
+      outcome: "approved" | "rejected" | "timed_out" | "unavailable";
+    }
+);
@@ -259,7 +259,8 @@ export function ConnectionsScreen({ copyToClipboard }: ConnectionsScreenProps) {
         use_local_relay: useLocalRelay,
       });
       setNewNwcUri(uri);
-      setLastCreatedRelay(useLocalRelay ? "ws://localhost:4869" : "wss://nostrue.com");
+      // The local relay may not be on its default port
+      setLastCreatedRelay(new URL(uri).searchParams.get("relay"));
       await loadConnections();
     } catch (err) {
       console.error("Failed to create NWC URI:", err);
@@ -11,9 +11,13 @@ import { Textarea } from "@/components/ui/textarea";
 import {
   createBolt11Invoice,
   createNut18PaymentRequest,
+  listDamagedPendingTokenFiles,
+  listPendingTokens,
   receiveCashuToken,
+  retryPendingToken,
   type Bolt11InvoiceInfo,
   type Nut18PaymentRequestInfo,
+  type PendingToken,
 } from "@/lib/wallet/api";
 
 const MODES = [
@@ -24,6 +28,10 @@ const MODES = [
 
 type ReceiveMode = (typeof MODES)[number]["id"];
 
+/** Incoming tokens still waiting to be redeemed with their mint */
+const isAwaitingMint = (token: PendingToken) =>
+  token.status === "pending" || token.status === "failed";
+
 type ReceiveScreenProps = {
   onBack: () => void;
   copyToClipboard: (value: string) => Promise<void> | void;
@@ -40,6 +48,28 @@ export function ReceiveScreen({ onBack, copyToClipboard, defaultMint }: ReceiveS
   const [isReceiving, setIsReceiving] = useState(false);
   const [error, setError] = useState<string | null>(null);
   const [success, setSuccess] = useState<string | null>(null);
+  const [pendingTokens, setPendingTokens] = useState<PendingToken[]>([]);
+  const [damagedFiles, setDamagedFiles] = useState<string[]>([]);
+
+  const refreshPendingTokens = async () => {
+    try {
+      const [tokens, damaged] = await Promise.all([
+        listPendingTokens(),
+        listDamagedPendingTokenFiles(),
+      ]);
+      setPendingTokens(tokens);
+      setDamagedFiles(damaged);
+    } catch (err) {
+      console.error("Failed to load pending tokens", err);
+    }
+  };
+
+  useEffect(() => {
+    if (mode !== "redeem") return;
+    void refreshPendingTokens();
+    const timer = window.setInterval(() => void refreshPendingTokens(), 10_000);
+    return () => window.clearInterval(timer);
+  }, [mode]);
 
   const activeRequest = mode === "cashu" ? cashuRequest : mode === "lightning" ? lightningInvoice : null;
   const qrValue = activeRequest?.request ?? "";
@@ -172,7 +202,16 @@ export function ReceiveScreen({ onBack, copyToClipboard, defaultMint }: ReceiveS
     await copyToClipboard(qrValue);
   };
 
-  const handleReceiveToken = async () => {
+  const handleRetryPending = async (id: string) => {
+    try {
+      await retryPendingToken(id);
+    } catch (err) {
+      console.error("Failed to retry pending token", err);
+    }
+    await refreshPendingTokens();
+  };
+
+  const handleReceiveToken = async (offline = false) => {
     if (!cashuTokenInput.trim()) return;
 
     setIsReceiving(true);
@@ -180,8 +219,32 @@ export function ReceiveScreen({ onBack, copyToClipboard, defaultMint }: ReceiveS
     setSuccess(null);
 
     try {
-      const result = await receiveCashuToken(cashuTokenInput.trim());
-      setSuccess(`Successfully received ${result.amount} sats!`);
+      const token = cashuTokenInput.trim();
+      let result = await receiveCashuToken(token, false, offline);
+      if (result.status === "approval_required") {
+        const approved = window.confirm(
+          `This token is from a mint you have not added yet:\n${result.mint_url}\n\nTrust this mint and receive the token?`,
+        );
+        if (!approved) {
+          setError("Token not received: mint was not approved.");
+          return;
+        }
+        result = await receiveCashuToken(token, true, offline);
+      }
+      if (result.status === "approval_required") {
+        setError("Token not received: mint was not approved.");
+        return;
+      }
+      if (result.status === "pending") {
+        setSuccess(`Accepted ${result.amount} sats offline. It will be redeemed once the mint is reachable.`);
+        await refreshPendingTokens();
+      } else {
+        setSuccess(
+          result.status === "swapped_to_default"
+            ? `Received ${result.amount} sats, swapped into ${result.mint_url}`
+            : `Successfully received ${result.amount} sats!`,
+        );
+      }
       setCashuTokenInput("");
     } catch (err) {
       console.error("Failed to receive token", err);
@@ -266,12 +329,19 @@ export function ReceiveScreen({ onBack, copyToClipboard, defaultMint }: ReceiveS
 
           <div className="flex gap-3">
             <Button
-              onClick={handleReceiveToken}
+              onClick={() => handleReceiveToken()}
               disabled={isReceiving || !cashuTokenInput.trim()}
               className="flex-1"
             >
               {isReceiving ? "Receiving…" : "Receive token"}
             </Button>
+            <Button
+              variant="outline"
+              onClick={() => handleReceiveToken(true)}
+              disabled={isReceiving || !cashuTokenInput.trim()}
+            >
+              Offline
+            </Button>
             <Button
               variant="outline"
               onClick={() => setCashuTokenInput("")}
@@ -283,6 +353,48 @@ export function ReceiveScreen({ onBack, copyToClipboard, defaultMint }: ReceiveS
 
           {error ? <p className="text-sm text-destructive">{error}</p> : null}
           {success ? <p className="text-sm text-green-600">{success}</p> : null}
+
+          {damagedFiles.length > 0 ? (
+            <div className="grid gap-1 rounded-md border border-destructive/50 p-2 text-sm">
+              <p className="text-destructive">Some pending tokens could not be read.</p>
+              <p className="text-xs text-muted-foreground">
+                A copy was kept in the file below. Paste each token from it above to redeem it, then
+                delete the file.
+              </p>
+              {damagedFiles.map((path) => (
+                <p key={path} className="break-all font-mono text-xs">
+                  {path}
+                </p>
+              ))}
+            </div>
+          ) : null}
+
+          {pendingTokens.some(isAwaitingMint) ? (
+            <div className="grid gap-2">
+              <Label>Waiting for mint</Label>
+              {pendingTokens
+                .filter(isAwaitingMint)
+                .map((token) => (
+                  <div
+                    key={token.id}
+                    className="flex items-start justify-between gap-3 rounded-md border p-2 text-sm"
+                  >
+                    <div className="min-w-0">
+                      <p>
+                        {token.amount} {token.unit} · {token.status}
+                      </p>
+                      <p className="truncate text-xs text-muted-foreground">{token.mint_url}</p>
+                      {token.last_error ? (
+                        <p className="text-xs text-destructive">{token.last_error}</p>
+                      ) : null}
+                    </div>
+                    <Button variant="outline" size="sm" onClick={() => handleRetryPending(token.id)}>
+                      Retry
+                    </Button>
+                  </div>
+                ))}
+            </div>
+          ) : null}
         </div>
       )}
 
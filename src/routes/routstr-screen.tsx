@@ -43,6 +43,7 @@ import {
   getSelectedMint,
   getWalletSummary,
 } from "@/lib/routstr/api";
+import { getRuntimeInfo } from "@/lib/wallet/api";
 import { discoverNostrProviders } from "@/lib/nostr-providers";
 
 type RoutstrScreenProps = {
@@ -76,7 +77,12 @@ export function RoutstrScreen({ copyToClipboard }: RoutstrScreenProps) {
   const [selectedMint, setSelectedMintState] = useState<string>("");
   const queryClient = useQueryClient();
 
-  const proxyEndpoint = "http://127.0.0.1:3737";
+  const { data: runtimeInfo } = useQuery({
+    queryKey: ["runtime-info"],
+    queryFn: getRuntimeInfo,
+    staleTime: Infinity,
+  });
+  const proxyEndpoint = `http://127.0.0.1:${runtimeInfo?.connection_port ?? 3737}`;
 
   const {
     data: providers = [],
@@ -717,6 +723,11 @@ export function RoutstrScreen({ copyToClipboard }: RoutstrScreenProps) {
                         requests to{" "}
                         {serviceUrl || "your configured target service"}.
                       </p>
+                      <p className="mt-1 text-xs text-blue-700">
+                        Clients send a token with the proxy scope in the
+                        X-Wally-Token header. Issue one under Local API
+                        Clients, or pair with POST /pair.
+                      </p>
                     </div>
                   )}
                 </div>
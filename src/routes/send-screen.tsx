@@ -44,17 +44,38 @@ export function SendScreen({
 
     setIsSubmitting(true);
     setError(null);
-    try {
+    const pay = async (approveSpend: boolean) => {
       if (requestType === "cashu") {
-        await payNut18PaymentRequest(trimmed, null);
+        await payNut18PaymentRequest(trimmed, null, approveSpend);
       } else if (requestType === "lightning") {
-        await payBolt11Invoice(trimmed);
+        await payBolt11Invoice(trimmed, approveSpend);
+      }
+    };
+
+    try {
+      try {
+        await pay(false);
+      } catch (err) {
+        // The spending policy asks for confirmation of this payment
+        const message = String(err);
+        if (!message.startsWith("Payment requires approval")) {
+          throw err;
+        }
+        if (!window.confirm(`${message}\n\nSend this payment anyway?`)) {
+          return;
+        }
+        await pay(true);
       }
 
       await onPaymentComplete();
     } catch (err) {
       console.error("Payment failed", err);
-      setError("Payment failed. Check your balance and try again.");
+      const message = String(err);
+      setError(
+        message.startsWith("Payment denied by spending policy")
+          ? message
+          : "Payment failed. Check your balance and try again.",
+      );
     } finally {
       setIsSubmitting(false);
     }
@@ -1,4 +1,6 @@
+import { ApiClientsCard } from "@/components/api-clients-card";
 import { BudgetControls, BudgetUsage } from "@/components/budget";
+import { LanModeCard } from "@/components/lan-mode-card";
 import { Screen } from "@/components/layout/screen";
 import { SectionHeader } from "@/components/layout/section-header";
 import { Button } from "@/components/ui/button";
@@ -232,6 +234,10 @@ export function SettingsScreen({
           </Collapsible>
         </Card>
 
+        <ApiClientsCard />
+
+        <LanModeCard />
+
         {/* Legacy Settings */}
         <Card className="mt-2 space-y-4 border border-dashed border-primary/20 bg-background/90 p-4">
           <div className="grid gap-3">